@@ -1,84 +1,1587 @@
-use std::fs;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fmt::Debug;
+use std::fs::{self, File};
+use std::env;
+use std::io::{self, BufRead, BufReader, BufWriter, IsTerminal, LineWriter, Write};
+use std::path::Path;
 use std::process;
+use std::sync::{atomic::{AtomicUsize, Ordering}, mpsc};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
 
-use isa::instruction::LabeledInstruction;
+use isa::database::{self, Entry};
+use isa::deadlock;
+use isa::event::{self, Observer};
+use isa::event_structure::EventStructure;
+use isa::explore::{self, Exploration, Explorer, Outcome, Sampler, Search};
+use isa::fence_map::FenceMap;
+use isa::json;
+use isa::linearizability::History;
+use isa::litmus;
+use isa::metrics::{self, Address, ThreadMetrics};
 use isa::memory_model::MemoryModel;
 use isa::memory_model::MemoryModelType;
-use isa::memory_model::PSO;
-use isa::memory_model::SC;
-use isa::memory_model::TSO;
-use isa::parser::parse_instruction;
+use isa::memory_model::TraceOptions;
+use isa::parser::parse_program;
+use isa::preprocessor::Source;
+use isa::program::Program;
+use isa::lockset::LocksetDetector;
+use isa::race::RaceDetector;
+use isa::reads_from;
+use isa::smt;
+use isa::spec::Spec;
+use isa::symbolic;
+use isa::testing;
+use isa::trace::{self as traces, Record, TraceReader, TraceWriter};
+use isa::repl::Repl;
+use isa::recovery::{self, Recovery, RecoveryOutcome};
+use isa::robustness::{self, Reordering};
+use isa::scheduler::SchedulerOptions;
 
-use clap::Parser;
+use clap::{Parser, Subcommand, ValueEnum};
+use rand::{rngs::SmallRng, seq::IndexedRandom, SeedableRng};
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     #[arg(short, long)]
-    file: String,
+    file: Option<String>,
+
+    #[arg(short, long, action = clap::ArgAction::Count)]
+    verbose: u8,
 
     #[arg(short, long)]
     trace: bool,
 
-    #[arg(short, long, default_value = "SC")]
+    #[arg(long, value_enum)]
+    trace_only: Option<TraceOnly>,
+
+    #[arg(long)]
+    trace_thread: Vec<String>,
+
+    #[arg(long)]
+    trace_addr: Vec<String>,
+
+    #[arg(long)]
+    trace_jsonl: Option<String>,
+
+    #[arg(long)]
+    trace_bin: Option<String>,
+
+    #[arg(long)]
+    no_color: bool,
+
+    #[arg(short, long)]
+    quiet: bool,
+
+    #[arg(short, long, default_value = "SC", global = true)]
     model: String,
+
+    #[arg(short, long, global = true)]
+    seed: Option<u64>,
+
+    #[arg(long, global = true)]
+    drain_bound: Option<usize>,
+
+    #[arg(long, global = true, num_args = 0..=1, default_missing_value = "10")]
+    fair: Option<usize>,
+
+    #[arg(short, long)]
+    watch: bool,
+
+    #[arg(short, long)]
+    interactive: bool,
+
+    #[arg(long, global = true)]
+    script: Option<String>,
+
+    #[arg(long)]
+    dot: Option<String>,
+
+    #[arg(long)]
+    event_structure: Option<String>,
+
+    #[arg(long)]
+    exhaustive: bool,
+
+    #[arg(long, value_enum, default_value = "interleavings")]
+    strategy: Strategy,
+
+    #[arg(long)]
+    runs: Option<usize>,
+
+    #[arg(long, default_value_t = 1000)]
+    max_steps: usize,
+
+    #[arg(long)]
+    no_sleep_sets: bool,
+
+    #[arg(long)]
+    witnesses: bool,
+
+    #[arg(long)]
+    checkpoint: Option<String>,
+
+    #[arg(long, default_value_t = 60)]
+    checkpoint_interval: u64,
+
+    #[arg(long, value_enum, num_args = 0..=1, default_missing_value = "hb")]
+    races: Option<RaceAnalysis>,
+
+    #[arg(long)]
+    stats: bool,
+
+    #[arg(long)]
+    rare: bool,
+
+    #[arg(long, num_args = 0..=1, default_missing_value = "100000")]
+    guided: Option<usize>,
+
+    #[arg(long)]
+    smt: Option<String>,
+
+    #[arg(long)]
+    alloy: Option<String>,
+
+    #[arg(long)]
+    symbolic: bool,
+
+    #[arg(long, default_value_t = 1)]
+    unroll: usize,
+
+    #[arg(long)]
+    strict_registers: bool,
+
+    #[arg(long)]
+    strict_memory: bool,
+
+    #[arg(long, global = true)]
+    clear_monitors: bool,
+
+    #[arg(long, global = true)]
+    fence_map: Option<String>,
+
+    #[arg(long)]
+    crash_after: Option<usize>,
+
+    #[arg(long, global = true, default_value = "0", allow_hyphen_values = true, value_parser = parse_value)]
+    default_mem: i32,
+
+    #[arg(long, global = true)]
+    record: Option<String>,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum RaceAnalysis {
+    /// Vector-clock happens-before analysis
+    Hb,
+    /// Eraser-style analysis of the locks protecting each location
+    Lockset,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum TraceOnly {
+    /// Loads, stores, updates, fences, locks and propagate steps
+    Mem,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum Strategy {
+    /// Enumerate interleavings of the operational model
+    Interleavings,
+    /// Enumerate reads-from and coherence orders and check them against the model's axioms
+    ReadsFrom,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Type instructions into threads and step the model interactively
+    Repl {
+        #[arg(long, default_value_t = 2)]
+        threads: usize,
+    },
+    /// Print a binary trace written by --trace-bin as JSON lines
+    Decode {
+        file: String,
+    },
+    /// Re-execute a trace from --trace-jsonl or --trace-bin on the program and check every step
+    Replay {
+        trace: String,
+    },
+    /// Re-execute a trace from --trace-jsonl or --trace-bin on the program, printing every step with where loads read from, the buffers and memory after it and the steps it waited for
+    Explain {
+        trace: String,
+    },
+    /// Write the execution a trace from --trace-jsonl or --trace-bin records as an AArch64 litmus test for rmem or isla-axiomatic, whose final condition holds only for that execution
+    Litmus {
+        trace: String,
+    },
+    /// Describe the program without running it: its instructions, the addresses each thread touches and potential races
+    Stats,
+    /// Explore two programs exhaustively and compare their outcomes
+    Equiv {
+        first: String,
+        second: String,
+    },
+    /// Check that the program reaches only its SC outcomes under TSO or PSO
+    Robust,
+    /// Run many program files, or the .isa files under directories, on several threads
+    Batch {
+        #[arg(required = true)]
+        paths: Vec<String>,
+        #[arg(long)]
+        jobs: Option<usize>,
+    },
+    /// Explore the program under the models of a spec file and check which final states they reach
+    Check {
+        #[arg(long)]
+        spec: String,
+    },
+    /// Compare the latest outcomes in a --record database with those before them or with a baseline
+    Report {
+        database: String,
+        #[arg(long)]
+        baseline: Option<String>,
+    },
+}
+
+// A decimal value, or a hexadecimal bit pattern such as 0xDEADBEEF.
+fn parse_value(value: &str) -> Result<i32, String> {
+    match value.strip_prefix("0x").or_else(|| value.strip_prefix("0X")) {
+        Some(digits) => u32::from_str_radix(digits, 16).map(|bits| bits as i32).map_err(|err| format!("invalid hexadecimal value {}: {}", value, err)),
+        None => value.parse().map_err(|err| format!("invalid value {}: {}", value, err))
+    }
+}
+
+fn read_source(file_path: &str, args: &Args) -> Result<Program, String> {
+    let mut program = read_unmapped(file_path, args)?;
+    if let Ok(model) = args.model.parse() {
+        map_fences(args, model, &mut program)?;
+    }
+    Ok(program)
+}
+
+fn map_fences(args: &Args, model: MemoryModelType, program: &mut Program) -> Result<(), String> {
+    if let Some(path) = &args.fence_map {
+        FenceMap::read(Path::new(path))?.apply(model, program);
+    }
+    Ok(())
+}
+
+// Reads of registers a thread never assigns are warnings, or errors with --strict-registers.
+fn read_unmapped(file_path: &str, args: &Args) -> Result<Program, String> {
+    // JSON litmus tests are converted to program text, whose lines are then reported.
+    let (text, source) = match json_test(file_path) {
+        true => {
+            let text = fs::read_to_string(file_path).map_err(|err| format!("Error reading file {}: {}", file_path, err))?;
+            (litmus::from_json(&text).map_err(|err| format!("{}: {}", file_path, err))?, None)
+        },
+        false => {
+            let source = Source::read(Path::new(file_path))?;
+            (source.text.clone(), Some(source))
+        }
+    };
+    let locate = |err: String| match &source {
+        Some(source) => source.locate(err),
+        None => format!("{}: {}", file_path, err)
+    };
+    let mut program = parse_program(&text).map_err(locate)?;
+    program.strict_memory = args.strict_memory;
+    program.default_memory = args.default_mem;
+    program.clear_monitors = args.clear_monitors;
+    for (thread_id, instruction, r) in program.unassigned_reads() {
+        let message = locate(format!("Line {}: thread {} reads register {} in {:?}, but never assigns it", instruction.line.unwrap_or(0), program.thread_names[thread_id], r, instruction.instruction));
+        if args.strict_registers {
+            return Err(message);
+        }
+        if !args.quiet && !matches!(args.command, Some(Command::Batch { .. })) {
+            eprintln!("warning: {}", message);
+        }
+    }
+    Ok(program)
+}
+
+fn read_program(file_path: &str, args: &Args) -> Result<Program, String> {
+    let program = read_source(file_path, args)?;
+    validate(program, args, args.model.parse().ok())
+}
+
+fn validate(program: Program, args: &Args, model: Option<MemoryModelType>) -> Result<Program, String> {
+    if let Some((thread_id, index)) = program.inputs.first() {
+        let instruction = &program.threads[*thread_id][*index];
+        return Err(format!("Line {}: symbolic inputs need --symbolic", instruction.line.unwrap_or(0)));
+    }
+    if let Some(instruction) = program.java().filter(|_| model != Some(MemoryModelType::JMM)) {
+        return Err(format!("Line {}: VOLATILE and PLAIN need the JMM model", instruction.line.unwrap_or(0)));
+    }
+    let operational = matches!(model, Some(MemoryModelType::SC | MemoryModelType::TSO | MemoryModelType::PSO));
+    // Only the operational models replay the steps between which a crash can happen.
+    let unsupported = args.symbolic || args.smt.is_some() || args.alloy.is_some() || args.event_structure.is_some() || (args.exhaustive && matches!(args.strategy, Strategy::ReadsFrom));
+    if (!program.persistent.is_empty() || !program.recovery.is_empty()) && (!operational || unsupported) {
+        return Err("persistent addresses and recovery threads need the SC, TSO or PSO model and are not supported by --symbolic, --smt, --alloy, --event-structure or --strategy reads-from".to_string());
+    }
+    // A commit writes straight to memory, which the models with views or per-thread deliveries lack.
+    if !operational && program.transactional() {
+        return Err("transactions need the SC, TSO or PSO model".to_string());
+    }
+    program.instances()?;
+    Ok(program)
+}
+
+// How much a single run prints: nothing, the final state (-v), every step's instruction (-vv) or
+// also the state after every step (-vvv). `jsonl` and `bin` are files that get one record per step.
+struct Trace {
+    level: u8,
+    options: TraceOptions,
+    jsonl: Option<String>,
+    bin: Option<String>,
+}
+
+// --trace and the trace filters without a -v level mean -vvv; `--trace-addr` takes an address such as
+// `#3` or the name of a region.
+fn trace(args: &Args, program: &Program) -> Result<Trace, String> {
+    let filtered = args.trace_only.is_some() || !args.trace_thread.is_empty() || !args.trace_addr.is_empty();
+    let level = match args.verbose {
+        0 if args.trace || filtered => 3,
+        level => level.min(3),
+    };
+    if let Some(name) = args.trace_thread.iter().find(|name| program.thread_id(name).is_none()) {
+        return Err(format!("--trace-thread: unknown thread {}", name));
+    }
+    let mut addresses = Vec::new();
+    for address in args.trace_addr.iter() {
+        let range = match program.regions.iter().find(|region| region.name == *address) {
+            Some(region) => region.base..region.base + region.size,
+            None => address.strip_prefix('#').unwrap_or(address).parse::<i32>().ok()
+                .filter(|value| *value >= 0)
+                .and_then(|value| Some(value..value.checked_add(1)?))
+                .ok_or_else(|| format!("--trace-addr: {} is neither an address nor a region", address))?,
+        };
+        addresses.push(range);
+    }
+    let options = TraceOptions {
+        brief: level < 3,
+        color: !args.no_color && env::var_os("NO_COLOR").is_none() && io::stdout().is_terminal(),
+        memory_only: matches!(args.trace_only, Some(TraceOnly::Mem)),
+        threads: args.trace_thread.clone(),
+        addresses,
+    };
+    Ok(Trace { level, options, jsonl: args.trace_jsonl.clone(), bin: args.trace_bin.clone() })
+}
+
+// With --crash-after, the run crashes once it has taken that many steps and goes on with the
+// recovery threads from one of the states the crash can leave, picked with the seed.
+fn run(memory_model: &MemoryModelType, mut program: Program, seed: Option<u64>, scheduler_options: SchedulerOptions, trace: Trace, races: Option<RaceAnalysis>, stats: bool) -> Result<(), String> {
+    if scheduler_options.crash_after.is_some() {
+        if program.recovery.is_empty() {
+            return Err("--crash-after needs recovery threads".to_string());
+        }
+        if trace.jsonl.is_some() || trace.bin.is_some() {
+            return Err("--crash-after is not supported with --trace-jsonl or --trace-bin".to_string());
+        }
+    }
+    let configure = |model: &mut Box<dyn MemoryModel>| {
+        model.set_recycling();
+        if let Some(seed) = seed {
+            model.set_seed(seed);
+        }
+        model.set_scheduler_options(scheduler_options);
+        model.set_trace_options(trace.options.clone());
+    };
+    let mut model: Box<dyn MemoryModel> = memory_model.create(&program);
+    configure(&mut model);
+    let mut rng: SmallRng = match seed {
+        Some(seed) => SmallRng::seed_from_u64(seed),
+        None => rand::make_rng()
+    };
+    let debug_print = trace.level >= 2;
+    let mut jsonl = match &trace.jsonl {
+        Some(path) => Some((path, LineWriter::new(File::create(path).map_err(|err| format!("Error writing file {}: {}", path, err))?))),
+        None => None,
+    };
+    let mut bin = match &trace.bin {
+        Some(path) => {
+            let file = File::create(path).map_err(|err| format!("Error writing file {}: {}", path, err))?;
+            Some((path, TraceWriter::new(BufWriter::new(file), &program.thread_names).map_err(|err| format!("Error writing file {}: {}", path, err))?))
+        },
+        None => None,
+    };
+    let mut race_detector = RaceDetector::new(program.threads.len());
+    let mut lockset_detector = LocksetDetector::new(program.threads.len());
+    let mut history = History::new(&program);
+    for step in 0.. {
+        if scheduler_options.crash_after == Some(step) {
+            let persisted = model.persistence().crash_states().choose(&mut rng).cloned().unwrap_or_default();
+            println!("# CRASH after {} steps: persisted {:?}", step, persisted);
+            program = program.recover(&persisted);
+            model = memory_model.create(&program);
+            configure(&mut model);
+            history = History::new(&program);
+        }
+        let event = match model.random_step(debug_print) {
+            Ok(Some(event)) => event,
+            Ok(None) => break,
+            Err(err) => {
+                if let Some((path, writer)) = jsonl.as_mut() {
+                    let mut fields = vec![("step".to_string(), step.to_string())];
+                    fields.extend(event::json_fields(&err.thread_name, &err.instruction, err.line, &None));
+                    fields.push(("error".to_string(), json::string(&err.to_string())));
+                    write_line(path, writer, json::object(fields))?;
+                }
+                if let Some((path, writer)) = bin.as_mut() {
+                    writer.write_error(&err).and_then(|_| writer.flush()).map_err(|err| format!("Error writing file {}: {}", path, err))?;
+                }
+                return Err(err.to_string());
+            }
+        };
+        if let Some((path, writer)) = jsonl.as_mut() {
+            let thread_id = event.node.thread_id;
+            let mut registers: Vec<(&String, &i32)> = model.registers(thread_id).iter().collect();
+            registers.sort();
+            let registers = json::object(registers.into_iter().map(|(r, value)| (r.clone(), value.to_string())).collect());
+            let mut fields = vec![("step".to_string(), step.to_string())];
+            fields.extend(event.json_fields(model.thread_name(thread_id)));
+            fields.push(("registers".to_string(), registers));
+            write_line(path, writer, json::object(fields))?;
+        }
+        if let Some((path, writer)) = bin.as_mut() {
+            writer.write_event(&event).map_err(|err| format!("Error writing file {}: {}", path, err))?;
+        }
+        history.record(&program, &event, model.registers(event.node.thread_id));
+        match races {
+            Some(RaceAnalysis::Hb) => race_detector.on_event(&event),
+            Some(RaceAnalysis::Lockset) => lockset_detector.on_event(&event),
+            None => {}
+        }
+    }
+    if let Some((path, writer)) = bin.as_mut() {
+        writer.flush().map_err(|err| format!("Error writing file {}: {}", path, err))?;
+    }
+    if trace.level >= 1 {
+        println!("# FINAL STATE");
+        println!("| {}", explore::outcome(model.as_ref()));
+    }
+    match races {
+        Some(RaceAnalysis::Hb) => {
+            println!("# RACES");
+            for race in race_detector.races() {
+                println!("| {}", race.describe(&program.thread_names));
+            }
+        }
+        Some(RaceAnalysis::Lockset) => {
+            println!("# RACES");
+            for violation in lockset_detector.violations() {
+                println!("| {}", violation.describe(&program.thread_names));
+            }
+        }
+        None => {}
+    }
+    if stats {
+        print!("{}", model.statistics());
+    }
+    if let Some(deadlock) = deadlock::detect(model.as_ref()) {
+        return Err(format!("{}", deadlock).trim_end().to_string());
+    }
+    history.check(&program).map_err(|err| format!("Not linearizable: {}", err))
+}
+
+// Each line is written out as soon as it is complete, so the file survives a run that is killed.
+fn write_line(path: &str, writer: &mut LineWriter<File>, line: String) -> Result<(), String> {
+    writeln!(writer, "{}", line).map_err(|err| format!("Error writing file {}: {}", path, err))
+}
+
+// Prints the records of a binary trace in the format of --trace-jsonl, without the registers.
+fn decode(path: &str) -> Result<(), String> {
+    let file = File::open(path).map_err(|err| format!("Error reading file {}: {}", path, err))?;
+    let mut reader = TraceReader::new(BufReader::new(file)).map_err(|err| format!("{}: {}", path, err))?;
+    let thread_names = reader.thread_names().to_vec();
+    let mut stdout = io::stdout().lock();
+    for (step, record) in (&mut reader).enumerate() {
+        let (s, error) = match record.map_err(|err| format!("{}: {}", path, err))? {
+            Record::Step(s) => (s, None),
+            Record::Error(s, message) => (s, Some(message)),
+        };
+        let mut fields = vec![("step".to_string(), step.to_string())];
+        fields.extend(event::json_fields(&thread_names[s.thread_id], &s.instruction, s.line, &s.access));
+        if let Some(message) = error {
+            fields.push(("error".to_string(), json::string(&message)));
+        }
+        let line = json::object(fields);
+        if writeln!(stdout, "{}", line).is_err() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+// Replays a JSON lines or binary trace of the program, telling them apart by the binary header.
+// With `explain`, every step is printed with the state after it, and the final state at the end.
+fn replay(args: &Args, file_path: &str, memory_model: &MemoryModelType, trace_path: &str, explain: bool) -> Result<usize, String> {
+    let program = read_program(file_path, args)?;
+    // Traces come from single runs, which recycle node ids.
+    let mut model = memory_model.create(&program);
+    model.set_recycling();
+    if explain {
+        let mut options = trace(args, &program)?.options;
+        options.brief = false;
+        model.set_trace_options(options);
+    }
+    let records = read_trace(trace_path, &program)?;
+    let steps = replay_records(model.as_mut(), records, explain).map_err(|err| format!("{}: {}", trace_path, err))?;
+    if explain {
+        println!("# FINAL STATE");
+        println!("| {}", explore::outcome(model.as_ref()));
+    }
+    Ok(steps)
+}
+
+fn read_trace<'a>(trace_path: &str, program: &'a Program) -> Result<Box<dyn Iterator<Item = Result<Record, String>> + 'a>, String> {
+    let file = File::open(trace_path).map_err(|err| format!("Error reading file {}: {}", trace_path, err))?;
+    let mut reader = BufReader::new(file);
+    let binary = reader.fill_buf().map_err(|err| format!("Error reading file {}: {}", trace_path, err))?.starts_with(b"ISAT");
+    if !binary {
+        return Ok(Box::new(traces::read_jsonl(reader, &program.thread_names)));
+    }
+    let records = TraceReader::new(reader)?;
+    if records.thread_names() != program.thread_names.as_slice() {
+        return Err(format!("the trace has threads {} but the program has threads {}", records.thread_names().join(", "), program.thread_names.join(", ")));
+    }
+    Ok(Box::new(records))
+}
+
+// Replays the trace and writes its execution as a litmus test named after the program file.
+fn litmus(args: &Args, file_path: &str, memory_model: &MemoryModelType, trace_path: &str) -> Result<String, String> {
+    let program = read_program(file_path, args)?;
+    let mut model = memory_model.create(&program);
+    model.set_recycling();
+    let events = traces::events(model.as_mut(), read_trace(trace_path, &program)?).map_err(|err| format!("{}: {}", trace_path, err))?;
+    let name = Path::new(file_path).file_stem().map_or("test".to_string(), |stem| stem.to_string_lossy().to_string());
+    let description = format!("{} under {:?}, {} steps of {}", file_path, memory_model, events.len(), trace_path);
+    litmus::aarch64(&name, &description, &program, &events, model.as_ref()).map_err(|err| format!("{}: {}", trace_path, err))
+}
+
+fn replay_records(model: &mut dyn MemoryModel, records: impl Iterator<Item = Result<Record, String>>, explain: bool) -> Result<usize, String> {
+    match explain {
+        true => traces::explain(model, records),
+        false => traces::replay(model, records)
+    }
+}
+
+// Both programs are compared on memory and the registers that both versions of a thread assign, so
+// scratch registers of one version do not make them differ.
+fn equiv(args: &Args, memory_model: &MemoryModelType, first_path: &str, second_path: &str) -> Result<bool, String> {
+    let first = read_program(first_path, args)?;
+    let second = read_program(second_path, args)?;
+    if first.has_ranges() || second.has_ranges() {
+        return Err("equiv needs fixed initial values".to_string());
+    }
+    if first.thread_names != second.thread_names {
+        return Err(format!("{} has threads {} but {} has threads {}", first_path, first.thread_names.join(", "), second_path, second.thread_names.join(", ")));
+    }
+    let assigned = |program: &Program, thread_id: usize| -> HashSet<String> {
+        program.threads[thread_id].iter().flat_map(|instruction| instruction.instruction.write_registers()).cloned().collect()
+    };
+    let observed: Vec<HashSet<String>> = (0..first.threads.len())
+        .map(|thread_id| assigned(&first, thread_id).intersection(&assigned(&second, thread_id)).cloned().collect())
+        .collect();
+    let mut outcomes = Vec::new();
+    for (path, program) in [(first_path, &first), (second_path, &second)] {
+        let mut explorer = Explorer::new(memory_model, program, args.max_steps);
+        explorer.set_sleep_sets(!args.no_sleep_sets);
+        let exploration = explorer.run();
+        if exploration.truncated > 0 {
+            eprintln!("warning: {} executions of {} were truncated at {} steps, so its outcomes may be incomplete", exploration.truncated, path, args.max_steps);
+        }
+        outcomes.push(explore::observe(memory_model, program, &exploration, &observed));
+    }
+    let mut equivalent = true;
+    for (path, program, own, other) in [(first_path, &first, &outcomes[0], &outcomes[1]), (second_path, &second, &outcomes[1], &outcomes[0])] {
+        let missing: Vec<(&String, &Outcome)> = own.iter().filter(|(outcome, _)| !other.contains_key(*outcome)).collect();
+        if missing.is_empty() {
+            continue;
+        }
+        equivalent = false;
+        println!("# ONLY IN {}", path);
+        for (outcome, Outcome { count: _, witness }) in missing {
+            println!("| {}", outcome);
+            for node in witness {
+                println!("|   {}: {:?}", program.thread_names[node.thread_id], node);
+            }
+        }
+    }
+    match equivalent {
+        true => println!("# EQUIVALENT under {:?}: {} outcomes", memory_model, outcomes[0].len()),
+        false => println!("# NOT EQUIVALENT under {:?}", memory_model),
+    }
+    Ok(equivalent)
+}
+
+// Lists the outcomes only the relaxed model reaches and the pairs of accesses that took effect out of
+// program order on the way to them, which are where a fence is missing.
+fn robust(args: &Args, file_path: &str, memory_model: &MemoryModelType) -> Result<bool, String> {
+    let program = read_program(file_path, args)?;
+    if program.has_ranges() {
+        return Err("robust needs fixed initial values".to_string());
+    }
+    let robustness = robustness::check(memory_model, &program, args.max_steps, !args.no_sleep_sets)?;
+    if robustness.truncated > 0 {
+        eprintln!("warning: {} executions were truncated at {} steps, so the outcomes may be incomplete", robustness.truncated, args.max_steps);
+    }
+    if robustness.is_robust() {
+        println!("# ROBUST under {:?}: {} outcomes, all reachable under SC", memory_model, robustness.outcomes);
+        return Ok(true);
+    }
+    println!("# ONLY UNDER {:?}", memory_model);
+    for (outcome, Outcome { count: _, witness }) in &robustness.violations {
+        println!("| {}", outcome);
+        for node in witness {
+            println!("|   {}: {:?}", program.thread_names[node.thread_id], node);
+        }
+    }
+    println!("# REORDERED");
+    for Reordering { thread_id, earlier, later } in &robustness.reorderings {
+        println!("| {}: {:?} took effect after {:?}", program.thread_names[*thread_id], earlier, later);
+    }
+    println!("# NOT ROBUST under {:?}", memory_model);
+    Ok(false)
+}
+
+// Explores the program under every model the spec names, or the --model one if it names none, and
+// reports the expectations each of them breaks.
+fn check(args: &Args, file_path: &str, memory_model: &MemoryModelType, spec_path: &str) -> Result<bool, String> {
+    let spec = Spec::read(Path::new(spec_path))?;
+    let source = read_unmapped(file_path, args)?;
+    let mut models = spec.models();
+    if models.is_empty() {
+        models.push(*memory_model);
+    }
+    let mut holds = true;
+    for model in models {
+        let mut program = source.clone();
+        map_fences(args, model, &mut program)?;
+        let program = validate(program, args, Some(model))?;
+        let (allowed, forbidden) = spec.expectations(model);
+        match testing::check_program(&program, &model, args.max_steps, &allowed, &forbidden) {
+            Ok(()) => println!("# HOLDS under {:?}: {} allowed, {} forbidden", model, allowed.len(), forbidden.len()),
+            Err(failures) => {
+                holds = false;
+                println!("# FAILS under {:?}", model);
+                println!("{}", failures);
+            }
+        }
+    }
+    Ok(holds)
+}
+
+// Counts are listed most frequent first.
+fn counts<K: Debug + Ord>(counts: &BTreeMap<K, usize>) -> String {
+    let mut counts: Vec<(&K, &usize)> = counts.iter().collect();
+    counts.sort_by(|(first, first_count), (second, second_count)| second_count.cmp(first_count).then(first.cmp(second)));
+    match counts.is_empty() {
+        true => "none".to_string(),
+        false => counts.iter().map(|(key, count)| format!("{} {}", format!("{:?}", key).trim_matches('"'), count)).collect::<Vec<String>>().join(", ")
+    }
+}
+
+fn stats(args: &Args, file_path: &str) -> Result<(), String> {
+    let program = read_source(file_path, args)?;
+    let metrics = metrics::analyze(&program);
+    let mut kinds: BTreeMap<&str, usize> = BTreeMap::new();
+    for thread in &metrics.threads {
+        for (kind, count) in &thread.kinds {
+            *kinds.entry(kind).or_default() += count;
+        }
+    }
+    let addresses = |addresses: &BTreeSet<Address>| match addresses.is_empty() {
+        true => "nothing".to_string(),
+        false => addresses.iter().map(|address| format!("{:?}", address)).collect::<Vec<String>>().join(", ")
+    };
+    println!("# PROGRAM {} threads, {} instructions", metrics.threads.len(), metrics.threads.iter().map(|thread| thread.instructions).sum::<usize>());
+    println!("| {}", counts(&kinds));
+    for (thread_id, thread) in metrics.threads.iter().enumerate() {
+        println!("# THREAD {}: {} instructions", program.thread_names[thread_id], thread.instructions);
+        println!("| {}", counts(&thread.kinds));
+        println!("| reads {}", addresses(&thread.reads));
+        println!("| writes {}", addresses(&thread.writes));
+        println!("| modes {}", counts(&thread.modes));
+        println!("| fences {}", counts(&thread.fences));
+    }
+    println!("# SHARED ADDRESSES");
+    let touched: BTreeSet<Address> = metrics.threads.iter().flat_map(|thread| thread.reads.union(&thread.writes)).cloned().collect();
+    for address in touched {
+        let threads = |accessed: fn(&ThreadMetrics) -> &BTreeSet<Address>| -> Vec<&str> {
+            metrics.threads.iter().enumerate().filter(|(_, thread)| accessed(thread).contains(&address)).map(|(thread_id, _)| program.thread_names[thread_id].as_str()).collect()
+        };
+        let (readers, writers) = (threads(|thread| &thread.reads), threads(|thread| &thread.writes));
+        if readers.iter().chain(writers.iter()).collect::<HashSet<_>>().len() > 1 {
+            println!("| {:?}: read by {}, written by {}", address, list(&readers), list(&writers));
+        }
+    }
+    println!("# POTENTIAL RACES");
+    for (first, second) in &metrics.races {
+        let describe = |access: &metrics::Access| match access.instruction.line {
+            Some(line) => format!("thread {} {:?} (line {})", program.thread_names[access.thread_id], access.instruction, line),
+            None => format!("thread {} {:?}", program.thread_names[access.thread_id], access.instruction)
+        };
+        // A known address is more telling than an unknown one that may alias it.
+        println!("| {:?}: {} and {}", first.address.min(second.address), describe(first), describe(second));
+    }
+    Ok(())
+}
+
+fn list(names: &[&str]) -> String {
+    match names.is_empty() {
+        true => "none".to_string(),
+        false => names.join(", ")
+    }
+}
+
+// Runs the program once and prints a single JSON object with the verdict (`ok`, `violation` of the
+// assert line, `deadlock`, `error` or `invalid` program), the final state, the step count and the seed.
+fn quiet(args: &Args, file_path: &str, memory_model: &MemoryModelType, scheduler_options: SchedulerOptions) -> Result<bool, String> {
+    let verdict = run_quietly(args, file_path, memory_model, scheduler_options);
+    println!("{}", json::object(verdict.fields));
+    if let (Some(path), Some(record)) = (&args.record, verdict.record) {
+        database::append(Path::new(path), &[record])?;
+    }
+    Ok(verdict.verdict == "ok")
+}
+
+// What --quiet prints for a file, and what --record keeps of it unless the file could not be read.
+struct Verdict {
+    verdict: &'static str,
+    fields: Vec<(String, String)>,
+    record: Option<Entry>,
+}
+
+fn run_quietly(args: &Args, file_path: &str, memory_model: &MemoryModelType, scheduler_options: SchedulerOptions) -> Verdict {
+    let seed = args.seed.unwrap_or_else(rand::random);
+    let mut fields = vec![
+        ("model".to_string(), json::string(&format!("{:?}", memory_model))),
+        ("seed".to_string(), seed.to_string()),
+    ];
+    let mut record = None;
+    let (verdict, error) = match read_program(file_path, args) {
+        Err(err) => ("invalid", Some(err)),
+        Ok(program) => {
+            let mut model = memory_model.create(&program);
+            model.set_recycling();
+            model.set_seed(seed);
+            model.set_scheduler_options(scheduler_options);
+            let error = loop {
+                match model.random_step(false) {
+                    Ok(Some(_)) => {}
+                    Ok(None) => break None,
+                    Err(err) => break Some(err.to_string()),
+                }
+            };
+            let deadlock = deadlock::detect(model.as_ref());
+            let verdict = match (&error, &deadlock) {
+                (Some(_), _) => "error",
+                (None, Some(_)) => "deadlock",
+                (None, None) if program.postcondition.as_ref().is_some_and(|condition| !condition.holds(model.as_ref())) => "violation",
+                (None, None) => "ok",
+            };
+            let outcome = explore::outcome(model.as_ref());
+            // The outcome as an exploration would have found it.
+            let found = match (&error, &deadlock) {
+                (Some(error), _) => format!("error: {}", error),
+                (None, Some(_)) => format!("{} (deadlock)", outcome),
+                (None, None) => outcome.clone(),
+            };
+            record = Some(Entry {
+                test: file_path.to_string(),
+                model: format!("{:?}", memory_model),
+                version: env!("CARGO_PKG_VERSION").to_string(),
+                seed: Some(seed),
+                executions: 1,
+                truncated: 0,
+                outcomes: [(found, 1)].into(),
+            });
+            fields.push(("outcome".to_string(), json::string(&outcome)));
+            fields.push(("steps".to_string(), model.statistics().steps.to_string()));
+            (verdict, error.or(deadlock.map(|deadlock| format!("{}", deadlock).trim_end().to_string())))
+        }
+    };
+    fields.insert(0, ("verdict".to_string(), json::string(verdict)));
+    if let Some(error) = error {
+        fields.push(("error".to_string(), json::string(&error)));
+    }
+    Verdict { verdict, fields, record }
+}
+
+// Explores the program as --exhaustive or --runs would and describes the outcomes in the fields of
+// --quiet. The verdict is the worst one among the outcomes, in the order `error`, `deadlock`,
+// `violation` and `ok`.
+fn explore_quietly(args: &Args, file_path: &str, memory_model: &MemoryModelType, scheduler_options: SchedulerOptions) -> Verdict {
+    let seed = args.seed.unwrap_or_else(rand::random);
+    let mut fields = vec![("model".to_string(), json::string(&format!("{:?}", memory_model)))];
+    if args.runs.is_some() {
+        fields.push(("seed".to_string(), seed.to_string()));
+    }
+    let verdicts = ["ok", "violation", "deadlock", "error"];
+    let mut worst = 0;
+    let explored = read_program(file_path, args).and_then(|program| explore::quantify(&program, |instance| {
+        let exploration = match args.runs {
+            Some(runs) => explore::sample(memory_model, instance, runs, Some(seed), scheduler_options, args.max_steps),
+            None => {
+                let mut explorer = Explorer::new(memory_model, instance, args.max_steps);
+                explorer.set_sleep_sets(!args.no_sleep_sets);
+                explorer.run()
+            }
+        };
+        for (outcome, Outcome { count: _, witness }) in exploration.outcomes.iter() {
+            let verdict = if outcome.starts_with("error: ") {
+                3
+            } else if outcome.ends_with(" (deadlock)") {
+                2
+            } else if program.postcondition.as_ref().is_some_and(|condition| explore::violates(memory_model, instance, witness, condition)) {
+                1
+            } else {
+                0
+            };
+            worst = worst.max(verdict);
+        }
+        Ok(exploration)
+    }));
+    let exploration = match explored {
+        Ok(exploration) => exploration,
+        Err(err) => {
+            fields.insert(0, ("verdict".to_string(), json::string("invalid")));
+            fields.push(("error".to_string(), json::string(&err)));
+            return Verdict { verdict: "invalid", fields, record: None };
+        }
+    };
+    let record = Entry::new(file_path, memory_model, args.runs.is_some().then_some(seed), &exploration);
+    let outcomes = exploration.outcomes.iter().map(|(outcome, Outcome { count, witness: _ })| (outcome.clone(), count.to_string())).collect();
+    fields.push(("outcomes".to_string(), json::object(outcomes)));
+    fields.push(("executions".to_string(), exploration.executions.to_string()));
+    fields.push(("truncated".to_string(), exploration.truncated.to_string()));
+    fields.insert(0, ("verdict".to_string(), json::string(verdicts[worst])));
+    Verdict { verdict: verdicts[worst], fields, record: Some(record) }
+}
+
+fn json_test(file_path: &str) -> bool {
+    Path::new(file_path).extension().is_some_and(|extension| extension == "json")
+}
+
+// The files named on the command line and the `.isa` and `.json` files anywhere under the
+// directories, each directory's in sorted order.
+fn program_files(paths: &[String]) -> Result<Vec<String>, String> {
+    let mut files = Vec::new();
+    let mut pending: Vec<String> = paths.iter().rev().cloned().collect();
+    while let Some(path) = pending.pop() {
+        if !Path::new(&path).is_dir() {
+            files.push(path);
+            continue;
+        }
+        let mut entries = Vec::new();
+        for entry in fs::read_dir(&path).map_err(|err| format!("Error reading directory {}: {}", path, err))? {
+            let entry = entry.map_err(|err| format!("Error reading directory {}: {}", path, err))?;
+            let entry_path = entry.path();
+            if entry_path.is_dir() || entry_path.extension().is_some_and(|extension| extension == "isa") || json_test(&entry_path.to_string_lossy()) {
+                entries.push(entry_path.to_string_lossy().into_owned());
+            }
+        }
+        entries.sort();
+        pending.extend(entries.into_iter().rev());
+    }
+    Ok(files)
+}
+
+// Appends what exploring the file found to the --record database.
+fn record(args: &Args, file_path: &str, memory_model: &MemoryModelType, seed: Option<u64>, exploration: &Exploration) -> Result<(), String> {
+    match &args.record {
+        Some(path) => database::append(Path::new(path), &[Entry::new(file_path, memory_model, seed, exploration)]),
+        None => Ok(()),
+    }
+}
+
+// Prints how the latest outcomes of every test in the database differ from their baseline, returning
+// whether none do.
+fn report(database_path: &str, baseline_path: Option<&str>) -> Result<bool, String> {
+    let entries = database::read(Path::new(database_path))?;
+    let baseline = baseline_path.map(|path| database::read(Path::new(path))).transpose()?;
+    let report = database::report(&entries, baseline.as_deref());
+    for change in report.changes.iter() {
+        println!("# CHANGED {} under {} ({} -> {})", change.latest.test, change.latest.model, change.baseline.version, change.latest.version);
+        for outcome in change.added.iter() {
+            println!("| + {}", outcome);
+        }
+        for outcome in change.removed.iter() {
+            println!("| - {}", outcome);
+        }
+    }
+    for entry in report.new.iter() {
+        println!("# NEW {} under {}", entry.test, entry.model);
+    }
+    println!("# REPORT {} unchanged, {} changed, {} new", report.unchanged, report.changes.len(), report.new.len());
+    Ok(report.is_clean())
+}
+
+// Runs every file on `jobs` threads and prints its JSON object with a `file` field in front. Results
+// are printed, and recorded with --record, in the order of the files as soon as all earlier ones are
+// done, whichever thread ran them. Returns whether every verdict was `ok`.
+fn batch(args: &Args, paths: &[String], jobs: Option<usize>, memory_model: &MemoryModelType, scheduler_options: SchedulerOptions) -> Result<bool, String> {
+    if args.checkpoint.is_some() || args.rare || matches!(args.strategy, Strategy::ReadsFrom) {
+        return Err("batch does not support --checkpoint, --rare or --strategy reads-from".to_string());
+    }
+    let files = program_files(paths)?;
+    let jobs = jobs.unwrap_or_else(|| thread::available_parallelism().map_or(1, |cores| cores.get())).max(1);
+    let next = AtomicUsize::new(0);
+    let (sender, receiver) = mpsc::channel();
+    let mut passed = true;
+    thread::scope(|scope| -> Result<(), String> {
+        for _ in 0..jobs.min(files.len()) {
+            let (sender, next, files) = (sender.clone(), &next, &files);
+            scope.spawn(move || {
+                loop {
+                    let index = next.fetch_add(1, Ordering::Relaxed);
+                    let Some(file) = files.get(index) else {
+                        break;
+                    };
+                    let mut verdict = match args.exhaustive || args.runs.is_some() {
+                        true => explore_quietly(args, file, memory_model, scheduler_options),
+                        false => run_quietly(args, file, memory_model, scheduler_options),
+                    };
+                    verdict.fields.insert(0, ("file".to_string(), json::string(file)));
+                    if sender.send((index, verdict)).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(sender);
+        let mut stdout = io::stdout().lock();
+        let mut done: HashMap<usize, Verdict> = HashMap::new();
+        let mut printed = 0;
+        for (index, verdict) in receiver {
+            done.insert(index, verdict);
+            while let Some(verdict) = done.remove(&printed) {
+                // A closed pipe stops the workers, which find the receiver gone.
+                if writeln!(stdout, "{}", json::object(verdict.fields)).is_err() {
+                    return Ok(());
+                }
+                if let (Some(path), Some(record)) = (&args.record, verdict.record) {
+                    database::append(Path::new(path), &[record])?;
+                }
+                passed &= verdict.verdict == "ok";
+                printed += 1;
+            }
+        }
+        Ok(())
+    })?;
+    Ok(passed)
+}
+
+fn print_outcomes(exploration: &Exploration, title: &str, max_steps: usize, witnesses: Option<&[String]>) {
+    println!("# OUTCOMES");
+    for (outcome, Outcome { count, witness: _ }) in exploration.outcomes.iter() {
+        println!("| {} x {}", count, outcome);
+    }
+    if let Some(thread_names) = witnesses {
+        for (outcome, Outcome { count: _, witness }) in exploration.outcomes.iter() {
+            println!("# WITNESS {}", outcome);
+            for node in witness {
+                println!("| {}: {:?}", thread_names[node.thread_id], node);
+            }
+        }
+    }
+    if !exploration.crashes.is_empty() {
+        println!("# CRASH STATES");
+        for (crash, Outcome { count, witness: _ }) in exploration.crashes.iter() {
+            println!("| {} x {}", count, crash);
+        }
+    }
+    if let Some(thread_names) = witnesses {
+        for (crash, Outcome { count: _, witness }) in exploration.crashes.iter() {
+            println!("# CRASH WITNESS {}", crash);
+            for node in witness {
+                println!("| {}: {:?}", thread_names[node.thread_id], node);
+            }
+        }
+    }
+    print!("# {} {} executions", title, exploration.executions);
+    if exploration.truncated > 0 {
+        print!(" ({} truncated at {} steps)", exploration.truncated, max_steps);
+    }
+    println!();
+}
+
+fn print_rarity(search: &Search) {
+    println!("# OUTCOMES BY RARITY");
+    for (outcome, Outcome { count, witness: _ }, first_found) in search.by_rarity() {
+        println!("| {} x (first in run {}) {}", count, first_found, outcome);
+    }
+    print!("# SEARCHED {} executions", search.exploration.executions);
+    if search.exploration.truncated > 0 {
+        print!(" ({} truncated)", search.exploration.truncated);
+    }
+    println!();
+}
+
+fn write_checkpoint(path: &str, checkpoint: String) -> Result<(), String> {
+    let temporary_path = format!("{}.tmp", path);
+    fs::write(&temporary_path, checkpoint)
+        .and_then(|_| fs::rename(&temporary_path, path))
+        .map_err(|err| format!("Error writing file {}: {}", path, err))
+}
+
+fn explore(memory_model: &MemoryModelType, program: Program, max_steps: usize, sleep_sets: bool, checkpoint: Option<(&str, Duration)>, witnesses: bool, record: impl FnOnce(&Exploration) -> Result<(), String>) -> Result<(), String> {
+    let mut explorer = Explorer::new(memory_model, &program, max_steps);
+    explorer.set_sleep_sets(sleep_sets);
+    if let Some((path, _)) = checkpoint {
+        if let Ok(content) = fs::read_to_string(path) {
+            explorer.resume(&content)?;
+            println!("# RESUMED {} ({} executions explored)", path, explorer.exploration().executions);
+        }
+    }
+    let mut last_checkpoint = Instant::now();
+    while explorer.step() {
+        if let Some((path, interval)) = checkpoint {
+            if last_checkpoint.elapsed() >= interval {
+                write_checkpoint(path, explorer.checkpoint())?;
+                last_checkpoint = Instant::now();
+            }
+        }
+    }
+    if let Some((path, _)) = checkpoint {
+        write_checkpoint(path, explorer.checkpoint())?;
+    }
+    print_outcomes(explorer.exploration(), "EXPLORED", max_steps, witnesses.then_some(program.thread_names.as_slice()));
+    record(explorer.exploration())?;
+    if !program.recovery.is_empty() {
+        let recovery = recovery::explore(memory_model, &program, explorer.exploration(), max_steps, sleep_sets);
+        print_recovery(&recovery, max_steps, witnesses.then_some(program.thread_names.as_slice()));
+    }
+    Ok(())
+}
+
+fn print_recovery(recovery: &Recovery, max_steps: usize, witnesses: Option<&[String]>) {
+    println!("# RECOVERY OUTCOMES");
+    for (outcome, RecoveryOutcome { count, crash: _, witness: _ }) in recovery.outcomes.iter() {
+        println!("| {} x {}", count, outcome);
+    }
+    if let Some(thread_names) = witnesses {
+        for (outcome, RecoveryOutcome { count: _, crash, witness }) in recovery.outcomes.iter() {
+            println!("# RECOVERY WITNESS {}", outcome);
+            for node in crash {
+                println!("| {}: {:?}", thread_names[node.thread_id], node);
+            }
+            println!("| crash");
+            for node in witness {
+                println!("| {}: {:?}", thread_names[node.thread_id], node);
+            }
+        }
+    }
+    print!("# RECOVERED {} executions", recovery.executions);
+    if recovery.truncated > 0 {
+        print!(" ({} truncated at {} steps)", recovery.truncated, max_steps);
+    }
+    println!();
+}
+
+fn sample(args: &Args, memory_model: &MemoryModelType, program: Program, runs: usize, scheduler_options: SchedulerOptions, path: &str, record: impl FnOnce(&Exploration) -> Result<(), String>) -> Result<(), String> {
+    let mut sampler = Sampler::new(memory_model, &program, args.seed, scheduler_options, args.max_steps);
+    let interval = Duration::from_secs(args.checkpoint_interval);
+    if let Ok(content) = fs::read_to_string(path) {
+        sampler.resume(&content)?;
+        println!("# RESUMED {} ({} executions sampled)", path, sampler.exploration().executions);
+    }
+    let mut last_checkpoint = Instant::now();
+    while sampler.step(runs) {
+        if last_checkpoint.elapsed() >= interval {
+            write_checkpoint(path, sampler.checkpoint())?;
+            last_checkpoint = Instant::now();
+        }
+    }
+    write_checkpoint(path, sampler.checkpoint())?;
+    print_outcomes(sampler.exploration(), "SAMPLED", args.max_steps, args.witnesses.then_some(program.thread_names.as_slice()));
+    if args.stats {
+        print!("{}", sampler.exploration().statistics);
+    }
+    record(sampler.exploration())
+}
+
+fn interact(memory_model: MemoryModelType, program: Program, seed: Option<u64>, scheduler_options: SchedulerOptions, script: Option<String>) {
+    let mut repl = Repl::new(memory_model, program);
+    if let Some(seed) = seed {
+        repl.set_seed(seed);
+    }
+    repl.set_scheduler_options(scheduler_options);
+    let result = match script.as_deref() {
+        None => {
+            repl.run(io::stdin().lock());
+            Ok(())
+        }
+        Some("-") => repl.run_script(io::stdin().lock()),
+        Some(script_path) => fs::File::open(script_path)
+            .map_err(|err| format!("Error reading file {}: {}", script_path, err))
+            .and_then(|file| repl.run_script(io::BufReader::new(file))),
+    };
+    if let Err(err) = result {
+        eprintln!("{}", err);
+        process::exit(1);
+    }
+}
+
+fn modification_time(file_path: &str) -> Option<SystemTime> {
+    fs::metadata(file_path).and_then(|metadata| metadata.modified()).ok()
+}
+
+fn watch(args: &Args, file_path: &str, memory_model: &MemoryModelType, seed: u64, scheduler_options: SchedulerOptions, races: Option<RaceAnalysis>) {
+    let mut last_modified = None;
+    loop {
+        let modified = modification_time(file_path);
+        if modified.is_some() && modified != last_modified {
+            last_modified = modified;
+            println!("# RUN {} (seed = {})", file_path, seed);
+            match read_program(file_path, args) {
+                Ok(program) => {
+                    let result = trace(args, &program)
+                        .and_then(|trace| run(memory_model, program, Some(seed), scheduler_options, trace, races, args.stats));
+                    if let Err(err) = result {
+                        eprintln!("{}", err);
+                    }
+                }
+                Err(err) => eprintln!("{}", err),
+            }
+            println!("# WAITING FOR CHANGES");
+        }
+        thread::sleep(Duration::from_millis(500));
+    }
 }
 
 fn main() {
     let args = Args::parse();
 
-    let file_path = args.file;
-    let content = fs::read_to_string(file_path.clone())
-        .unwrap_or_else(|err| {
-            eprintln!("Error reading file {}: {}", file_path, err);
+    let memory_model: MemoryModelType = args.model.parse()
+        .unwrap_or_else(|_| {
+            eprintln!("Invalid memory model. Choose from: SC, TSO, PSO, C11, JMM, ITANIUM, PRAM, PC, NMCA, SCOPED, EVENTUAL, CAUSAL, SPECULATIVE");
             process::exit(1);
         });
 
-    let memory_model = match &args.model[..] {
-        "SC" => MemoryModelType::SC,
-        "TSO" => MemoryModelType::TSO,
-        "PSO" => MemoryModelType::PSO,
-        _ => {
-            eprintln!("Invalid memory model. Choose from: SC, TSO, PSO");
+    let scheduler_options = SchedulerOptions {
+        drain_bound: args.drain_bound,
+        fairness_window: args.fair,
+        propagation_delay: None,
+        crash_after: args.crash_after,
+    };
+
+    if let Some(Command::Repl { threads }) = args.command {
+        let mut program = Program::new((0..threads).map(|_| Vec::new()).collect());
+        program.default_memory = args.default_mem;
+        program.clear_monitors = args.clear_monitors;
+        interact(memory_model, program, args.seed, scheduler_options, args.script);
+        return;
+    }
+
+    if let Some(Command::Decode { file }) = &args.command {
+        if let Err(err) = decode(file) {
+            eprintln!("{}", err);
             process::exit(1);
         }
-    };
+        return;
+    }
+
+    if let Some(Command::Report { database, baseline }) = &args.command {
+        match report(database, baseline.as_deref()) {
+            Ok(true) => return,
+            Ok(false) => process::exit(1),
+            Err(err) => {
+                eprintln!("{}", err);
+                process::exit(1);
+            }
+        }
+    }
+
+    if let Some(Command::Equiv { first, second }) = &args.command {
+        match equiv(&args, &memory_model, first, second) {
+            Ok(true) => return,
+            Ok(false) => process::exit(1),
+            Err(err) => {
+                eprintln!("{}", err);
+                process::exit(1);
+            }
+        }
+    }
+
+    if let Some(Command::Batch { paths, jobs }) = &args.command {
+        match batch(&args, paths, *jobs, &memory_model, scheduler_options) {
+            Ok(true) => return,
+            Ok(false) => process::exit(1),
+            Err(err) => {
+                eprintln!("{}", err);
+                process::exit(1);
+            }
+        }
+    }
+
+    if args.record.is_some() && !(args.exhaustive || args.runs.is_some() || args.quiet) {
+        eprintln!("--record needs --exhaustive, --runs, --quiet or batch");
+        process::exit(1);
+    }
+
+    let file_path = args.file.clone().unwrap_or_else(|| {
+        eprintln!("Missing --file argument");
+        process::exit(1);
+    });
+
+    // Only the operational models see which store a load reads, so the other engines cannot check it.
+    if args.strict_memory && (args.symbolic || args.smt.is_some() || args.alloy.is_some() || args.event_structure.is_some() || (args.exhaustive && matches!(args.strategy, Strategy::ReadsFrom))) {
+        eprintln!("--strict-memory is not supported with --symbolic, --smt, --alloy, --event-structure or --strategy reads-from");
+        process::exit(1);
+    }
+
+    if let Some(Command::Replay { trace }) = &args.command {
+        match replay(&args, &file_path, &memory_model, trace, false) {
+            Ok(steps) => println!("# REPLAY OK: {} steps match", steps),
+            Err(err) => {
+                eprintln!("{}", err);
+                process::exit(1);
+            }
+        }
+        return;
+    }
+
+    if let Some(Command::Explain { trace }) = &args.command {
+        match replay(&args, &file_path, &memory_model, trace, true) {
+            Ok(steps) => println!("# EXPLAINED {} steps", steps),
+            Err(err) => {
+                eprintln!("{}", err);
+                process::exit(1);
+            }
+        }
+        return;
+    }
+
+    if let Some(Command::Litmus { trace }) = &args.command {
+        match litmus(&args, &file_path, &memory_model, trace) {
+            Ok(test) => print!("{}", test),
+            Err(err) => {
+                eprintln!("{}", err);
+                process::exit(1);
+            }
+        }
+        return;
+    }
+
+    if let Some(Command::Check { spec }) = &args.command {
+        match check(&args, &file_path, &memory_model, spec) {
+            Ok(true) => return,
+            Ok(false) => process::exit(1),
+            Err(err) => {
+                eprintln!("{}", err);
+                process::exit(1);
+            }
+        }
+    }
+
+    if let Some(Command::Stats) = &args.command {
+        stats(&args, &file_path).unwrap_or_else(|err| {
+            eprintln!("{}", err);
+            process::exit(1);
+        });
+        return;
+    }
+
+    if let Some(Command::Robust) = &args.command {
+        match robust(&args, &file_path, &memory_model) {
+            Ok(true) => return,
+            Ok(false) => process::exit(1),
+            Err(err) => {
+                eprintln!("{}", err);
+                process::exit(1);
+            }
+        }
+    }
+
+    if args.interactive || args.script.is_some() {
+        let program = read_program(&file_path, &args)
+            .unwrap_or_else(|err| {
+                eprintln!("{}", err);
+                process::exit(1);
+            });
+        interact(memory_model, program, args.seed, scheduler_options, args.script);
+        return;
+    }
+
+    if let Some(dot_path) = &args.dot {
+        let program = read_program(&file_path, &args)
+            .unwrap_or_else(|err| {
+                eprintln!("{}", err);
+                process::exit(1);
+            });
+        let model = memory_model.create(&program);
+        fs::write(dot_path, model.graph().to_dot(&program.thread_names))
+            .unwrap_or_else(|err| {
+                eprintln!("Error writing file {}: {}", dot_path, err);
+                process::exit(1);
+            });
+    }
+
+    if let Some(path) = &args.event_structure {
+        let program = read_program(&file_path, &args)
+            .unwrap_or_else(|err| {
+                eprintln!("{}", err);
+                process::exit(1);
+            });
+        let structure = EventStructure::build(&program, args.max_steps)
+            .unwrap_or_else(|err| {
+                eprintln!("{}", err);
+                process::exit(1);
+            });
+        fs::write(path, structure.to_dot(&program.thread_names))
+            .unwrap_or_else(|err| {
+                eprintln!("Error writing file {}: {}", path, err);
+                process::exit(1);
+            });
+        println!("# EVENT STRUCTURE {} events, {} maximal configurations", structure.events.len(), structure.maximal_configurations().len());
+        return;
+    }
+
+    if args.symbolic {
+        let program = read_source(&file_path, &args)
+            .unwrap_or_else(|err| {
+                eprintln!("{}", err);
+                process::exit(1);
+            });
+        let symbolic = symbolic::explore(memory_model, &program, args.max_steps)
+            .unwrap_or_else(|err| {
+                eprintln!("{}", err);
+                process::exit(1);
+            });
+        print_outcomes(&symbolic.exploration, "EXPLORED", args.max_steps, None);
+        if let Some(condition) = &program.postcondition {
+            match symbolic.violation {
+                Some((outcome, inputs)) => {
+                    println!("# VIOLATION {:?}", condition);
+                    println!("| {}", outcome);
+                    println!("| inputs: {}", inputs);
+                }
+                None => println!("# NO VIOLATION {:?}", condition),
+            }
+        }
+        return;
+    }
+
+    if let Some(path) = &args.smt {
+        let program = read_program(&file_path, &args)
+            .unwrap_or_else(|err| {
+                eprintln!("{}", err);
+                process::exit(1);
+            });
+        let query = smt::encode(memory_model, &program, args.unroll)
+            .unwrap_or_else(|err| {
+                eprintln!("{}", err);
+                process::exit(1);
+            });
+        fs::write(path, query)
+            .unwrap_or_else(|err| {
+                eprintln!("Error writing file {}: {}", path, err);
+                process::exit(1);
+            });
+        return;
+    }
+
+    if let Some(path) = &args.alloy {
+        let program = read_program(&file_path, &args)
+            .unwrap_or_else(|err| {
+                eprintln!("{}", err);
+                process::exit(1);
+            });
+        let module = reads_from::alloy(memory_model, &program)
+            .unwrap_or_else(|err| {
+                eprintln!("{}", err);
+                process::exit(1);
+            });
+        fs::write(path, module)
+            .unwrap_or_else(|err| {
+                eprintln!("Error writing file {}: {}", path, err);
+                process::exit(1);
+            });
+        return;
+    }
 
-    let mut instructions: Vec<Vec<LabeledInstruction>> = Vec::new();
-    let mut current_thread = 0;
-    instructions.push(Vec::new());
-    for line in content.lines() {
-        if line.is_empty() {
-          instructions.push(Vec::new());
-          current_thread += 1;
-          continue;
+    if let Some(budget) = args.guided {
+        let program = read_program(&file_path, &args)
+            .unwrap_or_else(|err| {
+                eprintln!("{}", err);
+                process::exit(1);
+            });
+        let condition = program.postcondition.clone().unwrap_or_else(|| {
+            eprintln!("--guided needs an assert line in the program");
+            process::exit(1);
+        });
+        let instances = program.instances().unwrap_or_else(|err| {
+            eprintln!("{}", err);
+            process::exit(1);
+        });
+        let (mut expanded, mut exhausted) = (0, true);
+        let mut violation = None;
+        for instance in instances.iter() {
+            let mut explorer = Explorer::new(&memory_model, instance, args.max_steps);
+            explorer.set_sleep_sets(!args.no_sleep_sets);
+            let search = explorer.guided(&condition, budget);
+            expanded += search.expanded;
+            exhausted &= search.exhausted;
+            if let Some((outcome, witness)) = search.violation {
+                let outcome = if program.has_ranges() { format!("[{}] {}", instance.describe_initial(&program), outcome) } else { outcome };
+                violation = Some((outcome, witness));
+                break;
+            }
+        }
+        match violation {
+            Some((outcome, witness)) => {
+                println!("# VIOLATION {:?}", condition);
+                println!("| {}", outcome);
+                println!("# WITNESS");
+                for node in witness {
+                    println!("| {}: {:?}", program.thread_names[node.thread_id], node);
+                }
+            }
+            None if exhausted => println!("# NO VIOLATION {:?}", condition),
+            None => println!("# NO VIOLATION FOUND {:?} (budget exhausted)", condition),
         }
-        let instruction = parse_instruction(line)
+        println!("# GUIDED {} states", expanded);
+        return;
+    }
+
+    if args.exhaustive {
+        let program = read_program(&file_path, &args)
             .unwrap_or_else(|err| {
-                eprintln!("Error parsing instruction {}: {}", line, err);
+                eprintln!("{}", err);
                 process::exit(1);
             });
-        instructions[current_thread].push(instruction);
+        if let Strategy::ReadsFrom = args.strategy {
+            let exploration = explore::quantify(&program, |instance| reads_from::enumerate(memory_model, instance))
+                .unwrap_or_else(|err| {
+                    eprintln!("{}", err);
+                    process::exit(1);
+                });
+            print_outcomes(&exploration, "ENUMERATED", args.max_steps, None);
+            record(&args, &file_path, &memory_model, None, &exploration)
+                .unwrap_or_else(|err| {
+                    eprintln!("{}", err);
+                    process::exit(1);
+                });
+            return;
+        }
+        if program.has_ranges() {
+            if !program.recovery.is_empty() {
+                eprintln!("recovery threads need fixed initial values");
+                process::exit(1);
+            }
+            if args.checkpoint.is_some() {
+                eprintln!("--checkpoint needs fixed initial values");
+                process::exit(1);
+            }
+            let exploration = explore::quantify(&program, |instance| {
+                let mut explorer = Explorer::new(&memory_model, instance, args.max_steps);
+                explorer.set_sleep_sets(!args.no_sleep_sets);
+                Ok(explorer.run())
+            }).unwrap_or_else(|err| {
+                eprintln!("{}", err);
+                process::exit(1);
+            });
+            print_outcomes(&exploration, "EXPLORED", args.max_steps, args.witnesses.then_some(program.thread_names.as_slice()));
+            record(&args, &file_path, &memory_model, None, &exploration)
+                .unwrap_or_else(|err| {
+                    eprintln!("{}", err);
+                    process::exit(1);
+                });
+            return;
+        }
+        let checkpoint = args.checkpoint.as_deref().map(|path| (path, Duration::from_secs(args.checkpoint_interval)));
+        explore(&memory_model, program, args.max_steps, !args.no_sleep_sets, checkpoint, args.witnesses, |exploration| record(&args, &file_path, &memory_model, None, exploration))
+            .unwrap_or_else(|err| {
+                eprintln!("{}", err);
+                process::exit(1);
+            });
+        return;
     }
 
-    match memory_model {
-        MemoryModelType::SC => {
-            let mut model = SC::new(instructions);
-            while model.get_possible_executions().len() > 0 {
-                model.random_step(args.trace);
+    if let Some(runs) = args.runs {
+        let program = read_program(&file_path, &args)
+            .unwrap_or_else(|err| {
+                eprintln!("{}", err);
+                process::exit(1);
+            });
+        if let Some(path) = &args.checkpoint {
+            if program.has_ranges() || args.rare {
+                eprintln!("--checkpoint with --runs needs fixed initial values and cannot be combined with --rare");
+                process::exit(1);
             }
+            sample(&args, &memory_model, program, runs, scheduler_options, path, |exploration| record(&args, &file_path, &memory_model, args.seed, exploration))
+                .unwrap_or_else(|err| {
+                    eprintln!("{}", err);
+                    process::exit(1);
+                });
+            return;
         }
-        MemoryModelType::TSO => {
-            let mut model = TSO::new(instructions);
-            while model.get_possible_executions().len() > 0 {
-                model.random_step(args.trace);
+        if args.rare {
+            if program.has_ranges() {
+                eprintln!("--rare needs fixed initial values");
+                process::exit(1);
+            }
+            let search = explore::search(&memory_model, &program, runs, args.seed, scheduler_options, args.max_steps);
+            print_rarity(&search);
+            if args.stats {
+                print!("{}", search.exploration.statistics);
             }
+            record(&args, &file_path, &memory_model, args.seed, &search.exploration)
+            .unwrap_or_else(|err| {
+                eprintln!("{}", err);
+                process::exit(1);
+            });
+            return;
+        }
+        let exploration = explore::quantify(&program, |instance| Ok(explore::sample(&memory_model, instance, runs, args.seed, scheduler_options, args.max_steps)))
+            .unwrap_or_else(|err| {
+                eprintln!("{}", err);
+                process::exit(1);
+            });
+        print_outcomes(&exploration, "SAMPLED", args.max_steps, args.witnesses.then_some(program.thread_names.as_slice()));
+        if args.stats {
+            print!("{}", exploration.statistics);
         }
-        MemoryModelType::PSO => {
-            let mut model = PSO::new(instructions);
-            while model.get_possible_executions().len() > 0 {
-                model.random_step(args.trace);
+        record(&args, &file_path, &memory_model, args.seed, &exploration)
+            .unwrap_or_else(|err| {
+                eprintln!("{}", err);
+                process::exit(1);
+            });
+        return;
+    }
+
+    if args.watch {
+        let seed = args.seed.unwrap_or_else(rand::random);
+        watch(&args, &file_path, &memory_model, seed, scheduler_options, args.races);
+        return;
+    }
+
+    if args.quiet {
+        match quiet(&args, &file_path, &memory_model, scheduler_options) {
+            Ok(true) => return,
+            Ok(false) => process::exit(1),
+            Err(err) => {
+                eprintln!("{}", err);
+                process::exit(1);
             }
         }
-    };
-}
\ No newline at end of file
+    }
+
+    let program = read_program(&file_path, &args)
+        .unwrap_or_else(|err| {
+            eprintln!("{}", err);
+            process::exit(1);
+        });
+    trace(&args, &program)
+        .and_then(|trace| run(&memory_model, program, args.seed, scheduler_options, trace, args.races, args.stats))
+        .unwrap_or_else(|err| {
+            eprintln!("{}", err);
+            process::exit(1);
+        });
+}
@@ -0,0 +1,160 @@
+use std::fmt::Debug;
+
+use crate::memory_model::MemoryModel;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Comparison {
+  Eq,
+  Ne,
+  Lt,
+  Le,
+  Gt,
+  Ge
+}
+
+#[derive(Clone, PartialEq, Eq)]
+pub enum Operand {
+  Register { thread: String, r: String },
+  Memory { address: i32 }
+}
+
+#[derive(Clone, PartialEq, Eq)]
+pub struct Atom {
+  pub operand: Operand,
+  pub comparison: Comparison,
+  pub value: i32
+}
+
+// A postcondition on the final state: clauses joined by `||`, each a list of atoms joined by `&&`.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Condition {
+  pub clauses: Vec<Vec<Atom>>
+}
+
+impl Debug for Operand {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      Operand::Register { thread, r } => write!(f, "{}:{}", thread, r),
+      Operand::Memory { address } => write!(f, "#{}", address)
+    }
+  }
+}
+
+impl Debug for Atom {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    let comparison = match self.comparison {
+      Comparison::Eq => "==",
+      Comparison::Ne => "!=",
+      Comparison::Lt => "<",
+      Comparison::Le => "<=",
+      Comparison::Gt => ">",
+      Comparison::Ge => ">="
+    };
+    write!(f, "{:?} {} {}", self.operand, comparison, self.value)
+  }
+}
+
+impl Debug for Condition {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    let clauses: Vec<String> = self.clauses.iter()
+      .map(|atoms| atoms.iter().map(|atom| format!("{:?}", atom)).collect::<Vec<String>>().join(" && "))
+      .collect();
+    write!(f, "{}", clauses.join(" || "))
+  }
+}
+
+fn parse_atom(atom: &str) -> Result<Atom, String> {
+  let parts: Vec<&str> = atom.split_whitespace().collect();
+  let [operand, comparison, value] = parts.as_slice() else {
+    return Err(format!("Invalid comparison {}", atom.trim()));
+  };
+  let operand = match operand.strip_prefix('#') {
+    Some(address) => Operand::Memory { address: address.parse().map_err(|_| format!("Invalid address {}", operand))? },
+    None => match operand.split_once(':') {
+      Some((thread, r)) if !thread.is_empty() && !r.is_empty() => Operand::Register { thread: thread.to_string(), r: r.to_string() },
+      _ => return Err(format!("Invalid operand {}: expected thread:register or #address", operand))
+    }
+  };
+  let comparison = match *comparison {
+    "==" => Comparison::Eq,
+    "!=" => Comparison::Ne,
+    "<" => Comparison::Lt,
+    "<=" => Comparison::Le,
+    ">" => Comparison::Gt,
+    ">=" => Comparison::Ge,
+    _ => return Err(format!("Invalid comparison operator {}", comparison))
+  };
+  let value = value.parse().map_err(|_| format!("Invalid value {}", value))?;
+  Ok(Atom { operand, comparison, value })
+}
+
+impl Condition {
+  pub fn parse(condition: &str) -> Result<Condition, String> {
+    let clauses = condition.split("||")
+      .map(|clause| clause.split("&&").map(parse_atom).collect::<Result<Vec<Atom>, String>>())
+      .collect::<Result<Vec<Vec<Atom>>, String>>()?;
+    Ok(Condition { clauses })
+  }
+
+  pub fn threads(&self) -> Vec<&String> {
+    self.clauses.iter().flatten()
+      .filter_map(|atom| match &atom.operand {
+        Operand::Register { thread, r: _ } => Some(thread),
+        Operand::Memory { address: _ } => None
+      })
+      .collect()
+  }
+
+  // Registers that were never assigned read as 0 and addresses never stored to as the default value.
+  fn value(operand: &Operand, model: &dyn MemoryModel) -> i64 {
+    let value = match operand {
+      Operand::Register { thread, r } => (0..model.number_of_threads())
+        .find(|thread_id| model.thread_name(*thread_id) == thread)
+        .and_then(|thread_id| model.registers(thread_id).get(r).cloned())
+        .unwrap_or(0),
+      Operand::Memory { address } => model.memory().get(address).cloned().unwrap_or(model.default_memory())
+    };
+    value as i64
+  }
+
+  pub fn holds(&self, model: &dyn MemoryModel) -> bool {
+    self.distance_to_holding(model) == 0
+  }
+
+  fn distance_to_holding(&self, model: &dyn MemoryModel) -> i64 {
+    self.clauses.iter()
+      .map(|atoms| atoms.iter().map(|atom| Self::distance(atom, model, false)).sum())
+      .min()
+      .unwrap_or(0)
+  }
+
+  // How far the state is from breaking the condition: 0 once it is broken, otherwise the sum over
+  // clauses of how much the closest atom of the clause has to change to become false.
+  pub fn distance_to_violation(&self, model: &dyn MemoryModel) -> i64 {
+    self.clauses.iter()
+      .map(|atoms| atoms.iter().map(|atom| Self::distance(atom, model, true)).min().unwrap_or(0))
+      .sum()
+  }
+
+  fn distance(atom: &Atom, model: &dyn MemoryModel, negated: bool) -> i64 {
+    let x = Self::value(&atom.operand, model);
+    let v = atom.value as i64;
+    let comparison = match (negated, atom.comparison) {
+      (false, comparison) => comparison,
+      (true, Comparison::Eq) => Comparison::Ne,
+      (true, Comparison::Ne) => Comparison::Eq,
+      (true, Comparison::Lt) => Comparison::Ge,
+      (true, Comparison::Le) => Comparison::Gt,
+      (true, Comparison::Gt) => Comparison::Le,
+      (true, Comparison::Ge) => Comparison::Lt
+    };
+    match comparison {
+      Comparison::Eq => (x - v).abs(),
+      Comparison::Ne => (x == v) as i64,
+      Comparison::Lt => (x - v + 1).max(0),
+      Comparison::Le => (x - v).max(0),
+      Comparison::Gt => (v - x + 1).max(0),
+      Comparison::Ge => (v - x).max(0)
+    }
+  }
+}
@@ -0,0 +1,156 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+
+use crate::{explore::Exploration, json::{self, Value}, memory_model::MemoryModelType};
+
+// What running or exploring a test under one model found, one JSON line of an outcome database.
+// The seed is kept as a string, since a random seed may not fit a JSON number.
+#[derive(Clone, Debug)]
+pub struct Entry {
+  pub test: String,
+  pub model: String,
+  pub version: String,
+  pub seed: Option<u64>,
+  pub executions: usize,
+  pub truncated: usize,
+  pub outcomes: BTreeMap<String, usize>
+}
+
+impl Entry {
+  pub fn new(test: &str, memory_model: &MemoryModelType, seed: Option<u64>, exploration: &Exploration) -> Entry {
+    Entry {
+      test: test.to_string(),
+      model: format!("{:?}", memory_model),
+      version: env!("CARGO_PKG_VERSION").to_string(),
+      seed,
+      executions: exploration.executions,
+      truncated: exploration.truncated,
+      outcomes: exploration.outcomes.iter().map(|(outcome, found)| (outcome.clone(), found.count)).collect()
+    }
+  }
+
+  pub fn to_json(&self) -> String {
+    json::object(vec![
+      ("test".to_string(), json::string(&self.test)),
+      ("model".to_string(), json::string(&self.model)),
+      ("version".to_string(), json::string(&self.version)),
+      ("seed".to_string(), self.seed.map_or("null".to_string(), |seed| json::string(&seed.to_string()))),
+      ("executions".to_string(), self.executions.to_string()),
+      ("truncated".to_string(), self.truncated.to_string()),
+      ("outcomes".to_string(), json::object(self.outcomes.iter().map(|(outcome, count)| (outcome.clone(), count.to_string())).collect()))
+    ])
+  }
+
+  pub fn parse(line: &str) -> Result<Entry, String> {
+    let value = json::parse(line)?;
+    let text = |key: &str| match value.get(key) {
+      Some(Value::String(text)) => Ok(text.clone()),
+      _ => Err(format!("expected a string {}", key))
+    };
+    let count = |value: Option<&Value>, key: &str| match value {
+      Some(Value::Number(count)) if *count >= 0 => Ok(*count as usize),
+      _ => Err(format!("expected a count {}", key))
+    };
+    let seed = match value.get("seed") {
+      Some(Value::Null) | None => None,
+      Some(Value::String(seed)) => Some(seed.parse().map_err(|_| format!("invalid seed {}", seed))?),
+      Some(_) => return Err("expected a string seed".to_string())
+    };
+    let Some(Value::Object(fields)) = value.get("outcomes") else {
+      return Err("expected an object outcomes".to_string());
+    };
+    let mut outcomes = BTreeMap::new();
+    for (outcome, found) in fields {
+      outcomes.insert(outcome.clone(), count(Some(found), outcome)?);
+    }
+    Ok(Entry {
+      test: text("test")?,
+      model: text("model")?,
+      version: text("version")?,
+      seed,
+      executions: count(value.get("executions"), "executions")?,
+      truncated: count(value.get("truncated"), "truncated")?,
+      outcomes
+    })
+  }
+}
+
+// Adds the records to the end of the database, creating it if need be.
+pub fn append(path: &Path, records: &[Entry]) -> Result<(), String> {
+  let mut file = OpenOptions::new().create(true).append(true).open(path)
+    .map_err(|err| format!("Error writing file {}: {}", path.display(), err))?;
+  let lines: String = records.iter().map(|record| format!("{}\n", record.to_json())).collect();
+  file.write_all(lines.as_bytes()).map_err(|err| format!("Error writing file {}: {}", path.display(), err))
+}
+
+pub fn read(path: &Path) -> Result<Vec<Entry>, String> {
+  let content = fs::read_to_string(path).map_err(|err| format!("Error reading file {}: {}", path.display(), err))?;
+  content.lines().enumerate()
+    .filter(|(_, line)| !line.trim().is_empty())
+    .map(|(line_number, line)| Entry::parse(line).map_err(|err| format!("{}: line {}: {}", path.display(), line_number + 1, err)))
+    .collect()
+}
+
+// The outcomes a test reaches under a model that its baseline did not, and those it no longer reaches.
+pub struct Change {
+  pub latest: Entry,
+  pub baseline: Entry,
+  pub added: Vec<String>,
+  pub removed: Vec<String>
+}
+
+pub struct Report {
+  pub changes: Vec<Change>,
+  // Tests and models with nothing to compare against.
+  pub new: Vec<Entry>,
+  pub unchanged: usize
+}
+
+impl Report {
+  pub fn is_clean(&self) -> bool {
+    self.changes.is_empty()
+  }
+}
+
+// Compares the latest record of every test and model with its baseline: the latest record for them
+// in `baseline`, or without one the record before it in `records`. Only the sets of outcomes are
+// compared, since the counts of sampled runs differ from seed to seed.
+pub fn report(records: &[Entry], baseline: Option<&[Entry]>) -> Report {
+  let mut latest: BTreeMap<(&str, &str), (&Entry, Option<&Entry>)> = BTreeMap::new();
+  for record in records {
+    let key = (record.test.as_str(), record.model.as_str());
+    let previous = latest.get(&key).map(|(previous, _)| *previous);
+    latest.insert(key, (record, previous));
+  }
+  if let Some(baseline) = baseline {
+    let mut earlier: BTreeMap<(&str, &str), &Entry> = BTreeMap::new();
+    for record in baseline {
+      earlier.insert((record.test.as_str(), record.model.as_str()), record);
+    }
+    for (key, (_, previous)) in latest.iter_mut() {
+      *previous = earlier.get(key).copied();
+    }
+  }
+  let mut report = Report { changes: Vec::new(), new: Vec::new(), unchanged: 0 };
+  for (record, previous) in latest.into_values() {
+    let Some(previous) = previous else {
+      report.new.push(record.clone());
+      continue;
+    };
+    let outcomes: BTreeSet<&String> = record.outcomes.keys().collect();
+    let earlier: BTreeSet<&String> = previous.outcomes.keys().collect();
+    if outcomes == earlier {
+      report.unchanged += 1;
+      continue;
+    }
+    report.changes.push(Change {
+      latest: record.clone(),
+      baseline: previous.clone(),
+      added: outcomes.difference(&earlier).map(|outcome| outcome.to_string()).collect(),
+      removed: earlier.difference(&outcomes).map(|outcome| outcome.to_string()).collect()
+    });
+  }
+  report
+}
@@ -0,0 +1,119 @@
+use std::fmt::Display;
+
+use crate::{graph::{Edge, Node}, instruction::Instruction, memory_model::{MemoryModel, Parked}};
+
+// What a blocked thread waits for at the address.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Blocker {
+  Lock { owner: usize },
+  Semaphore,
+  Barrier,
+  Wake
+}
+
+// A blocked node and the address it waits on. A thread parked at a barrier or a `wait` is shown at
+// that node.
+pub struct Wait {
+  pub node: Node,
+  pub address: i32,
+  pub blocker: Blocker
+}
+
+pub struct Deadlock {
+  pub waits: Vec<Wait>,
+  pub cycle: Vec<usize>,
+  pub ordering: Vec<Edge>,
+  nodes: Vec<Node>,
+  thread_names: Vec<String>
+}
+
+fn find_cycle(waits: &[Wait], start: usize) -> Vec<usize> {
+  let mut cycle: Vec<usize> = Vec::new();
+  let mut thread_id = start;
+  while let Some(wait) = waits.iter().find(|wait| wait.node.thread_id == thread_id) {
+    if let Some(position) = cycle.iter().position(|visited| *visited == thread_id) {
+      cycle.drain(..position);
+      cycle.push(thread_id);
+      return cycle;
+    }
+    cycle.push(thread_id);
+    match wait.blocker {
+      Blocker::Lock { owner } => thread_id = owner,
+      _ => break
+    }
+  }
+  Vec::new()
+}
+
+pub fn detect(model: &dyn MemoryModel) -> Option<Deadlock> {
+  if !model.get_possible_executions().is_empty() {
+    return None;
+  }
+  let mut waits: Vec<Wait> = Vec::new();
+  for thread_id in 0..model.number_of_threads() {
+    match model.parked(thread_id) {
+      Some(Parked::Barrier { node, address, target: _ }) => waits.push(Wait { node, address, blocker: Blocker::Barrier }),
+      Some(Parked::Futex { node, address }) => waits.push(Wait { node, address, blocker: Blocker::Wake }),
+      None => {}
+    }
+  }
+  for node in model.pending_executions() {
+    if waits.iter().any(|wait| wait.node.thread_id == node.thread_id) {
+      continue;
+    }
+    let register = |r: &String| *model.registers(node.thread_id).get(r).unwrap_or(&0);
+    match &node.instruction.instruction {
+      Instruction::Lock { address } => {
+        let address = register(address);
+        if let Some(owner) = model.lock_owner(address) {
+          waits.push(Wait { node, address, blocker: Blocker::Lock { owner } });
+        }
+      }
+      Instruction::SemWait { address } => {
+        let address = register(address);
+        waits.push(Wait { node, address, blocker: Blocker::Semaphore });
+      }
+      _ => {}
+    }
+  }
+  let ordering = model.graph().active_cycle().map(|cycle| cycle.to_vec()).unwrap_or_default();
+  if waits.is_empty() && ordering.is_empty() {
+    return None;
+  }
+  let thread_names = (0..model.number_of_threads()).map(|thread_id| model.thread_name(thread_id).to_string()).collect();
+  let cycle = waits.iter().map(|wait| find_cycle(&waits, wait.node.thread_id)).find(|cycle| !cycle.is_empty()).unwrap_or_default();
+  let nodes = model.graph().instructions.to_vec();
+  Some(Deadlock { waits, cycle, ordering, nodes, thread_names })
+}
+
+impl Display for Deadlock {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    writeln!(f, "# DEADLOCK")?;
+    for wait in self.waits.iter() {
+      let thread_name = &self.thread_names[wait.node.thread_id];
+      match wait.blocker {
+        Blocker::Lock { owner } => writeln!(f, "| thread {} is blocked at {:?} on lock {} held by thread {}",
+          thread_name, wait.node, wait.address, self.thread_names[owner])?,
+        Blocker::Semaphore => writeln!(f, "| thread {} is blocked at {:?} on semaphore {} at zero",
+          thread_name, wait.node, wait.address)?,
+        Blocker::Barrier => writeln!(f, "| thread {} is blocked at {:?} until the barrier at {} opens",
+          thread_name, wait.node, wait.address)?,
+        Blocker::Wake => writeln!(f, "| thread {} is asleep at {:?} with no wake of {} to come",
+          thread_name, wait.node, wait.address)?
+      }
+    }
+    if !self.cycle.is_empty() {
+      let cycle: Vec<&str> = self.cycle.iter().map(|thread_id| self.thread_names[*thread_id].as_str()).collect();
+      writeln!(f, "| wait-for cycle: {}", cycle.join(" -> "))?;
+    }
+    if !self.ordering.is_empty() {
+      writeln!(f, "| unsatisfiable ordering:")?;
+      for edge in self.ordering.iter() {
+        let (from, to) = (&self.nodes[edge.from], &self.nodes[edge.to]);
+        writeln!(f, "|   thread {}: {:?} waits for thread {}: {:?} ({})",
+          self.thread_names[from.thread_id], from, self.thread_names[to.thread_id], to, edge.kind.name())?;
+      }
+    }
+    Ok(())
+  }
+}
@@ -0,0 +1,34 @@
+use std::fmt::{Debug, Display};
+
+#[derive(Clone)]
+pub struct RuntimeError {
+  pub thread_id: usize,
+  pub thread_name: String,
+  pub instruction: String,
+  pub line: Option<usize>,
+  pub message: String,
+  pub registers: Vec<(String, i32)>
+}
+
+impl Display for RuntimeError {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(f, "Runtime error in thread {} at {}", self.thread_name, self.instruction)?;
+    if let Some(line) = self.line {
+      write!(f, " (line {})", line)?;
+    }
+    write!(f, ": {}", self.message)?;
+    if !self.registers.is_empty() {
+      let registers: Vec<String> = self.registers.iter().map(|(r, value)| format!("{} = {}", r, value)).collect();
+      write!(f, " ({})", registers.join(", "))?;
+    }
+    Ok(())
+  }
+}
+
+impl Debug for RuntimeError {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(f, "{}", self)
+  }
+}
+
+impl std::error::Error for RuntimeError {}
@@ -0,0 +1,68 @@
+use crate::{graph::Node, instruction::Mode, json};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum AccessKind {
+  Read,
+  Write,
+  Update,
+  Lock,
+  Unlock
+}
+
+impl AccessKind {
+  pub fn is_write(&self) -> bool {
+    matches!(self, AccessKind::Write | AccessKind::Update)
+  }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Access {
+  pub address: i32,
+  pub kind: AccessKind,
+  pub mode: Mode,
+  pub value: i32,
+  pub source: Option<usize> // the store read or written, numbered per address, if the storage tracks it
+}
+
+#[derive(Clone, Debug)]
+pub struct Event {
+  pub node: Node,
+  pub access: Option<Access>
+}
+
+impl Event {
+  pub fn new(node: Node) -> Event {
+    Event {
+      node,
+      access: None
+    }
+  }
+
+  pub fn json_fields(&self, thread_name: &str) -> Vec<(String, String)> {
+    json_fields(thread_name, &format!("{:?}", self.node.instruction), self.node.instruction.line, &self.access)
+  }
+}
+
+// The fields describing a step in a JSON trace.
+pub fn json_fields(thread_name: &str, instruction: &str, line: Option<usize>, access: &Option<Access>) -> Vec<(String, String)> {
+  let access = match access {
+    Some(Access { address, kind, mode, value, source }) => json::object(vec![
+      ("kind".to_string(), json::string(&format!("{:?}", kind).to_lowercase())),
+      ("address".to_string(), address.to_string()),
+      ("mode".to_string(), json::string(&format!("{:?}", mode))),
+      ("value".to_string(), value.to_string()),
+      ("source".to_string(), source.map_or("null".to_string(), |source| source.to_string()))
+    ]),
+    None => "null".to_string()
+  };
+  vec![
+    ("thread".to_string(), json::string(thread_name)),
+    ("instruction".to_string(), json::string(instruction)),
+    ("line".to_string(), line.map_or("null".to_string(), |line| line.to_string())),
+    ("access".to_string(), access)
+  ]
+}
+
+pub trait Observer {
+  fn on_event(&mut self, event: &Event);
+}
@@ -0,0 +1,318 @@
+use std::collections::{BTreeSet, HashMap};
+
+use crate::{instruction::{Instruction, LabeledInstruction}, program::Program};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum EventKind {
+  Read,
+  Write,
+  Update,
+  Fence
+}
+
+#[derive(Clone)]
+pub struct StructureEvent {
+  pub id: usize,
+  pub thread_id: usize,
+  pub instruction: LabeledInstruction,
+  pub kind: EventKind,
+  pub address: i32,
+  pub read: i32,
+  pub written: i32,
+  pub predecessor: Option<usize>,
+  // For reads: the write read from, `None` for the initial value.
+  pub reads_from: Option<usize>
+}
+
+impl StructureEvent {
+  fn reads(&self) -> bool {
+    matches!(self.kind, EventKind::Read | EventKind::Update)
+  }
+
+  fn writes(&self) -> bool {
+    matches!(self.kind, EventKind::Write | EventKind::Update)
+  }
+}
+
+enum Next {
+  Done,
+  Read { instruction: LabeledInstruction, address: i32, registers: HashMap<String, i32> },
+  Write { instruction: LabeledInstruction, address: i32, value: i32 },
+  Fence { instruction: LabeledInstruction }
+}
+
+// Prime event structure: events are ordered by causality (program order and reads-from) and two
+// events are in conflict when they are alternative continuations of the same history.
+pub struct EventStructure {
+  pub events: Vec<StructureEvent>,
+  histories: Vec<BTreeSet<usize>>,
+  initial: HashMap<i32, i32>,
+  default: i32,
+  max_steps: usize
+}
+
+impl EventStructure {
+  pub fn build(program: &Program, max_steps: usize) -> Result<EventStructure, String> {
+    for instruction in program.threads.iter().flatten() {
+      if instruction.instruction.is_blocking() || instruction.instruction.is_exclusive() || instruction.instruction.is_transactional() || matches!(instruction.instruction, Instruction::Lock { address: _ } | Instruction::Unlock { address: _ } | Instruction::Flush { address: _ }) {
+        return Err(format!("Event structures do not support {:?}", instruction));
+      }
+    }
+    if program.has_ranges() {
+      return Err("Event structures need fixed initial values".to_string());
+    }
+    let mut structure = EventStructure { events: Vec::new(), histories: Vec::new(), initial: program.initial_memory(), default: program.default_memory, max_steps };
+    loop {
+      let count = structure.events.len();
+      for thread_id in 0..program.threads.len() {
+        let mut points: Vec<Option<usize>> = vec![None];
+        points.extend(structure.events.iter().filter(|event| event.thread_id == thread_id).map(|event| Some(event.id)));
+        for point in points {
+          structure.extend(&program.threads[thread_id], thread_id, point)?;
+        }
+      }
+      if structure.events.len() == count {
+        return Ok(structure);
+      }
+    }
+  }
+
+  fn chain(&self, point: Option<usize>) -> Vec<&StructureEvent> {
+    let mut chain = Vec::new();
+    let mut current = point;
+    while let Some(id) = current {
+      chain.push(&self.events[id]);
+      current = self.events[id].predecessor;
+    }
+    chain.reverse();
+    chain
+  }
+
+  // Re-executes the thread along the values read by the events up to `point`.
+  fn next(&self, instructions: &[LabeledInstruction], point: Option<usize>) -> Result<Next, String> {
+    let chain = self.chain(point);
+    let mut consumed = chain.iter();
+    let mut registers: HashMap<String, i32> = HashMap::new();
+    let mut pc = 0;
+    let mut returns = Vec::new();
+    let mut stack = Vec::new();
+    for _ in 0..self.max_steps {
+      let instruction = match instructions.get(pc) {
+        Some(instruction) => instruction,
+        None => return Ok(Next::Done)
+      };
+      let get = |registers: &HashMap<String, i32>, r: &String| *registers.get(r).unwrap_or(&0);
+      pc += 1;
+      let (r, value) = match &instruction.instruction {
+        Instruction::Const { r, value } => (r, Some(*value)),
+        Instruction::ArithPlus { r1, r2, r3 } => (r1, get(&registers, r2).checked_add(get(&registers, r3))),
+        Instruction::ArithMinus { r1, r2, r3 } => (r1, get(&registers, r2).checked_sub(get(&registers, r3))),
+        Instruction::ArithMul { r1, r2, r3 } => (r1, get(&registers, r2).checked_mul(get(&registers, r3))),
+        Instruction::ArithDiv { r1, r2, r3 } => (r1, get(&registers, r2).checked_div(get(&registers, r3))),
+        Instruction::Select { r1, r2, r3, r4 } => (r1, Some(get(&registers, if get(&registers, r2) != 0 { r3 } else { r4 }))),
+        Instruction::Cond { r, target } => {
+          if get(&registers, r) != 0 {
+            pc = target.resolve(instructions.iter(), pc - 1)
+              .ok_or_else(|| format!("Event structures only support jumps within a thread: {:?}", instruction))?;
+          }
+          continue;
+        }
+        Instruction::Call { target } => {
+          returns.push(pc);
+          pc = target.resolve(instructions.iter(), pc - 1)
+            .ok_or_else(|| format!("Event structures only support jumps within a thread: {:?}", instruction))?;
+          continue;
+        }
+        Instruction::Ret => {
+          pc = returns.pop().unwrap_or(instructions.len());
+          continue;
+        }
+        Instruction::Push { r } => {
+          stack.push(get(&registers, r));
+          continue;
+        }
+        Instruction::Pop { r } => (r, stack.pop()),
+        _ => {
+          let event = match consumed.next() {
+            Some(event) => event,
+            None => return Ok(match &instruction.instruction {
+              Instruction::Fence { mode: _, order: _ } => Next::Fence { instruction: instruction.clone() },
+              Instruction::Store { mode: _, address, r } => {
+                Next::Write { instruction: instruction.clone(), address: get(&registers, address), value: get(&registers, r) }
+              }
+              Instruction::Load { mode: _, address, r: _ }
+              | Instruction::Cas { mode: _, failure: _, address, to: _, exp: _, des: _, flag: _ }
+              | Instruction::Fai { op: _, mode: _, address, to: _, inc: _ } => {
+                Next::Read { instruction: instruction.clone(), address: get(&registers, address), registers }
+              }
+              _ => unreachable!()
+            })
+          };
+          match &instruction.instruction {
+            Instruction::Load { mode: _, address: _, r } => (r, Some(event.read)),
+            Instruction::Cas { mode: _, failure: _, address: _, to, exp, des: _, flag } => {
+              if let Some(flag) = flag {
+                registers.insert(flag.clone(), (event.read == get(&registers, exp)) as i32);
+              }
+              (to, Some(event.read))
+            }
+            Instruction::Fai { op: _, mode: _, address: _, to, inc: _ } => (to, Some(event.read)),
+            _ => continue
+          }
+        }
+      };
+      match value {
+        Some(value) => {
+          registers.insert(r.clone(), value);
+        }
+        None => return Ok(Next::Done)
+      }
+    }
+    Ok(Next::Done)
+  }
+
+  fn in_immediate_conflict(&self, first: usize, second: usize) -> bool {
+    let (a, b) = (&self.events[first], &self.events[second]);
+    if first == second {
+      return false;
+    }
+    (a.thread_id == b.thread_id && a.predecessor == b.predecessor)
+      || (a.kind == EventKind::Update && b.kind == EventKind::Update && a.address == b.address && a.reads_from == b.reads_from)
+  }
+
+  fn conflict_free(&self, events: &BTreeSet<usize>) -> bool {
+    events.iter().all(|a| events.iter().all(|b| !self.in_immediate_conflict(*a, *b)))
+  }
+
+  pub fn in_conflict(&self, first: usize, second: usize) -> bool {
+    self.histories[first].iter().any(|a| self.histories[second].iter().any(|b| self.in_immediate_conflict(*a, *b)))
+  }
+
+  pub fn causes(&self, first: usize, second: usize) -> bool {
+    first != second && self.histories[second].contains(&first)
+  }
+
+  fn add(&mut self, event: StructureEvent) {
+    let exists = self.events.iter().any(|other| other.thread_id == event.thread_id && other.predecessor == event.predecessor
+      && other.kind == event.kind && other.reads_from == event.reads_from);
+    if exists {
+      return;
+    }
+    let mut history: BTreeSet<usize> = BTreeSet::new();
+    for cause in event.predecessor.iter().chain(event.reads_from.iter()) {
+      history.extend(self.histories[*cause].iter().cloned());
+    }
+    let id = self.events.len();
+    history.insert(id);
+    self.histories.push(history);
+    self.events.push(StructureEvent { id, ..event });
+    if !self.conflict_free(&self.histories[id]) {
+      self.histories.pop();
+      self.events.pop();
+    }
+  }
+
+  fn extend(&mut self, instructions: &[LabeledInstruction], thread_id: usize, point: Option<usize>) -> Result<(), String> {
+    let event = |instruction: LabeledInstruction, kind: EventKind, address: i32| StructureEvent {
+      id: 0, thread_id, instruction, kind, address, read: 0, written: 0, predecessor: point, reads_from: None
+    };
+    match self.next(instructions, point)? {
+      Next::Done => {}
+      Next::Fence { instruction } => self.add(event(instruction, EventKind::Fence, 0)),
+      Next::Write { instruction, address, value } => self.add(StructureEvent { written: value, ..event(instruction, EventKind::Write, address) }),
+      Next::Read { instruction, address, registers } => {
+        let mut sources: Vec<(Option<usize>, i32)> = vec![(None, *self.initial.get(&address).unwrap_or(&self.default))];
+        for write in self.events.iter().filter(|write| write.writes() && write.address == address) {
+          let after_point = self.histories[write.id].iter()
+            .any(|id| self.events[*id].thread_id == thread_id && point.is_none_or(|point| !self.histories[point].contains(id)));
+          if !after_point {
+            sources.push((Some(write.id), write.written));
+          }
+        }
+        // Coherence: a write that the history already overwrote at the address cannot be read.
+        let history = point.map_or_else(BTreeSet::new, |point| self.histories[point].clone());
+        sources.retain(|(source, _)| !history.iter().any(|id| {
+          let write = &self.events[*id];
+          write.writes() && write.address == address && Some(*id) != *source
+            && source.is_none_or(|source| self.histories[*id].contains(&source))
+        }));
+        for (source, value) in sources {
+          let get = |r: &String| *registers.get(r).unwrap_or(&0);
+          let read = StructureEvent { read: value, reads_from: source, ..event(instruction.clone(), EventKind::Read, address) };
+          let read = match &instruction.instruction {
+            Instruction::Cas { mode: _, failure: _, address: _, to: _, exp, des, flag: _ } if get(exp) == value => {
+              StructureEvent { kind: EventKind::Update, written: get(des), ..read }
+            }
+            Instruction::Fai { op, mode: _, address: _, to: _, inc } => match op.apply(value, get(inc)) {
+              Some(written) => StructureEvent { kind: EventKind::Update, written, ..read },
+              None => continue
+            },
+            _ => read
+          };
+          self.add(read);
+        }
+      }
+    }
+    Ok(())
+  }
+
+  fn enabled(&self, configuration: &BTreeSet<usize>, id: usize) -> bool {
+    !configuration.contains(&id)
+      && self.histories[id].iter().all(|cause| *cause == id || configuration.contains(cause))
+      && configuration.iter().all(|other| !self.in_immediate_conflict(*other, id))
+  }
+
+  // Maximal conflict-free, causally closed sets of events: one per combination of reads-from choices.
+  pub fn maximal_configurations(&self) -> Vec<BTreeSet<usize>> {
+    let mut result = Vec::new();
+    self.configurations(0, BTreeSet::new(), &mut result);
+    result
+  }
+
+  // Event ids are topologically sorted, so each event is decided after all of its causes.
+  fn configurations(&self, id: usize, configuration: BTreeSet<usize>, result: &mut Vec<BTreeSet<usize>>) {
+    if id == self.events.len() {
+      if (0..self.events.len()).all(|id| !self.enabled(&configuration, id)) {
+        result.push(configuration);
+      }
+      return;
+    }
+    if self.enabled(&configuration, id) {
+      let mut next = configuration.clone();
+      next.insert(id);
+      self.configurations(id + 1, next, result);
+    }
+    self.configurations(id + 1, configuration, result);
+  }
+
+  pub fn to_dot(&self, thread_names: &[String]) -> String {
+    let mut dot = String::from("digraph events {\n");
+    for event in self.events.iter() {
+      let mut label = format!("{}: {:?}", thread_names[event.thread_id], event.instruction);
+      if event.reads() {
+        label.push_str(&format!(" [read {}]", event.read));
+      }
+      if event.writes() {
+        label.push_str(&format!(" [write {}]", event.written));
+      }
+      dot.push_str(&format!("  e{} [label=\"{}\"];\n", event.id, label.replace('"', "\\\"")));
+    }
+    for event in self.events.iter() {
+      if let Some(predecessor) = event.predecessor {
+        dot.push_str(&format!("  e{} -> e{};\n", predecessor, event.id));
+      }
+      if let Some(source) = event.reads_from {
+        dot.push_str(&format!("  e{} -> e{} [style=dashed, label=\"rf\"];\n", source, event.id));
+      }
+    }
+    for first in self.events.iter() {
+      for second in self.events.iter().filter(|second| second.id > first.id) {
+        if self.in_immediate_conflict(first.id, second.id) {
+          dot.push_str(&format!("  e{} -> e{} [dir=none, style=dotted, color=red];\n", first.id, second.id));
+        }
+      }
+    }
+    dot.push_str("}\n");
+    dot
+  }
+}
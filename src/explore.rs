@@ -0,0 +1,839 @@
+use std::cmp::Reverse;
+use std::collections::{hash_map::Entry, BTreeMap, BinaryHeap, HashMap, HashSet};
+use std::hash::{DefaultHasher, Hash, Hasher};
+
+use rand::{distr::{weighted::WeightedIndex, Distribution}, rngs::SmallRng, SeedableRng};
+
+use crate::{condition::Condition, deadlock, error::RuntimeError, graph::Node, instruction::{Instruction, Mode}, linearizability::History, memory_model::{MemoryModel, MemoryModelType}, program::Program, scheduler::SchedulerOptions, statistics::Statistics};
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Footprint {
+  Local,
+  Access { address: i32, write: bool },
+  Fence,
+  Lock { address: i32 },
+  View { address: i32 },
+  Global
+}
+
+fn footprint(memory_model: &MemoryModelType, model: &dyn MemoryModel, node: &Node) -> Footprint {
+  let address = |r: &String| *model.registers(node.thread_id).get(r).unwrap_or(&0);
+  // Under C11 a propagate step only moves its thread's view, while SEQ_CST instructions and locks
+  // synchronise through the global SC view.
+  if memory_model.is_view_based() {
+    match &node.instruction.instruction {
+      Instruction::Propagate { thread_id: _, address, value: _ } => return Footprint::View { address: *address },
+      Instruction::Lock { address: _ } | Instruction::Unlock { address: _ } => return Footprint::Global,
+      _ if matches!(node.instruction.get_mode(), Some(Mode::SeqCst)) => return Footprint::Global,
+      _ => {}
+    }
+  }
+  match &node.instruction.instruction {
+    Instruction::Const { r: _, value: _ }
+    | Instruction::ArithPlus { r1: _, r2: _, r3: _ }
+    | Instruction::ArithMinus { r1: _, r2: _, r3: _ }
+    | Instruction::ArithMul { r1: _, r2: _, r3: _ }
+    | Instruction::ArithDiv { r1: _, r2: _, r3: _ }
+    | Instruction::Select { r1: _, r2: _, r3: _, r4: _ }
+    | Instruction::Push { r: _ }
+    | Instruction::Pop { r: _ }
+    | Instruction::ClearExclusive => Footprint::Local,
+    Instruction::Cond { r: _, target: _ } | Instruction::Call { target: _ } | Instruction::Ret => Footprint::Global,
+    Instruction::Load { mode: _, address: r, r: _ } | Instruction::LoadLinked { address: r, r: _ } => Footprint::Access { address: address(r), write: false },
+    Instruction::Store { mode: _, address: r, r: _ }
+    | Instruction::Cas { mode: _, failure: _, address: r, to: _, exp: _, des: _, flag: _ }
+    | Instruction::Fai { op: _, mode: _, address: r, to: _, inc: _ }
+    | Instruction::StoreConditional { address: r, r: _, flag: _ } => Footprint::Access { address: address(r), write: true },
+    Instruction::Fence { mode: _, order: _ } => Footprint::Fence,
+    Instruction::Lock { address: r } | Instruction::Unlock { address: r } => Footprint::Lock { address: address(r) },
+    Instruction::Propagate { thread_id: _, address, value: _ } => Footprint::Access { address: *address, write: true },
+    // Semaphores, barriers and futexes decide which steps of other threads are blocked.
+    _ => Footprint::Global
+  }
+}
+
+fn registers_disjoint(first: &Node, second: &Node) -> bool {
+  let first = &first.instruction.instruction;
+  let second = &second.instruction.instruction;
+  let first_writes = first.write_registers();
+  let second_writes = second.write_registers();
+  !first_writes.iter().any(|r| second_writes.contains(r) || second.read_registers().contains(r))
+    && !second_writes.iter().any(|r| first.read_registers().contains(r))
+}
+
+// Observing a message commutes with the thread's own steps unless they access the address, write
+// or acquire, which may move the view past the message.
+fn observation_independent(address: i32, other: (&Node, Footprint)) -> bool {
+  match other.1 {
+    Footprint::Local | Footprint::View { address: _ } => true,
+    Footprint::Access { address: other_address, write } => {
+      other_address != address && !write && !matches!(other.0.instruction.get_mode(), Some(Mode::Acq | Mode::RelAcq | Mode::SeqCst))
+    }
+    _ => false
+  }
+}
+
+// Steps of the same thread (unordered under TSO and PSO) commute if they use disjoint registers
+// and at most one of them writes memory, at different addresses.
+fn independent_in_thread(first: (&Node, Footprint), second: (&Node, Footprint)) -> bool {
+  registers_disjoint(first.0, second.0) && match (first.1, second.1) {
+    (Footprint::View { address }, _) => observation_independent(address, second),
+    (_, Footprint::View { address }) => observation_independent(address, first),
+    (Footprint::Local, Footprint::Local | Footprint::Access { address: _, write: _ })
+    | (Footprint::Access { address: _, write: _ }, Footprint::Local) => true,
+    (Footprint::Access { address: a1, write: w1 }, Footprint::Access { address: a2, write: w2 }) => a1 != a2 && !(w1 && w2),
+    _ => false
+  }
+}
+
+// Steps of different threads commute unless they touch the same address (at least one writing),
+// a store races with a fence waiting for the buffers, both take the same lock or one of them may jump.
+fn independent(first: (&Node, Footprint), second: (&Node, Footprint)) -> bool {
+  if first.0.thread_id == second.0.thread_id {
+    return independent_in_thread(first, second);
+  }
+  match (first.1, second.1) {
+    (Footprint::Global, _) | (_, Footprint::Global) => false,
+    (Footprint::Local | Footprint::View { address: _ }, _) | (_, Footprint::Local | Footprint::View { address: _ }) => true,
+    (Footprint::Fence | Footprint::Lock { address: _ }, Footprint::Access { address: _, write })
+    | (Footprint::Access { address: _, write }, Footprint::Fence | Footprint::Lock { address: _ }) => !write,
+    (Footprint::Lock { address: a1 }, Footprint::Lock { address: a2 }) => a1 != a2,
+    (Footprint::Fence | Footprint::Lock { address: _ }, Footprint::Fence | Footprint::Lock { address: _ }) => true,
+    (Footprint::Access { address: a1, write: w1 }, Footprint::Access { address: a2, write: w2 }) => a1 != a2 || !(w1 || w2)
+  }
+}
+
+pub(crate) fn describe_state(thread_names: &[&str], registers: &[&HashMap<String, i32>], memory: &HashMap<i32, i32>) -> String {
+  let mut parts = Vec::new();
+  for (thread_name, registers) in thread_names.iter().zip(registers) {
+    let registers: BTreeMap<&String, &i32> = registers.iter().collect();
+    parts.push(format!("{}: {:?}", thread_name, registers));
+  }
+  let memory: BTreeMap<&i32, &i32> = memory.iter().collect();
+  parts.push(format!("memory: {:?}", memory));
+  parts.join(" ")
+}
+
+pub fn outcome(model: &dyn MemoryModel) -> String {
+  let thread_names: Vec<&str> = (0..model.number_of_threads()).map(|thread_id| model.thread_name(thread_id)).collect();
+  let registers: Vec<&HashMap<String, i32>> = (0..model.number_of_threads()).map(|thread_id| model.registers(thread_id)).collect();
+  describe_state(&thread_names, &registers, model.memory())
+}
+
+pub(crate) fn final_outcome(model: &dyn MemoryModel) -> String {
+  match deadlock::detect(model) {
+    Some(_) => format!("{} (deadlock)", outcome(model)),
+    None => outcome(model)
+  }
+}
+
+fn checked_outcome(model: &dyn MemoryModel, program: &Program, history: &History) -> String {
+  match history.check(program) {
+    Ok(()) => final_outcome(model),
+    Err(_) => format!("{} (not linearizable)", final_outcome(model))
+  }
+}
+
+fn preemptions(schedule: &[Node]) -> usize {
+  schedule.windows(2).filter(|pair| pair[0].thread_id != pair[1].thread_id).count()
+}
+
+pub struct Outcome {
+  pub count: usize,
+  pub witness: Vec<Node>
+}
+
+pub struct Exploration {
+  pub outcomes: BTreeMap<String, Outcome>,
+  // The states of the persistent addresses a crash can leave, counted once per explored state.
+  pub crashes: BTreeMap<String, Outcome>,
+  pub executions: usize,
+  pub truncated: usize,
+  pub statistics: Statistics
+}
+
+impl Exploration {
+  pub(crate) fn new() -> Exploration {
+    Exploration { outcomes: BTreeMap::new(), crashes: BTreeMap::new(), executions: 0, truncated: 0, statistics: Statistics::new() }
+  }
+
+  // Keeps the witness with the fewest thread switches, then the fewest steps.
+  pub(crate) fn record(&mut self, outcome: String, witness: Vec<Node>) {
+    self.executions += 1;
+    let entry = self.outcomes.entry(outcome).or_insert_with(|| Outcome { count: 0, witness: witness.clone() });
+    entry.count += 1;
+    if (preemptions(&witness), witness.len()) < (preemptions(&entry.witness), entry.witness.len()) {
+      entry.witness = witness;
+    }
+  }
+
+  pub(crate) fn record_crash(&mut self, crash: String, witness: &[Node]) {
+    let entry = self.crashes.entry(crash).or_insert_with(|| Outcome { count: 0, witness: witness.to_vec() });
+    entry.count += 1;
+    if witness.len() < entry.witness.len() {
+      entry.witness = witness.to_vec();
+    }
+  }
+
+  pub fn merge(&mut self, other: Exploration, prefix: &str) {
+    for (outcome, Outcome { count, witness }) in other.outcomes {
+      self.outcomes.entry(format!("{}{}", prefix, outcome)).or_insert(Outcome { count: 0, witness }).count += count;
+    }
+    for (crash, Outcome { count, witness }) in other.crashes {
+      self.crashes.entry(format!("{}{}", prefix, crash)).or_insert(Outcome { count: 0, witness }).count += count;
+    }
+    self.executions += other.executions;
+    self.truncated += other.truncated;
+    self.statistics.merge(&other.statistics);
+  }
+}
+
+// Restricts every outcome to memory and the `observed` registers of each thread by replaying its
+// witness, merging outcomes that differ only in other registers. Errors are kept as they are.
+pub fn observe(memory_model: &MemoryModelType, program: &Program, exploration: &Exploration, observed: &[HashSet<String>]) -> BTreeMap<String, Outcome> {
+  let mut outcomes: BTreeMap<String, Outcome> = BTreeMap::new();
+  for (outcome, Outcome { count, witness }) in exploration.outcomes.iter() {
+    let observed_outcome = if outcome.starts_with("error: ") {
+      outcome.clone()
+    } else {
+      let schedule: Vec<usize> = witness.iter().map(|node| node.id).collect();
+      let model = replay(memory_model, program, &schedule).expect("witness schedules are replayed without errors");
+      let thread_names: Vec<&str> = program.thread_names.iter().map(|name| name.as_str()).collect();
+      let registers: Vec<HashMap<String, i32>> = observed.iter().enumerate()
+        .map(|(thread_id, names)| model.registers(thread_id).iter().filter(|(r, _)| names.contains(*r)).map(|(r, value)| (r.clone(), *value)).collect())
+        .collect();
+      let state = describe_state(&thread_names, &registers.iter().collect::<Vec<_>>(), model.memory());
+      match deadlock::detect(model.as_ref()) {
+        Some(_) => format!("{} (deadlock)", state),
+        None => state
+      }
+    };
+    let entry = outcomes.entry(observed_outcome).or_insert_with(|| Outcome { count: 0, witness: witness.clone() });
+    entry.count += count;
+    if (preemptions(witness), witness.len()) < (preemptions(&entry.witness), entry.witness.len()) {
+      entry.witness = witness.clone();
+    }
+  }
+  outcomes
+}
+
+// Whether the final state a witness leads to breaks the condition, found by replaying it.
+pub fn violates(memory_model: &MemoryModelType, program: &Program, witness: &[Node], condition: &Condition) -> bool {
+  let schedule: Vec<usize> = witness.iter().map(|node| node.id).collect();
+  let model = replay(memory_model, program, &schedule).expect("witness schedules are replayed without errors");
+  !condition.holds(model.as_ref())
+}
+
+// Explores the program once per combination of the initial values its ranges allow, prefixing every
+// outcome with the values it started from.
+pub fn quantify(program: &Program, mut explore: impl FnMut(&Program) -> Result<Exploration, String>) -> Result<Exploration, String> {
+  if !program.has_ranges() {
+    return explore(program);
+  }
+  let mut exploration = Exploration::new();
+  for instance in program.instances()? {
+    let prefix = format!("[{}] ", instance.describe_initial(program));
+    exploration.merge(explore(&instance)?, &prefix);
+  }
+  Ok(exploration)
+}
+
+// Runs one random execution and records its final state, which is returned unless the run was truncated.
+fn sample_run(exploration: &mut Exploration, model: &mut dyn MemoryModel, program: &Program, max_steps: usize) -> Option<String> {
+  let mut schedule: Vec<Node> = Vec::new();
+  let mut history = History::new(program);
+  let outcome = loop {
+    if schedule.len() >= max_steps {
+      exploration.executions += 1;
+      exploration.truncated += 1;
+      break None;
+    }
+    match model.random_step(false) {
+      Ok(Some(event)) => {
+        history.record(program, &event, model.registers(event.node.thread_id));
+        schedule.push(event.node);
+      },
+      Ok(None) => break Some(checked_outcome(model, program, &history)),
+      Err(err) => break Some(format!("error: {}", err))
+    }
+  };
+  if let Some(outcome) = &outcome {
+    exploration.record(outcome.clone(), schedule);
+  }
+  exploration.statistics.merge(model.statistics());
+  outcome
+}
+
+// Counts the final states of `runs` random executions; run `i` uses seed `seed + i`.
+pub fn sample(memory_model: &MemoryModelType, program: &Program, runs: usize, seed: Option<u64>, options: SchedulerOptions, max_steps: usize) -> Exploration {
+  let mut sampler = Sampler::new(memory_model, program, seed, options, max_steps);
+  while sampler.step(runs) {}
+  sampler.exploration
+}
+
+// Random runs that can be interrupted: the seed and the number of runs made so far are all the
+// state of the random number generator that a checkpoint has to keep.
+pub struct Sampler<'a> {
+  memory_model: &'a MemoryModelType,
+  program: &'a Program,
+  seed: Option<u64>,
+  options: SchedulerOptions,
+  max_steps: usize,
+  exploration: Exploration
+}
+
+impl<'a> Sampler<'a> {
+  pub fn new(memory_model: &'a MemoryModelType, program: &'a Program, seed: Option<u64>, options: SchedulerOptions, max_steps: usize) -> Sampler<'a> {
+    Sampler { memory_model, program, seed, options, max_steps, exploration: Exploration::new() }
+  }
+
+  pub fn exploration(&self) -> &Exploration {
+    &self.exploration
+  }
+
+  // Makes the next run unless `runs` runs have been made.
+  pub fn step(&mut self, runs: usize) -> bool {
+    let run = self.exploration.executions;
+    if run >= runs {
+      return false;
+    }
+    let seed = *self.seed.get_or_insert_with(rand::random);
+    let mut model = self.memory_model.create(self.program);
+    model.set_seed(seed.wrapping_add(run as u64));
+    model.set_scheduler_options(self.options);
+    sample_run(&mut self.exploration, model.as_mut(), self.program, self.max_steps);
+    true
+  }
+
+  fn settings(&self) -> Vec<(&'static str, String)> {
+    let mut hasher = DefaultHasher::new();
+    format!("{:?} {:?}", self.program.thread_names, self.program.threads).hash(&mut hasher);
+    vec![
+      ("model", format!("{:?}", self.memory_model)),
+      ("program", format!("{:x}", hasher.finish())),
+      ("max-steps", self.max_steps.to_string()),
+      ("scheduler", format!("{:?}", self.options).replace(' ', ""))
+    ]
+  }
+
+  pub fn checkpoint(&self) -> String {
+    let mut lines = vec!["# isa sampling checkpoint".to_string()];
+    for (key, value) in self.settings() {
+      lines.push(format!("{} {}", key, value));
+    }
+    if let Some(seed) = self.seed {
+      lines.push(format!("seed {}", seed));
+    }
+    lines.push(format!("executions {}", self.exploration.executions));
+    lines.push(format!("truncated {}", self.exploration.truncated));
+    let Statistics { steps, buffered_stores, max_buffer_depth, forwarded_loads, reorderings } = self.exploration.statistics;
+    lines.push(format!("statistics {}", ids(&[steps, buffered_stores, max_buffer_depth, forwarded_loads, reorderings])));
+    for (outcome, Outcome { count, witness }) in self.exploration.outcomes.iter() {
+      let witness: Vec<usize> = witness.iter().map(|node| node.id).collect();
+      lines.push(format!("outcome {};{};{}", count, ids(&witness), outcome));
+    }
+    lines.join("\n") + "\n"
+  }
+
+  // A checkpoint without a seed of its own is resumed with the seed it was made with.
+  pub fn resume(&mut self, checkpoint: &str) -> Result<(), String> {
+    let settings = self.settings();
+    let mut exploration = Exploration::new();
+    for (line_number, line) in checkpoint.lines().enumerate() {
+      if line.starts_with('#') || line.is_empty() {
+        continue;
+      }
+      let error = || format!("Checkpoint line {}: invalid entry {}", line_number + 1, line);
+      let (key, value) = line.split_once(' ').ok_or_else(error)?;
+      if let Some((_, expected)) = settings.iter().find(|(name, _)| *name == key) {
+        if value != expected {
+          return Err(format!("Checkpoint was made with {} {}, not {}", key, value, expected));
+        }
+        continue;
+      }
+      match key {
+        "seed" => {
+          let seed = value.parse().map_err(|_| error())?;
+          if self.seed.is_some_and(|expected| expected != seed) {
+            return Err(format!("Checkpoint was made with seed {}, not {}", seed, self.seed.unwrap_or_default()));
+          }
+          self.seed = Some(seed);
+        }
+        "executions" => exploration.executions = value.parse().map_err(|_| error())?,
+        "truncated" => exploration.truncated = value.parse().map_err(|_| error())?,
+        "statistics" => match parse_ids(value)?.as_slice() {
+          [steps, buffered_stores, max_buffer_depth, forwarded_loads, reorderings] => {
+            exploration.statistics = Statistics { steps: *steps, buffered_stores: *buffered_stores, max_buffer_depth: *max_buffer_depth, forwarded_loads: *forwarded_loads, reorderings: *reorderings };
+          }
+          _ => return Err(error())
+        },
+        "outcome" => {
+          let (count, value) = value.split_once(';').ok_or_else(error)?;
+          let (witness, outcome) = value.split_once(';').ok_or_else(error)?;
+          let witness = parse_ids(witness)?;
+          let model = replay(self.memory_model, self.program, &witness).map_err(|_| error())?;
+          let witness = witness.iter().map(|id| model.graph().instructions[*id].clone()).collect();
+          exploration.outcomes.insert(outcome.to_string(), Outcome { count: count.parse().map_err(|_| error())?, witness });
+        }
+        _ => return Err(error())
+      }
+    }
+    self.exploration = exploration;
+    Ok(())
+  }
+}
+
+const DELAYS: [u32; 6] = [1, 2, 4, 8, 16, 32];
+
+pub struct Search {
+  pub exploration: Exploration,
+  pub first_found: HashMap<String, usize>
+}
+
+impl Search {
+  // Outcomes seen least often first; among equally rare ones, those found later first.
+  pub fn by_rarity(&self) -> Vec<(&String, &Outcome, usize)> {
+    let mut outcomes: Vec<(&String, &Outcome, usize)> = self.exploration.outcomes.iter()
+      .map(|(outcome, entry)| (outcome, entry, self.first_found[outcome]))
+      .collect();
+    outcomes.sort_by_key(|(_, entry, first_found)| (entry.count, Reverse(*first_found)));
+    outcomes
+  }
+}
+
+// Samples like `sample`, but every run delays propagations by one of `DELAYS`, picked with a weight
+// that grows with the number of new outcomes the delay has found.
+pub fn search(memory_model: &MemoryModelType, program: &Program, runs: usize, seed: Option<u64>, options: SchedulerOptions, max_steps: usize) -> Search {
+  let mut rng: SmallRng = match seed {
+    Some(seed) => SmallRng::seed_from_u64(seed),
+    None => rand::make_rng()
+  };
+  let mut weights = [1.0; DELAYS.len()];
+  let mut search = Search { exploration: Exploration::new(), first_found: HashMap::new() };
+  for run in 0..runs {
+    let level = match WeightedIndex::new(weights) {
+      Ok(distribution) => distribution.sample(&mut rng),
+      Err(_) => 0
+    };
+    let mut model = memory_model.create(program);
+    if let Some(seed) = seed {
+      model.set_seed(seed.wrapping_add(run as u64));
+    }
+    model.set_scheduler_options(SchedulerOptions {
+      drain_bound: options.drain_bound,
+      fairness_window: options.fairness_window,
+      propagation_delay: Some(DELAYS[level]),
+      crash_after: None
+    });
+    if let Some(outcome) = sample_run(&mut search.exploration, model.as_mut(), program, max_steps) {
+      if let Entry::Vacant(entry) = search.first_found.entry(outcome) {
+        entry.insert(run);
+        weights[level] += 1.0;
+      }
+    }
+  }
+  search
+}
+
+pub(crate) fn replay(memory_model: &MemoryModelType, program: &Program, schedule: &[usize]) -> Result<Box<dyn MemoryModel>, RuntimeError> {
+  let mut model = memory_model.create(program);
+  step_through(model.as_mut(), schedule)?;
+  Ok(model)
+}
+
+fn step_through(model: &mut dyn MemoryModel, schedule: &[usize]) -> Result<(), RuntimeError> {
+  for id in schedule {
+    let node = model.get_possible_executions().into_iter().find(|node| node.id == *id)
+      .expect("explored schedules only contain executable nodes");
+    model.step(node, false)?;
+  }
+  Ok(())
+}
+
+pub struct Guided {
+  pub violation: Option<(String, Vec<Node>)>,
+  pub expanded: usize,
+  pub exhausted: bool
+}
+
+pub struct Executions<'a> {
+  explorer: Explorer<'a>
+}
+
+impl Executions<'_> {
+  // What the executions handed out so far add up to.
+  pub fn exploration(&self) -> &Exploration {
+    &self.explorer.exploration
+  }
+}
+
+impl Iterator for Executions<'_> {
+  type Item = Execution;
+
+  fn next(&mut self) -> Option<Execution> {
+    loop {
+      if let Some(execution) = self.explorer.finished.take() {
+        return Some(execution);
+      }
+      if !self.explorer.step() {
+        return None;
+      }
+    }
+  }
+}
+
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+struct Task {
+  schedule: Vec<usize>,
+  sleep: Vec<usize>
+}
+
+fn ids(ids: &[usize]) -> String {
+  ids.iter().map(|id| id.to_string()).collect::<Vec<String>>().join(",")
+}
+
+fn parse_ids(ids: &str) -> Result<Vec<usize>, String> {
+  ids.split(',').filter(|id| !id.is_empty())
+    .map(|id| id.parse().map_err(|_| format!("Invalid node id {}", id)))
+    .collect()
+}
+
+// One schedule the exploration followed to its end, with the registers of every thread and the
+// memory it left them with and the runtime error it stopped at, if any. A failing execution keeps
+// the state before the failing step. The outcome describes the final state or error as the outcomes
+// of an `Exploration` do, and is missing if the execution was cut off at the step bound.
+#[derive(Clone, Debug)]
+pub struct Execution {
+  pub schedule: Vec<Node>,
+  pub registers: Vec<HashMap<String, i32>>,
+  pub memory: HashMap<i32, i32>,
+  pub error: Option<RuntimeError>,
+  pub outcome: Option<String>
+}
+
+impl Execution {
+  fn new(schedule: Vec<Node>, model: &dyn MemoryModel, error: Option<RuntimeError>, outcome: Option<String>) -> Execution {
+    Execution {
+      schedule,
+      registers: (0..model.number_of_threads()).map(|thread_id| model.registers(thread_id).clone()).collect(),
+      memory: model.memory().clone(),
+      error,
+      outcome
+    }
+  }
+
+  pub fn is_error(&self) -> bool {
+    self.error.is_some()
+  }
+}
+
+pub struct Explorer<'a> {
+  memory_model: &'a MemoryModelType,
+  program: &'a Program,
+  max_steps: usize,
+  sleep_sets: bool,
+  // Whether the executions are handed out one by one as they end, and the latest one if so.
+  streaming: bool,
+  finished: Option<Execution>,
+  // Whether the program has transactions, which the footprints of its steps do not account for.
+  transactional: bool,
+  frontier: Vec<Task>,
+  carried: Option<(Vec<usize>, Box<dyn MemoryModel>)>,
+  // The states of the schedules on the current path that have more than one child, so that later
+  // children do not have to be replayed from the start.
+  snapshots: Vec<(Vec<usize>, Box<dyn MemoryModel>)>,
+  exploration: Exploration
+}
+
+impl<'a> Explorer<'a> {
+  pub fn new(memory_model: &'a MemoryModelType, program: &'a Program, max_steps: usize) -> Explorer<'a> {
+    Explorer {
+      memory_model,
+      program,
+      max_steps,
+      sleep_sets: true,
+      streaming: false,
+      finished: None,
+      transactional: program.transactional(),
+      frontier: vec![Task { schedule: Vec::new(), sleep: Vec::new() }],
+      carried: None,
+      snapshots: Vec::new(),
+      exploration: Exploration::new()
+    }
+  }
+
+  pub fn set_sleep_sets(&mut self, sleep_sets: bool) {
+    self.sleep_sets = sleep_sets;
+  }
+
+  pub fn exploration(&self) -> &Exploration {
+    &self.exploration
+  }
+
+  fn replay(&self, schedule: &[usize]) -> Result<Box<dyn MemoryModel>, RuntimeError> {
+    replay(self.memory_model, self.program, schedule)
+  }
+
+  // Starts from the deepest snapshot on the way to the schedule, dropping those of branches the
+  // search has left, and replays the rest of it.
+  fn restore(&mut self, schedule: &[usize]) -> Result<Box<dyn MemoryModel>, RuntimeError> {
+    while self.snapshots.last().is_some_and(|(prefix, _)| !schedule.starts_with(prefix)) {
+      self.snapshots.pop();
+    }
+    let Some((prefix, snapshot)) = self.snapshots.last() else {
+      return self.replay(schedule);
+    };
+    let mut model = snapshot.snapshot();
+    step_through(model.as_mut(), &schedule[prefix.len()..])?;
+    Ok(model)
+  }
+
+  // The calls of the operations along a schedule that ran to the end.
+  fn history(&self, schedule: &[usize]) -> History {
+    let mut model = self.memory_model.create(self.program);
+    let mut history = History::new(self.program);
+    for id in schedule {
+      let node = model.get_possible_executions().into_iter().find(|node| node.id == *id)
+        .expect("explored schedules only contain executable nodes");
+      let event = model.step(node, false).expect("final schedules are replayed without errors");
+      history.record(self.program, &event, model.registers(event.node.thread_id));
+    }
+    history
+  }
+
+  fn finish(&mut self, outcome: String, witness: Vec<Node>, model: &dyn MemoryModel, error: Option<RuntimeError>) {
+    if self.streaming {
+      self.finished = Some(Execution::new(witness.clone(), model, error, Some(outcome.clone())));
+    }
+    self.exploration.record(outcome, witness);
+  }
+
+  fn witness(&self, schedule: &[usize]) -> Vec<Node> {
+    let model = self.replay(schedule).expect("witness schedules are replayed without errors");
+    schedule.iter().map(|id| model.graph().instructions[*id].clone()).collect()
+  }
+
+  // Only the last step of a schedule can fail: its prefix was executed when the task was created.
+  fn expand(&mut self, task: Task) {
+    let model = match self.carried.take() {
+      Some((schedule, model)) if schedule == task.schedule => Ok(model),
+      _ => self.restore(&task.schedule)
+    };
+    let mut model = match model {
+      Ok(model) => model,
+      Err(err) => {
+        let prefix = &task.schedule[..task.schedule.len() - 1];
+        let model = self.replay(prefix).expect("witness schedules are replayed without errors");
+        let witness = prefix.iter().map(|id| model.graph().instructions[*id].clone()).collect();
+        return self.finish(format!("error: {}", err), witness, model.as_ref(), Some(err));
+      }
+    };
+    // A crash can happen in every state, including the final one.
+    if !model.persistence().is_empty() {
+      let witness: Vec<Node> = task.schedule.iter().map(|id| model.graph().instructions[*id].clone()).collect();
+      for state in model.persistence().crash_states() {
+        self.exploration.record_crash(format!("{:?}", state), &witness);
+      }
+    }
+    let executions = model.get_possible_executions();
+    if executions.is_empty() {
+      let witness = task.schedule.iter().map(|id| model.graph().instructions[*id].clone()).collect();
+      if self.program.spec.is_none() {
+        return self.finish(final_outcome(model.as_ref()), witness, model.as_ref(), None);
+      }
+      let history = self.history(&task.schedule);
+      return self.finish(checked_outcome(model.as_ref(), self.program, &history), witness, model.as_ref(), None);
+    }
+    if task.schedule.len() >= self.max_steps {
+      self.exploration.executions += 1;
+      self.exploration.truncated += 1;
+      if self.streaming {
+        let schedule = task.schedule.iter().map(|id| model.graph().instructions[*id].clone()).collect();
+        self.finished = Some(Execution::new(schedule, model.as_ref(), None, None));
+      }
+      return;
+    }
+    let children = self.children(model.as_ref(), &task, &executions);
+    if children.len() > 1 {
+      self.snapshots.push((task.schedule.clone(), model.snapshot()));
+    }
+    if let Some(first) = children.first() {
+      let node = executions.into_iter().find(|node| Some(&node.id) == first.schedule.last()).unwrap();
+      if model.step(node, false).is_ok() {
+        self.carried = Some((first.schedule.clone(), model));
+      }
+    }
+    self.frontier.extend(children.into_iter().rev());
+  }
+
+  fn children(&self, model: &dyn MemoryModel, task: &Task, executions: &[Node]) -> Vec<Task> {
+    // When a thread switch clears monitors, which thread runs next matters to every later `sc`, a
+    // crash can happen between any two steps and a step of one thread can abort another's transaction.
+    let footprints: Vec<Footprint> = match self.program.clear_monitors || !self.program.persistent.is_empty() || self.transactional {
+      true => vec![Footprint::Global; executions.len()],
+      false => executions.iter().map(|node| footprint(self.memory_model, model, node)).collect()
+    };
+    let mut sleep: Vec<(&Node, Footprint)> = executions.iter().zip(footprints.iter().cloned())
+      .filter(|(node, _)| task.sleep.contains(&node.id))
+      .collect();
+    let mut children = Vec::new();
+    for (node, node_footprint) in executions.iter().zip(footprints) {
+      if task.sleep.contains(&node.id) {
+        continue;
+      }
+      // Steps that commute still move where operations start and end, so programs with operations
+      // are explored without sleep sets.
+      let child_sleep: Vec<usize> = if self.sleep_sets && self.program.spec.is_none() {
+        sleep.iter()
+          .filter(|(other, other_footprint)| independent((other, *other_footprint), (node, node_footprint)))
+          .map(|(other, _)| other.id)
+          .collect()
+      } else {
+        Vec::new()
+      };
+      let mut schedule = task.schedule.clone();
+      schedule.push(node.id);
+      children.push(Task { schedule, sleep: child_sleep });
+      sleep.push((node, node_footprint));
+    }
+    children
+  }
+
+  // Best-first search for a final state breaking the postcondition: schedules whose state is closest
+  // to breaking it are extended first, deeper ones on ties. At most `budget` states are expanded.
+  pub fn guided(&self, condition: &Condition, budget: usize) -> Guided {
+    let mut frontier: BinaryHeap<(Reverse<i64>, usize, Reverse<usize>, Task)> = BinaryHeap::new();
+    frontier.push((Reverse(0), 0, Reverse(0), Task { schedule: Vec::new(), sleep: Vec::new() }));
+    let mut pushed = 1;
+    let mut result = Guided { violation: None, expanded: 0, exhausted: false };
+    while let Some((_, _, _, task)) = frontier.pop() {
+      if result.expanded >= budget {
+        return result;
+      }
+      result.expanded += 1;
+      let model = match self.replay(&task.schedule) {
+        Ok(model) => model,
+        Err(_) => continue
+      };
+      let executions = model.get_possible_executions();
+      if executions.is_empty() {
+        if !condition.holds(model.as_ref()) {
+          let witness = task.schedule.iter().map(|id| model.graph().instructions[*id].clone()).collect();
+          result.violation = Some((final_outcome(model.as_ref()), witness));
+          return result;
+        }
+        continue;
+      }
+      if task.schedule.len() >= self.max_steps {
+        continue;
+      }
+      for child in self.children(model.as_ref(), &task, &executions) {
+        let mut child_model = model.snapshot();
+        let distance = match step_through(child_model.as_mut(), &child.schedule[task.schedule.len()..]) {
+          Ok(()) => condition.distance_to_violation(child_model.as_ref()),
+          Err(_) => i64::MAX
+        };
+        frontier.push((Reverse(distance), child.schedule.len(), Reverse(pushed), child));
+        pushed += 1;
+      }
+    }
+    result.exhausted = true;
+    result
+  }
+
+  pub fn step(&mut self) -> bool {
+    match self.frontier.pop() {
+      Some(task) => {
+        self.expand(task);
+        true
+      }
+      None => false
+    }
+  }
+
+  pub fn run(mut self) -> Exploration {
+    while self.step() {}
+    self.exploration
+  }
+
+  // Explores lazily, handing out every execution as soon as it ends, so the caller can stop early.
+  pub fn executions(mut self) -> Executions<'a> {
+    self.streaming = true;
+    Executions { explorer: self }
+  }
+
+  fn settings(&self) -> Vec<(&'static str, String)> {
+    let mut hasher = DefaultHasher::new();
+    format!("{:?} {:?}", self.program.thread_names, self.program.threads).hash(&mut hasher);
+    vec![
+      ("model", format!("{:?}", self.memory_model)),
+      ("program", format!("{:x}", hasher.finish())),
+      ("max-steps", self.max_steps.to_string()),
+      ("sleep-sets", self.sleep_sets.to_string())
+    ]
+  }
+
+  pub fn checkpoint(&self) -> String {
+    let mut lines = vec!["# isa exploration checkpoint".to_string()];
+    for (key, value) in self.settings() {
+      lines.push(format!("{} {}", key, value));
+    }
+    lines.push(format!("executions {}", self.exploration.executions));
+    lines.push(format!("truncated {}", self.exploration.truncated));
+    for (outcome, Outcome { count, witness }) in self.exploration.outcomes.iter() {
+      let witness: Vec<usize> = witness.iter().map(|node| node.id).collect();
+      lines.push(format!("outcome {};{};{}", count, ids(&witness), outcome));
+    }
+    for (crash, Outcome { count, witness }) in self.exploration.crashes.iter() {
+      let witness: Vec<usize> = witness.iter().map(|node| node.id).collect();
+      lines.push(format!("crash {};{};{}", count, ids(&witness), crash));
+    }
+    for task in self.frontier.iter() {
+      lines.push(format!("task {};{}", ids(&task.schedule), ids(&task.sleep)));
+    }
+    lines.join("\n") + "\n"
+  }
+
+  pub fn resume(&mut self, checkpoint: &str) -> Result<(), String> {
+    let settings = self.settings();
+    let mut exploration = Exploration::new();
+    let mut frontier = Vec::new();
+    for (line_number, line) in checkpoint.lines().enumerate() {
+      if line.starts_with('#') || line.is_empty() {
+        continue;
+      }
+      let error = || format!("Checkpoint line {}: invalid entry {}", line_number + 1, line);
+      let (key, value) = line.split_once(' ').ok_or_else(error)?;
+      if let Some((_, expected)) = settings.iter().find(|(name, _)| *name == key) {
+        if value != expected {
+          return Err(format!("Checkpoint was made with {} {}, not {}", key, value, expected));
+        }
+        continue;
+      }
+      match key {
+        "executions" => exploration.executions = value.parse().map_err(|_| error())?,
+        "truncated" => exploration.truncated = value.parse().map_err(|_| error())?,
+        "outcome" => {
+          let (count, value) = value.split_once(';').ok_or_else(error)?;
+          let (witness, outcome) = value.split_once(';').ok_or_else(error)?;
+          let witness = self.witness(&parse_ids(witness)?);
+          exploration.outcomes.insert(outcome.to_string(), Outcome { count: count.parse().map_err(|_| error())?, witness });
+        }
+        "crash" => {
+          let (count, value) = value.split_once(';').ok_or_else(error)?;
+          let (witness, crash) = value.split_once(';').ok_or_else(error)?;
+          let witness = self.witness(&parse_ids(witness)?);
+          exploration.crashes.insert(crash.to_string(), Outcome { count: count.parse().map_err(|_| error())?, witness });
+        }
+        "task" => {
+          let (schedule, sleep) = value.split_once(';').ok_or_else(error)?;
+          frontier.push(Task { schedule: parse_ids(schedule)?, sleep: parse_ids(sleep)? });
+        }
+        _ => return Err(error())
+      }
+    }
+    self.exploration = exploration;
+    self.frontier = frontier;
+    Ok(())
+  }
+}
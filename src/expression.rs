@@ -0,0 +1,197 @@
+use crate::instruction::{Instruction, LabeledInstruction};
+
+// The right-hand side of an assignment with several operators, parentheses or integer operands,
+// such as `r1 = (r2 + r3) * 2`. `*` and `/` bind tighter than `+` and `-`, and operators of the
+// same precedence group to the left.
+#[derive(Clone, PartialEq, Eq, Debug)]
+enum Expression {
+  Register(String),
+  Constant(i32),
+  Binary(Box<Expression>, char, Box<Expression>)
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+enum Token {
+  Name(String),
+  Number(i32),
+  Symbol(char)
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>, String> {
+  let mut tokens = Vec::new();
+  let mut chars = text.chars().peekable();
+  while let Some(&c) = chars.peek() {
+    if c.is_whitespace() {
+      chars.next();
+    } else if "+-*/()".contains(c) {
+      tokens.push(Token::Symbol(c));
+      chars.next();
+    } else if c.is_alphanumeric() || c == '_' || c == '.' {
+      let mut word = String::new();
+      while let Some(&c) = chars.peek().filter(|c| c.is_alphanumeric() || **c == '_' || **c == '.') {
+        word.push(c);
+        chars.next();
+      }
+      if word.starts_with(|c: char| c.is_ascii_digit()) {
+        tokens.push(Token::Number(word.parse().map_err(|_| format!("Invalid constant {}", word))?));
+      } else {
+        tokens.push(Token::Name(word));
+      }
+    } else {
+      return Err(format!("Unexpected character {}", c));
+    }
+  }
+  Ok(tokens)
+}
+
+struct Parser {
+  tokens: Vec<Token>,
+  position: usize
+}
+
+impl Parser {
+  fn peek(&self) -> Option<&Token> {
+    self.tokens.get(self.position)
+  }
+
+  fn next(&mut self) -> Option<Token> {
+    let token = self.tokens.get(self.position).cloned();
+    self.position += 1;
+    token
+  }
+
+  // A chain of operands joined by the operators in `operators`, one precedence level below `operand`.
+  fn chain(&mut self, operators: &str, operand: fn(&mut Parser) -> Result<Expression, String>) -> Result<Expression, String> {
+    let mut expression = operand(self)?;
+    while let Some(&Token::Symbol(op)) = self.peek().filter(|token| matches!(token, Token::Symbol(op) if operators.contains(*op))) {
+      self.next();
+      expression = Expression::Binary(Box::new(expression), op, Box::new(operand(self)?));
+    }
+    Ok(expression)
+  }
+
+  fn sum(&mut self) -> Result<Expression, String> {
+    self.chain("+-", Parser::product)
+  }
+
+  fn product(&mut self) -> Result<Expression, String> {
+    self.chain("*/", Parser::atom)
+  }
+
+  // A register, an integer, a parenthesized expression or a negation, which is lowered as `0 - e`.
+  fn atom(&mut self) -> Result<Expression, String> {
+    match self.next() {
+      Some(Token::Name(name)) => Ok(Expression::Register(name)),
+      Some(Token::Number(value)) => Ok(Expression::Constant(value)),
+      Some(Token::Symbol('(')) => {
+        let expression = self.sum()?;
+        match self.next() {
+          Some(Token::Symbol(')')) => Ok(expression),
+          _ => Err("Missing )".to_string())
+        }
+      },
+      Some(Token::Symbol('-')) => match self.atom()? {
+        Expression::Constant(value) => value.checked_neg().map(Expression::Constant).ok_or(format!("Invalid constant -{}", value)),
+        expression => Ok(Expression::Binary(Box::new(Expression::Constant(0)), '-', Box::new(expression)))
+      },
+      Some(Token::Symbol(c)) => Err(format!("Unexpected {}", c)),
+      None => Err("Unexpected end of expression".to_string())
+    }
+  }
+}
+
+fn parse(text: &str) -> Result<Expression, String> {
+  let mut parser = Parser { tokens: tokenize(text)?, position: 0 };
+  let expression = parser.sum()?;
+  match parser.peek() {
+    None => Ok(expression),
+    Some(Token::Symbol(c)) => Err(format!("Unexpected {}", c)),
+    Some(Token::Name(name)) => Err(format!("Unexpected {}", name)),
+    Some(Token::Number(value)) => Err(format!("Unexpected {}", value))
+  }
+}
+
+// Emits single-operator instructions, keeping intermediate values in the temporary registers
+// `$t1`, `$t2`, ... Temporaries are numbered afresh on every line, as none is read after its line.
+struct Lowering {
+  instructions: Vec<Instruction>,
+  temporaries: usize
+}
+
+impl Lowering {
+  fn temporary(&mut self) -> String {
+    self.temporaries += 1;
+    format!("$t{}", self.temporaries)
+  }
+
+  // The register holding the value of `expression`.
+  fn operand(&mut self, expression: &Expression) -> String {
+    match expression {
+      Expression::Register(r) => r.clone(),
+      _ => {
+        let r = self.temporary();
+        self.assign(&r, expression);
+        r
+      }
+    }
+  }
+
+  // The target is written only by the last instruction, so it may also appear in the expression.
+  fn assign(&mut self, target: &str, expression: &Expression) {
+    let instruction = match expression {
+      Expression::Constant(value) => Instruction::Const { r: target.to_string(), value: *value },
+      Expression::Register(r) => {
+        let zero = self.operand(&Expression::Constant(0));
+        Instruction::ArithPlus { r1: target.to_string(), r2: r.clone(), r3: zero }
+      },
+      Expression::Binary(left, op, right) => {
+        let (r1, r2, r3) = (target.to_string(), self.operand(left), self.operand(right));
+        match op {
+          '+' => Instruction::ArithPlus { r1, r2, r3 },
+          '-' => Instruction::ArithMinus { r1, r2, r3 },
+          '*' => Instruction::ArithMul { r1, r2, r3 },
+          _ => Instruction::ArithDiv { r1, r2, r3 }
+        }
+      }
+    };
+    self.instructions.push(instruction);
+  }
+}
+
+// Lowers `[label:] r = expression` when the expression is more than a constant, one operator
+// between two registers or a select, which `parse_instruction` handles itself. The label goes on the first
+// instruction of the sequence.
+pub fn parse_assignment(line: &str) -> Option<Result<Vec<LabeledInstruction>, String>> {
+  let (label, rest) = match line.split_whitespace().next()?.strip_suffix(':') {
+    Some(label) => (Some(label.to_string()), line.trim_start().split_once(':')?.1),
+    None => (None, line)
+  };
+  let (target, text) = rest.split_once('=')?;
+  let target = match target.split_whitespace().collect::<Vec<&str>>().as_slice() {
+    [target] if !target.ends_with(':') && !text.starts_with('=') => target.to_string(),
+    _ => return None
+  };
+  let parts: Vec<&str> = text.split_whitespace().collect();
+  let simple = match parts.as_slice() {
+    [value] => value.parse::<i32>().is_ok(),
+    [r2, op, r3] => ["+", "-", "*", "/"].contains(op) && [r2, r3].iter().all(|r| matches!(tokenize(r).as_deref(), Ok([Token::Name(_)]))),
+    [_, "?", _, ":", _] => true,
+    _ => false
+  };
+  if simple {
+    return None;
+  }
+  let expression = match parse(text) {
+    Ok(expression) => expression,
+    Err(err) => return Some(Err(err))
+  };
+  let mut lowering = Lowering { instructions: Vec::new(), temporaries: 0 };
+  lowering.assign(&target, &expression);
+  Some(Ok(lowering.instructions.into_iter().enumerate().map(|(index, instruction)| LabeledInstruction {
+    label: if index == 0 { label.clone() } else { None },
+    instruction,
+    line: None,
+    scope: None,
+    java: false
+  }).collect()))
+}
@@ -0,0 +1,191 @@
+use std::{fs, path::Path};
+
+use crate::{instruction::{Instruction, LabeledInstruction, Mode}, memory_model::MemoryModelType, parser::{parse_instruction, relocate_offsets}, program::Program};
+
+// The accesses a mapping rule rewrites; `fai` also covers the other fetch operations.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Kind {
+  Load,
+  Store,
+  Cas,
+  Fai,
+  Fence
+}
+
+impl Kind {
+  fn parse(kind: &str) -> Result<Kind, String> {
+    match kind {
+      "load" => Ok(Kind::Load),
+      "store" => Ok(Kind::Store),
+      "cas" => Ok(Kind::Cas),
+      "fai" => Ok(Kind::Fai),
+      "fence" => Ok(Kind::Fence),
+      _ => Err(format!("Invalid access {}; expected load, store, cas, fai or fence", kind))
+    }
+  }
+
+  fn name(self) -> &'static str {
+    match self {
+      Kind::Load => "load",
+      Kind::Store => "store",
+      Kind::Cas => "cas",
+      Kind::Fai => "fai",
+      Kind::Fence => "fence"
+    }
+  }
+
+  fn of(instruction: &Instruction) -> Option<(Kind, Mode)> {
+    match instruction {
+      Instruction::Load { mode, .. } => Some((Kind::Load, *mode)),
+      Instruction::Store { mode, .. } => Some((Kind::Store, *mode)),
+      Instruction::Cas { mode, .. } => Some((Kind::Cas, *mode)),
+      Instruction::Fai { mode, .. } => Some((Kind::Fai, *mode)),
+      Instruction::Fence { mode, order: None } => Some((Kind::Fence, *mode)),
+      _ => None
+    }
+  }
+}
+
+// One item of a rule's right-hand side: a fence, or the access itself in its new mode.
+#[derive(Clone, Debug)]
+enum Step {
+  Fence(Instruction),
+  Access(Mode)
+}
+
+// `MODEL KIND MODE = step; step; ...`, where MODEL is `*` for every model.
+#[derive(Clone, Debug)]
+struct Rule {
+  model: Option<MemoryModelType>,
+  kind: Kind,
+  mode: Mode,
+  steps: Vec<Step>
+}
+
+// Rewrites the accesses of each mode into the fences and plain accesses a model implements them
+// with, such as `PSO store REL = fence st,st; store`. The first rule that matches an access wins.
+#[derive(Clone, Debug, Default)]
+pub struct FenceMap {
+  rules: Vec<Rule>
+}
+
+impl FenceMap {
+  pub fn read(path: &Path) -> Result<FenceMap, String> {
+    let content = fs::read_to_string(path)
+      .map_err(|err| format!("Error reading file {}: {}", path.display(), err))?;
+    FenceMap::parse(&content).map_err(|err| format!("{}: {}", path.display(), err))
+  }
+
+  pub fn parse(content: &str) -> Result<FenceMap, String> {
+    let mut rules = Vec::new();
+    for (line_number, line) in content.lines().enumerate() {
+      let line = line.split('#').next().unwrap_or("").trim();
+      if line.is_empty() {
+        continue;
+      }
+      rules.push(parse_rule(line).map_err(|err| format!("Line {}: {}", line_number + 1, err))?);
+    }
+    Ok(FenceMap { rules })
+  }
+
+  fn steps(&self, model: MemoryModelType, kind: Kind, mode: Mode) -> Option<&[Step]> {
+    self.rules.iter()
+      .find(|rule| rule.model.is_none_or(|m| m == model) && rule.kind == kind && rule.mode == mode)
+      .map(|rule| rule.steps.as_slice())
+  }
+
+  pub fn apply(&self, model: MemoryModelType, program: &mut Program) {
+    for (thread_id, instructions) in program.threads.iter_mut().enumerate() {
+      let starts = self.rewrite(model, instructions);
+      for (input_thread, pc) in program.inputs.iter_mut() {
+        if *input_thread == thread_id {
+          *pc = starts[*pc];
+        }
+      }
+    }
+    for instructions in program.recovery.iter_mut() {
+      self.rewrite(model, instructions);
+    }
+  }
+
+  // Expanded instructions keep the line and scope of the one they replace, and the first of them its label.
+  // Returns where each original instruction starts now.
+  fn rewrite(&self, model: MemoryModelType, instructions: &mut Vec<LabeledInstruction>) -> Vec<usize> {
+    let mut rewritten = Vec::new();
+    let mut starts = Vec::new();
+    for instruction in instructions.drain(..) {
+      starts.push(rewritten.len());
+      let steps = Kind::of(&instruction.instruction).and_then(|(kind, mode)| self.steps(model, kind, mode));
+      let Some(steps) = steps else {
+        rewritten.push(instruction);
+        continue;
+      };
+      for (index, step) in steps.iter().enumerate() {
+        rewritten.push(LabeledInstruction {
+          label: if index == 0 { instruction.label.clone() } else { None },
+          instruction: match step {
+            Step::Fence(fence) => fence.clone(),
+            Step::Access(mode) => with_mode(&instruction.instruction, *mode)
+          },
+          line: instruction.line,
+          scope: instruction.scope,
+          java: false
+        });
+      }
+    }
+    relocate_offsets(&mut rewritten, &starts);
+    *instructions = rewritten;
+    starts
+  }
+}
+
+fn parse_rule(line: &str) -> Result<Rule, String> {
+  let (left, right) = line.split_once('=').ok_or("Expected MODEL ACCESS MODE = ...".to_string())?;
+  let (model, kind, mode) = match left.split_whitespace().collect::<Vec<&str>>().as_slice() {
+    [model, kind, mode] => (*model, Kind::parse(kind)?, mode.parse::<Mode>().map_err(|_| format!("Invalid mode {}", mode))?),
+    _ => return Err("Expected MODEL ACCESS MODE = ...".to_string())
+  };
+  let model = match model {
+    "*" => None,
+    model => Some(model.parse::<MemoryModelType>().map_err(|_| format!("Invalid memory model {}", model))?)
+  };
+  let mut steps = Vec::new();
+  for item in right.split(';').map(str::trim).filter(|item| !item.is_empty()) {
+    let parts: Vec<&str> = item.split_whitespace().collect();
+    let step = match parts.as_slice() {
+      [access] | [access, _] if kind != Kind::Fence && Kind::parse(access) == Ok(kind) => match parts.get(1) {
+        Some(mode) => Step::Access(mode.parse().map_err(|_| format!("Invalid mode {}", mode))?),
+        None => Step::Access(Mode::Rlx)
+      },
+      _ => match parse_instruction(item)?.instruction {
+        fence @ Instruction::Fence { .. } => Step::Fence(fence),
+        _ => return Err(format!("{} is neither a fence nor the {} being mapped", item, kind.name()))
+      }
+    };
+    steps.push(step);
+  }
+  if steps.is_empty() {
+    return Err("A mapping needs at least one instruction".to_string());
+  }
+  let accesses = steps.iter().filter(|step| matches!(step, Step::Access(_))).count();
+  if kind != Kind::Fence && accesses != 1 {
+    return Err(format!("A {} mapping needs the {} exactly once", kind.name(), kind.name()));
+  }
+  Ok(Rule { model, kind, mode, steps })
+}
+
+// The failure mode of a cas follows the new mode unless the program gave its own.
+fn with_mode(instruction: &Instruction, new: Mode) -> Instruction {
+  let mut instruction = instruction.clone();
+  match &mut instruction {
+    Instruction::Load { mode, .. } | Instruction::Store { mode, .. } | Instruction::Fai { mode, .. } => *mode = new,
+    Instruction::Cas { mode, failure, .. } => {
+      if *failure == mode.failure() {
+        *failure = new.failure();
+      }
+      *mode = new;
+    },
+    _ => {}
+  }
+  instruction
+}
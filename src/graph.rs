@@ -1,6 +1,8 @@
 use std::collections::{HashSet, HashMap};
+use std::fmt::Debug;
+use smallvec::SmallVec;
 
-use crate::instruction::LabeledInstruction;
+use crate::{instruction::{Instruction, LabeledInstruction, Target}, shared::Chunks};
 
 #[derive(Clone)]
 pub struct Node {
@@ -19,70 +21,296 @@ impl Node {
   }
 }
 
+impl Debug for Node {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self.instruction.line {
+      Some(line) => write!(f, "{:?} (line {})", self.instruction, line),
+      None => write!(f, "{:?}", self.instruction)
+    }
+  }
+}
+
+// Why `from` waits for `to`.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum EdgeKind {
+  ProgramOrder,
+  Acquire, // ordered by an acquire
+  Release, // ordered by a release
+  Fence, // a fence waits for a buffered store
+  Buffer, // buffered stores drain in order
+  Dependency, // register dependency
+  Control, // `to` is a jump
+  Speculation // like Control, but `from` may run ahead of the jump and is rolled back if it is taken
+}
+
+impl EdgeKind {
+  pub fn name(&self) -> &'static str {
+    match self {
+      EdgeKind::ProgramOrder => "po",
+      EdgeKind::Acquire => "acq",
+      EdgeKind::Release => "rel",
+      EdgeKind::Fence => "fence",
+      EdgeKind::Buffer => "buffer",
+      EdgeKind::Dependency => "dep",
+      EdgeKind::Control => "ctrl",
+      EdgeKind::Speculation => "spec"
+    }
+  }
+
+  pub fn is_blocking(&self) -> bool {
+    *self != EdgeKind::Speculation
+  }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub struct Edge {
+  pub from: usize,
+  pub to: usize,
+  pub kind: EdgeKind
+}
+
+// A set of node ids as a bitset over the ids the graph has handed out. Iteration is in increasing
+// id order, so the candidates come out the same on every run.
+#[derive(Clone, Default)]
+pub struct NodeSet {
+  words: Vec<u64>
+}
+
+impl NodeSet {
+  pub fn new() -> NodeSet {
+    NodeSet { words: Vec::new() }
+  }
+
+  pub fn insert(&mut self, id: usize) {
+    let word = id / 64;
+    if word >= self.words.len() {
+      self.words.resize(word + 1, 0);
+    }
+    self.words[word] |= 1 << (id % 64);
+  }
+
+  pub fn remove(&mut self, id: usize) {
+    if let Some(word) = self.words.get_mut(id / 64) {
+      *word &= !(1 << (id % 64));
+    }
+  }
+
+  pub fn contains(&self, id: usize) -> bool {
+    self.words.get(id / 64).is_some_and(|word| word & (1 << (id % 64)) != 0)
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.words.iter().all(|word| *word == 0)
+  }
+
+  pub fn iter(&self) -> impl Iterator<Item = usize> + '_ {
+    self.words.iter().enumerate().flat_map(|(index, word)| {
+      let mut word = *word;
+      std::iter::from_fn(move || {
+        if word == 0 {
+          return None;
+        }
+        let bit = word.trailing_zeros() as usize;
+        word &= word - 1;
+        Some(index * 64 + bit)
+      })
+    })
+  }
+}
+
+impl Debug for NodeSet {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.debug_set().entries(self.iter()).finish()
+  }
+}
+
+// Cloning a graph shares the nodes, edges and activity of the nodes in chunks, and a copy only copies
+// the chunks it changes or adds to.
+#[derive(Clone)]
 pub struct Graph {
-  label_to_node: HashMap<String, usize>,
-  pub instructions: Vec<Node>,
-  pub rev_edges: Vec<Vec<usize>>,
-  pub active_neighbors: Vec<usize>,
-  pub is_active: Vec<bool>,
+  pub instructions: Chunks<Node>,
+  edges: Chunks<Vec<Edge>>,
+  // Most nodes have only a few nodes waiting for them.
+  rev_edges: Chunks<SmallVec<[Edge; 4]>>,
+  cycles: Vec<Vec<Edge>>,
+  pub active_neighbors: Chunks<usize>,
+  pub is_active: Chunks<bool>,
   pub active_fence_nodes: HashSet<usize>,
   pub execution_stack: Vec<usize>,
-  pub execution_candidates: HashSet<usize>
+  pub execution_candidates: NodeSet,
+  returns: HashMap<usize, Vec<usize>>,
+  // With recycling, executed propagate nodes give their ids to new ones, so a long run that keeps
+  // storing does not keep growing the graph.
+  recycle: bool,
+  free: Vec<usize>,
+  // Whether debug builds check the whole graph after every change rather than the nodes it touched.
+  full_checks: bool
+}
+
+impl Default for Graph {
+  fn default() -> Self {
+    Self::new()
+  }
 }
 
 impl Graph {
   pub fn new() -> Graph {
     Graph {
-      label_to_node: HashMap::new(),
-      instructions: Vec::new(),
-      rev_edges: Vec::new(),
-      active_neighbors: Vec::new(),
-      is_active: Vec::new(),
+      instructions: Chunks::new(),
+      edges: Chunks::new(),
+      rev_edges: Chunks::new(),
+      cycles: Vec::new(),
+      active_neighbors: Chunks::new(),
+      is_active: Chunks::new(),
       active_fence_nodes: HashSet::new(),
       execution_stack: Vec::new(),
-      execution_candidates: HashSet::new()
+      execution_candidates: NodeSet::new(),
+      returns: HashMap::new(),
+      recycle: false,
+      free: Vec::new(),
+      full_checks: cfg!(debug_assertions) && std::env::var_os("ISA_CHECK_GRAPH").is_some()
     }
   }
 
-  pub fn is_label_active(&self, label: String) -> bool {
-    match self.label_to_node.get(&label) {
-      Some(id) => {
-        self.is_node_active(*id)
-      },
-      None => {
-        true
+  // Joins graphs of threads built apart, in order, shifting the ids of each past the nodes of the
+  // ones before it. No segment has executed anything yet.
+  pub fn join(segments: Vec<Graph>) -> Graph {
+    let mut graph = Graph::new();
+    for segment in segments {
+      let offset = graph.instructions.len();
+      let shift = |edge: &Edge| Edge { from: edge.from + offset, to: edge.to + offset, kind: edge.kind };
+      graph.instructions.extend(segment.instructions.iter().cloned().map(|mut node| {
+        node.id += offset;
+        node
+      }));
+      graph.edges.extend(segment.edges.iter().map(|edges| edges.iter().map(shift).collect()));
+      graph.rev_edges.extend(segment.rev_edges.iter().map(|edges| edges.iter().map(shift).collect()));
+      graph.cycles.extend(segment.cycles.iter().map(|cycle| cycle.iter().map(shift).collect()));
+      graph.active_neighbors.extend(segment.active_neighbors.iter().cloned());
+      graph.is_active.extend(segment.is_active.iter().cloned());
+      graph.active_fence_nodes.extend(segment.active_fence_nodes.iter().map(|id| id + offset));
+      for id in segment.execution_candidates.iter() {
+        graph.execution_candidates.insert(id + offset);
       }
     }
+    graph.validate();
+    graph
   }
 
   pub fn is_node_active(&self, id: usize) -> bool {
     self.is_active[id]
   }
 
+  // Ids no longer name the same node for the whole run, so schedules of node ids can only be
+  // replayed on a graph that recycles as well.
+  pub fn set_recycling(&mut self) {
+    self.recycle = true;
+  }
+
   pub fn add_node(&mut self, thread_id: usize, instruction: LabeledInstruction) -> usize {
-    let id = self.instructions.len();
-    if instruction.label.is_some() {
-      self.label_to_node.insert(instruction.label.clone().unwrap(), id);
+    if matches!(instruction.instruction, Instruction::Propagate { .. }) {
+      if let Some(id) = self.free.pop() {
+        self.instructions[id] = Node::new(id, thread_id, instruction);
+        self.active_neighbors[id] = 0;
+        self.is_active[id] = true;
+        self.execution_candidates.insert(id);
+        self.validate_change(id);
+        return id;
+      }
     }
+    let id = self.instructions.len();
     if instruction.is_fence() {
       self.active_fence_nodes.insert(id);
     }
     self.instructions.push(Node::new(id, thread_id, instruction));
-    self.rev_edges.push(Vec::new());
+    self.edges.push(Vec::new());
+    self.rev_edges.push(SmallVec::new());
     self.active_neighbors.push(0);
     self.is_active.push(true);
     self.execution_candidates.insert(id);
+    self.validate_change(id);
     id
   }
 
-  pub fn add_edge(&mut self, from: usize, to: usize) {
-    if self.is_active[to] {
+  pub fn add_edge(&mut self, from: usize, to: usize, kind: EdgeKind) {
+    if kind.is_blocking() && self.is_active[to] {
       self.active_neighbors[from] += 1;
+      self.execution_candidates.remove(from);
     }
-    self.rev_edges[to].push(from);
-    if self.execution_candidates.contains(&from) {
-      self.execution_candidates.remove(&from);
+    let edge = Edge { from, to, kind };
+    // Only a node that something active waits for can close a cycle, which a new node cannot.
+    let waited_for = from == to || self.rev_edges[from].iter().any(|edge| edge.kind.is_blocking() && self.is_active[edge.from]);
+    if kind.is_blocking() && self.is_active[from] && self.is_active[to] && waited_for {
+      if let Some(mut cycle) = self.path(to, from) {
+        cycle.insert(0, edge);
+        self.cycles.push(cycle);
+      }
     }
+    self.edges[from].push(edge);
+    self.rev_edges[to].push(edge);
+    self.validate_change(to);
+  }
+
+  // The blocking edges along which the active node `from` waits for `to`, if it does.
+  fn path(&self, from: usize, to: usize) -> Option<Vec<Edge>> {
+    let mut parent: HashMap<usize, Edge> = HashMap::new();
+    let mut stack = vec![from];
+    while let Some(id) = stack.pop() {
+      if id == to {
+        let mut path = Vec::new();
+        let mut current = to;
+        while current != from {
+          let edge = parent[&current];
+          path.push(edge);
+          current = edge.from;
+        }
+        path.reverse();
+        return Some(path);
+      }
+      for edge in self.edges[id].iter().filter(|edge| edge.kind.is_blocking() && self.is_active[edge.to]) {
+        if edge.to != from && !parent.contains_key(&edge.to) {
+          parent.insert(edge.to, *edge);
+          stack.push(edge.to);
+        }
+      }
+    }
+    None
+  }
+
+  // Orderings that could never be satisfied: each cycle starts with the edge that closed it.
+  pub fn cycles(&self) -> &[Vec<Edge>] {
+    &self.cycles
+  }
+
+  // A cycle among nodes that are all still waiting to execute.
+  pub fn active_cycle(&self) -> Option<&[Edge]> {
+    self.cycles.iter().find(|cycle| cycle.iter().all(|edge| self.is_active[edge.from])).map(|cycle| cycle.as_slice())
+  }
+
+  pub fn edges(&self) -> impl Iterator<Item = &Edge> {
+    self.edges.iter().flatten()
+  }
+
+  // The edges of the nodes `id` waits for.
+  pub fn edges_from(&self, id: usize) -> &[Edge] {
+    &self.edges[id]
+  }
+
+  // The edges of the nodes waiting for `id`.
+  pub fn edges_to(&self, id: usize) -> &[Edge] {
+    &self.rev_edges[id]
+  }
+
+  pub fn is_speculative(&self, id: usize) -> bool {
+    self.edges[id].iter().any(|edge| edge.kind == EdgeKind::Speculation && self.is_active[edge.to])
+  }
+
+  // The nodes that ran ahead of the branch, most recently executed first.
+  pub fn speculated(&self, branch: usize) -> Vec<usize> {
+    self.execution_stack.iter().rev()
+      .filter(|id| self.rev_edges[branch].iter().any(|edge| edge.kind == EdgeKind::Speculation && edge.from == **id))
+      .cloned()
+      .collect()
   }
 
   pub fn remove_node(&mut self, id: usize) {
@@ -94,32 +322,239 @@ impl Graph {
     }
     self.execution_stack.push(id);
     self.is_active[id] = false;
-    self.execution_candidates.remove(&id);
-    for from in self.rev_edges[id].iter() {
-      if self.is_active[*from] {
-        self.active_neighbors[*from] -= 1;
-        if self.active_neighbors[*from] == 0 {
-          self.execution_candidates.insert(*from);
+    self.execution_candidates.remove(id);
+    for edge in self.rev_edges[id].iter().filter(|edge| edge.kind.is_blocking()) {
+      if self.is_active[edge.from] {
+        self.active_neighbors[edge.from] -= 1;
+        if self.active_neighbors[edge.from] == 0 {
+          self.execution_candidates.insert(edge.from);
         }
       }
     }
+    self.validate_change(id);
+    if self.recycle && matches!(self.instructions[id].instruction.instruction, Instruction::Propagate { .. }) {
+      self.retire(id);
+      self.validate_change(id);
+    }
+  }
+
+  // Frees an executed propagate node: nothing jumps back to it, so it leaves the execution stack,
+  // and it drops out of the edges and cycles it was part of.
+  fn retire(&mut self, id: usize) {
+    self.execution_stack.pop();
+    for edge in std::mem::take(&mut self.edges[id]) {
+      self.rev_edges[edge.to].retain(|other| other.from != id);
+    }
+    for edge in std::mem::take(&mut self.rev_edges[id]) {
+      self.edges[edge.from].retain(|other| other.to != id);
+    }
+    self.cycles.retain(|cycle| cycle.iter().all(|edge| edge.from != id && edge.to != id));
+    self.free.push(id);
   }
 
-  pub fn restore_node(&mut self) -> Option<String> {
-    let id = self.execution_stack.pop().unwrap();
+  // `nodes` are the jumping thread's instructions in program order. A backward jump makes the ones
+  // from the target on that have run wait to run again, a forward jump skips the ones before the
+  // target that have not. Targets outside the thread are ignored. `call` pushes the instruction
+  // after it on the thread's return stack and `ret` pops it, ending the thread if the stack is empty.
+  pub fn jump(&mut self, nodes: &[usize], branch: usize) {
+    let pc = nodes.iter().position(|id| *id == branch).unwrap();
+    let thread_id = self.instructions[branch].thread_id;
+    let resolve = |target: &Target| target.resolve(nodes.iter().map(|id| &self.instructions[*id].instruction), pc);
+    let target = match &self.instructions[branch].instruction.instruction {
+      Instruction::Cond { r: _, target } => resolve(target),
+      Instruction::Call { target } => {
+        let target = resolve(target);
+        if target.is_some() {
+          self.returns.entry(thread_id).or_default().push(pc + 1);
+        }
+        target
+      }
+      Instruction::Ret => Some(self.returns.get_mut(&thread_id).and_then(|returns| returns.pop()).unwrap_or(nodes.len())),
+      _ => None
+    };
+    let Some(target) = target else {
+      return;
+    };
+    if target <= pc {
+      self.reactivate(&nodes[target..]);
+    } else {
+      for id in nodes[pc + 1..target].iter() {
+        self.remove_node(*id);
+      }
+    }
+  }
+
+  // Makes the instructions after `from` in `nodes`, the thread's instructions in program order, wait
+  // to run again, as when an aborted transaction goes back to its start.
+  pub fn restart(&mut self, nodes: &[usize], from: usize) {
+    let pc = nodes.iter().position(|id| *id == from).unwrap();
+    self.reactivate(&nodes[pc + 1..]);
+  }
+
+  fn reactivate(&mut self, nodes: &[usize]) {
+    for id in nodes.iter().rev() {
+      if !self.is_active[*id] {
+        self.execution_stack.retain(|other| other != id);
+        self.activate(*id);
+        self.validate_change(*id);
+      }
+    }
+  }
+
+  pub fn rollback_node(&mut self, id: usize) {
+    self.execution_stack.retain(|other| *other != id);
+    self.activate(id);
+    self.validate_change(id);
+  }
+
+  fn activate(&mut self, id: usize) {
     self.is_active[id] = true;
+    self.active_neighbors[id] = self.edges[id].iter().filter(|edge| edge.kind.is_blocking() && self.is_active[edge.to]).count();
     if self.instructions[id].instruction.is_fence() {
       self.active_fence_nodes.insert(id);
     }
-    for from in self.rev_edges[id].iter() {
-      if self.is_active[*from] {
-        self.active_neighbors[*from] += 1;
-        if self.active_neighbors[*from] == 1 {
-          self.execution_candidates.remove(&from);
+    for edge in self.rev_edges[id].iter().filter(|edge| edge.kind.is_blocking()) {
+      if self.is_active[edge.from] {
+        self.active_neighbors[edge.from] += 1;
+        if self.active_neighbors[edge.from] == 1 {
+          self.execution_candidates.remove(edge.from);
         }
       }
     }
-    self.execution_candidates.insert(id);
-    self.instructions[id].instruction.label.clone()
+    if self.active_neighbors[id] == 0 {
+      self.execution_candidates.insert(id);
+    }
+  }
+
+  // Checks that the bookkeeping agrees with the edges and the set of active nodes, returning the
+  // first violation found.
+  pub fn check_invariants(&self) -> Result<(), String> {
+    let describe = |id: usize| format!("node {} ({:?})", id, self.instructions[id]);
+    let n = self.instructions.len();
+    if [self.edges.len(), self.rev_edges.len(), self.active_neighbors.len(), self.is_active.len()].iter().any(|len| *len != n) {
+      return Err(format!("per-node vectors do not all have {} entries", n));
+    }
+    // Every edge is kept by the node it leaves and by the node it enters, so the reverse edges of
+    // each node are exactly the edges entering it.
+    let mut entering: Vec<Vec<Edge>> = vec![Vec::new(); n];
+    for (id, edges) in self.edges.iter().enumerate() {
+      if let Some(edge) = edges.iter().find(|edge| edge.from != id || edge.to >= n) {
+        return Err(format!("edge {} -> {} ({}) is kept by {}", edge.from, edge.to, edge.kind.name(), describe(id)));
+      }
+      for edge in edges {
+        entering[edge.to].push(*edge);
+      }
+    }
+    for (id, mut expected) in entering.into_iter().enumerate() {
+      let mut reverse = self.rev_edges[id].to_vec();
+      reverse.sort();
+      expected.sort();
+      if reverse != expected {
+        return Err(format!("{} has reverse edges {:?}, but the edges entering it are {:?}", describe(id), reverse, expected));
+      }
+    }
+    for id in 0..n {
+      self.check_node(id)?;
+    }
+    if let Some(id) = self.execution_candidates.iter().find(|id| *id >= n) {
+      return Err(format!("node {} is an execution candidate but not in the graph", id));
+    }
+    let mut free = vec![false; n];
+    for id in self.free.iter() {
+      if self.is_active[*id] || !self.edges[*id].is_empty() || !self.rev_edges[*id].is_empty() {
+        return Err(format!("{} is free but active or has edges", describe(*id)));
+      }
+      free[*id] = true;
+    }
+    let mut executed = vec![false; n];
+    for id in self.execution_stack.iter() {
+      if self.is_active[*id] {
+        return Err(format!("{} is on the execution stack but active", describe(*id)));
+      }
+      if executed[*id] {
+        return Err(format!("{} is on the execution stack twice", describe(*id)));
+      }
+      if free[*id] {
+        return Err(format!("{} is free but on the execution stack", describe(*id)));
+      }
+      executed[*id] = true;
+    }
+    if let Some(id) = (0..n).find(|id| !self.is_active[*id] && !executed[*id] && !free[*id]) {
+      return Err(format!("{} is inactive but not on the execution stack", describe(id)));
+    }
+    Ok(())
+  }
+
+  // The invariants of a single node: its edges are kept at both ends, and its count of active nodes
+  // it waits for, its candidacy and its fence entry agree with its edges and whether it is active.
+  fn check_node(&self, id: usize) -> Result<(), String> {
+    let describe = |id: usize| format!("node {} ({:?})", id, self.instructions[id]);
+    if let Some(edge) = self.edges[id].iter().find(|edge| edge.from != id || !self.rev_edges[edge.to].contains(edge)) {
+      return Err(format!("edge {} -> {} ({}) of {} is not among the reverse edges of node {}", edge.from, edge.to, edge.kind.name(), describe(id), edge.to));
+    }
+    if let Some(edge) = self.rev_edges[id].iter().find(|edge| edge.to != id || !self.edges[edge.from].contains(edge)) {
+      return Err(format!("reverse edge {} -> {} ({}) of {} is not among the edges of node {}", edge.from, edge.to, edge.kind.name(), describe(id), edge.from));
+    }
+    if !self.is_active[id] {
+      if self.execution_candidates.contains(id) {
+        return Err(format!("{} is an execution candidate but not active", describe(id)));
+      }
+      if self.active_fence_nodes.contains(&id) {
+        return Err(format!("{} is in active_fence_nodes but not active", describe(id)));
+      }
+      return Ok(());
+    }
+    let waiting = self.edges[id].iter().filter(|edge| edge.kind.is_blocking() && self.is_active[edge.to]).count();
+    if self.active_neighbors[id] != waiting {
+      return Err(format!("{} waits for {} active nodes, but active_neighbors is {}", describe(id), waiting, self.active_neighbors[id]));
+    }
+    if self.execution_candidates.contains(id) != (waiting == 0) {
+      return Err(format!("{} waits for {} active nodes, but is {}an execution candidate", describe(id), waiting, if waiting == 0 { "not " } else { "" }));
+    }
+    if self.active_fence_nodes.contains(&id) != self.instructions[id].instruction.is_fence() {
+      return Err(format!("{} is {}in active_fence_nodes", describe(id), if self.instructions[id].instruction.is_fence() { "not " } else { "" }));
+    }
+    Ok(())
+  }
+
+  // Debug builds check the invariants once the graph is built.
+  pub(crate) fn validate(&self) {
+    if cfg!(debug_assertions) {
+      if let Err(message) = self.check_invariants() {
+        panic!("graph invariant violated: {}", message);
+      }
+    }
+  }
+
+  // After every change debug builds check the node it was about and the nodes waiting for it. With
+  // `ISA_CHECK_GRAPH` set when the graph was made, they check the whole graph instead, which takes
+  // time quadratic in its size.
+  fn validate_change(&self, id: usize) {
+    if !cfg!(debug_assertions) {
+      return;
+    }
+    if self.full_checks {
+      self.validate();
+      return;
+    }
+    let mut nodes = std::iter::once(id).chain(self.rev_edges[id].iter().map(|edge| edge.from));
+    if let Err(message) = nodes.try_for_each(|id| self.check_node(id)) {
+      panic!("graph invariant violated: {}", message);
+    }
+  }
+
+  pub fn to_dot(&self, thread_names: &[String]) -> String {
+    let mut dot = String::from("digraph program {\n");
+    for node in self.instructions.iter() {
+      let label = format!("{}: {:?}", thread_names[node.thread_id], node).replace('"', "\\\"");
+      let style = if self.is_active[node.id] { "solid" } else { "dashed" };
+      dot.push_str(&format!("  n{} [label=\"{}\", style={}];\n", node.id, label, style));
+    }
+    for edge in self.edges() {
+      let style = if edge.kind.is_blocking() { "solid" } else { "dotted" };
+      dot.push_str(&format!("  n{} -> n{} [label=\"{}\", style={}];\n", edge.to, edge.from, edge.kind.name(), style));
+    }
+    dot.push_str("}\n");
+    dot
   }
 }
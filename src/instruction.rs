@@ -1,12 +1,14 @@
 use std::fmt::Debug;
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, PartialEq, Eq)]
 pub enum Mode {
   SeqCst,
   Rel,
   Acq,
   RelAcq,
-  Rlx
+  Rlx,
+  // Plain accesses, which order nothing and whose races are undefined behavior.
+  NA
 }
 
 impl Debug for Mode {
@@ -16,12 +18,160 @@ impl Debug for Mode {
       Mode::Rel => write!(f, "REL"),
       Mode::Acq => write!(f, "ACQ"),
       Mode::RelAcq => write!(f, "REL_ACQ"),
-      Mode::Rlx => write!(f, "RLX")
+      Mode::Rlx => write!(f, "RLX"),
+      Mode::NA => write!(f, "NA")
     }
   }
 
 }
 
+impl Mode {
+  pub fn acquires(&self) -> bool {
+    matches!(self, Mode::Acq | Mode::RelAcq | Mode::SeqCst)
+  }
+
+  pub fn releases(&self) -> bool {
+    matches!(self, Mode::Rel | Mode::RelAcq | Mode::SeqCst)
+  }
+
+  // The weakest mode ordering at least as much as both.
+  pub fn join(self, other: Mode) -> Mode {
+    match (self, other) {
+      (Mode::SeqCst, _) | (_, Mode::SeqCst) => Mode::SeqCst,
+      _ if self.acquires() || other.acquires() => {
+        if self.releases() || other.releases() { Mode::RelAcq } else { Mode::Acq }
+      }
+      _ if self.releases() || other.releases() => Mode::Rel,
+      _ => Mode::Rlx
+    }
+  }
+
+  // The failure ordering C11 derives for a `compare_exchange` given only the success ordering: a
+  // failed exchange does not write, so it drops the release half.
+  pub fn failure(self) -> Mode {
+    match self {
+      Mode::Rel => Mode::Rlx,
+      Mode::RelAcq => Mode::Acq,
+      mode => mode
+    }
+  }
+}
+
+// The threads an atomic access or fence synchronises with, from an `@wg`, `@dev` or `@sys` suffix:
+// its workgroup, its device or every thread.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Scope {
+  Workgroup,
+  Device,
+  System
+}
+
+impl Debug for Scope {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      Scope::Workgroup => write!(f, "wg"),
+      Scope::Device => write!(f, "dev"),
+      Scope::System => write!(f, "sys")
+    }
+  }
+}
+
+// The accesses one side of a hardware fence orders: loads, stores or both.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Accesses {
+  Loads,
+  Stores,
+  All
+}
+
+impl Debug for Accesses {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      Accesses::Loads => write!(f, "ld"),
+      Accesses::Stores => write!(f, "st"),
+      Accesses::All => write!(f, "ldst")
+    }
+  }
+}
+
+impl Accesses {
+  pub fn loads(&self) -> bool {
+    matches!(self, Accesses::Loads | Accesses::All)
+  }
+
+  pub fn stores(&self) -> bool {
+    matches!(self, Accesses::Stores | Accesses::All)
+  }
+
+  // The weakest fence mode ordering at least these accesses before the `after` ones, for the
+  // models without hardware fences: only SEQ_CST orders a store before a later load.
+  pub fn fence_mode(self, after: Accesses) -> Mode {
+    match (self, after) {
+      _ if self.stores() && after.loads() => Mode::SeqCst,
+      (Accesses::Loads, Accesses::Loads) => Mode::Acq,
+      (Accesses::Stores, Accesses::Stores) => Mode::Rel,
+      _ => Mode::RelAcq
+    }
+  }
+}
+
+// The update a fetch-and-op applies to the value it reads.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum FetchOp {
+  Add,
+  Sub,
+  Max,
+  Min
+}
+
+impl FetchOp {
+  pub fn apply(self, value: i32, operand: i32) -> Option<i32> {
+    match self {
+      FetchOp::Add => value.checked_add(operand),
+      FetchOp::Sub => value.checked_sub(operand),
+      FetchOp::Max => Some(value.max(operand)),
+      FetchOp::Min => Some(value.min(operand))
+    }
+  }
+
+  pub fn mnemonic(self) -> &'static str {
+    match self {
+      FetchOp::Add => "fai",
+      FetchOp::Sub => "fetch_sub",
+      FetchOp::Max => "fetch_max",
+      FetchOp::Min => "fetch_min"
+    }
+  }
+}
+
+// Where `if r goto` jumps: a label in the same thread or an offset from the jump, `+1` being the
+// next instruction.
+#[derive(Clone, PartialEq, Eq)]
+pub enum Target {
+  Label(String),
+  Relative(i32)
+}
+
+impl Debug for Target {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      Target::Label(label) => write!(f, "{}", label),
+      Target::Relative(offset) => write!(f, "{:+}", offset)
+    }
+  }
+}
+
+impl Target {
+  // The index in the thread's instructions the jump at `pc` goes to; one past the last ends the thread.
+  pub fn resolve<'a>(&self, instructions: impl ExactSizeIterator<Item = &'a LabeledInstruction>, pc: usize) -> Option<usize> {
+    let len = instructions.len();
+    match self {
+      Target::Label(label) => instructions.into_iter().position(|instruction| instruction.label.as_ref() == Some(label)),
+      Target::Relative(offset) => pc.checked_add_signed(*offset as isize).filter(|target| *target <= len)
+    }
+  }
+}
+
 #[derive(Clone)]
 pub enum Instruction {
   Const { r: String, value: i32,  },
@@ -29,12 +179,45 @@ pub enum Instruction {
   ArithMinus { r1: String, r2: String, r3: String },
   ArithMul { r1: String, r2: String, r3: String },
   ArithDiv { r1: String, r2: String, r3: String },
-  Cond { r: String, label: String },
+  // `r1 = r2 ? r3 : r4`: r3 if r2 is not 0 and r4 otherwise, a data dependency on r2 rather than a jump.
+  Select { r1: String, r2: String, r3: String, r4: String },
+  Cond { r: String, target: Target },
+  Call { target: Target },
+  Ret,
+  Push { r: String },
+  Pop { r: String },
   Load { mode: Mode, address: String, r: String },
   Store { mode: Mode, address: String, r: String },
-  Cas { mode: Mode, address: String, to: String, exp: String, des: String },
-  Fai { mode: Mode, address: String, to: String, inc: String },
-  Fence { mode: Mode },
+  // `flag`, if any, is set to 1 when the swap succeeds and to 0 when it fails.
+  Cas { mode: Mode, failure: Mode, address: String, to: String, exp: String, des: String, flag: Option<String> },
+  Fai { op: FetchOp, mode: Mode, address: String, to: String, inc: String },
+  // `order`, if any, makes a hardware fence such as `fence st,ld`, which only orders the accesses
+  // before it in the first class with those after it in the second under TSO and PSO. Other models
+  // treat it as a fence of `mode`, the weakest mode that orders at least as much.
+  Fence { mode: Mode, order: Option<(Accesses, Accesses)> },
+  Lock { address: String },
+  Unlock { address: String },
+  // Semaphores and barriers count in memory at their address; see `memory_model::blocked`.
+  SemWait { address: String },
+  SemPost { address: String },
+  Barrier { address: String, count: i32 },
+  // Futex-style sleeping on an address while it holds the value of `r`, and waking sleepers on it.
+  Wait { address: String, r: String },
+  Wake { address: String, count: i32 },
+  // Load-linked reserves the address; store-conditional stores only while the reservation lasts
+  // and sets `flag` to whether it did.
+  LoadLinked { address: String, r: String },
+  StoreConditional { address: String, r: String, flag: String },
+  // Clears the thread's exclusive monitor, so its next `sc` fails until another `ll`.
+  ClearExclusive,
+  // Writes the address back to persistent memory once the thread's next fence ordering stores runs.
+  Flush { address: String },
+  // A hardware transaction: `txbegin` sets `r` to 0 and starts buffering the thread's accesses, and
+  // `txcommit` makes its stores visible at once. An abort discards them and goes back to just after
+  // the `txbegin` with the thread's registers as they were, `r` then holding the abort code.
+  TxBegin { r: String },
+  TxCommit,
+  TxAbort,
   Propagate { thread_id: usize, address: i32, value: i32 }
 }
 
@@ -46,28 +229,162 @@ impl Debug for Instruction {
       Instruction::ArithMinus { r1, r2, r3 } => write!(f, "{} = {} - {}", r1, r2, r3),
       Instruction::ArithMul { r1, r2, r3 } => write!(f, "{} = {} * {}", r1, r2, r3),
       Instruction::ArithDiv { r1, r2, r3 } => write!(f, "{} = {} / {}", r1, r2, r3),
-      Instruction::Cond { r, label } => write!(f, "if {} goto {}", r, label),
+      Instruction::Select { r1, r2, r3, r4 } => write!(f, "{} = {} ? {} : {}", r1, r2, r3, r4),
+      Instruction::Cond { r, target } => write!(f, "if {} goto {:?}", r, target),
+      Instruction::Call { target } => write!(f, "call {:?}", target),
+      Instruction::Ret => write!(f, "ret"),
+      Instruction::Push { r } => write!(f, "push {}", r),
+      Instruction::Pop { r } => write!(f, "pop {}", r),
       Instruction::Load { mode, address, r } => write!(f, "load {:?} #{} {}", mode, address, r),
       Instruction::Store { mode, address, r } => write!(f, "store {:?} #{} {}", mode, address, r),
-      Instruction::Cas { mode, address, to, exp, des } => write!(f, "{} := cas {:?} #{} {} {}", to, mode, address, exp, des),
-      Instruction::Fai { mode, address, to, inc } => write!(f, "{} := fai {:?} #{} {}", to, mode, address, inc),
-      Instruction::Fence { mode } => write!(f, "fence {:?}", mode),
+      Instruction::Cas { mode, failure, address, to, exp, des, flag } => {
+        match flag {
+          Some(flag) => write!(f, "{}, {} := cas {:?} ", to, flag, mode)?,
+          None => write!(f, "{} := cas {:?} ", to, mode)?
+        }
+        if *failure != mode.failure() {
+          write!(f, "{:?} ", failure)?;
+        }
+        write!(f, "#{} {} {}", address, exp, des)
+      }
+      Instruction::Fai { op, mode, address, to, inc } => write!(f, "{} := {} {:?} #{} {}", to, op.mnemonic(), mode, address, inc),
+
+      Instruction::Fence { mode: _, order: Some((Accesses::All, Accesses::All)) } => write!(f, "fence full"),
+      Instruction::Fence { mode: _, order: Some((before, after)) } => write!(f, "fence {:?},{:?}", before, after),
+      Instruction::Fence { mode, order: None } => write!(f, "fence {:?}", mode),
+      Instruction::Lock { address } => write!(f, "lock #{}", address),
+      Instruction::Unlock { address } => write!(f, "unlock #{}", address),
+      Instruction::SemWait { address } => write!(f, "sem_wait #{}", address),
+      Instruction::SemPost { address } => write!(f, "sem_post #{}", address),
+      Instruction::Barrier { address, count } => write!(f, "barrier #{} {}", address, count),
+      Instruction::Wait { address, r } => write!(f, "wait #{} {}", address, r),
+      Instruction::Wake { address, count } => write!(f, "wake #{} {}", address, count),
+      Instruction::LoadLinked { address, r } => write!(f, "ll #{} {}", address, r),
+      Instruction::StoreConditional { address, r, flag } => write!(f, "sc #{} {} -> {}", address, r, flag),
+      Instruction::ClearExclusive => write!(f, "clrex"),
+      Instruction::Flush { address } => write!(f, "flush #{}", address),
+      Instruction::TxBegin { r } => write!(f, "txbegin {}", r),
+      Instruction::TxCommit => write!(f, "txcommit"),
+      Instruction::TxAbort => write!(f, "txabort"),
       Instruction::Propagate { thread_id, address, value } => write!(f, "propagate with thread_id = {}, address = {} and value = {}", thread_id, address, value)
     }
   }
 }
 
+impl Instruction {
+  pub fn read_registers(&self) -> Vec<&String> {
+    match self {
+      Instruction::Const { r: _, value: _ } => vec![],
+      Instruction::ArithPlus { r1: _, r2, r3 } => vec![r2, r3],
+      Instruction::ArithMinus { r1: _, r2, r3 } => vec![r2, r3],
+      Instruction::ArithMul { r1: _, r2, r3 } => vec![r2, r3],
+      Instruction::ArithDiv { r1: _, r2, r3 } => vec![r2, r3],
+      Instruction::Select { r1: _, r2, r3, r4 } => vec![r2, r3, r4],
+      Instruction::Cond { r, target: _ } => vec![r],
+      Instruction::Call { target: _ } => vec![],
+      Instruction::Ret => vec![],
+      Instruction::Push { r } => vec![r],
+      Instruction::Pop { r: _ } => vec![],
+      Instruction::Load { mode: _, address, r: _ } => vec![address],
+      Instruction::Store { mode: _, address, r } => vec![address, r],
+      Instruction::Cas { mode: _, failure: _, address, to: _, exp, des, flag: _ } => vec![address, exp, des],
+      Instruction::Fai { op: _, mode: _, address, to: _, inc } => vec![address, inc],
+      Instruction::Fence { mode: _, order: _ } => vec![],
+      Instruction::Lock { address } => vec![address],
+      Instruction::Unlock { address } => vec![address],
+      Instruction::SemWait { address } => vec![address],
+      Instruction::SemPost { address } => vec![address],
+      Instruction::Barrier { address, count: _ } => vec![address],
+      Instruction::Wait { address, r } => vec![address, r],
+      Instruction::Wake { address, count: _ } => vec![address],
+      Instruction::LoadLinked { address, r: _ } => vec![address],
+      Instruction::StoreConditional { address, r, flag: _ } => vec![address, r],
+      Instruction::ClearExclusive => vec![],
+      Instruction::Flush { address } => vec![address],
+      Instruction::TxBegin { r: _ } | Instruction::TxCommit | Instruction::TxAbort => vec![],
+      Instruction::Propagate { thread_id: _, address: _, value: _ } => vec![]
+    }
+  }
+
+  // Instructions that may continue somewhere other than the next instruction.
+  pub fn is_jump(&self) -> bool {
+    matches!(self, Instruction::Cond { r: _, target: _ } | Instruction::Call { target: _ } | Instruction::Ret)
+  }
+
+  // Semaphores, barriers and futex operations, which block on a count or a wake rather than a lock.
+  pub fn is_blocking(&self) -> bool {
+    matches!(self, Instruction::SemWait { address: _ } | Instruction::SemPost { address: _ } | Instruction::Barrier { address: _, count: _ }
+      | Instruction::Wait { address: _, r: _ } | Instruction::Wake { address: _, count: _ })
+  }
+
+  // Load-linked, store-conditional and `clrex`, whose exclusive monitor only the interpreter tracks.
+  pub fn is_exclusive(&self) -> bool {
+    matches!(self, Instruction::LoadLinked { address: _, r: _ } | Instruction::StoreConditional { address: _, r: _, flag: _ } | Instruction::ClearExclusive)
+  }
+
+  // `txbegin`, `txcommit` and `txabort`, which only the interpreter runs.
+  pub fn is_transactional(&self) -> bool {
+    matches!(self, Instruction::TxBegin { r: _ } | Instruction::TxCommit | Instruction::TxAbort)
+  }
+
+  // Fences ordering the thread's earlier stores, which wait for its flushes to persist.
+  pub fn completes_flushes(&self) -> bool {
+    match self {
+      Instruction::Fence { mode: _, order: Some((before, _)) } => before.stores(),
+      Instruction::Fence { mode, order: None } => mode.releases(),
+      _ => false
+    }
+  }
+
+  // Instructions using the thread's private stack, which keep their order in every model.
+  pub fn uses_stack(&self) -> bool {
+    matches!(self, Instruction::Push { r: _ } | Instruction::Pop { r: _ })
+  }
+
+  pub fn write_registers(&self) -> Vec<&String> {
+    match self {
+      Instruction::Const { r, value: _ } => vec![r],
+      Instruction::ArithPlus { r1, r2: _, r3: _ } => vec![r1],
+      Instruction::ArithMinus { r1, r2: _, r3: _ } => vec![r1],
+      Instruction::ArithMul { r1, r2: _, r3: _ } => vec![r1],
+      Instruction::ArithDiv { r1, r2: _, r3: _ } => vec![r1],
+      Instruction::Select { r1, r2: _, r3: _, r4: _ } => vec![r1],
+      Instruction::Load { mode: _, address: _, r } => vec![r],
+      Instruction::Cas { mode: _, failure: _, address: _, to, exp: _, des: _, flag } => std::iter::once(to).chain(flag).collect(),
+      Instruction::Fai { op: _, mode: _, address: _, to, inc: _ } => vec![to],
+      Instruction::LoadLinked { address: _, r } => vec![r],
+      Instruction::StoreConditional { address: _, r: _, flag } => vec![flag],
+      Instruction::Pop { r } => vec![r],
+      Instruction::TxBegin { r } => vec![r],
+      _ => vec![]
+    }
+  }
+}
+
 #[derive(Clone)]
 pub struct LabeledInstruction {
   pub label: Option<String>,
-  pub instruction: Instruction
+  pub instruction: Instruction,
+  pub line: Option<usize>,
+  // Only set on the accesses and fences of scoped programs; no scope means `sys`.
+  pub scope: Option<Scope>,
+  // Whether the modes were written as Java's `VOLATILE` and `PLAIN`, which print back that way.
+  pub java: bool
 }
 
 impl Debug for LabeledInstruction {
   fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    let mut instruction = format!("{:?}", self.instruction);
+    if self.java {
+      instruction = instruction.replace("SEQ_CST", "VOLATILE").replace("RLX", "PLAIN");
+    }
     match &self.label {
-      Some(label) => write!(f, "{}: {:?}", label, self.instruction),
-      None => write!(f, "{:?}", self.instruction)
+      Some(label) => write!(f, "{}: {}", label, instruction)?,
+      None => write!(f, "{}", instruction)?
+    }
+    match self.scope {
+      Some(scope) => write!(f, " @{:?}", scope),
+      None => Ok(())
     }
   }
 
@@ -81,20 +398,39 @@ impl LabeledInstruction {
       Instruction::ArithMinus { r1: _, r2: _, r3: _ } => None,
       Instruction::ArithMul { r1: _, r2: _, r3: _ } => None,
       Instruction::ArithDiv { r1: _, r2: _, r3: _ } => None,
-      Instruction::Cond { r: _, label: _ } => None,
+      Instruction::Select { r1: _, r2: _, r3: _, r4: _ } => None,
+      Instruction::Cond { r: _, target: _ } => None,
+      Instruction::Call { target: _ } => None,
+      Instruction::Ret => None,
+      Instruction::Push { r: _ } => None,
+      Instruction::Pop { r: _ } => None,
       Instruction::Load { mode, address: _, r: _ } => Some(mode),
       Instruction::Store { mode, address: _, r: _ } => Some(mode),
-      Instruction::Cas { mode, address: _, to: _, exp: _, des: _ } => Some(mode),
-      Instruction::Fai { mode, address: _, to: _, inc: _ } => Some(mode),
-      Instruction::Fence { mode } => Some(mode),
+      Instruction::Cas { mode, failure, address: _, to: _, exp: _, des: _, flag: _ } => Some(mode.join(failure)),
+      Instruction::Fai { op: _, mode, address: _, to: _, inc: _ } => Some(mode),
+      Instruction::Fence { mode, order: _ } => Some(mode),
+      Instruction::Lock { address: _ } => Some(Mode::RelAcq),
+      Instruction::Unlock { address: _ } => Some(Mode::RelAcq),
+      Instruction::SemWait { address: _ } => Some(Mode::Acq),
+      Instruction::SemPost { address: _ } => Some(Mode::Rel),
+      Instruction::Barrier { address: _, count: _ } => Some(Mode::RelAcq),
+      Instruction::Wait { address: _, r: _ } => Some(Mode::Acq),
+      Instruction::Wake { address: _, count: _ } => Some(Mode::Rel),
+      Instruction::LoadLinked { address: _, r: _ } => Some(Mode::Rlx),
+      Instruction::StoreConditional { address: _, r: _, flag: _ } => Some(Mode::Rlx),
+      Instruction::ClearExclusive => None,
+      Instruction::Flush { address: _ } => None,
+      Instruction::TxBegin { r: _ } | Instruction::TxCommit | Instruction::TxAbort => Some(Mode::RelAcq),
       Instruction::Propagate { thread_id: _, address: _, value: _ } => None
     }
   }
 
+  // Semaphores and barriers wait for buffered stores like locks, so their counts are updated atomically in memory.
+  // A flush waits for its thread's buffered stores, so it flushes the values they wrote, and so do
+  // `txbegin` and `txcommit`, so a transaction starts and commits with an empty buffer.
   pub fn is_fence(&self) -> bool {
-    match self.instruction {
-      Instruction::Fence { mode: _ } => true,
-      _ => false
-    }
+    matches!(self.instruction, Instruction::Fence { mode: _, order: _ } | Instruction::Lock { address: _ } | Instruction::Unlock { address: _ }
+      | Instruction::SemWait { address: _ } | Instruction::SemPost { address: _ } | Instruction::Barrier { address: _, count: _ }
+      | Instruction::Flush { address: _ } | Instruction::TxBegin { r: _ } | Instruction::TxCommit)
   }
 }
\ No newline at end of file
@@ -0,0 +1,180 @@
+use std::{iter::Peekable, str::Chars};
+
+use crate::memory_model::MemoryModel;
+
+// A parsed JSON value; numbers are kept as integers since every number this crate writes is one.
+#[derive(Clone, PartialEq, Debug)]
+pub enum Value {
+  Null,
+  Bool(bool),
+  Number(i64),
+  String(String),
+  Array(Vec<Value>),
+  Object(Vec<(String, Value)>)
+}
+
+impl Value {
+  pub fn get(&self, key: &str) -> Option<&Value> {
+    match self {
+      Value::Object(fields) => fields.iter().find(|(name, _)| name == key).map(|(_, value)| value),
+      _ => None
+    }
+  }
+}
+
+pub fn string(value: &str) -> String {
+  let mut result = String::from("\"");
+  for c in value.chars() {
+    match c {
+      '"' => result.push_str("\\\""),
+      '\\' => result.push_str("\\\\"),
+      '\n' => result.push_str("\\n"),
+      '\t' => result.push_str("\\t"),
+      c if (c as u32) < 0x20 => result.push_str(&format!("\\u{:04x}", c as u32)),
+      c => result.push(c)
+    }
+  }
+  result.push('"');
+  result
+}
+
+pub fn array(items: Vec<String>) -> String {
+  format!("[{}]", items.join(", "))
+}
+
+pub fn object(fields: Vec<(String, String)>) -> String {
+  let fields: Vec<String> = fields.into_iter().map(|(key, value)| format!("{}: {}", string(&key), value)).collect();
+  format!("{{{}}}", fields.join(", "))
+}
+
+pub fn model_state(model: &dyn MemoryModel) -> String {
+  let mut registers = Vec::new();
+  let mut buffers = Vec::new();
+  for thread_id in 0..model.number_of_threads() {
+    let mut thread_registers: Vec<(&String, &i32)> = model.registers(thread_id).iter().collect();
+    thread_registers.sort();
+    registers.push(object(thread_registers.into_iter().map(|(r, value)| (r.clone(), value.to_string())).collect()));
+    buffers.push(array(model.buffer(thread_id).iter().map(|(address, value)| array(vec![address.to_string(), value.to_string()])).collect()));
+  }
+  let mut memory: Vec<(&i32, &i32)> = model.memory().iter().collect();
+  memory.sort();
+  let threads = (0..model.number_of_threads()).map(|thread_id| string(model.thread_name(thread_id))).collect();
+  object(vec![
+    ("threads".to_string(), array(threads)),
+    ("registers".to_string(), array(registers)),
+    ("buffers".to_string(), array(buffers)),
+    ("memory".to_string(), object(memory.into_iter().map(|(address, value)| (address.to_string(), value.to_string())).collect())),
+    ("statistics".to_string(), model.statistics().to_json())
+  ])
+}
+
+pub fn parse(text: &str) -> Result<Value, String> {
+  let mut chars = text.chars().peekable();
+  let value = parse_value(&mut chars)?;
+  skip_whitespace(&mut chars);
+  match chars.next() {
+    None => Ok(value),
+    Some(c) => Err(format!("unexpected {:?} after JSON value", c))
+  }
+}
+
+fn skip_whitespace(chars: &mut Peekable<Chars>) {
+  while chars.next_if(|c| c.is_whitespace()).is_some() {}
+}
+
+fn expect(chars: &mut Peekable<Chars>, expected: char) -> Result<(), String> {
+  skip_whitespace(chars);
+  match chars.next() {
+    Some(c) if c == expected => Ok(()),
+    Some(c) => Err(format!("expected {:?} but found {:?} in JSON", expected, c)),
+    None => Err(format!("expected {:?} but the JSON ends", expected))
+  }
+}
+
+fn parse_value(chars: &mut Peekable<Chars>) -> Result<Value, String> {
+  skip_whitespace(chars);
+  match chars.peek() {
+    Some('{') => {
+      chars.next();
+      let mut fields = Vec::new();
+      skip_whitespace(chars);
+      if chars.next_if_eq(&'}').is_some() {
+        return Ok(Value::Object(fields));
+      }
+      loop {
+        skip_whitespace(chars);
+        let key = match parse_value(chars)? {
+          Value::String(key) => key,
+          other => return Err(format!("expected a key but found {:?} in JSON", other))
+        };
+        expect(chars, ':')?;
+        fields.push((key, parse_value(chars)?));
+        skip_whitespace(chars);
+        if chars.next_if_eq(&'}').is_some() {
+          return Ok(Value::Object(fields));
+        }
+        expect(chars, ',')?;
+      }
+    },
+    Some('[') => {
+      chars.next();
+      let mut items = Vec::new();
+      skip_whitespace(chars);
+      if chars.next_if_eq(&']').is_some() {
+        return Ok(Value::Array(items));
+      }
+      loop {
+        items.push(parse_value(chars)?);
+        skip_whitespace(chars);
+        if chars.next_if_eq(&']').is_some() {
+          return Ok(Value::Array(items));
+        }
+        expect(chars, ',')?;
+      }
+    },
+    Some('"') => {
+      chars.next();
+      let mut result = String::new();
+      loop {
+        match chars.next() {
+          Some('"') => return Ok(Value::String(result)),
+          Some('\\') => match chars.next() {
+            Some('n') => result.push('\n'),
+            Some('t') => result.push('\t'),
+            Some('r') => result.push('\r'),
+            Some('u') => {
+              let digits: String = (0..4).filter_map(|_| chars.next()).collect();
+              let c = u32::from_str_radix(&digits, 16).ok().and_then(char::from_u32).ok_or_else(|| format!("invalid escape \\u{} in JSON", digits))?;
+              result.push(c);
+            },
+            Some(c) => result.push(c),
+            None => return Err("unterminated string in JSON".to_string())
+          },
+          Some(c) => result.push(c),
+          None => return Err("unterminated string in JSON".to_string())
+        }
+      }
+    },
+    Some(c) if *c == '-' || c.is_ascii_digit() => {
+      let mut number = String::new();
+      while let Some(c) = chars.next_if(|c| *c == '-' || c.is_ascii_digit()) {
+        number.push(c);
+      }
+      number.parse().map(Value::Number).map_err(|_| format!("invalid number {} in JSON", number))
+    },
+    Some(c) if c.is_alphabetic() => {
+      let mut word = String::new();
+      while let Some(c) = chars.next_if(|c| c.is_alphabetic()) {
+        word.push(c);
+      }
+      match word.as_str() {
+        "null" => Ok(Value::Null),
+        "true" => Ok(Value::Bool(true)),
+        "false" => Ok(Value::Bool(false)),
+        _ => Err(format!("unexpected {} in JSON", word))
+      }
+    },
+    Some(c) => Err(format!("unexpected {:?} in JSON", c)),
+    None => Err("the JSON ends too early".to_string())
+  }
+}
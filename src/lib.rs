@@ -1,6 +1,40 @@
+pub mod condition;
+pub mod database;
+pub mod deadlock;
+pub mod error;
+pub mod event;
+pub mod event_structure;
+pub mod expression;
+pub mod explore;
+pub mod fence_map;
 pub mod graph;
 pub mod instruction;
+pub mod json;
+pub mod linearizability;
+pub mod litmus;
+pub mod lockset;
 pub mod memory_model;
+pub mod metrics;
 pub mod storage;
+#[cfg(feature = "proptest")]
+pub mod strategies;
 pub mod threads;
-pub mod parser;
\ No newline at end of file
+pub mod transaction;
+pub mod parser;
+pub mod persistence;
+pub mod preprocessor;
+pub mod program;
+pub mod race;
+pub mod reads_from;
+pub mod recovery;
+pub mod repl;
+pub mod robustness;
+pub mod scheduler;
+pub mod shared;
+pub mod simulator;
+pub mod smt;
+pub mod spec;
+pub mod statistics;
+pub mod symbolic;
+pub mod testing;
+pub mod trace;
@@ -0,0 +1,171 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::{event::Event, program::Program};
+
+// The sequential object that a program's operations implement, from its `spec` line. Results that
+// an empty queue or stack cannot give are -1.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Spec {
+  Register,
+  Counter,
+  Queue,
+  Stack
+}
+
+impl Spec {
+  pub fn parse(name: &str) -> Result<Spec, String> {
+    match name {
+      "register" => Ok(Spec::Register),
+      "counter" => Ok(Spec::Counter),
+      "queue" => Ok(Spec::Queue),
+      "stack" => Ok(Spec::Stack),
+      _ => Err(format!("unknown spec {}; expected register, counter, queue or stack", name))
+    }
+  }
+
+  // The number of arguments of operation `name`.
+  pub fn arity(&self, name: &str) -> Result<usize, String> {
+    match (self, name) {
+      (Spec::Register, "write") | (Spec::Counter, "add") | (Spec::Queue, "enqueue") | (Spec::Stack, "push") => Ok(1),
+      (Spec::Register, "read") | (Spec::Counter, "inc") | (Spec::Counter, "get") | (Spec::Queue, "dequeue") | (Spec::Stack, "pop") => Ok(0),
+      _ => Err(format!("{:?} has no operation {}", self, name).to_lowercase())
+    }
+  }
+
+  // Applies the operation to the object and returns its result.
+  fn apply(&self, state: &mut Vec<i32>, name: &str, args: &[i32]) -> i32 {
+    match (self, name) {
+      (Spec::Register, "write") => {
+        *state = vec![args[0]];
+        0
+      },
+      (Spec::Register, "read") | (Spec::Counter, "get") => state.first().copied().unwrap_or(0),
+      (Spec::Counter, "add") | (Spec::Counter, "inc") => {
+        let value = state.first().copied().unwrap_or(0);
+        *state = vec![value.wrapping_add(args.first().copied().unwrap_or(1))];
+        value
+      },
+      (Spec::Queue, "enqueue") | (Spec::Stack, "push") => {
+        state.push(args[0]);
+        0
+      },
+      (Spec::Queue, "dequeue") if !state.is_empty() => state.remove(0),
+      (Spec::Stack, "pop") => state.pop().unwrap_or(-1),
+      _ => -1
+    }
+  }
+}
+
+// An `op NAME(args) -> r { ... }` block: the instructions of `thread_id` on lines `first..=last`,
+// whose result is register `result` once they have run.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Operation {
+  pub thread_id: usize,
+  pub name: String,
+  pub args: Vec<i32>,
+  pub result: Option<String>,
+  pub first: usize,
+  pub last: usize
+}
+
+impl Operation {
+  fn describe(&self, value: i32) -> String {
+    let args: Vec<String> = self.args.iter().map(|arg| arg.to_string()).collect();
+    match &self.result {
+      Some(_) => format!("{}({}) -> {}", self.name, args.join(", "), value),
+      None => format!("{}({})", self.name, args.join(", "))
+    }
+  }
+}
+
+// An operation of a run: the steps of its first and last instruction and its result at the last one.
+#[derive(Clone, Copy, Debug)]
+struct Call {
+  invoked: usize,
+  returned: usize,
+  value: i32
+}
+
+// The calls of one run. An operation that runs again, e.g. in a loop, extends the same call.
+#[derive(Clone, Debug)]
+pub struct History {
+  calls: Vec<Option<Call>>,
+  steps: usize
+}
+
+impl History {
+  pub fn new(program: &Program) -> History {
+    History { calls: vec![None; program.operations.len()], steps: 0 }
+  }
+
+  pub fn record(&mut self, program: &Program, event: &Event, registers: &HashMap<String, i32>) {
+    let step = self.steps;
+    self.steps += 1;
+    let line = match event.node.instruction.line {
+      Some(line) => line,
+      None => return
+    };
+    for (operation, call) in program.operations.iter().zip(self.calls.iter_mut()) {
+      if operation.thread_id != event.node.thread_id || line < operation.first || line > operation.last {
+        continue;
+      }
+      let value = operation.result.as_ref().and_then(|r| registers.get(r)).copied().unwrap_or(0);
+      let invoked = call.map_or(step, |call| call.invoked);
+      *call = Some(Call { invoked, returned: step, value });
+    }
+  }
+
+  // Searches for an order of the calls that respects real time, a call returning before another is
+  // invoked comes first, and gives every call with a result register the result the spec gives.
+  pub fn check(&self, program: &Program) -> Result<(), String> {
+    let spec = match program.spec {
+      Some(spec) => spec,
+      None => return Ok(())
+    };
+    let calls: Vec<(&Operation, Call)> = program.operations.iter().zip(self.calls.iter())
+      .filter_map(|(operation, call)| call.map(|call| (operation, call)))
+      .collect();
+    let mut visited = HashSet::new();
+    if linearize(spec, &calls, &mut vec![false; calls.len()], &[], &mut visited) {
+      return Ok(());
+    }
+    let mut lines = vec![format!("no order of the operations is consistent with a {:?} and with real time:", spec).to_lowercase()];
+    let mut sorted = calls.clone();
+    sorted.sort_by_key(|(_, call)| (call.invoked, call.returned));
+    for (operation, call) in sorted {
+      lines.push(format!("  {}: {} at steps {} to {}", program.thread_names[operation.thread_id], operation.describe(call.value), call.invoked, call.returned));
+    }
+    Err(lines.join("\n"))
+  }
+}
+
+fn linearize(spec: Spec, calls: &[(&Operation, Call)], done: &mut [bool], state: &[i32], visited: &mut HashSet<(Vec<bool>, Vec<i32>)>) -> bool {
+  if done.iter().all(|done| *done) {
+    return true;
+  }
+  if !visited.insert((done.to_vec(), state.to_vec())) {
+    return false;
+  }
+  let deadline = calls.iter().zip(done.iter())
+    .filter(|(_, done)| !**done)
+    .map(|((_, call), _)| call.returned)
+    .min()
+    .unwrap_or(usize::MAX);
+  for index in 0..calls.len() {
+    let (operation, call) = calls[index];
+    if done[index] || call.invoked > deadline {
+      continue;
+    }
+    let mut next = state.to_vec();
+    let value = spec.apply(&mut next, &operation.name, &operation.args);
+    if operation.result.is_some() && value != call.value {
+      continue;
+    }
+    done[index] = true;
+    if linearize(spec, calls, done, &next, visited) {
+      return true;
+    }
+    done[index] = false;
+  }
+  false
+}
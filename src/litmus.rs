@@ -0,0 +1,599 @@
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+
+use crate::{event::{Access, Event}, instruction::{Accesses, FetchOp, Instruction, LabeledInstruction, Mode}, json::{self, Value}, memory_model::MemoryModel, program::Program};
+
+// General purpose registers besides the zero register.
+const REGISTERS: usize = 31;
+
+fn location(address: i32) -> String {
+  format!("m{}", address).replace('-', "_")
+}
+
+fn unsupported(instruction: &LabeledInstruction) -> String {
+  match instruction.line {
+    Some(line) => format!("Litmus export does not support {:?} (line {})", instruction, line),
+    None => format!("Litmus export does not support {:?}", instruction)
+  }
+}
+
+// The suffix of the AArch64 atomics for acquire, release or both.
+fn ordering(mode: Mode) -> &'static str {
+  match mode {
+    Mode::Rlx | Mode::NA => "",
+    Mode::Acq => "A",
+    Mode::Rel => "L",
+    Mode::RelAcq | Mode::SeqCst => "AL"
+  }
+}
+
+// The weakest barrier ordering what the fence orders, if anything.
+fn barrier(mode: Mode, order: Option<(Accesses, Accesses)>) -> Option<&'static str> {
+  match order {
+    Some((before, after)) if before.stores() && after.loads() => Some("DMB ISH"),
+    Some((Accesses::Loads, _)) => Some("DMB ISHLD"),
+    Some((Accesses::Stores, Accesses::Stores)) => Some("DMB ISHST"),
+    Some(_) => Some("DMB ISH"),
+    None => match mode {
+      Mode::Rlx | Mode::NA => None,
+      Mode::Acq => Some("DMB ISHLD"),
+      Mode::Rel | Mode::RelAcq | Mode::SeqCst => Some("DMB ISH")
+    }
+  }
+}
+
+// The code of one thread as it is translated: every assignment gets a register of its own, so
+// each load keeps the value it read to the end and the final condition can name it.
+struct Thread {
+  name: String,
+  code: Vec<String>,
+  next: usize,
+  registers: HashMap<String, usize>,
+  values: HashMap<String, i32>,
+  // The registers computed from loaded values; addresses must not depend on them, as litmus tests
+  // name their locations up front.
+  loaded: HashSet<String>,
+  locations: BTreeMap<i32, usize>,
+  observed: Vec<(usize, i32)>
+}
+
+impl Thread {
+  fn fresh(&mut self) -> Result<usize, String> {
+    if self.next == REGISTERS {
+      return Err(format!("Litmus export needs more than {} registers in thread {}", REGISTERS, self.name));
+    }
+    self.next += 1;
+    Ok(self.next - 1)
+  }
+
+  fn operand(&self, r: &String) -> String {
+    self.registers.get(r).map_or("WZR".to_string(), |register| format!("W{}", register))
+  }
+
+  fn value(&self, r: &String) -> i32 {
+    self.values.get(r).copied().unwrap_or(0)
+  }
+
+  fn assign(&mut self, r: &String, value: i32, loaded: bool) -> Result<usize, String> {
+    let register = self.fresh()?;
+    self.registers.insert(r.clone(), register);
+    self.values.insert(r.clone(), value);
+    match loaded {
+      true => self.loaded.insert(r.clone()),
+      false => self.loaded.remove(r)
+    };
+    Ok(register)
+  }
+
+  // The access must be the one the registers give in program order, which the code written
+  // computes; models that run register assignments out of order may make another.
+  fn address(&mut self, r: &String, instruction: &LabeledInstruction, access: Option<&Access>) -> Result<String, String> {
+    if self.loaded.contains(r) {
+      return Err(format!("{}: its address depends on a load", unsupported(instruction)));
+    }
+    let address = self.value(r);
+    match access {
+      Some(access) if access.address != address => return Err(format!("{:?} accesses #{} in the trace but #{} in program order", instruction, access.address, address)),
+      None => return Err(format!("the trace does not run {:?}", instruction)),
+      Some(_) => {}
+    }
+    let register = match self.locations.get(&address) {
+      Some(register) => *register,
+      None => {
+        let register = self.fresh()?;
+        self.locations.insert(address, register);
+        register
+      }
+    };
+    Ok(format!("[X{}]", register))
+  }
+
+  fn translate(&mut self, instruction: &LabeledInstruction, access: Option<&Access>) -> Result<(), String> {
+    let accessed = access.map_or(0, |access| access.value);
+    match &instruction.instruction {
+      Instruction::Const { r, value } => {
+        let register = self.assign(r, *value, false)?;
+        self.code.push(format!("MOV W{},#{}", register, value));
+      },
+      Instruction::ArithPlus { r1, r2, r3 } | Instruction::ArithMinus { r1, r2, r3 } | Instruction::ArithMul { r1, r2, r3 } | Instruction::ArithDiv { r1, r2, r3 } => {
+        let (a, b) = (self.value(r2), self.value(r3));
+        let (mnemonic, value) = match &instruction.instruction {
+          Instruction::ArithPlus { .. } => ("ADD", a.wrapping_add(b)),
+          Instruction::ArithMinus { .. } => ("SUB", a.wrapping_sub(b)),
+          Instruction::ArithMul { .. } => ("MUL", a.wrapping_mul(b)),
+          _ => ("SDIV", a.checked_div(b).unwrap_or(0))
+        };
+        let operands = (self.operand(r2), self.operand(r3));
+        let loaded = self.loaded.contains(r2) || self.loaded.contains(r3);
+        let register = self.assign(r1, value, loaded)?;
+        self.code.push(format!("{} W{},{},{}", mnemonic, register, operands.0, operands.1));
+      },
+      Instruction::Select { r1, r2, r3, r4 } => {
+        let value = if self.value(r2) != 0 { self.value(r3) } else { self.value(r4) };
+        let operands = (self.operand(r2), self.operand(r3), self.operand(r4));
+        let loaded = [r2, r3, r4].iter().any(|r| self.loaded.contains(*r));
+        let register = self.assign(r1, value, loaded)?;
+        self.code.push(format!("CMP {},#0", operands.0));
+        self.code.push(format!("CSEL W{},{},{},NE", register, operands.1, operands.2));
+      },
+      Instruction::Load { mode, address, r } => {
+        let address = self.address(address, instruction, access)?;
+        let register = self.assign(r, accessed, true)?;
+        let mnemonic = if matches!(mode, Mode::Rlx | Mode::NA | Mode::Rel) { "LDR" } else { "LDAR" };
+        self.code.push(format!("{} W{},{}", mnemonic, register, address));
+        self.observed.push((register, accessed));
+      },
+      Instruction::Store { mode, address, r } => {
+        let address = self.address(address, instruction, access)?;
+        if accessed != self.value(r) {
+          return Err(format!("{:?} stores {} in the trace but {} in program order", instruction, accessed, self.value(r)));
+        }
+        let mnemonic = if matches!(mode, Mode::Rlx | Mode::NA | Mode::Acq) { "STR" } else { "STLR" };
+        self.code.push(format!("{} {},{}", mnemonic, self.operand(r), address));
+      },
+      // The access of an update holds the value it wrote, from which the one it read follows.
+      Instruction::Fai { op: op @ (FetchOp::Add | FetchOp::Sub), mode, address, to, inc } => {
+        let address = self.address(address, instruction, access)?;
+        let mut operand = self.operand(inc);
+        let value = match op {
+          FetchOp::Add => accessed.wrapping_sub(self.value(inc)),
+          _ => {
+            let negated = self.fresh()?;
+            self.code.push(format!("SUB W{},WZR,{}", negated, operand));
+            operand = format!("W{}", negated);
+            accessed.wrapping_add(self.value(inc))
+          }
+        };
+        let register = self.assign(to, value, true)?;
+        self.code.push(format!("LDADD{} {},W{},{}", ordering(*mode), operand, register, address));
+        self.observed.push((register, value));
+      },
+      Instruction::Fence { mode, order } => if let Some(barrier) = barrier(*mode, *order) {
+        self.code.push(barrier.to_string());
+      },
+      _ => return Err(unsupported(instruction))
+    }
+    Ok(())
+  }
+}
+
+// Writes a complete execution recorded in a trace as an AArch64 litmus test in the format of herd,
+// which rmem reads and isla-litmus converts for isla-axiomatic. The final condition holds exactly
+// for the execution: it names the value every load and update read and the final value of every
+// location, so a reference model that forbids it disagrees with the model that ran it. Only code
+// without jumps whose addresses do not depend on loads can be written this way.
+pub fn aarch64(name: &str, description: &str, program: &Program, events: &[Event], model: &dyn MemoryModel) -> Result<String, String> {
+  if !model.get_possible_executions().is_empty() {
+    return Err("the trace does not run the program to the end".to_string());
+  }
+  if program.has_ranges() {
+    return Err("Litmus export needs fixed initial values".to_string());
+  }
+  if !program.recovery.is_empty() {
+    return Err("Litmus export does not support recovery threads".to_string());
+  }
+  // The accesses of each thread by instruction in the order they ran, matched with the
+  // instructions in program order.
+  let mut accesses: HashMap<(usize, Option<usize>, String), VecDeque<&Access>> = HashMap::new();
+  for event in events {
+    if let Some(access) = &event.access {
+      let key = (event.node.thread_id, event.node.instruction.line, format!("{:?}", event.node.instruction));
+      accesses.entry(key).or_default().push_back(access);
+    }
+  }
+  let mut threads = Vec::new();
+  for (thread_id, instructions) in program.threads.iter().enumerate() {
+    let mut thread = Thread {
+      name: program.thread_names[thread_id].clone(),
+      code: Vec::new(),
+      next: 0,
+      registers: HashMap::new(),
+      values: HashMap::new(),
+      loaded: HashSet::new(),
+      locations: BTreeMap::new(),
+      observed: Vec::new()
+    };
+    for instruction in instructions {
+      let key = (thread_id, instruction.line, format!("{:?}", instruction));
+      let access = accesses.get_mut(&key).and_then(|accesses| accesses.pop_front());
+      thread.translate(instruction, access)?;
+    }
+    threads.push(thread);
+  }
+  let initial = program.initial_memory();
+  let initial_value = |address: &i32| initial.get(address).copied().unwrap_or(program.default_memory);
+  let mut locations: Vec<i32> = threads.iter().flat_map(|thread| thread.locations.keys().copied()).collect();
+  locations.sort();
+  locations.dedup();
+  let mut lines = vec![
+    format!("AArch64 {}", name.replace(|c: char| !c.is_ascii_alphanumeric(), "_")),
+    format!("\"{}\"", description.replace('"', "'")),
+    "{".to_string()
+  ];
+  let initialized: Vec<String> = locations.iter()
+    .filter(|address| initial_value(address) != 0)
+    .map(|address| format!("{}={};", location(*address), initial_value(address)))
+    .collect();
+  if !initialized.is_empty() {
+    lines.push(initialized.join(" "));
+  }
+  for (thread_id, thread) in threads.iter().enumerate() {
+    if !thread.locations.is_empty() {
+      let registers: Vec<String> = thread.locations.iter().map(|(address, register)| format!("{}:X{}={};", thread_id, register, location(*address))).collect();
+      lines.push(registers.join(" "));
+    }
+  }
+  lines.push("}".to_string());
+  let mut columns: Vec<Vec<String>> = threads.iter().enumerate()
+    .map(|(thread_id, thread)| std::iter::once(format!("P{}", thread_id)).chain(thread.code.iter().cloned()).collect())
+    .collect();
+  let rows = columns.iter().map(|column| column.len()).max().unwrap_or(0);
+  for column in columns.iter_mut() {
+    let width = column.iter().map(|cell| cell.len()).max().unwrap_or(0);
+    column.resize(rows, String::new());
+    for cell in column.iter_mut() {
+      *cell = format!(" {:width$} ", cell, width = width);
+    }
+  }
+  for row in 0..rows {
+    let cells: Vec<&str> = columns.iter().map(|column| column[row].as_str()).collect();
+    lines.push(format!("{};", cells.join("|")));
+  }
+  let mut conditions: Vec<String> = threads.iter().enumerate()
+    .flat_map(|(thread_id, thread)| thread.observed.iter().map(move |(register, value)| format!("{}:X{}={}", thread_id, register, value)))
+    .collect();
+  for address in locations {
+    let value = model.memory().get(&address).copied().unwrap_or_else(|| initial_value(&address));
+    conditions.push(format!("{}={}", location(address), value));
+  }
+  if conditions.is_empty() {
+    conditions.push("true".to_string());
+  }
+  lines.push(format!("exists ({})", conditions.join(" /\\ ")));
+  Ok(lines.join("\n") + "\n")
+}
+
+// More clauses than this in the negation of a condition are refused rather than written out.
+const MAX_CLAUSES: usize = 4096;
+
+fn field<'a>(value: &'a Value, keys: &[&str]) -> Option<&'a Value> {
+  keys.iter().find_map(|key| value.get(key))
+}
+
+fn name<'a>(value: Option<&'a Value>, what: &str) -> Result<&'a str, String> {
+  match value {
+    Some(Value::String(name)) if !name.is_empty() && !name.starts_with(|c: char| c.is_ascii_digit()) && name.chars().all(|c| c.is_alphanumeric() || c == '_') => Ok(name),
+    Some(value) => Err(format!("invalid {} {:?}", what, value)),
+    None => Err(format!("missing {}", what))
+  }
+}
+
+fn number(value: &Value, what: &str) -> Result<i32, String> {
+  match value {
+    Value::Number(number) => i32::try_from(*number).map_err(|_| format!("{} {} is out of range", what, number)),
+    value => Err(format!("invalid {} {:?}", what, value))
+  }
+}
+
+fn mode(value: Option<&Value>, default: Mode) -> Result<Mode, String> {
+  let Some(value) = value else {
+    return Ok(default);
+  };
+  let Value::String(mode) = value else {
+    return Err(format!("invalid mode {:?}", value));
+  };
+  match mode.to_lowercase().trim_start_matches("memory_order_") {
+    "rlx" | "relaxed" | "once" => Ok(Mode::Rlx),
+    "na" | "non_atomic" | "nonatomic" => Ok(Mode::NA),
+    "acq" | "acquire" => Ok(Mode::Acq),
+    "rel" | "release" => Ok(Mode::Rel),
+    "acq_rel" | "rel_acq" | "acqrel" => Ok(Mode::RelAcq),
+    "sc" | "seq_cst" | "seqcst" | "mb" => Ok(Mode::SeqCst),
+    _ => Err(format!("unknown mode {}", mode))
+  }
+}
+
+// The code of one thread of a JSON test: its events become instructions, every location is
+// addressed through a register named after it, and constants are put in registers `$vN`.
+#[derive(Default)]
+struct Imported {
+  name: Option<String>,
+  code: Vec<String>,
+  locations: Vec<String>,
+  constants: Vec<i32>
+}
+
+impl Imported {
+  fn location(&mut self, event: &Value, locations: &mut Vec<String>) -> Result<String, String> {
+    let location = name(field(event, &["loc", "location", "addr", "address", "var"]), "location")?.to_string();
+    if !locations.contains(&location) {
+      locations.push(location.clone());
+    }
+    if !self.locations.contains(&location) {
+      self.locations.push(location.clone());
+    }
+    Ok(format!("#${}", location))
+  }
+
+  fn operand(&mut self, value: Option<&Value>, what: &str) -> Result<String, String> {
+    match value {
+      Some(Value::String(_)) => name(value, what).map(|r| r.to_string()),
+      Some(value) => {
+        let constant = number(value, what)?;
+        if !self.constants.contains(&constant) {
+          self.constants.push(constant);
+        }
+        Ok(format!("$v{}", constant).replace('-', "_"))
+      },
+      None => Err(format!("missing {}", what))
+    }
+  }
+
+  fn event(&mut self, event: &Value, locations: &mut Vec<String>) -> Result<(), String> {
+    let kind = match field(event, &["type", "kind"]) {
+      Some(Value::String(kind)) => kind.to_lowercase(),
+      _ => return Err(format!("event without a type: {:?}", event))
+    };
+    let register = || name(field(event, &["reg", "register"]), "register").map(|r| r.to_string());
+    let value = field(event, &["value", "val"]);
+    let line = match kind.as_str() {
+      "r" | "read" | "load" => {
+        let mode = mode(field(event, &["mode", "order", "ordering"]), Mode::Rlx)?;
+        format!("load {:?} {} {}", mode, self.location(event, locations)?, register()?)
+      },
+      "w" | "write" | "store" => {
+        let mode = mode(field(event, &["mode", "order", "ordering"]), Mode::Rlx)?;
+        let address = self.location(event, locations)?;
+        format!("store {:?} {} {}", mode, address, self.operand(value, "value")?)
+      },
+      "f" | "fence" => format!("fence {:?}", mode(field(event, &["mode", "order", "ordering"]), Mode::SeqCst)?),
+      "rmw" | "update" => {
+        let mode = mode(field(event, &["mode", "order", "ordering"]), Mode::Rlx)?;
+        let mnemonic = match field(event, &["op", "operation"]) {
+          None => "fai",
+          Some(Value::String(op)) => match op.to_lowercase().as_str() {
+            "add" => "fai",
+            "sub" => "fetch_sub",
+            "max" => "fetch_max",
+            "min" => "fetch_min",
+            _ => return Err(format!("unknown update {}", op))
+          },
+          Some(op) => return Err(format!("invalid update {:?}", op))
+        };
+        let address = self.location(event, locations)?;
+        format!("{} := {} {:?} {} {}", register()?, mnemonic, mode, address, self.operand(value, "value")?)
+      },
+      "cas" => {
+        let mode = mode(field(event, &["mode", "order", "ordering"]), Mode::Rlx)?;
+        let address = self.location(event, locations)?;
+        let expected = self.operand(field(event, &["expected", "exp"]), "expected value")?;
+        let desired = self.operand(field(event, &["desired", "des"]), "desired value")?;
+        format!("{} := cas {:?} {} {} {}", register()?, mode, address, expected, desired)
+      },
+      _ => return Err(format!("unknown event type {}", kind))
+    };
+    self.code.push(line);
+    Ok(())
+  }
+}
+
+// A comparison of a register or location with a value, in the syntax of the `assert` line.
+#[derive(Clone)]
+struct Comparison {
+  operand: String,
+  equal: bool,
+  value: i32
+}
+
+// Parses a herd-style condition such as `exists (0:r0=0 /\ 1:r0=0)` into clauses joined by `\/`,
+// each a list of comparisons joined by `/\`.
+fn condition(text: &str, addresses: &[String]) -> Result<(String, Vec<Vec<Comparison>>), String> {
+  let text = text.trim();
+  let (quantifier, body) = ["~exists", "exists", "forall"].iter()
+    .find_map(|quantifier| text.strip_prefix(quantifier).map(|body| (quantifier.to_string(), body)))
+    .or_else(|| text.strip_prefix('~').and_then(|rest| rest.trim_start().strip_prefix("exists")).map(|body| ("~exists".to_string(), body)))
+    .ok_or_else(|| format!("condition {} does not start with exists, ~exists or forall", text))?;
+  let mut tokens = Vec::new();
+  let mut rest = body.trim();
+  while !rest.is_empty() {
+    let token = ["(", ")", "/\\", "\\/"].iter().find(|token| rest.starts_with(**token)).map_or_else(
+      || &rest[..rest.find(['(', ')', '/', '\\']).unwrap_or(rest.len()).max(1)],
+      |token| &rest[..token.len()]
+    );
+    tokens.push(token.trim());
+    rest = rest[token.len()..].trim_start();
+  }
+  let mut position = 0;
+  let clauses = disjunction(&tokens, &mut position, addresses)?;
+  if position < tokens.len() {
+    return Err(format!("unexpected {} in condition", tokens[position]));
+  }
+  Ok((quantifier, clauses))
+}
+
+fn disjunction(tokens: &[&str], position: &mut usize, addresses: &[String]) -> Result<Vec<Vec<Comparison>>, String> {
+  let mut clauses = conjunction(tokens, position, addresses)?;
+  while tokens.get(*position) == Some(&"\\/") {
+    *position += 1;
+    clauses.extend(conjunction(tokens, position, addresses)?);
+  }
+  Ok(clauses)
+}
+
+fn conjunction(tokens: &[&str], position: &mut usize, addresses: &[String]) -> Result<Vec<Vec<Comparison>>, String> {
+  let mut clauses = factor(tokens, position, addresses)?;
+  while tokens.get(*position) == Some(&"/\\") {
+    *position += 1;
+    let other = factor(tokens, position, addresses)?;
+    clauses = product(&clauses, &other)?;
+  }
+  Ok(clauses)
+}
+
+fn factor(tokens: &[&str], position: &mut usize, addresses: &[String]) -> Result<Vec<Vec<Comparison>>, String> {
+  let token = *tokens.get(*position).ok_or_else(|| "the condition ends too early".to_string())?;
+  *position += 1;
+  if token == "(" {
+    let clauses = disjunction(tokens, position, addresses)?;
+    if tokens.get(*position) != Some(&")") {
+      return Err("missing ) in condition".to_string());
+    }
+    *position += 1;
+    return Ok(clauses);
+  }
+  if token == "true" {
+    return Ok(vec![Vec::new()]);
+  }
+  let (operand, equal, value) = match token.split_once("!=") {
+    Some((operand, value)) => (operand, false, value),
+    None => token.split_once('=').map(|(operand, value)| (operand, true, value)).ok_or_else(|| format!("invalid comparison {}", token))?
+  };
+  let value: i32 = value.trim().parse().map_err(|_| format!("invalid value in {}", token))?;
+  let operand = operand.trim();
+  let location = operand.strip_prefix('[').and_then(|operand| operand.strip_suffix(']')).unwrap_or(operand);
+  let operand = match (operand.split_once(':'), addresses.iter().position(|address| address == location)) {
+    (Some((thread, r)), _) => format!("{}:{}", thread.trim(), r.trim()),
+    (None, Some(address)) => format!("#{}", address),
+    (None, None) => return Err(format!("unknown location {} in condition", location))
+  };
+  Ok(vec![vec![Comparison { operand, equal, value }]])
+}
+
+// The clauses of the conjunction of two conditions in disjunctive form.
+fn product(first: &[Vec<Comparison>], second: &[Vec<Comparison>]) -> Result<Vec<Vec<Comparison>>, String> {
+  if first.len() * second.len() > MAX_CLAUSES {
+    return Err(format!("the condition has more than {} clauses", MAX_CLAUSES));
+  }
+  Ok(first.iter().flat_map(|clause| second.iter().map(move |other| clause.iter().chain(other).cloned().collect())).collect())
+}
+
+fn negate(clauses: &[Vec<Comparison>]) -> Result<Vec<Vec<Comparison>>, String> {
+  let mut negated = vec![Vec::new()];
+  for clause in clauses {
+    let alternatives: Vec<Vec<Comparison>> = clause.iter().map(|comparison| vec![Comparison { equal: !comparison.equal, ..comparison.clone() }]).collect();
+    negated = product(&negated, &alternatives)?;
+  }
+  Ok(negated)
+}
+
+// Converts a litmus test in the JSON form of other tools into program text. The test is an object
+// with `threads`, a list whose entries are lists of events or objects with a `name` and `events`,
+// or instead a flat list `events` whose entries name their `thread`; `init` maps locations to
+// initial values and `condition` is a herd-style final condition. Each location becomes a region
+// of one address, numbered in the order the test first names them.
+pub fn from_json(text: &str) -> Result<String, String> {
+  let test = json::parse(text)?;
+  let mut locations: Vec<String> = Vec::new();
+  let mut initial = Vec::new();
+  match test.get("init") {
+    Some(Value::Object(fields)) => for (location, value) in fields {
+      let location = name(Some(&Value::String(location.clone())), "location")?.to_string();
+      initial.push((location.clone(), number(value, "initial value")?));
+      locations.push(location);
+    },
+    Some(value) => return Err(format!("invalid init {:?}", value)),
+    None => {}
+  }
+  let mut threads: Vec<(Imported, Vec<&Value>)> = Vec::new();
+  match (test.get("threads"), test.get("events")) {
+    (Some(Value::Array(entries)), _) => for entry in entries {
+      match entry {
+        Value::Array(events) => threads.push((Imported::default(), events.iter().collect())),
+        Value::Object(_) => {
+          let name = entry.get("name").map(|value| name(Some(value), "thread name").map(|name| name.to_string())).transpose()?;
+          let Some(Value::Array(events)) = field(entry, &["events", "instructions"]) else {
+            return Err(format!("thread without events: {:?}", entry));
+          };
+          threads.push((Imported { name, ..Imported::default() }, events.iter().collect()));
+        },
+        entry => return Err(format!("invalid thread {:?}", entry))
+      }
+    },
+    (None, Some(Value::Array(events))) => {
+      let mut names: Vec<&Value> = Vec::new();
+      for event in events {
+        let thread = event.get("thread").ok_or_else(|| format!("event without a thread: {:?}", event))?;
+        let index = match names.iter().position(|name| *name == thread) {
+          Some(index) => index,
+          None => {
+            let name = match thread {
+              Value::Number(_) => None,
+              thread => Some(name(Some(thread), "thread name")?.to_string())
+            };
+            names.push(thread);
+            threads.push((Imported { name, ..Imported::default() }, Vec::new()));
+            names.len() - 1
+          }
+        };
+        threads[index].1.push(event);
+      }
+    },
+    _ => return Err("expected a list threads or events".to_string())
+  }
+  for (thread, events) in threads.iter_mut() {
+    for event in events.iter() {
+      thread.event(event, &mut locations)?;
+    }
+  }
+  let mut lines: Vec<String> = locations.iter().enumerate().map(|(address, location)| format!("region {} #{} 1", location, address)).collect();
+  for (location, value) in initial {
+    let address = locations.iter().position(|other| *other == location).unwrap();
+    lines.push(format!("init #{} = {}", address, value));
+  }
+  for (index, (thread, _)) in threads.iter().enumerate() {
+    if index > 0 {
+      lines.push(String::new());
+    }
+    if let Some(name) = &thread.name {
+      lines.push(format!("thread {}:", name));
+    }
+    for location in &thread.locations {
+      lines.push(format!("${} = {}", location, locations.iter().position(|other| other == location).unwrap()));
+    }
+    for constant in &thread.constants {
+      lines.push(format!("{} = {}", format!("$v{}", constant).replace('-', "_"), constant));
+    }
+    lines.extend(thread.code.iter().cloned());
+  }
+  // An `exists` condition holds when some final state satisfies it and `~exists` when none does,
+  // so both check its negation on every final state, and the final states breaking the `assert`
+  // are those that satisfy the condition.
+  if let Some(text) = test.get("condition") {
+    let Value::String(text) = text else {
+      return Err(format!("invalid condition {:?}", text));
+    };
+    let (quantifier, clauses) = condition(text, &locations)?;
+    let clauses = match quantifier.as_str() {
+      "forall" => clauses,
+      _ => negate(&clauses)?
+    };
+    let clauses: Vec<String> = clauses.iter()
+      .map(|clause| clause.iter().map(|comparison| format!("{} {} {}", comparison.operand, if comparison.equal { "==" } else { "!=" }, comparison.value)).collect::<Vec<String>>().join(" && "))
+      .collect();
+    match clauses.iter().any(|clause| clause.is_empty()) {
+      true => {},
+      false if clauses.is_empty() => return Err(format!("condition {} cannot be written as an assert line", text)),
+      false => lines.push(format!("assert {}", clauses.join(" || ")))
+    }
+  }
+  Ok(lines.join("\n") + "\n")
+}
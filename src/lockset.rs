@@ -0,0 +1,93 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::{event::{AccessKind, Event, Observer}, instruction::Mode};
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum State {
+  Exclusive(usize),
+  Shared,
+  SharedModified
+}
+
+struct Location {
+  state: State,
+  lockset: HashSet<i32>
+}
+
+#[derive(Clone)]
+pub struct LocksetViolation {
+  pub address: i32,
+  pub event: Event
+}
+
+impl LocksetViolation {
+  pub fn describe(&self, thread_names: &[String]) -> String {
+    format!("address {}: thread {} {:?} is not protected by a consistent lock",
+      self.address, thread_names[self.event.node.thread_id], self.event.node)
+  }
+}
+
+pub struct LocksetDetector {
+  held: Vec<HashSet<i32>>,
+  locations: HashMap<i32, Location>,
+  violations: Vec<LocksetViolation>
+}
+
+impl LocksetDetector {
+  pub fn new(number_of_threads: usize) -> LocksetDetector {
+    LocksetDetector {
+      held: vec![HashSet::new(); number_of_threads],
+      locations: HashMap::new(),
+      violations: Vec::new()
+    }
+  }
+
+  pub fn violations(&self) -> &[LocksetViolation] {
+    &self.violations
+  }
+}
+
+impl Observer for LocksetDetector {
+  fn on_event(&mut self, event: &Event) {
+    let thread_id = event.node.thread_id;
+    let access = match &event.access {
+      Some(access) => access,
+      None => return
+    };
+    match access.kind {
+      AccessKind::Lock => {
+        self.held[thread_id].insert(access.address);
+        return;
+      }
+      AccessKind::Unlock => {
+        self.held[thread_id].remove(&access.address);
+        return;
+      }
+      _ => {}
+    }
+    if !matches!(access.mode, Mode::Rlx | Mode::NA) {
+      return;
+    }
+    let held = &self.held[thread_id];
+    let location = self.locations.entry(access.address).or_insert_with(|| Location {
+      state: State::Exclusive(thread_id),
+      lockset: held.clone()
+    });
+    let previous_state = location.state;
+    let reported = previous_state == State::SharedModified && location.lockset.is_empty();
+    location.state = match (location.state, access.kind.is_write()) {
+      (State::Exclusive(owner), _) if owner == thread_id => return,
+      (State::Exclusive(_), false) => State::Shared,
+      (State::Shared, false) => State::Shared,
+      _ => State::SharedModified
+    };
+    if let State::Exclusive(_) = previous_state {
+      location.lockset = held.clone();
+    } else {
+      location.lockset.retain(|lock| held.contains(lock));
+    }
+    if location.state == State::SharedModified && location.lockset.is_empty() && !reported {
+      self.violations.push(LocksetViolation { address: access.address, event: event.clone() });
+    }
+  }
+}
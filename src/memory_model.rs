@@ -1,324 +1,2288 @@
-use rand::seq::SliceRandom;
+use std::{collections::{BTreeSet, HashMap, HashSet}, fmt::Debug, ops::Range};
 
-use crate::{threads::{SCThreadSystem, ThreadSystem, TSOThreadSystem, PSOThreadSystem}, storage::{SCStorageSystem, StorageSystem, TSOStorageSystem, PSOStorageSystem}, graph::Node, instruction::{Instruction, LabeledInstruction}};
+use rand::rngs::SmallRng;
 
+use crate::{error::RuntimeError, explore::{Executions, Explorer}, event::{Access, AccessKind, Event, Observer}, threads::{SCThreadSystem, ThreadSystem, TSOThreadSystem, PSOThreadSystem, C11ThreadSystem, ItaniumThreadSystem, PRAMThreadSystem, NMCAThreadSystem}, storage::{Origin, SCStorageSystem, StorageSystem, TSOStorageSystem, PSOStorageSystem, C11StorageSystem, PRAMStorageSystem, NMCAStorageSystem, DSMStorageSystem}, graph::{Graph, Node}, scheduler::{Latencies, RandomScheduler, SchedulerOptions, StateView}, instruction::{FetchOp, Instruction, LabeledInstruction, Mode, Scope}, persistence::Persistence, program::{check_region, Program, Region, Topology}, race::RaceDetector, statistics::Statistics, transaction::{Transactional, EXPLICIT}};
 
-pub trait MemoryModel {
+
+pub trait MemoryModel: Debug + Send + Sync {
   fn get_possible_executions(&self) -> Vec<Node>;
-  fn random_step(&mut self, debug_print: bool);
-  fn step(&mut self, node: Node, debug_print: bool);
+  fn random_step(&mut self, debug_print: bool) -> Result<Option<Event>, RuntimeError>;
+  fn set_seed(&mut self, seed: u64);
+  // Replaces the scheduler's generator, for callers that keep their own source of randomness.
+  fn set_rng(&mut self, rng: SmallRng);
+  fn set_scheduler_options(&mut self, options: SchedulerOptions);
+  fn set_latencies(&mut self, latencies: Latencies);
+  // Keeps the graph from growing with every store in long runs; see `Graph::set_recycling`.
+  fn set_recycling(&mut self);
+  fn step(&mut self, node: Node, debug_print: bool) -> Result<Event, RuntimeError>;
+  fn append_instruction(&mut self, thread_id: usize, instruction: LabeledInstruction);
+  fn number_of_threads(&self) -> usize;
+  fn registers(&self, thread_id: usize) -> &HashMap<String, i32>;
+  fn memory(&self) -> &HashMap<i32, i32>;
+  fn initialize(&mut self, address: i32, value: i32);
+  // Makes reading the initial value of an address outside `initialized` a runtime error.
+  fn set_strict_memory(&mut self, initialized: HashSet<i32>);
+  fn set_default_memory(&mut self, value: i32);
+  // Makes accessing an address outside every region a runtime error.
+  fn set_regions(&mut self, regions: Vec<Region>);
+  // Makes every switch to another thread clear the exclusive monitor of the thread switched away from.
+  fn set_clear_monitors(&mut self);
+  // Makes a race of an `NA` access with another access of a different thread a runtime error, and
+  // with `relaxed` a race of a `RLX` access too.
+  fn set_race_checks(&mut self, relaxed: bool);
+  // Keeps a persistent copy of the addresses, starting from their current values.
+  fn set_persistent(&mut self, addresses: BTreeSet<i32>);
+  fn persistence(&self) -> &Persistence;
+  // The value of the addresses that no `init` line sets, before any store.
+  fn default_memory(&self) -> i32;
+  fn set_trace_options(&mut self, options: TraceOptions);
+  fn buffer(&self, thread_id: usize) -> &[(i32, i32)];
+  fn set_thread_names(&mut self, thread_names: Vec<String>);
+  fn thread_name(&self, thread_id: usize) -> &str;
+  fn graph(&self) -> &Graph;
+  // The model's parts, to read their state directly rather than from what `Debug` prints.
+  fn thread_system(&self) -> &dyn ThreadSystem;
+  fn storage_system(&self) -> &dyn StorageSystem;
+  fn pending_executions(&self) -> Vec<Node>;
+  fn lock_owner(&self, address: i32) -> Option<usize>;
+  // Where the thread is stopped after a `barrier` that has not opened yet or a `wait` not woken yet.
+  fn parked(&self, thread_id: usize) -> Option<Parked>;
+  fn statistics(&self) -> &Statistics;
+  // A copy of the whole state to branch from. Copies share the graph's nodes and edges until one of
+  // them adds to them.
+  fn snapshot(&self) -> Box<dyn MemoryModel>;
+}
+
+// `thread_system` and `storage_system` of a model keeping its parts in fields of those names.
+macro_rules! system_accessors {
+  () => {
+    fn thread_system(&self) -> &dyn ThreadSystem {
+      &self.thread_system
+    }
+
+    fn storage_system(&self) -> &dyn StorageSystem {
+      &self.storage_system
+    }
+  };
+}
+
+impl<M: MemoryModel + ?Sized> StateView for M {
+  fn number_of_threads(&self) -> usize {
+    MemoryModel::number_of_threads(self)
+  }
+
+  fn registers(&self, thread_id: usize) -> &HashMap<String, i32> {
+    MemoryModel::registers(self, thread_id)
+  }
+
+  fn memory(&self) -> &HashMap<i32, i32> {
+    MemoryModel::memory(self)
+  }
+
+  fn buffer(&self, thread_id: usize) -> &[(i32, i32)] {
+    MemoryModel::buffer(self, thread_id)
+  }
+
+  fn lock_owner(&self, address: i32) -> Option<usize> {
+    MemoryModel::lock_owner(self, address)
+  }
+}
+
+// So that the models `MemoryModelType::create` returns can be passed on as `&dyn StateView`.
+impl StateView for Box<dyn MemoryModel> {
+  fn number_of_threads(&self) -> usize {
+    self.as_ref().number_of_threads()
+  }
+
+  fn registers(&self, thread_id: usize) -> &HashMap<String, i32> {
+    self.as_ref().registers(thread_id)
+  }
+
+  fn memory(&self) -> &HashMap<i32, i32> {
+    self.as_ref().memory()
+  }
+
+  fn buffer(&self, thread_id: usize) -> &[(i32, i32)] {
+    self.as_ref().buffer(thread_id)
+  }
+
+  fn lock_owner(&self, address: i32) -> Option<usize> {
+    self.as_ref().lock_owner(address)
+  }
+}
+
+// Lets the models made by `MemoryModelType::create` be branched with `clone()` like the models
+// themselves; the copies go their own ways from the state they were taken in.
+impl Clone for Box<dyn MemoryModel> {
+  fn clone(&self) -> Self {
+    self.snapshot()
+  }
+}
+
+// Models and the explorations over them can be moved to other threads and shared between them,
+// for parallel exploration and for services holding them across `await` points.
+const _: fn() = || {
+  fn shared<T: Send + Sync>() {}
+  shared::<Box<dyn MemoryModel>>();
+  shared::<MemoryModelType>();
+  shared::<Program>();
+  shared::<Explorer>();
+  shared::<Executions>();
+  shared::<crate::explore::Exploration>();
+  shared::<crate::simulator::Simulator>();
+  shared::<crate::simulator::RunResult>();
+};
+
+fn runtime_error<T: ThreadSystem>(thread_system: &T, node: &Node, message: &str) -> RuntimeError {
+  let mut registers: Vec<(String, i32)> = Vec::new();
+  for r in node.instruction.instruction.read_registers() {
+    let value = thread_system.get_register(node.thread_id, r.clone());
+    if !registers.contains(&(r.clone(), value)) {
+      registers.push((r.clone(), value));
+    }
+  }
+  RuntimeError {
+    thread_id: node.thread_id,
+    thread_name: thread_system.thread_name(node.thread_id).to_string(),
+    instruction: format!("{:?}", node.instruction),
+    line: node.instruction.line,
+    message: message.to_string(),
+    registers
+  }
+}
+
+// What a program allows accesses to: the addresses in its regions and, with `--strict-memory`,
+// reads of the initial value only from the addresses `init` lines set.
+#[derive(Clone, Default)]
+struct AddressChecks {
+  initialized: Option<HashSet<i32>>,
+  regions: Vec<Region>
+}
+
+fn get_address<T: ThreadSystem>(thread_system: &T, checks: &AddressChecks, node: &Node, address: String) -> Result<i32, RuntimeError> {
+  let address_value = thread_system.get_register(node.thread_id, address);
+  if address_value < 0 {
+    return Err(runtime_error(thread_system, node, "negative memory address"));
+  }
+  check_region(&checks.regions, address_value).map_err(|message| runtime_error(thread_system, node, &message))?;
+  Ok(address_value)
+}
+
+// Whether the thread's last read returned the implicit value of an address no store or `init` line wrote.
+fn check_initialized<T: ThreadSystem, S: StorageSystem>(thread_system: &T, storage_system: &S, checks: &AddressChecks, node: &Node, address: i32) -> Result<(), RuntimeError> {
+  match &checks.initialized {
+    Some(initialized) if storage_system.read_from(node.thread_id).is_none() && !initialized.contains(&address) => {
+      Err(runtime_error(thread_system, node, &format!("read of uninitialized address #{}", address)))
+    }
+    _ => Ok(())
+  }
+}
+
+// Adds `inc` to the count of a semaphore or barrier as a fetch-and-add would, returning the count
+// it read and the access.
+fn count<T: ThreadSystem, S: StorageSystem>(thread_system: &mut T, storage_system: &mut S, checks: &AddressChecks, node: &Node, address: i32, inc: i32, mode: Mode) -> Result<(i32, Access), RuntimeError> {
+  let thread_id = node.thread_id;
+  let value = storage_system.fai(thread_id, address, FetchOp::Add, inc, mode)
+    .ok_or_else(|| runtime_error(thread_system, node, "arithmetic overflow"))?;
+  check_initialized(thread_system, storage_system, checks, node, address)?;
+  let written = value + inc;
+  storage_system.tag(thread_id, address, Origin { thread_id, line: node.instruction.line, value: written });
+  thread_system.add_propagate_node(thread_id, address, written);
+  Ok((value, Access { address, kind: AccessKind::Update, mode, value: written, source: storage_system.source(thread_id, address) }))
+}
+
+fn arith<T: ThreadSystem>(thread_system: &mut T, node: &Node, r1: String, r2: String, r3: String, op: fn(i32, i32) -> Option<i32>, message: &str) -> Result<(), RuntimeError> {
+  let r2_value = thread_system.get_register(node.thread_id, r2);
+  let r3_value = thread_system.get_register(node.thread_id, r3);
+  match op(r2_value, r3_value) {
+    Some(value) => {
+      thread_system.assign_register(node.thread_id, r1, value);
+      Ok(())
+    }
+    None => Err(runtime_error(thread_system, node, message))
+  }
+}
+
+// A thread stopped by the `barrier` or `wait` node: until the count at the address reaches
+// `target`, or until a `wake` of the address. Its buffered stores still propagate.
+#[derive(Clone)]
+pub enum Parked {
+  Barrier { node: Node, address: i32, target: i32 },
+  Futex { node: Node, address: i32 }
+}
+
+impl Parked {
+  fn parks<S: StorageSystem>(&self, storage_system: &S, thread_id: usize) -> bool {
+    match self {
+      Parked::Barrier { node: _, address, target } => storage_system.peek(thread_id, *address) < *target,
+      Parked::Futex { node: _, address: _ } => true
+    }
+  }
+}
+
+// Where each thread is parked, and the exclusive monitor of each thread: the address its last `ll`
+// reserved with the value it read there. Any write to the address by another thread clears the
+// monitor, and so does a `clrex` or, with `clear_on_switch`, another thread running in between.
+// The persistent copy of memory also keeps the flushes each thread has pending, and the race
+// detector the happens-before order of the accesses when races of `NA` accesses, or with
+// `relaxed_races` of `RLX` ones too, are checked.
+#[derive(Clone, Default)]
+struct ThreadStates {
+  parked: HashMap<usize, Parked>,
+  reservations: HashMap<usize, (i32, i32)>,
+  clear_on_switch: bool,
+  last_thread: Option<usize>,
+  persistence: Persistence,
+  races: Option<RaceDetector>,
+  relaxed_races: bool
+}
+
+impl Debug for ThreadStates {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    if !self.reservations.is_empty() {
+      writeln!(f, "# MONITORS")?;
+      let mut reservations: Vec<(&usize, &(i32, i32))> = self.reservations.iter().collect();
+      reservations.sort();
+      for (thread_id, (address, value)) in reservations {
+        writeln!(f, "| Thread {}: #{} = {}", thread_id, address, value)?;
+      }
+    }
+    write!(f, "{:?}", self.persistence)
+  }
+}
+
+fn still_parked<S: StorageSystem>(storage_system: &S, parked: &HashMap<usize, Parked>, thread_id: usize) -> Option<Parked> {
+  parked.get(&thread_id).filter(|parked| parked.parks(storage_system, thread_id)).cloned()
+}
+
+// Whether the node waits: for a lock another thread holds, for a semaphore its thread sees at zero
+// or, while its thread is parked, for a barrier to open or a `wake`.
+fn blocked<T: ThreadSystem, S: StorageSystem>(thread_system: &T, storage_system: &S, locks: &HashMap<i32, usize>, parked: &HashMap<usize, Parked>, node: &Node) -> bool {
+  let instruction = &node.instruction.instruction;
+  if let Some(parked) = parked.get(&node.thread_id) {
+    if !matches!(instruction, Instruction::Propagate { thread_id: _, address: _, value: _ }) && parked.parks(storage_system, node.thread_id) {
+      return true;
+    }
+  }
+  match instruction {
+    Instruction::Lock { address } => locks.contains_key(&thread_system.get_register(node.thread_id, address.clone())),
+    Instruction::SemWait { address } => storage_system.peek(node.thread_id, thread_system.get_register(node.thread_id, address.clone())) <= 0,
+    _ => false
+  }
+}
+
+fn enabled_executions<T: ThreadSystem, S: StorageSystem>(thread_system: &T, storage_system: &S, locks: &HashMap<i32, usize>, parked: &HashMap<usize, Parked>) -> Vec<Node> {
+  thread_system.get_possible_executions().into_iter()
+    .filter(|node| !blocked(thread_system, storage_system, locks, parked, node))
+    .collect()
+}
+
+// Wraps the text in an ANSI escape code, e.g. "36" for cyan, when colours are on.
+fn paint(text: String, code: &str, color: bool) -> String {
+  if color && !code.is_empty() {
+    format!("\x1b[{}m{}\x1b[0m", code, text)
+  } else {
+    text
+  }
+}
+
+// Memory accesses are bold and `propagate` steps, which move stores between threads, magenta.
+fn print_step<T: ThreadSystem>(thread_system: &T, node: &Node, color: bool) {
+  let code = match &node.instruction.instruction {
+    Instruction::Propagate { thread_id: _, address: _, value: _ } => "35",
+    Instruction::Load { mode: _, address: _, r: _ }
+    | Instruction::Store { mode: _, address: _, r: _ }
+    | Instruction::Cas { mode: _, failure: _, address: _, to: _, exp: _, des: _, flag: _ }
+    | Instruction::Fai { op: _, mode: _, address: _, to: _, inc: _ }
+    | Instruction::Lock { address: _ }
+    | Instruction::Unlock { address: _ }
+    | Instruction::SemWait { address: _ }
+    | Instruction::SemPost { address: _ }
+    | Instruction::Barrier { address: _, count: _ }
+    | Instruction::Wait { address: _, r: _ }
+    | Instruction::Wake { address: _, count: _ }
+    | Instruction::LoadLinked { address: _, r: _ }
+    | Instruction::StoreConditional { address: _, r: _, flag: _ }
+    | Instruction::Flush { address: _ } => "1",
+    _ => ""
+  };
+  println!("{}", paint(format!("{}: {:?}", thread_system.thread_name(node.thread_id), node), code, color));
+}
+
+// One trace line naming the store a load or update read, with its thread and source line. Reads
+// from another thread are cyan, reads from the thread's own buffer yellow and initial values dim.
+fn print_read_from<T: ThreadSystem, S: StorageSystem>(thread_system: &T, storage_system: &S, event: &Event, color: bool) {
+  if let Some(Access { address, kind: AccessKind::Read | AccessKind::Update, mode: _, value: accessed, source: _ }) = &event.access {
+    let forwarded = if storage_system.forwarded(event.node.thread_id) { ", forwarded from the buffer" } else { "" };
+    let code = |writer: usize| if !forwarded.is_empty() { "33" } else if writer != event.node.thread_id { "36" } else { "" };
+    match storage_system.read_from(event.node.thread_id) {
+      Some(Origin { thread_id, line: Some(line), value }) => println!("{}", paint(format!("# READ #{} = {} from thread {} (line {}){}", address, value, thread_system.thread_name(thread_id), line, forwarded), code(thread_id), color)),
+      Some(Origin { thread_id, line: None, value }) => println!("{}", paint(format!("# READ #{} = {} from thread {}{}", address, value, thread_system.thread_name(thread_id), forwarded), code(thread_id), color)),
+      None => {
+        // The access of an update holds the value it wrote; the one it read is in its register.
+        let value = match &event.node.instruction.instruction {
+          Instruction::Cas { mode: _, failure: _, address: _, to, exp: _, des: _, flag: _ } | Instruction::Fai { op: _, mode: _, address: _, to, inc: _ } => thread_system.get_register(event.node.thread_id, to.clone()),
+          _ => *accessed
+        };
+        println!("{}", paint(format!("# READ #{} = {} from initial value", address, value), "2", color))
+      }
+    }
+  }
+}
+
+// Which steps a trace shows: all of them, unless it is limited to memory instructions, to some
+// threads or to some addresses. A brief trace leaves out the state after each step.
+#[derive(Clone, Default, Debug)]
+pub struct TraceOptions {
+  pub brief: bool,
+  pub color: bool,
+  pub memory_only: bool,
+  pub threads: Vec<String>,
+  pub addresses: Vec<Range<i32>>
+}
+
+// A `propagate` step concerns both the writer and the thread it delivers to.
+fn traced<T: ThreadSystem>(thread_system: &T, options: &TraceOptions, node: &Node) -> bool {
+  let instruction = &node.instruction.instruction;
+  let (address, threads) = match instruction {
+    Instruction::Load { mode: _, address, r: _ }
+    | Instruction::Store { mode: _, address, r: _ }
+    | Instruction::Cas { mode: _, failure: _, address, to: _, exp: _, des: _, flag: _ }
+    | Instruction::Fai { op: _, mode: _, address, to: _, inc: _ }
+    | Instruction::Lock { address }
+    | Instruction::Unlock { address }
+    | Instruction::SemWait { address }
+    | Instruction::SemPost { address }
+    | Instruction::Barrier { address, count: _ }
+    | Instruction::Wait { address, r: _ }
+    | Instruction::Wake { address, count: _ }
+    | Instruction::LoadLinked { address, r: _ }
+    | Instruction::StoreConditional { address, r: _, flag: _ }
+    | Instruction::Flush { address } => (Some(thread_system.get_register(node.thread_id, address.clone())), vec![node.thread_id]),
+    Instruction::Propagate { thread_id: writer, address, value: _ } => (Some(*address), vec![node.thread_id, *writer]),
+    _ => (None, vec![node.thread_id])
+  };
+  let memory = address.is_some() || matches!(instruction, Instruction::Fence { mode: _, order: _ }) || instruction.is_transactional();
+  (!options.memory_only || memory)
+    && (options.threads.is_empty() || threads.iter().any(|thread_id| options.threads.iter().any(|name| name == thread_system.thread_name(*thread_id))))
+    && (options.addresses.is_empty() || address.is_some_and(|address| options.addresses.iter().any(|range| range.contains(&address))))
+}
+
+// Whether a memory instruction runs ahead of one that comes before it in its thread.
+fn overtakes<T: ThreadSystem>(thread_system: &T, node: &Node) -> bool {
+  let graph = thread_system.graph();
+  node.instruction.get_mode().is_some() && graph.instructions.iter().take(node.id)
+    .any(|other| other.thread_id == node.thread_id && graph.is_node_active(other.id) && other.instruction.get_mode().is_some())
+}
+
+fn record<T: ThreadSystem, S: StorageSystem>(thread_system: &T, storage_system: &S, statistics: &mut Statistics, event: &Event, buffered: usize) {
+  let thread_id = event.node.thread_id;
+  statistics.steps += 1;
+  if let Some(access) = &event.access {
+    if access.kind.is_write() && storage_system.buffer(thread_id).len() > buffered {
+      statistics.buffered_stores += 1;
+    }
+    if matches!(access.kind, AccessKind::Read | AccessKind::Update) && storage_system.forwarded(thread_id) {
+      statistics.forwarded_loads += 1;
+    }
+  }
+  for thread_id in 0..thread_system.number_of_threads() {
+    statistics.max_buffer_depth = statistics.max_buffer_depth.max(storage_system.buffer(thread_id).len());
+  }
+}
+
+fn execute<T: ThreadSystem, S: StorageSystem>(thread_system: &mut T, storage_system: &mut Transactional<S>, locks: &mut HashMap<i32, usize>, states: &mut ThreadStates, checks: &AddressChecks, statistics: &mut Statistics, node: Node) -> Result<Event, RuntimeError> {
+  let reordered = overtakes(thread_system, &node);
+  let buffered = storage_system.buffer(node.thread_id).len();
+  let event = apply(thread_system, storage_system, locks, states, checks, node)?;
+  if reordered {
+    statistics.reorderings += 1;
+  }
+  record(thread_system, storage_system, statistics, &event, buffered);
+  if let Some(detector) = states.races.as_mut() {
+    let known = detector.races().len();
+    detector.on_event(&event);
+    if let Some(race) = detector.races()[known..].iter().find(|race| states.relaxed_races || race.undefined()) {
+      let other = &race.first.node;
+      let line = other.instruction.line.map_or(String::new(), |line| format!(" (line {})", line));
+      let undefined = if race.undefined() { "undefined behavior: " } else { "" };
+      let message = format!("{}data race on #{} with thread {} at {:?}{}", undefined, race.address, thread_system.thread_name(other.thread_id), other.instruction, line);
+      return Err(runtime_error(thread_system, &event.node, &message));
+    }
+  }
+  Ok(event)
+}
+
+fn apply<T: ThreadSystem, S: StorageSystem>(thread_system: &mut T, storage_system: &mut Transactional<S>, locks: &mut HashMap<i32, usize>, states: &mut ThreadStates, checks: &AddressChecks, node: Node) -> Result<Event, RuntimeError> {
+  thread_system.remove_node(&node);
+  let thread_id = node.thread_id;
+  let mut access = None;
+  if !matches!(node.instruction.instruction, Instruction::Propagate { thread_id: _, address: _, value: _ }) {
+    // A thread switched away from loses its monitor, as on a context switch.
+    if let Some(last) = states.last_thread.replace(thread_id).filter(|last| states.clear_on_switch && *last != thread_id) {
+      states.reservations.remove(&last);
+    }
+    match states.parked.remove(&thread_id) {
+      Some(Parked::Barrier { node: _, address, target }) if storage_system.peek(thread_id, address) < target => {
+        return Err(runtime_error(thread_system, &node, &format!("thread is waiting at the barrier at #{}", address)));
+      }
+      Some(Parked::Barrier { node: _, address, target: _ }) => storage_system.acquire(thread_id, address),
+      Some(Parked::Futex { node: _, address }) => {
+        return Err(runtime_error(thread_system, &node, &format!("thread is waiting for a wake of #{}", address)));
+      }
+      None => {}
+    }
+    let instruction = &node.instruction.instruction;
+    if storage_system.in_transaction(thread_id) && (instruction.is_blocking() || instruction.is_exclusive()
+      || matches!(instruction, Instruction::Lock { address: _ } | Instruction::Unlock { address: _ } | Instruction::Flush { address: _ })) {
+      return Err(runtime_error(thread_system, &node, "instruction cannot run inside a transaction"));
+    }
+  }
+  match node.instruction.instruction.clone() {
+    Instruction::Const { r, value } => {
+      thread_system.assign_register(thread_id, r, value);
+    }
+    Instruction::ArithPlus { r1, r2, r3 } => {
+      arith(thread_system, &node, r1, r2, r3, i32::checked_add, "arithmetic overflow")?;
+    }
+    Instruction::ArithMinus { r1, r2, r3 } => {
+      arith(thread_system, &node, r1, r2, r3, i32::checked_sub, "arithmetic overflow")?;
+    }
+    Instruction::ArithMul { r1, r2, r3 } => {
+      arith(thread_system, &node, r1, r2, r3, i32::checked_mul, "arithmetic overflow")?;
+    }
+    Instruction::ArithDiv { r1, r2, r3 } => {
+      if thread_system.get_register(thread_id, r3.clone()) == 0 {
+        return Err(runtime_error(thread_system, &node, "division by zero"));
+      }
+      arith(thread_system, &node, r1, r2, r3, i32::checked_div, "arithmetic overflow")?;
+    }
+    Instruction::Select { r1, r2, r3, r4 } => {
+      let chosen = if thread_system.get_register(thread_id, r2) != 0 { r3 } else { r4 };
+      let value = thread_system.get_register(thread_id, chosen);
+      thread_system.assign_register(thread_id, r1, value);
+    }
+    Instruction::Cond { r, target: _ } => {
+      let value = thread_system.get_register(thread_id, r);
+      if value != 0 {
+        thread_system.rollback(node.id);
+        thread_system.jump(&node);
+      }
+    }
+    Instruction::Call { target: _ } | Instruction::Ret => {
+      thread_system.jump(&node);
+    }
+    Instruction::Push { r } => {
+      let value = thread_system.get_register(thread_id, r);
+      storage_system.push(thread_id, value);
+    }
+    Instruction::Pop { r } => {
+      let value = storage_system.pop(thread_id)
+        .ok_or_else(|| runtime_error(thread_system, &node, "pop from an empty stack"))?;
+      thread_system.assign_register(thread_id, r, value);
+    }
+    Instruction::Load { mode, address, r } => {
+      let address_value = get_address(thread_system, checks, &node, address)?;
+      let value = storage_system.load(thread_id, address_value, mode);
+      check_initialized(thread_system, storage_system, checks, &node, address_value)?;
+      thread_system.assign_register(thread_id, r, value);
+      access = Some(Access { address: address_value, kind: AccessKind::Read, mode, value, source: storage_system.source(thread_id, address_value) });
+    }
+    Instruction::Store { mode, address, r } => {
+      let address_value = get_address(thread_system, checks, &node, address)?;
+      let value = thread_system.get_register(thread_id, r);
+      storage_system.store(thread_id, address_value, value, mode);
+      storage_system.tag(thread_id, address_value, Origin { thread_id, line: node.instruction.line, value });
+      if !storage_system.in_transaction(thread_id) {
+        thread_system.add_propagate_node(thread_id, address_value, value);
+      }
+      access = Some(Access { address: address_value, kind: AccessKind::Write, mode, value, source: storage_system.source(thread_id, address_value) });
+    }
+    Instruction::Cas { mode, failure, address, to, exp, des, flag } => {
+      let address_value = get_address(thread_system, checks, &node, address)?;
+      let exp_value = thread_system.get_register(thread_id, exp);
+      let des_value = thread_system.get_register(thread_id, des);
+      let value = storage_system.cas(thread_id, address_value, exp_value, des_value, mode, failure);
+      check_initialized(thread_system, storage_system, checks, &node, address_value)?;
+      if value == exp_value {
+        storage_system.tag(thread_id, address_value, Origin { thread_id, line: node.instruction.line, value: des_value });
+        if !storage_system.in_transaction(thread_id) {
+          thread_system.add_propagate_node(thread_id, address_value, des_value);
+        }
+        access = Some(Access { address: address_value, kind: AccessKind::Update, mode, value: des_value, source: storage_system.source(thread_id, address_value) });
+      } else {
+        access = Some(Access { address: address_value, kind: AccessKind::Read, mode: failure, value, source: storage_system.source(thread_id, address_value) });
+      }
+      thread_system.assign_register(thread_id, to, value);
+      if let Some(flag) = flag {
+        thread_system.assign_register(thread_id, flag, (value == exp_value) as i32);
+      }
+    }
+    Instruction::Fai { op, mode, address, to, inc } => {
+      let address_value = get_address(thread_system, checks, &node, address)?;
+      let inc_value = thread_system.get_register(thread_id, inc);
+      let value = match storage_system.fai(thread_id, address_value, op, inc_value, mode) {
+        Some(value) => value,
+        None => return Err(runtime_error(thread_system, &node, "arithmetic overflow"))
+      };
+      check_initialized(thread_system, storage_system, checks, &node, address_value)?;
+      let written = op.apply(value, inc_value).unwrap();
+      storage_system.tag(thread_id, address_value, Origin { thread_id, line: node.instruction.line, value: written });
+      thread_system.assign_register(thread_id, to, value);
+      if !storage_system.in_transaction(thread_id) {
+        thread_system.add_propagate_node(thread_id, address_value, written);
+      }
+      access = Some(Access { address: address_value, kind: AccessKind::Update, mode, value: written, source: storage_system.source(thread_id, address_value) });
+    }
+    Instruction::Fence { mode, order: _ } => {
+      storage_system.fence(thread_id, mode);
+      if node.instruction.instruction.completes_flushes() {
+        states.persistence.complete(thread_id);
+      }
+    }
+    Instruction::Lock { address } => {
+      let address_value = get_address(thread_system, checks, &node, address)?;
+      if let Some(owner) = locks.get(&address_value) {
+        let message = format!("lock is held by thread {}", thread_system.thread_name(*owner));
+        return Err(runtime_error(thread_system, &node, &message));
+      }
+      locks.insert(address_value, thread_id);
+      storage_system.fence(thread_id, Mode::SeqCst);
+      access = Some(Access { address: address_value, kind: AccessKind::Lock, mode: Mode::Acq, value: 0, source: None });
+    }
+    Instruction::Unlock { address } => {
+      let address_value = get_address(thread_system, checks, &node, address)?;
+      if locks.get(&address_value) != Some(&thread_id) {
+        return Err(runtime_error(thread_system, &node, "lock is not held by the thread"));
+      }
+      storage_system.fence(thread_id, Mode::SeqCst);
+      locks.remove(&address_value);
+      access = Some(Access { address: address_value, kind: AccessKind::Unlock, mode: Mode::Rel, value: 0, source: None });
+    }
+    Instruction::SemWait { address } => {
+      let address_value = get_address(thread_system, checks, &node, address)?;
+      if storage_system.peek(thread_id, address_value) <= 0 {
+        return Err(runtime_error(thread_system, &node, "semaphore count is not positive"));
+      }
+      access = Some(count(thread_system, storage_system, checks, &node, address_value, -1, Mode::Acq)?.1);
+    }
+    Instruction::SemPost { address } => {
+      let address_value = get_address(thread_system, checks, &node, address)?;
+      access = Some(count(thread_system, storage_system, checks, &node, address_value, 1, Mode::Rel)?.1);
+    }
+    Instruction::Barrier { address, count: arrivals } => {
+      let address_value = get_address(thread_system, checks, &node, address)?;
+      let (value, barrier_access) = count(thread_system, storage_system, checks, &node, address_value, 1, Mode::RelAcq)?;
+      // The barrier opens once the count reaches the next multiple of its arrivals, so it can be reused.
+      let target = (value.div_euclid(arrivals) + 1).checked_mul(arrivals)
+        .ok_or_else(|| runtime_error(thread_system, &node, "arithmetic overflow"))?;
+      states.parked.insert(thread_id, Parked::Barrier { node: node.clone(), address: address_value, target });
+      access = Some(barrier_access);
+    }
+    Instruction::Wait { address, r } => {
+      let address_value = get_address(thread_system, checks, &node, address)?;
+      let value = storage_system.load(thread_id, address_value, Mode::Acq);
+      check_initialized(thread_system, storage_system, checks, &node, address_value)?;
+      // A thread that reads another value goes on at once, like a futex wait that finds the word changed.
+      if value == thread_system.get_register(thread_id, r) {
+        states.parked.insert(thread_id, Parked::Futex { node: node.clone(), address: address_value });
+      }
+      access = Some(Access { address: address_value, kind: AccessKind::Read, mode: Mode::Acq, value, source: storage_system.source(thread_id, address_value) });
+    }
+    Instruction::Wake { address, count } => {
+      let address_value = get_address(thread_system, checks, &node, address)?;
+      let mut sleepers: Vec<usize> = states.parked.iter()
+        .filter(|(_, parked)| matches!(parked, Parked::Futex { node: _, address } if *address == address_value))
+        .map(|(thread_id, _)| *thread_id)
+        .collect();
+      sleepers.sort();
+      for sleeper in sleepers.into_iter().take(count as usize) {
+        states.parked.remove(&sleeper);
+      }
+    }
+    Instruction::LoadLinked { address, r } => {
+      let address_value = get_address(thread_system, checks, &node, address)?;
+      let value = storage_system.load(thread_id, address_value, Mode::Rlx);
+      check_initialized(thread_system, storage_system, checks, &node, address_value)?;
+      thread_system.assign_register(thread_id, r, value);
+      states.reservations.insert(thread_id, (address_value, value));
+      access = Some(Access { address: address_value, kind: AccessKind::Read, mode: Mode::Rlx, value, source: storage_system.source(thread_id, address_value) });
+    }
+    Instruction::StoreConditional { address, r, flag } => {
+      let address_value = get_address(thread_system, checks, &node, address)?;
+      // Every `sc` uses up the reservation, so a second one fails until the next `ll`. The store is
+      // a swap from the value the `ll` read, which also fails when that value was already stale.
+      let mut stored = false;
+      if let Some((_, linked)) = states.reservations.remove(&thread_id).filter(|(reserved, _)| *reserved == address_value) {
+        let value = thread_system.get_register(thread_id, r);
+        let read = storage_system.cas(thread_id, address_value, linked, value, Mode::Rlx, Mode::Rlx);
+        stored = read == linked;
+        if stored {
+          storage_system.tag(thread_id, address_value, Origin { thread_id, line: node.instruction.line, value });
+          thread_system.add_propagate_node(thread_id, address_value, value);
+          access = Some(Access { address: address_value, kind: AccessKind::Update, mode: Mode::Rlx, value, source: storage_system.source(thread_id, address_value) });
+        } else {
+          access = Some(Access { address: address_value, kind: AccessKind::Read, mode: Mode::Rlx, value: read, source: storage_system.source(thread_id, address_value) });
+        }
+      }
+      thread_system.assign_register(thread_id, flag, stored as i32);
+    }
+    Instruction::ClearExclusive => {
+      states.reservations.remove(&thread_id);
+    }
+    Instruction::Flush { address } => {
+      let address_value = get_address(thread_system, checks, &node, address)?;
+      states.persistence.flush(thread_id, address_value);
+    }
+    Instruction::TxBegin { r } => {
+      if storage_system.in_transaction(thread_id) {
+        return Err(runtime_error(thread_system, &node, "transactions cannot be nested"));
+      }
+      storage_system.begin(thread_id, node.clone(), r.clone(), thread_system.registers(thread_id).clone());
+      thread_system.assign_register(thread_id, r, 0);
+    }
+    Instruction::TxCommit => {
+      if !storage_system.commit(thread_id) {
+        return Err(runtime_error(thread_system, &node, "no transaction to commit"));
+      }
+    }
+    Instruction::TxAbort => {
+      if !storage_system.in_transaction(thread_id) {
+        return Err(runtime_error(thread_system, &node, "no transaction to abort"));
+      }
+      storage_system.abort(thread_id, EXPLICIT);
+    }
+    Instruction::Propagate { thread_id: writer, address, value: _ } => {
+      storage_system.propagate(writer, address, thread_id);
+    }
+  }
+  // An aborted transaction takes its thread back to just after its `txbegin`.
+  for aborted in storage_system.take_aborted() {
+    thread_system.set_registers(aborted.thread_id, aborted.registers);
+    thread_system.assign_register(aborted.thread_id, aborted.status, aborted.code);
+    thread_system.restart(&aborted.begin);
+  }
+  if let Some(Access { address, kind, mode: _, value: _, source: _ }) = &access {
+    if kind.is_write() {
+      states.reservations.retain(|holder, (reserved, _)| *holder == thread_id || reserved != address);
+    }
+  }
+  states.persistence.observe(storage_system.memory(), storage_system.default_value());
+  Ok(Event { node, access })
+}
+
+#[derive(Clone)]
+pub struct SC<S: StorageSystem = SCStorageSystem> {
+  thread_system: SCThreadSystem,
+  storage_system: Transactional<S>,
+  scheduler: RandomScheduler,
+  locks: HashMap<i32, usize>,
+  states: ThreadStates,
+  checks: AddressChecks,
+  trace_options: TraceOptions,
+  statistics: Statistics
+}
+
+impl SC {
+  pub fn new(instructions: Vec<Vec<LabeledInstruction>>) -> SC {
+    SC::with_storage(instructions, SCStorageSystem::new())
+  }
+}
+
+// With another storage in place of the model's own, such as one that logs the accesses or writes
+// through, under the same thread ordering.
+impl<S: StorageSystem> SC<S> {
+  pub fn with_storage(instructions: Vec<Vec<LabeledInstruction>>, storage: S) -> SC<S> {
+    SC {
+      thread_system: SCThreadSystem::new(instructions),
+      storage_system: Transactional::new(storage),
+      scheduler: RandomScheduler::new(),
+      locks: HashMap::new(),
+      states: ThreadStates::default(),
+      checks: AddressChecks::default(),
+      trace_options: TraceOptions::default(),
+      statistics: Statistics::new()
+    }
+  }
+}
+
+impl<S: StorageSystem + Debug> Debug for SC<S> {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(f, "{:?}", self.thread_system)?;
+    write!(f, "{:?}", self.storage_system)?;
+    write!(f, "{:?}", self.states)
+  }
+}
+
+impl<S: StorageSystem + Clone + Debug + 'static> MemoryModel for SC<S> {
+    fn get_possible_executions(&self) -> Vec<Node> {
+      enabled_executions(&self.thread_system, &self.storage_system, &self.locks, &self.states.parked)
+    }
+
+    fn random_step(&mut self, debug_print: bool) -> Result<Option<Event>, RuntimeError> {
+      let executions = self.get_possible_executions();
+      let execution = match self.scheduler.choose(&executions) {
+        Some(execution) => execution,
+        None => return Ok(None)
+      };
+      self.step(execution, debug_print).map(Some)
+    }
+
+    fn set_seed(&mut self, seed: u64) {
+      self.scheduler.set_seed(seed);
+    }
+
+    fn set_rng(&mut self, rng: SmallRng) {
+      self.scheduler.set_rng(rng);
+    }
+
+    fn set_scheduler_options(&mut self, options: SchedulerOptions) {
+      self.scheduler.set_options(options);
+    }
+
+    fn set_latencies(&mut self, latencies: Latencies) {
+      self.scheduler.set_latencies(latencies);
+    }
+
+    fn set_recycling(&mut self) {
+      self.thread_system.set_recycling();
+    }
+
+    fn append_instruction(&mut self, thread_id: usize, instruction: LabeledInstruction) {
+      self.thread_system.append_instruction(thread_id, instruction);
+    }
+
+    fn number_of_threads(&self) -> usize {
+      self.thread_system.number_of_threads()
+    }
+
+    fn registers(&self, thread_id: usize) -> &HashMap<String, i32> {
+      self.thread_system.registers(thread_id)
+    }
+
+    fn memory(&self) -> &HashMap<i32, i32> {
+      self.storage_system.memory()
+    }
+
+    fn initialize(&mut self, address: i32, value: i32) {
+      self.storage_system.initialize(address, value);
+    }
+
+    fn set_strict_memory(&mut self, initialized: HashSet<i32>) {
+      self.checks.initialized = Some(initialized);
+    }
+
+    fn set_default_memory(&mut self, value: i32) {
+      self.storage_system.set_default(value);
+    }
+
+    fn set_regions(&mut self, regions: Vec<Region>) {
+      self.checks.regions = regions;
+    }
+
+    fn set_clear_monitors(&mut self) {
+      self.states.clear_on_switch = true;
+    }
+
+    fn set_race_checks(&mut self, relaxed: bool) {
+      self.states.races = Some(RaceDetector::new(self.thread_system.number_of_threads()));
+      self.states.relaxed_races = relaxed;
+    }
+
+    fn set_persistent(&mut self, addresses: BTreeSet<i32>) {
+      self.states.persistence = Persistence::new(&addresses, self.storage_system.memory(), self.storage_system.default_value());
+    }
+
+    fn persistence(&self) -> &Persistence {
+      &self.states.persistence
+    }
+
+    fn set_trace_options(&mut self, options: TraceOptions) {
+      self.trace_options = options;
+    }
+
+    fn default_memory(&self) -> i32 {
+      self.storage_system.default_value()
+    }
+
+    fn buffer(&self, thread_id: usize) -> &[(i32, i32)] {
+      self.storage_system.buffer(thread_id)
+    }
+
+    fn set_thread_names(&mut self, thread_names: Vec<String>) {
+      self.storage_system.inner.set_thread_names(thread_names.clone());
+      self.thread_system.set_thread_names(thread_names);
+    }
+
+    fn thread_name(&self, thread_id: usize) -> &str {
+      self.thread_system.thread_name(thread_id)
+    }
+
+    fn graph(&self) -> &Graph {
+      self.thread_system.graph()
+    }
+
+    system_accessors!();
+
+    fn pending_executions(&self) -> Vec<Node> {
+      self.thread_system.get_possible_executions()
+    }
+
+    fn lock_owner(&self, address: i32) -> Option<usize> {
+      self.locks.get(&address).cloned()
+    }
+
+    fn parked(&self, thread_id: usize) -> Option<Parked> {
+      still_parked(&self.storage_system, &self.states.parked, thread_id)
+    }
+
+    fn statistics(&self) -> &Statistics {
+      &self.statistics
+    }
+
+    fn snapshot(&self) -> Box<dyn MemoryModel> {
+      Box::new(self.clone())
+    }
+
+    fn step(&mut self, node: Node, debug_print: bool) -> Result<Event, RuntimeError> {
+      let debug_print = debug_print && traced(&self.thread_system, &self.trace_options, &node);
+      if debug_print {
+        print_step(&self.thread_system, &node, self.trace_options.color);
+      }
+      let event = execute(&mut self.thread_system, &mut self.storage_system, &mut self.locks, &mut self.states, &self.checks, &mut self.statistics, node)?;
+      if debug_print {
+        print_read_from(&self.thread_system, &self.storage_system, &event, self.trace_options.color);
+        if !self.trace_options.brief {
+          print!("{:?}", self.thread_system);
+          print!("{:?}", self.states);
+          println!("{:?}", self.storage_system);
+        }
+      }
+      Ok(event)
+    }
 }
 
-pub struct SC {
-  thread_system: SCThreadSystem,
-  storage_system: SCStorageSystem
-}
+#[derive(Clone)]
+pub struct TSO<S: StorageSystem = TSOStorageSystem> {
+  thread_system: TSOThreadSystem,
+  storage_system: Transactional<S>,
+  scheduler: RandomScheduler,
+  locks: HashMap<i32, usize>,
+  states: ThreadStates,
+  checks: AddressChecks,
+  trace_options: TraceOptions,
+  statistics: Statistics
+}
+
+impl TSO {
+  pub fn new(instructions: Vec<Vec<LabeledInstruction>>) -> TSO {
+    let storage = TSOStorageSystem::new(instructions.len());
+    TSO::with_storage(instructions, storage)
+  }
+}
+
+impl<S: StorageSystem> TSO<S> {
+  pub fn with_storage(instructions: Vec<Vec<LabeledInstruction>>, storage: S) -> TSO<S> {
+    TSO {
+      storage_system: Transactional::new(storage),
+      thread_system: TSOThreadSystem::new(instructions),
+      scheduler: RandomScheduler::new(),
+      locks: HashMap::new(),
+      states: ThreadStates::default(),
+      checks: AddressChecks::default(),
+      trace_options: TraceOptions::default(),
+      statistics: Statistics::new()
+    }
+  }
+}
+
+impl<S: StorageSystem + Debug> Debug for TSO<S> {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(f, "{:?}", self.thread_system)?;
+    write!(f, "{:?}", self.storage_system)?;
+    write!(f, "{:?}", self.states)
+  }
+}
+
+impl<S: StorageSystem + Clone + Debug + 'static> MemoryModel for TSO<S> {
+    fn get_possible_executions(&self) -> Vec<Node> {
+      enabled_executions(&self.thread_system, &self.storage_system, &self.locks, &self.states.parked)
+    }
+
+    fn random_step(&mut self, debug_print: bool) -> Result<Option<Event>, RuntimeError> {
+      let executions = self.get_possible_executions();
+      let execution = match self.scheduler.choose(&executions) {
+        Some(execution) => execution,
+        None => return Ok(None)
+      };
+      self.step(execution, debug_print).map(Some)
+    }
+
+    fn set_seed(&mut self, seed: u64) {
+      self.scheduler.set_seed(seed);
+    }
+
+    fn set_rng(&mut self, rng: SmallRng) {
+      self.scheduler.set_rng(rng);
+    }
+
+    fn set_scheduler_options(&mut self, options: SchedulerOptions) {
+      self.scheduler.set_options(options);
+    }
+
+    fn set_latencies(&mut self, latencies: Latencies) {
+      self.scheduler.set_latencies(latencies);
+    }
+
+    fn set_recycling(&mut self) {
+      self.thread_system.set_recycling();
+    }
+
+    fn append_instruction(&mut self, thread_id: usize, instruction: LabeledInstruction) {
+      self.thread_system.append_instruction(thread_id, instruction);
+    }
+
+    fn number_of_threads(&self) -> usize {
+      self.thread_system.number_of_threads()
+    }
+
+    fn registers(&self, thread_id: usize) -> &HashMap<String, i32> {
+      self.thread_system.registers(thread_id)
+    }
+
+    fn memory(&self) -> &HashMap<i32, i32> {
+      self.storage_system.memory()
+    }
+
+    fn initialize(&mut self, address: i32, value: i32) {
+      self.storage_system.initialize(address, value);
+    }
+
+    fn set_strict_memory(&mut self, initialized: HashSet<i32>) {
+      self.checks.initialized = Some(initialized);
+    }
+
+    fn set_default_memory(&mut self, value: i32) {
+      self.storage_system.set_default(value);
+    }
+
+    fn set_regions(&mut self, regions: Vec<Region>) {
+      self.checks.regions = regions;
+    }
+
+    fn set_clear_monitors(&mut self) {
+      self.states.clear_on_switch = true;
+    }
+
+    fn set_race_checks(&mut self, relaxed: bool) {
+      self.states.races = Some(RaceDetector::new(self.thread_system.number_of_threads()));
+      self.states.relaxed_races = relaxed;
+    }
+
+    fn set_persistent(&mut self, addresses: BTreeSet<i32>) {
+      self.states.persistence = Persistence::new(&addresses, self.storage_system.memory(), self.storage_system.default_value());
+    }
+
+    fn persistence(&self) -> &Persistence {
+      &self.states.persistence
+    }
+
+    fn set_trace_options(&mut self, options: TraceOptions) {
+      self.trace_options = options;
+    }
+
+    fn default_memory(&self) -> i32 {
+      self.storage_system.default_value()
+    }
+
+    fn buffer(&self, thread_id: usize) -> &[(i32, i32)] {
+      self.storage_system.buffer(thread_id)
+    }
+
+    fn set_thread_names(&mut self, thread_names: Vec<String>) {
+      self.storage_system.inner.set_thread_names(thread_names.clone());
+      self.thread_system.set_thread_names(thread_names);
+    }
+
+    fn thread_name(&self, thread_id: usize) -> &str {
+      self.thread_system.thread_name(thread_id)
+    }
+
+    fn graph(&self) -> &Graph {
+      self.thread_system.graph()
+    }
+
+    system_accessors!();
+
+    fn pending_executions(&self) -> Vec<Node> {
+      self.thread_system.get_possible_executions()
+    }
+
+    fn lock_owner(&self, address: i32) -> Option<usize> {
+      self.locks.get(&address).cloned()
+    }
+
+    fn parked(&self, thread_id: usize) -> Option<Parked> {
+      still_parked(&self.storage_system, &self.states.parked, thread_id)
+    }
+
+    fn statistics(&self) -> &Statistics {
+      &self.statistics
+    }
+
+    fn snapshot(&self) -> Box<dyn MemoryModel> {
+      Box::new(self.clone())
+    }
+
+    fn step(&mut self, node: Node, debug_print: bool) -> Result<Event, RuntimeError> {
+      let debug_print = debug_print && traced(&self.thread_system, &self.trace_options, &node);
+      if debug_print {
+        print_step(&self.thread_system, &node, self.trace_options.color);
+      }
+      let event = execute(&mut self.thread_system, &mut self.storage_system, &mut self.locks, &mut self.states, &self.checks, &mut self.statistics, node)?;
+      if debug_print {
+        print_read_from(&self.thread_system, &self.storage_system, &event, self.trace_options.color);
+        if !self.trace_options.brief {
+          print!("{:?}", self.thread_system);
+          print!("{:?}", self.states);
+          println!("{:?}", self.storage_system);
+        }
+      }
+      Ok(event)
+    }
+}
+
+#[derive(Clone)]
+pub struct PSO<S: StorageSystem = PSOStorageSystem> {
+  thread_system: PSOThreadSystem,
+  storage_system: Transactional<S>,
+  scheduler: RandomScheduler,
+  locks: HashMap<i32, usize>,
+  states: ThreadStates,
+  checks: AddressChecks,
+  trace_options: TraceOptions,
+  statistics: Statistics
+}
+
+impl PSO {
+  pub fn new(instructions: Vec<Vec<LabeledInstruction>>) -> PSO {
+    let storage = PSOStorageSystem::new(instructions.len());
+    PSO::with_storage(instructions, storage)
+  }
+}
+
+impl<S: StorageSystem> PSO<S> {
+  pub fn with_storage(instructions: Vec<Vec<LabeledInstruction>>, storage: S) -> PSO<S> {
+    PSO {
+      storage_system: Transactional::new(storage),
+      thread_system: PSOThreadSystem::new(instructions),
+      scheduler: RandomScheduler::new(),
+      locks: HashMap::new(),
+      states: ThreadStates::default(),
+      checks: AddressChecks::default(),
+      trace_options: TraceOptions::default(),
+      statistics: Statistics::new()
+    }
+  }
+}
+
+impl<S: StorageSystem + Debug> Debug for PSO<S> {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(f, "{:?}", self.thread_system)?;
+    write!(f, "{:?}", self.storage_system)?;
+    write!(f, "{:?}", self.states)
+  }
+}
+
+impl<S: StorageSystem + Clone + Debug + 'static> MemoryModel for PSO<S> {
+    fn get_possible_executions(&self) -> Vec<Node> {
+      enabled_executions(&self.thread_system, &self.storage_system, &self.locks, &self.states.parked)
+    }
+
+    fn random_step(&mut self, debug_print: bool) -> Result<Option<Event>, RuntimeError> {
+      let executions = self.get_possible_executions();
+      let execution = match self.scheduler.choose(&executions) {
+        Some(execution) => execution,
+        None => return Ok(None)
+      };
+      self.step(execution, debug_print).map(Some)
+    }
+
+    fn set_seed(&mut self, seed: u64) {
+      self.scheduler.set_seed(seed);
+    }
+
+    fn set_rng(&mut self, rng: SmallRng) {
+      self.scheduler.set_rng(rng);
+    }
+
+    fn set_scheduler_options(&mut self, options: SchedulerOptions) {
+      self.scheduler.set_options(options);
+    }
+
+    fn set_latencies(&mut self, latencies: Latencies) {
+      self.scheduler.set_latencies(latencies);
+    }
+
+    fn set_recycling(&mut self) {
+      self.thread_system.set_recycling();
+    }
+
+    fn append_instruction(&mut self, thread_id: usize, instruction: LabeledInstruction) {
+      self.thread_system.append_instruction(thread_id, instruction);
+    }
+
+    fn number_of_threads(&self) -> usize {
+      self.thread_system.number_of_threads()
+    }
+
+    fn registers(&self, thread_id: usize) -> &HashMap<String, i32> {
+      self.thread_system.registers(thread_id)
+    }
+
+    fn memory(&self) -> &HashMap<i32, i32> {
+      self.storage_system.memory()
+    }
+
+    fn initialize(&mut self, address: i32, value: i32) {
+      self.storage_system.initialize(address, value);
+    }
+
+    fn set_strict_memory(&mut self, initialized: HashSet<i32>) {
+      self.checks.initialized = Some(initialized);
+    }
+
+    fn set_default_memory(&mut self, value: i32) {
+      self.storage_system.set_default(value);
+    }
+
+    fn set_regions(&mut self, regions: Vec<Region>) {
+      self.checks.regions = regions;
+    }
+
+    fn set_clear_monitors(&mut self) {
+      self.states.clear_on_switch = true;
+    }
+
+    fn set_race_checks(&mut self, relaxed: bool) {
+      self.states.races = Some(RaceDetector::new(self.thread_system.number_of_threads()));
+      self.states.relaxed_races = relaxed;
+    }
+
+    fn set_persistent(&mut self, addresses: BTreeSet<i32>) {
+      self.states.persistence = Persistence::new(&addresses, self.storage_system.memory(), self.storage_system.default_value());
+    }
+
+    fn persistence(&self) -> &Persistence {
+      &self.states.persistence
+    }
+
+    fn set_trace_options(&mut self, options: TraceOptions) {
+      self.trace_options = options;
+    }
+
+    fn default_memory(&self) -> i32 {
+      self.storage_system.default_value()
+    }
+
+    fn buffer(&self, thread_id: usize) -> &[(i32, i32)] {
+      self.storage_system.buffer(thread_id)
+    }
+
+    fn set_thread_names(&mut self, thread_names: Vec<String>) {
+      self.storage_system.inner.set_thread_names(thread_names.clone());
+      self.thread_system.set_thread_names(thread_names);
+    }
+
+    fn thread_name(&self, thread_id: usize) -> &str {
+      self.thread_system.thread_name(thread_id)
+    }
+
+    fn graph(&self) -> &Graph {
+      self.thread_system.graph()
+    }
+
+    system_accessors!();
+
+    fn pending_executions(&self) -> Vec<Node> {
+      self.thread_system.get_possible_executions()
+    }
+
+    fn lock_owner(&self, address: i32) -> Option<usize> {
+      self.locks.get(&address).cloned()
+    }
+
+    fn parked(&self, thread_id: usize) -> Option<Parked> {
+      still_parked(&self.storage_system, &self.states.parked, thread_id)
+    }
+
+    fn statistics(&self) -> &Statistics {
+      &self.statistics
+    }
+
+    fn snapshot(&self) -> Box<dyn MemoryModel> {
+      Box::new(self.clone())
+    }
+
+    fn step(&mut self, node: Node, debug_print: bool) -> Result<Event, RuntimeError> {
+      let debug_print = debug_print && traced(&self.thread_system, &self.trace_options, &node);
+      if debug_print {
+        print_step(&self.thread_system, &node, self.trace_options.color);
+      }
+      let event = execute(&mut self.thread_system, &mut self.storage_system, &mut self.locks, &mut self.states, &self.checks, &mut self.statistics, node)?;
+      if debug_print {
+        print_read_from(&self.thread_system, &self.storage_system, &event, self.trace_options.color);
+        if !self.trace_options.brief {
+          print!("{:?}", self.thread_system);
+          print!("{:?}", self.states);
+          println!("{:?}", self.storage_system);
+        }
+      }
+      Ok(event)
+    }
+}
+
+#[derive(Clone)]
+pub struct C11 {
+  thread_system: C11ThreadSystem,
+  storage_system: Transactional<C11StorageSystem>,
+  scheduler: RandomScheduler,
+  locks: HashMap<i32, usize>,
+  states: ThreadStates,
+  checks: AddressChecks,
+  trace_options: TraceOptions,
+  statistics: Statistics
+}
+
+impl C11 {
+  pub fn new(instructions: Vec<Vec<LabeledInstruction>>) -> C11 {
+    C11 {
+      storage_system: Transactional::new(C11StorageSystem::new(instructions.len())),
+      thread_system: C11ThreadSystem::new(instructions),
+      scheduler: RandomScheduler::new(),
+      locks: HashMap::new(),
+      states: ThreadStates::default(),
+      checks: AddressChecks::default(),
+      trace_options: TraceOptions::default(),
+      statistics: Statistics::new()
+    }
+  }
+
+  // A propagate node is executable while its message is the next one the thread has not observed.
+  fn observable(&self, node: &Node) -> bool {
+    match self.thread_system.observation(node.id) {
+      Some((address, timestamp)) => self.storage_system.inner.timestamp(node.thread_id, address) + 1 == timestamp,
+      None => true
+    }
+  }
+}
+
+impl Debug for C11 {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(f, "{:?}", self.thread_system)?;
+    write!(f, "{:?}", self.storage_system)?;
+    write!(f, "{:?}", self.states)
+  }
+}
+
+impl MemoryModel for C11 {
+    fn get_possible_executions(&self) -> Vec<Node> {
+      enabled_executions(&self.thread_system, &self.storage_system, &self.locks, &self.states.parked).into_iter()
+        .filter(|node| self.observable(node))
+        .collect()
+    }
+
+    fn random_step(&mut self, debug_print: bool) -> Result<Option<Event>, RuntimeError> {
+      let executions = self.get_possible_executions();
+      let execution = match self.scheduler.choose(&executions) {
+        Some(execution) => execution,
+        None => return Ok(None)
+      };
+      self.step(execution, debug_print).map(Some)
+    }
+
+    fn set_seed(&mut self, seed: u64) {
+      self.scheduler.set_seed(seed);
+    }
+
+    fn set_rng(&mut self, rng: SmallRng) {
+      self.scheduler.set_rng(rng);
+    }
+
+    fn set_scheduler_options(&mut self, options: SchedulerOptions) {
+      self.scheduler.set_options(options);
+    }
+
+    fn set_latencies(&mut self, latencies: Latencies) {
+      self.scheduler.set_latencies(latencies);
+    }
+
+    fn set_recycling(&mut self) {
+      self.thread_system.set_recycling();
+    }
+
+    fn append_instruction(&mut self, thread_id: usize, instruction: LabeledInstruction) {
+      self.thread_system.append_instruction(thread_id, instruction);
+    }
+
+    fn number_of_threads(&self) -> usize {
+      self.thread_system.number_of_threads()
+    }
+
+    fn registers(&self, thread_id: usize) -> &HashMap<String, i32> {
+      self.thread_system.registers(thread_id)
+    }
+
+    fn memory(&self) -> &HashMap<i32, i32> {
+      self.storage_system.memory()
+    }
+
+    fn initialize(&mut self, address: i32, value: i32) {
+      self.storage_system.initialize(address, value);
+    }
+
+    fn set_strict_memory(&mut self, initialized: HashSet<i32>) {
+      self.checks.initialized = Some(initialized);
+    }
+
+    fn set_default_memory(&mut self, value: i32) {
+      self.storage_system.set_default(value);
+    }
+
+    fn set_regions(&mut self, regions: Vec<Region>) {
+      self.checks.regions = regions;
+    }
+
+    fn set_clear_monitors(&mut self) {
+      self.states.clear_on_switch = true;
+    }
+
+    fn set_race_checks(&mut self, relaxed: bool) {
+      self.states.races = Some(RaceDetector::new(self.thread_system.number_of_threads()));
+      self.states.relaxed_races = relaxed;
+    }
+
+    fn set_persistent(&mut self, addresses: BTreeSet<i32>) {
+      self.states.persistence = Persistence::new(&addresses, self.storage_system.memory(), self.storage_system.default_value());
+    }
+
+    fn persistence(&self) -> &Persistence {
+      &self.states.persistence
+    }
+
+    fn set_trace_options(&mut self, options: TraceOptions) {
+      self.trace_options = options;
+    }
+
+    fn default_memory(&self) -> i32 {
+      self.storage_system.default_value()
+    }
+
+    fn buffer(&self, thread_id: usize) -> &[(i32, i32)] {
+      self.storage_system.buffer(thread_id)
+    }
+
+    fn set_thread_names(&mut self, thread_names: Vec<String>) {
+      self.storage_system.inner.set_thread_names(thread_names.clone());
+      self.thread_system.set_thread_names(thread_names);
+    }
+
+    fn thread_name(&self, thread_id: usize) -> &str {
+      self.thread_system.thread_name(thread_id)
+    }
+
+    fn graph(&self) -> &Graph {
+      self.thread_system.graph()
+    }
+
+    system_accessors!();
+
+    fn pending_executions(&self) -> Vec<Node> {
+      self.thread_system.get_possible_executions().into_iter()
+        .filter(|node| self.observable(node))
+        .collect()
+    }
+
+    fn lock_owner(&self, address: i32) -> Option<usize> {
+      self.locks.get(&address).cloned()
+    }
+
+    fn parked(&self, thread_id: usize) -> Option<Parked> {
+      still_parked(&self.storage_system, &self.states.parked, thread_id)
+    }
+
+    fn statistics(&self) -> &Statistics {
+      &self.statistics
+    }
+
+    fn snapshot(&self) -> Box<dyn MemoryModel> {
+      Box::new(self.clone())
+    }
+
+    fn step(&mut self, node: Node, debug_print: bool) -> Result<Event, RuntimeError> {
+      let debug_print = debug_print && traced(&self.thread_system, &self.trace_options, &node);
+      if debug_print {
+        print_step(&self.thread_system, &node, self.trace_options.color);
+      }
+      let event = execute(&mut self.thread_system, &mut self.storage_system, &mut self.locks, &mut self.states, &self.checks, &mut self.statistics, node)?;
+      if debug_print {
+        print_read_from(&self.thread_system, &self.storage_system, &event, self.trace_options.color);
+        if !self.trace_options.brief {
+          print!("{:?}", self.thread_system);
+          print!("{:?}", self.states);
+          println!("{:?}", self.storage_system);
+        }
+      }
+      Ok(event)
+    }
+}
+
+#[derive(Clone)]
+pub struct Itanium {
+  thread_system: ItaniumThreadSystem,
+  storage_system: Transactional<SCStorageSystem>,
+  scheduler: RandomScheduler,
+  locks: HashMap<i32, usize>,
+  states: ThreadStates,
+  checks: AddressChecks,
+  trace_options: TraceOptions,
+  statistics: Statistics
+}
+
+impl Itanium {
+  pub fn new(instructions: Vec<Vec<LabeledInstruction>>, speculative: bool) -> Itanium {
+    Itanium {
+      thread_system: ItaniumThreadSystem::new(instructions, speculative),
+      storage_system: Transactional::new(SCStorageSystem::new()),
+      scheduler: RandomScheduler::new(),
+      locks: HashMap::new(),
+      states: ThreadStates::default(),
+      checks: AddressChecks::default(),
+      trace_options: TraceOptions::default(),
+      statistics: Statistics::new()
+    }
+  }
+}
+
+impl Debug for Itanium {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(f, "{:?}", self.thread_system)?;
+    write!(f, "{:?}", self.storage_system)?;
+    write!(f, "{:?}", self.states)
+  }
+}
+
+impl MemoryModel for Itanium {
+    fn get_possible_executions(&self) -> Vec<Node> {
+      enabled_executions(&self.thread_system, &self.storage_system, &self.locks, &self.states.parked)
+    }
+
+    fn random_step(&mut self, debug_print: bool) -> Result<Option<Event>, RuntimeError> {
+      let executions = self.get_possible_executions();
+      let execution = match self.scheduler.choose(&executions) {
+        Some(execution) => execution,
+        None => return Ok(None)
+      };
+      self.step(execution, debug_print).map(Some)
+    }
+
+    fn set_seed(&mut self, seed: u64) {
+      self.scheduler.set_seed(seed);
+    }
+
+    fn set_rng(&mut self, rng: SmallRng) {
+      self.scheduler.set_rng(rng);
+    }
+
+    fn set_scheduler_options(&mut self, options: SchedulerOptions) {
+      self.scheduler.set_options(options);
+    }
+
+    fn set_latencies(&mut self, latencies: Latencies) {
+      self.scheduler.set_latencies(latencies);
+    }
+
+    fn set_recycling(&mut self) {
+      self.thread_system.set_recycling();
+    }
+
+    fn append_instruction(&mut self, thread_id: usize, instruction: LabeledInstruction) {
+      self.thread_system.append_instruction(thread_id, instruction);
+    }
+
+    fn number_of_threads(&self) -> usize {
+      self.thread_system.number_of_threads()
+    }
+
+    fn registers(&self, thread_id: usize) -> &HashMap<String, i32> {
+      self.thread_system.registers(thread_id)
+    }
+
+    fn memory(&self) -> &HashMap<i32, i32> {
+      self.storage_system.memory()
+    }
+
+    fn initialize(&mut self, address: i32, value: i32) {
+      self.storage_system.initialize(address, value);
+    }
+
+    fn set_strict_memory(&mut self, initialized: HashSet<i32>) {
+      self.checks.initialized = Some(initialized);
+    }
+
+    fn set_default_memory(&mut self, value: i32) {
+      self.storage_system.set_default(value);
+    }
+
+    fn set_regions(&mut self, regions: Vec<Region>) {
+      self.checks.regions = regions;
+    }
+
+    fn set_clear_monitors(&mut self) {
+      self.states.clear_on_switch = true;
+    }
+
+    fn set_race_checks(&mut self, relaxed: bool) {
+      self.states.races = Some(RaceDetector::new(self.thread_system.number_of_threads()));
+      self.states.relaxed_races = relaxed;
+    }
+
+    fn set_persistent(&mut self, addresses: BTreeSet<i32>) {
+      self.states.persistence = Persistence::new(&addresses, self.storage_system.memory(), self.storage_system.default_value());
+    }
+
+    fn persistence(&self) -> &Persistence {
+      &self.states.persistence
+    }
+
+    fn set_trace_options(&mut self, options: TraceOptions) {
+      self.trace_options = options;
+    }
+
+    fn default_memory(&self) -> i32 {
+      self.storage_system.default_value()
+    }
+
+    fn buffer(&self, thread_id: usize) -> &[(i32, i32)] {
+      self.storage_system.buffer(thread_id)
+    }
+
+    fn set_thread_names(&mut self, thread_names: Vec<String>) {
+      self.thread_system.set_thread_names(thread_names);
+    }
+
+    fn thread_name(&self, thread_id: usize) -> &str {
+      self.thread_system.thread_name(thread_id)
+    }
+
+    fn graph(&self) -> &Graph {
+      self.thread_system.graph()
+    }
+
+    system_accessors!();
+
+    fn pending_executions(&self) -> Vec<Node> {
+      self.thread_system.get_possible_executions()
+    }
+
+    fn lock_owner(&self, address: i32) -> Option<usize> {
+      self.locks.get(&address).cloned()
+    }
+
+    fn parked(&self, thread_id: usize) -> Option<Parked> {
+      still_parked(&self.storage_system, &self.states.parked, thread_id)
+    }
+
+    fn statistics(&self) -> &Statistics {
+      &self.statistics
+    }
+
+    fn snapshot(&self) -> Box<dyn MemoryModel> {
+      Box::new(self.clone())
+    }
+
+    fn step(&mut self, node: Node, debug_print: bool) -> Result<Event, RuntimeError> {
+      let debug_print = debug_print && traced(&self.thread_system, &self.trace_options, &node);
+      if debug_print {
+        print_step(&self.thread_system, &node, self.trace_options.color);
+      }
+      let event = execute(&mut self.thread_system, &mut self.storage_system, &mut self.locks, &mut self.states, &self.checks, &mut self.statistics, node)?;
+      if debug_print {
+        print_read_from(&self.thread_system, &self.storage_system, &event, self.trace_options.color);
+        if !self.trace_options.brief {
+          print!("{:?}", self.thread_system);
+          print!("{:?}", self.states);
+          println!("{:?}", self.storage_system);
+        }
+      }
+      Ok(event)
+    }
+}
+
+#[derive(Clone)]
+pub struct PRAM {
+  thread_system: PRAMThreadSystem,
+  storage_system: Transactional<PRAMStorageSystem>,
+  scheduler: RandomScheduler,
+  locks: HashMap<i32, usize>,
+  states: ThreadStates,
+  checks: AddressChecks,
+  trace_options: TraceOptions,
+  statistics: Statistics
+}
+
+impl PRAM {
+  pub fn new(instructions: Vec<Vec<LabeledInstruction>>, coherent: bool) -> PRAM {
+    PRAM {
+      storage_system: Transactional::new(PRAMStorageSystem::new(instructions.len(), coherent)),
+      thread_system: PRAMThreadSystem::new(instructions),
+      scheduler: RandomScheduler::new(),
+      locks: HashMap::new(),
+      states: ThreadStates::default(),
+      checks: AddressChecks::default(),
+      trace_options: TraceOptions::default(),
+      statistics: Statistics::new()
+    }
+  }
+}
+
+impl Debug for PRAM {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(f, "{:?}", self.thread_system)?;
+    write!(f, "{:?}", self.storage_system)?;
+    write!(f, "{:?}", self.states)
+  }
+}
+
+impl MemoryModel for PRAM {
+    fn get_possible_executions(&self) -> Vec<Node> {
+      enabled_executions(&self.thread_system, &self.storage_system, &self.locks, &self.states.parked)
+    }
+
+    fn random_step(&mut self, debug_print: bool) -> Result<Option<Event>, RuntimeError> {
+      let executions = self.get_possible_executions();
+      let execution = match self.scheduler.choose(&executions) {
+        Some(execution) => execution,
+        None => return Ok(None)
+      };
+      self.step(execution, debug_print).map(Some)
+    }
+
+    fn set_seed(&mut self, seed: u64) {
+      self.scheduler.set_seed(seed);
+    }
+
+    fn set_rng(&mut self, rng: SmallRng) {
+      self.scheduler.set_rng(rng);
+    }
+
+    fn set_scheduler_options(&mut self, options: SchedulerOptions) {
+      self.scheduler.set_options(options);
+    }
+
+    fn set_latencies(&mut self, latencies: Latencies) {
+      self.scheduler.set_latencies(latencies);
+    }
+
+    fn set_recycling(&mut self) {
+      self.thread_system.set_recycling();
+    }
 
-impl SC {
-  pub fn new(instructions: Vec<Vec<LabeledInstruction>>) -> SC {
-    SC {
-      thread_system: SCThreadSystem::new(instructions),
-      storage_system: SCStorageSystem::new()
+    fn append_instruction(&mut self, thread_id: usize, instruction: LabeledInstruction) {
+      self.thread_system.append_instruction(thread_id, instruction);
     }
-  }
-}
 
-impl MemoryModel for SC {
-    fn get_possible_executions(&self) -> Vec<Node> {
+    fn number_of_threads(&self) -> usize {
+      self.thread_system.number_of_threads()
+    }
+
+    fn registers(&self, thread_id: usize) -> &HashMap<String, i32> {
+      self.thread_system.registers(thread_id)
+    }
+
+    fn memory(&self) -> &HashMap<i32, i32> {
+      self.storage_system.memory()
+    }
+
+    fn initialize(&mut self, address: i32, value: i32) {
+      self.storage_system.initialize(address, value);
+    }
+
+    fn set_strict_memory(&mut self, initialized: HashSet<i32>) {
+      self.checks.initialized = Some(initialized);
+    }
+
+    fn set_default_memory(&mut self, value: i32) {
+      self.storage_system.set_default(value);
+    }
+
+    fn set_regions(&mut self, regions: Vec<Region>) {
+      self.checks.regions = regions;
+    }
+
+    fn set_clear_monitors(&mut self) {
+      self.states.clear_on_switch = true;
+    }
+
+    fn set_race_checks(&mut self, relaxed: bool) {
+      self.states.races = Some(RaceDetector::new(self.thread_system.number_of_threads()));
+      self.states.relaxed_races = relaxed;
+    }
+
+    fn set_persistent(&mut self, addresses: BTreeSet<i32>) {
+      self.states.persistence = Persistence::new(&addresses, self.storage_system.memory(), self.storage_system.default_value());
+    }
+
+    fn persistence(&self) -> &Persistence {
+      &self.states.persistence
+    }
+
+    fn set_trace_options(&mut self, options: TraceOptions) {
+      self.trace_options = options;
+    }
+
+    fn default_memory(&self) -> i32 {
+      self.storage_system.default_value()
+    }
+
+    fn buffer(&self, thread_id: usize) -> &[(i32, i32)] {
+      self.storage_system.buffer(thread_id)
+    }
+
+    fn set_thread_names(&mut self, thread_names: Vec<String>) {
+      self.storage_system.inner.set_thread_names(thread_names.clone());
+      self.thread_system.set_thread_names(thread_names);
+    }
+
+    fn thread_name(&self, thread_id: usize) -> &str {
+      self.thread_system.thread_name(thread_id)
+    }
+
+    fn graph(&self) -> &Graph {
+      self.thread_system.graph()
+    }
+
+    system_accessors!();
+
+    fn pending_executions(&self) -> Vec<Node> {
       self.thread_system.get_possible_executions()
     }
 
-    fn random_step(&mut self, debug_print: bool) {
-      let executions = self.get_possible_executions();
-      if executions.is_empty() {
-        return;
-      }
-      let execution = executions.choose(&mut rand::thread_rng()).unwrap().clone();
-      if debug_print {
-        println!("{}: {:?}", execution.thread_id, execution.instruction);
-      }
-      self.step(execution, debug_print);
+    fn lock_owner(&self, address: i32) -> Option<usize> {
+      self.locks.get(&address).cloned()
     }
 
-    fn step(&mut self, node: Node, debug_print: bool) {
-      self.thread_system.remove_node(&node);
-      let thread_id = node.thread_id;
-      let current_step = node.instruction.instruction;
-      match current_step {
-        Instruction::Const { r, value } => {
-          self.thread_system.assign_register(thread_id, r, value);
-        }
-        Instruction::ArithPlus { r1, r2, r3 } => {
-          let r2_value = self.thread_system.get_register(thread_id, r2);
-          let r3_value = self.thread_system.get_register(thread_id, r3);
-          self.thread_system.assign_register(thread_id, r1, r2_value + r3_value);
-        }
-        Instruction::ArithMinus { r1, r2, r3 } => {
-          let r2_value = self.thread_system.get_register(thread_id, r2);
-          let r3_value = self.thread_system.get_register(thread_id, r3);
-          self.thread_system.assign_register(thread_id, r1, r2_value - r3_value);
-        }
-        Instruction::ArithMul { r1, r2, r3 } => {
-          let r2_value = self.thread_system.get_register(thread_id, r2);
-          let r3_value = self.thread_system.get_register(thread_id, r3);
-          self.thread_system.assign_register(thread_id, r1, r2_value * r3_value);
-        }
-        Instruction::ArithDiv { r1, r2, r3 } => {
-          let r2_value = self.thread_system.get_register(thread_id, r2);
-          let r3_value = self.thread_system.get_register(thread_id, r3);
-          self.thread_system.assign_register(thread_id, r1, r2_value / r3_value);
-        }
-        Instruction::Cond { r, label } => {
-          let value = self.thread_system.get_register(thread_id, r);
-          if value != 0 {
-            self.thread_system.goto(label);
-          }
-        }
-        Instruction::Load { mode: _, address, r } => {
-          let address_value = self.thread_system.get_register(thread_id, address);
-          let value = self.storage_system.load(thread_id, address_value);
-          self.thread_system.assign_register(thread_id, r, value);
-        }
-        Instruction::Store { mode: _, address, r } => {
-          let address_value = self.thread_system.get_register(thread_id, address);
-          let value = self.thread_system.get_register(thread_id, r);
-          self.storage_system.store(thread_id, address_value, value);
-        }
-        Instruction::Cas { mode: _, address, to, exp, des } => {
-          let address_value = self.thread_system.get_register(thread_id, address);
-          let exp_value = self.thread_system.get_register(thread_id, exp);
-          let des_value = self.thread_system.get_register(thread_id, des);
-          let value = self.storage_system.cas(thread_id, address_value, exp_value, des_value);
-          self.thread_system.assign_register(thread_id, to, value);
-        }
-        Instruction::Fai { mode: _, address, to, inc } => {
-          let address_value = self.thread_system.get_register(thread_id, address);
-          let inc_value = self.thread_system.get_register(thread_id, inc);
-          let value = self.storage_system.fai(thread_id, address_value, inc_value);
-          self.thread_system.assign_register(thread_id, to, value);
-        }
-        Instruction::Fence { mode: _ } => {}
-        Instruction::Propagate { thread_id: _, address: _, value: _ } => {}
-      };
+    fn parked(&self, thread_id: usize) -> Option<Parked> {
+      still_parked(&self.storage_system, &self.states.parked, thread_id)
+    }
+
+    fn statistics(&self) -> &Statistics {
+      &self.statistics
+    }
+
+    fn snapshot(&self) -> Box<dyn MemoryModel> {
+      Box::new(self.clone())
+    }
+
+    fn step(&mut self, node: Node, debug_print: bool) -> Result<Event, RuntimeError> {
+      let debug_print = debug_print && traced(&self.thread_system, &self.trace_options, &node);
+      if debug_print {
+        print_step(&self.thread_system, &node, self.trace_options.color);
+      }
+      let event = execute(&mut self.thread_system, &mut self.storage_system, &mut self.locks, &mut self.states, &self.checks, &mut self.statistics, node)?;
       if debug_print {
-        print!("{:?}", self.thread_system);
-        print!("{:?}\n", self.storage_system);
+        print_read_from(&self.thread_system, &self.storage_system, &event, self.trace_options.color);
+        if !self.trace_options.brief {
+          print!("{:?}", self.thread_system);
+          print!("{:?}", self.states);
+          println!("{:?}", self.storage_system);
+        }
       }
+      Ok(event)
     }
 }
 
-pub struct TSO {
-  thread_system: TSOThreadSystem,
-  storage_system: TSOStorageSystem
+#[derive(Clone)]
+pub struct NMCA {
+  thread_system: NMCAThreadSystem,
+  storage_system: Transactional<NMCAStorageSystem>,
+  scheduler: RandomScheduler,
+  locks: HashMap<i32, usize>,
+  states: ThreadStates,
+  checks: AddressChecks,
+  trace_options: TraceOptions,
+  statistics: Statistics
 }
 
-impl TSO {
-  pub fn new(instructions: Vec<Vec<LabeledInstruction>>) -> TSO {
-    TSO {
-      storage_system: TSOStorageSystem::new(instructions.len()),
-      thread_system: TSOThreadSystem::new(instructions)
+impl NMCA {
+  pub fn new(instructions: Vec<Vec<LabeledInstruction>>, topology: Option<Topology>) -> NMCA {
+    NMCA {
+      storage_system: Transactional::new(NMCAStorageSystem::new(instructions.len(), topology)),
+      thread_system: NMCAThreadSystem::new(instructions),
+      scheduler: RandomScheduler::new(),
+      locks: HashMap::new(),
+      states: ThreadStates::default(),
+      checks: AddressChecks::default(),
+      trace_options: TraceOptions::default(),
+      statistics: Statistics::new()
+    }
+  }
+
+  // A delivery waits for the stores its release depends on, a fence or lock for the latest stores
+  // its thread has seen to reach every thread in its scope.
+  fn observable(&self, node: &Node) -> bool {
+    if let Some(message) = self.thread_system.delivery(node.id) {
+      return self.storage_system.inner.deliverable(message, node.thread_id);
     }
+    !node.instruction.is_fence() || self.storage_system.inner.settled(node.thread_id, node.instruction.scope.unwrap_or(Scope::System))
   }
 }
 
-impl MemoryModel for TSO {
+impl Debug for NMCA {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(f, "{:?}", self.thread_system)?;
+    write!(f, "{:?}", self.storage_system)?;
+    write!(f, "{:?}", self.states)
+  }
+}
+
+impl MemoryModel for NMCA {
     fn get_possible_executions(&self) -> Vec<Node> {
-      self.thread_system.get_possible_executions()
+      enabled_executions(&self.thread_system, &self.storage_system, &self.locks, &self.states.parked).into_iter()
+        .filter(|node| self.observable(node))
+        .collect()
     }
 
-    fn random_step(&mut self, debug_print: bool) {
+    fn random_step(&mut self, debug_print: bool) -> Result<Option<Event>, RuntimeError> {
       let executions = self.get_possible_executions();
-      if executions.is_empty() {
-        return;
-      }
-      let execution = executions.choose(&mut rand::thread_rng()).unwrap().clone();
-      if debug_print {
-        println!("{}: {:?}", execution.thread_id, execution.instruction);
-      }
-      self.step(execution, debug_print);
+      let execution = match self.scheduler.choose(&executions) {
+        Some(execution) => execution,
+        None => return Ok(None)
+      };
+      self.step(execution, debug_print).map(Some)
     }
 
-    fn step(&mut self, node: Node, debug_print: bool) {
-      self.thread_system.remove_node(&node);
-      let thread_id = node.thread_id;
-      let current_step = node.instruction.instruction;
-      match current_step {
-        Instruction::Const { r, value } => {
-          self.thread_system.assign_register(thread_id, r, value);
-        }
-        Instruction::ArithPlus { r1, r2, r3 } => {
-          let r2_value = self.thread_system.get_register(thread_id, r2);
-          let r3_value = self.thread_system.get_register(thread_id, r3);
-          self.thread_system.assign_register(thread_id, r1, r2_value + r3_value);
-        }
-        Instruction::ArithMinus { r1, r2, r3 } => {
-          let r2_value = self.thread_system.get_register(thread_id, r2);
-          let r3_value = self.thread_system.get_register(thread_id, r3);
-          self.thread_system.assign_register(thread_id, r1, r2_value - r3_value);
-        }
-        Instruction::ArithMul { r1, r2, r3 } => {
-          let r2_value = self.thread_system.get_register(thread_id, r2);
-          let r3_value = self.thread_system.get_register(thread_id, r3);
-          self.thread_system.assign_register(thread_id, r1, r2_value * r3_value);
-        }
-        Instruction::ArithDiv { r1, r2, r3 } => {
-          let r2_value = self.thread_system.get_register(thread_id, r2);
-          let r3_value = self.thread_system.get_register(thread_id, r3);
-          self.thread_system.assign_register(thread_id, r1, r2_value / r3_value);
-        }
-        Instruction::Cond { r, label } => {
-          let value = self.thread_system.get_register(thread_id, r);
-          if value != 0 {
-            self.thread_system.goto(label);
-          }
-        }
-        Instruction::Load { mode: _, address, r } => {
-          let address_value = self.thread_system.get_register(thread_id, address);
-          let value = self.storage_system.load(thread_id, address_value);
-          self.thread_system.assign_register(thread_id, r, value);
-        }
-        Instruction::Store { mode: _, address, r } => {
-          let address_value = self.thread_system.get_register(thread_id, address);
-          let value = self.thread_system.get_register(thread_id, r);
-          self.storage_system.store(thread_id, address_value, value);
-          self.thread_system.add_propagate_node(thread_id, address_value, value);
-        }
-        Instruction::Cas { mode: _, address, to, exp, des } => {
-          let address_value = self.thread_system.get_register(thread_id, address);
-          let exp_value = self.thread_system.get_register(thread_id, exp);
-          let des_value = self.thread_system.get_register(thread_id, des);
-          let value = self.storage_system.cas(thread_id, address_value, exp_value, des_value);
-          if value == exp_value {
-            self.thread_system.add_propagate_node(thread_id, address_value, des_value);
-          }
-          self.thread_system.assign_register(thread_id, to, value);
-        }
-        Instruction::Fai { mode: _, address, to, inc } => {
-          let address_value = self.thread_system.get_register(thread_id, address);
-          let inc_value = self.thread_system.get_register(thread_id, inc);
-          let value = self.storage_system.fai(thread_id, address_value, inc_value);
-          self.thread_system.assign_register(thread_id, to, value);
-          self.thread_system.add_propagate_node(thread_id, address_value, value + inc_value);
-        }
-        Instruction::Fence { mode: _ } => {}
-        Instruction::Propagate { thread_id, address, value: _ } => {
-          self.storage_system.propagate(thread_id, address);
-        }
+    fn set_seed(&mut self, seed: u64) {
+      self.scheduler.set_seed(seed);
+    }
+
+    fn set_rng(&mut self, rng: SmallRng) {
+      self.scheduler.set_rng(rng);
+    }
+
+    fn set_scheduler_options(&mut self, options: SchedulerOptions) {
+      self.scheduler.set_options(options);
+    }
+
+    fn set_latencies(&mut self, latencies: Latencies) {
+      self.scheduler.set_latencies(latencies);
+    }
+
+    fn set_recycling(&mut self) {
+      self.thread_system.set_recycling();
+    }
+
+    fn append_instruction(&mut self, thread_id: usize, instruction: LabeledInstruction) {
+      self.thread_system.append_instruction(thread_id, instruction);
+    }
+
+    fn number_of_threads(&self) -> usize {
+      self.thread_system.number_of_threads()
+    }
+
+    fn registers(&self, thread_id: usize) -> &HashMap<String, i32> {
+      self.thread_system.registers(thread_id)
+    }
+
+    fn memory(&self) -> &HashMap<i32, i32> {
+      self.storage_system.memory()
+    }
+
+    fn initialize(&mut self, address: i32, value: i32) {
+      self.storage_system.initialize(address, value);
+    }
+
+    fn set_strict_memory(&mut self, initialized: HashSet<i32>) {
+      self.checks.initialized = Some(initialized);
+    }
+
+    fn set_default_memory(&mut self, value: i32) {
+      self.storage_system.set_default(value);
+    }
+
+    fn set_regions(&mut self, regions: Vec<Region>) {
+      self.checks.regions = regions;
+    }
+
+    fn set_clear_monitors(&mut self) {
+      self.states.clear_on_switch = true;
+    }
+
+    fn set_race_checks(&mut self, relaxed: bool) {
+      self.states.races = Some(RaceDetector::new(self.thread_system.number_of_threads()));
+      self.states.relaxed_races = relaxed;
+    }
+
+    fn set_persistent(&mut self, addresses: BTreeSet<i32>) {
+      self.states.persistence = Persistence::new(&addresses, self.storage_system.memory(), self.storage_system.default_value());
+    }
+
+    fn persistence(&self) -> &Persistence {
+      &self.states.persistence
+    }
+
+    fn set_trace_options(&mut self, options: TraceOptions) {
+      self.trace_options = options;
+    }
+
+    fn default_memory(&self) -> i32 {
+      self.storage_system.default_value()
+    }
+
+    fn buffer(&self, thread_id: usize) -> &[(i32, i32)] {
+      self.storage_system.buffer(thread_id)
+    }
+
+    fn set_thread_names(&mut self, thread_names: Vec<String>) {
+      self.storage_system.inner.set_thread_names(thread_names.clone());
+      self.thread_system.set_thread_names(thread_names);
+    }
+
+    fn thread_name(&self, thread_id: usize) -> &str {
+      self.thread_system.thread_name(thread_id)
+    }
+
+    fn graph(&self) -> &Graph {
+      self.thread_system.graph()
+    }
+
+    system_accessors!();
+
+    fn pending_executions(&self) -> Vec<Node> {
+      self.thread_system.get_possible_executions().into_iter()
+        .filter(|node| self.observable(node))
+        .collect()
+    }
+
+    fn lock_owner(&self, address: i32) -> Option<usize> {
+      self.locks.get(&address).cloned()
+    }
+
+    fn parked(&self, thread_id: usize) -> Option<Parked> {
+      still_parked(&self.storage_system, &self.states.parked, thread_id)
+    }
+
+    fn statistics(&self) -> &Statistics {
+      &self.statistics
+    }
+
+    fn snapshot(&self) -> Box<dyn MemoryModel> {
+      Box::new(self.clone())
+    }
+
+    fn step(&mut self, node: Node, debug_print: bool) -> Result<Event, RuntimeError> {
+      let debug_print = debug_print && traced(&self.thread_system, &self.trace_options, &node);
+      if debug_print {
+        print_step(&self.thread_system, &node, self.trace_options.color);
       }
+      self.storage_system.inner.set_scope(node.instruction.scope);
+      let event = execute(&mut self.thread_system, &mut self.storage_system, &mut self.locks, &mut self.states, &self.checks, &mut self.statistics, node)?;
       if debug_print {
-        print!("{:?}", self.thread_system);
-        print!("{:?}\n", self.storage_system);
+        print_read_from(&self.thread_system, &self.storage_system, &event, self.trace_options.color);
+        if !self.trace_options.brief {
+          print!("{:?}", self.thread_system);
+          print!("{:?}", self.states);
+          println!("{:?}", self.storage_system);
+        }
       }
+      Ok(event)
     }
 }
 
-pub struct PSO {
-  thread_system: PSOThreadSystem,
-  storage_system: PSOStorageSystem
+#[derive(Clone)]
+pub struct DSM {
+  thread_system: NMCAThreadSystem,
+  storage_system: Transactional<DSMStorageSystem>,
+  scheduler: RandomScheduler,
+  locks: HashMap<i32, usize>,
+  states: ThreadStates,
+  checks: AddressChecks,
+  trace_options: TraceOptions,
+  statistics: Statistics
 }
 
-impl PSO {
-  pub fn new(instructions: Vec<Vec<LabeledInstruction>>) -> PSO {
-    PSO {
-      storage_system: PSOStorageSystem::new(instructions.len()),
-      thread_system: PSOThreadSystem::new(instructions)
+impl DSM {
+  pub fn new(instructions: Vec<Vec<LabeledInstruction>>, causal: bool) -> DSM {
+    DSM {
+      storage_system: Transactional::new(DSMStorageSystem::new(instructions.len(), causal)),
+      thread_system: NMCAThreadSystem::new(instructions),
+      scheduler: RandomScheduler::new(),
+      locks: HashMap::new(),
+      states: ThreadStates::default(),
+      checks: AddressChecks::default(),
+      trace_options: TraceOptions::default(),
+      statistics: Statistics::new()
+    }
+  }
+
+  // The thread system delivers every store to every other thread on its own, as under NMCA. A causal
+  // delivery waits for the stores its message depends on, a fence or lock for the thread's stores to
+  // reach every replica.
+  fn observable(&self, node: &Node) -> bool {
+    if let Some(message) = self.thread_system.delivery(node.id) {
+      return self.storage_system.inner.deliverable(message, node.thread_id);
     }
+    !node.instruction.is_fence() || self.storage_system.inner.settled(node.thread_id)
+  }
+}
+
+impl Debug for DSM {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(f, "{:?}", self.thread_system)?;
+    write!(f, "{:?}", self.storage_system)?;
+    write!(f, "{:?}", self.states)
   }
 }
 
-impl MemoryModel for PSO {
+impl MemoryModel for DSM {
     fn get_possible_executions(&self) -> Vec<Node> {
-      self.thread_system.get_possible_executions()
+      enabled_executions(&self.thread_system, &self.storage_system, &self.locks, &self.states.parked).into_iter()
+        .filter(|node| self.observable(node))
+        .collect()
     }
 
-    fn random_step(&mut self, debug_print: bool) {
+    fn random_step(&mut self, debug_print: bool) -> Result<Option<Event>, RuntimeError> {
       let executions = self.get_possible_executions();
-      if executions.is_empty() {
-        return;
-      }
-      let execution = executions.choose(&mut rand::thread_rng()).unwrap().clone();
-      if debug_print {
-        println!("{}: {:?}", execution.thread_id, execution.instruction);
-      }
-      self.step(execution, debug_print);
+      let execution = match self.scheduler.choose(&executions) {
+        Some(execution) => execution,
+        None => return Ok(None)
+      };
+      self.step(execution, debug_print).map(Some)
     }
 
-    fn step(&mut self, node: Node, debug_print: bool) {
-      self.thread_system.remove_node(&node);
-      let thread_id = node.thread_id;
-      let current_step = node.instruction.instruction;
-      match current_step {
-        Instruction::Const { r, value } => {
-          self.thread_system.assign_register(thread_id, r, value);
-        }
-        Instruction::ArithPlus { r1, r2, r3 } => {
-          let r2_value = self.thread_system.get_register(thread_id, r2);
-          let r3_value = self.thread_system.get_register(thread_id, r3);
-          self.thread_system.assign_register(thread_id, r1, r2_value + r3_value);
-        }
-        Instruction::ArithMinus { r1, r2, r3 } => {
-          let r2_value = self.thread_system.get_register(thread_id, r2);
-          let r3_value = self.thread_system.get_register(thread_id, r3);
-          self.thread_system.assign_register(thread_id, r1, r2_value - r3_value);
-        }
-        Instruction::ArithMul { r1, r2, r3 } => {
-          let r2_value = self.thread_system.get_register(thread_id, r2);
-          let r3_value = self.thread_system.get_register(thread_id, r3);
-          self.thread_system.assign_register(thread_id, r1, r2_value * r3_value);
-        }
-        Instruction::ArithDiv { r1, r2, r3 } => {
-          let r2_value = self.thread_system.get_register(thread_id, r2);
-          let r3_value = self.thread_system.get_register(thread_id, r3);
-          self.thread_system.assign_register(thread_id, r1, r2_value / r3_value);
-        }
-        Instruction::Cond { r, label } => {
-          let value = self.thread_system.get_register(thread_id, r);
-          if value != 0 {
-            self.thread_system.goto(label);
-          }
-        }
-        Instruction::Load { mode: _, address, r } => {
-          let address_value = self.thread_system.get_register(thread_id, address);
-          let value = self.storage_system.load(thread_id, address_value);
-          self.thread_system.assign_register(thread_id, r, value);
-        }
-        Instruction::Store { mode: _, address, r } => {
-          let address_value = self.thread_system.get_register(thread_id, address);
-          let value = self.thread_system.get_register(thread_id, r);
-          self.storage_system.store(thread_id, address_value, value);
-          self.thread_system.add_propagate_node(thread_id, address_value, value);
-        }
-        Instruction::Cas { mode: _, address, to, exp, des } => {
-          let address_value = self.thread_system.get_register(thread_id, address);
-          let exp_value = self.thread_system.get_register(thread_id, exp);
-          let des_value = self.thread_system.get_register(thread_id, des);
-          let value = self.storage_system.cas(thread_id, address_value, exp_value, des_value);
-          if value == exp_value {
-            self.thread_system.add_propagate_node(thread_id, address_value, des_value);
-          }
-          self.thread_system.assign_register(thread_id, to, value);
-        }
-        Instruction::Fai { mode: _, address, to, inc } => {
-          let address_value = self.thread_system.get_register(thread_id, address);
-          let inc_value = self.thread_system.get_register(thread_id, inc);
-          let value = self.storage_system.fai(thread_id, address_value, inc_value);
-          self.thread_system.assign_register(thread_id, to, value);
-          self.thread_system.add_propagate_node(thread_id, address_value, value + inc_value);
-        }
-        Instruction::Fence { mode: _ } => {}
-        Instruction::Propagate { thread_id, address, value: _ } => {
-          self.storage_system.propagate(thread_id, address);
-        }
+    fn set_seed(&mut self, seed: u64) {
+      self.scheduler.set_seed(seed);
+    }
+
+    fn set_rng(&mut self, rng: SmallRng) {
+      self.scheduler.set_rng(rng);
+    }
+
+    fn set_scheduler_options(&mut self, options: SchedulerOptions) {
+      self.scheduler.set_options(options);
+    }
+
+    fn set_latencies(&mut self, latencies: Latencies) {
+      self.scheduler.set_latencies(latencies);
+    }
+
+    fn set_recycling(&mut self) {
+      self.thread_system.set_recycling();
+    }
+
+    fn append_instruction(&mut self, thread_id: usize, instruction: LabeledInstruction) {
+      self.thread_system.append_instruction(thread_id, instruction);
+    }
+
+    fn number_of_threads(&self) -> usize {
+      self.thread_system.number_of_threads()
+    }
+
+    fn registers(&self, thread_id: usize) -> &HashMap<String, i32> {
+      self.thread_system.registers(thread_id)
+    }
+
+    fn memory(&self) -> &HashMap<i32, i32> {
+      self.storage_system.memory()
+    }
+
+    fn initialize(&mut self, address: i32, value: i32) {
+      self.storage_system.initialize(address, value);
+    }
+
+    fn set_strict_memory(&mut self, initialized: HashSet<i32>) {
+      self.checks.initialized = Some(initialized);
+    }
+
+    fn set_default_memory(&mut self, value: i32) {
+      self.storage_system.set_default(value);
+    }
+
+    fn set_regions(&mut self, regions: Vec<Region>) {
+      self.checks.regions = regions;
+    }
+
+    fn set_clear_monitors(&mut self) {
+      self.states.clear_on_switch = true;
+    }
+
+    fn set_race_checks(&mut self, relaxed: bool) {
+      self.states.races = Some(RaceDetector::new(self.thread_system.number_of_threads()));
+      self.states.relaxed_races = relaxed;
+    }
+
+    fn set_persistent(&mut self, addresses: BTreeSet<i32>) {
+      self.states.persistence = Persistence::new(&addresses, self.storage_system.memory(), self.storage_system.default_value());
+    }
+
+    fn persistence(&self) -> &Persistence {
+      &self.states.persistence
+    }
+
+    fn set_trace_options(&mut self, options: TraceOptions) {
+      self.trace_options = options;
+    }
+
+    fn default_memory(&self) -> i32 {
+      self.storage_system.default_value()
+    }
+
+    fn buffer(&self, thread_id: usize) -> &[(i32, i32)] {
+      self.storage_system.buffer(thread_id)
+    }
+
+    fn set_thread_names(&mut self, thread_names: Vec<String>) {
+      self.storage_system.inner.set_thread_names(thread_names.clone());
+      self.thread_system.set_thread_names(thread_names);
+    }
+
+    fn thread_name(&self, thread_id: usize) -> &str {
+      self.thread_system.thread_name(thread_id)
+    }
+
+    fn graph(&self) -> &Graph {
+      self.thread_system.graph()
+    }
+
+    system_accessors!();
+
+    fn pending_executions(&self) -> Vec<Node> {
+      self.thread_system.get_possible_executions().into_iter()
+        .filter(|node| self.observable(node))
+        .collect()
+    }
+
+    fn lock_owner(&self, address: i32) -> Option<usize> {
+      self.locks.get(&address).cloned()
+    }
+
+    fn parked(&self, thread_id: usize) -> Option<Parked> {
+      still_parked(&self.storage_system, &self.states.parked, thread_id)
+    }
+
+    fn statistics(&self) -> &Statistics {
+      &self.statistics
+    }
+
+    fn snapshot(&self) -> Box<dyn MemoryModel> {
+      Box::new(self.clone())
+    }
+
+    fn step(&mut self, node: Node, debug_print: bool) -> Result<Event, RuntimeError> {
+      let debug_print = debug_print && traced(&self.thread_system, &self.trace_options, &node);
+      if debug_print {
+        print_step(&self.thread_system, &node, self.trace_options.color);
       }
+      let event = execute(&mut self.thread_system, &mut self.storage_system, &mut self.locks, &mut self.states, &self.checks, &mut self.statistics, node)?;
       if debug_print {
-        print!("{:?}", self.thread_system);
-        print!("{:?}\n", self.storage_system);
+        print_read_from(&self.thread_system, &self.storage_system, &event, self.trace_options.color);
+        if !self.trace_options.brief {
+          print!("{:?}", self.thread_system);
+          print!("{:?}", self.states);
+          println!("{:?}", self.storage_system);
+        }
       }
+      Ok(event)
     }
 }
 
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum MemoryModelType {
   SC, // Sequential Consistency
   TSO, // Total Store Order
   PSO, // Partial Store Order
+  C11, // View-based release/acquire fragment of C11
+  JMM, // Java Memory Model approximation: volatile accesses are SEQ_CST, plain ones RLX
+  Itanium, // Release/acquire ordering at the instruction level
+  PRAM, // Pipelined RAM: every thread sees the stores of each other thread in program order
+  PC, // Processor Consistency: PRAM where threads agree on the order of stores to each address
+  NMCA, // Non-multi-copy-atomic: stores reach each thread separately, fences are cumulative
+  Scoped, // NMCA where releases and fences only order stores for the threads in their scope
+  Eventual, // Distributed shared memory: replicas take each other's stores in any order, the latest one wins
+  Causal, // Distributed shared memory where replicas take stores in causal order
+  Speculative, // Itanium where loads run ahead of unresolved branches
+}
+
+// Gives a new model the thread names, memory and settings of the program.
+fn set_up(mut model: Box<dyn MemoryModel>, memory_model: &MemoryModelType, program: &Program) -> Box<dyn MemoryModel> {
+  model.set_thread_names(program.thread_names.clone());
+  model.set_default_memory(program.default_memory);
+  model.set_regions(program.regions.clone());
+  if !program.latencies.is_empty() {
+    model.set_latencies(program.latencies.clone());
+  }
+  for (address, value) in program.initial_memory() {
+    model.initialize(address, value);
+  }
+  if program.strict_memory {
+    model.set_strict_memory(program.initial.keys().cloned().collect());
+  }
+  if program.clear_monitors {
+    model.set_clear_monitors();
+  }
+  // Java leaves racy plain accesses without a guaranteed meaning, so under JMM every race is one.
+  let jmm = *memory_model == MemoryModelType::JMM;
+  if program.non_atomic() || jmm {
+    model.set_race_checks(jmm);
+  }
+  if !program.persistent.is_empty() {
+    model.set_persistent(program.persistent.clone());
+  }
+  model
+}
+
+impl MemoryModelType {
+  pub fn create(&self, program: &Program) -> Box<dyn MemoryModel> {
+    let instructions = program.threads.clone();
+    let model: Box<dyn MemoryModel> = match self {
+      MemoryModelType::SC => Box::new(SC::new(instructions)),
+      MemoryModelType::TSO => Box::new(TSO::new(instructions)),
+      MemoryModelType::PSO => Box::new(PSO::new(instructions)),
+      MemoryModelType::C11 | MemoryModelType::JMM => Box::new(C11::new(instructions)),
+      MemoryModelType::Itanium => Box::new(Itanium::new(instructions, false)),
+      MemoryModelType::PRAM => Box::new(PRAM::new(instructions, false)),
+      MemoryModelType::PC => Box::new(PRAM::new(instructions, true)),
+      MemoryModelType::NMCA => Box::new(NMCA::new(instructions, None)),
+      MemoryModelType::Scoped => Box::new(NMCA::new(instructions, Some(program.topology()))),
+      MemoryModelType::Eventual => Box::new(DSM::new(instructions, false)),
+      MemoryModelType::Causal => Box::new(DSM::new(instructions, true)),
+      MemoryModelType::Speculative => Box::new(Itanium::new(instructions, true))
+    };
+    set_up(model, self, program)
+  }
+
+  // Makes the model with the storage in place of its own, set up for the program as `create` does.
+  // The storage is for as many threads as the program has; only SC, TSO and PSO take one.
+  pub fn create_with_storage<S: StorageSystem + Clone + Debug + 'static>(&self, program: &Program, storage: S) -> Result<Box<dyn MemoryModel>, String> {
+    let instructions = program.threads.clone();
+    let model: Box<dyn MemoryModel> = match self {
+      MemoryModelType::SC => Box::new(SC::with_storage(instructions, storage)),
+      MemoryModelType::TSO => Box::new(TSO::with_storage(instructions, storage)),
+      MemoryModelType::PSO => Box::new(PSO::with_storage(instructions, storage)),
+      _ => return Err(format!("the {:?} model cannot take another storage", self))
+    };
+    Ok(set_up(model, self, program))
+  }
+
+  // Every execution of the program under the model, enumerated lazily as `--exhaustive` explores
+  // them; executions longer than `max_steps` are cut off. Ranges of initial values start from
+  // their lower bounds.
+  pub fn executions<'a>(&'a self, program: &'a Program, max_steps: usize) -> Executions<'a> {
+    Explorer::new(self, program, max_steps).executions()
+  }
+
+  pub fn is_view_based(&self) -> bool {
+    matches!(self, MemoryModelType::C11 | MemoryModelType::JMM)
+  }
 }
\ No newline at end of file
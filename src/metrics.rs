@@ -0,0 +1,203 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt::Debug;
+
+use crate::{instruction::{Instruction, LabeledInstruction, Mode}, program::Program};
+
+// An address as far as the program text tells: the value of a register that every assignment in
+// its thread sets to the same constant, or unknown.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Address {
+  Known(i32),
+  Unknown
+}
+
+impl Debug for Address {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      Address::Known(address) => write!(f, "#{}", address),
+      Address::Unknown => write!(f, "#?")
+    }
+  }
+}
+
+impl Address {
+  fn may_alias(self, other: Address) -> bool {
+    self == Address::Unknown || other == Address::Unknown || self == other
+  }
+}
+
+// A memory access of an instruction; locks, semaphores and barriers are left out, since they
+// synchronise rather than race.
+#[derive(Clone)]
+pub struct Access {
+  pub thread_id: usize,
+  pub instruction: LabeledInstruction,
+  pub address: Address,
+  pub reads: bool,
+  pub writes: bool,
+  pub mode: Mode,
+  // The known addresses of the mutexes the thread holds, going through its code from the top.
+  locks: BTreeSet<i32>
+}
+
+#[derive(Default)]
+pub struct ThreadMetrics {
+  pub instructions: usize,
+  pub kinds: BTreeMap<&'static str, usize>,
+  pub modes: BTreeMap<String, usize>,
+  pub fences: BTreeMap<String, usize>,
+  pub reads: BTreeSet<Address>,
+  pub writes: BTreeSet<Address>
+}
+
+// What `stats` reports about a program without running it. Potential races are pairs of accesses
+// of different threads that may touch the same address, at least one of them writing and one of
+// them relaxed or plain, as the race detectors count them, and that hold no mutex in common.
+pub struct Metrics {
+  pub threads: Vec<ThreadMetrics>,
+  pub races: Vec<(Access, Access)>
+}
+
+fn kind(instruction: &Instruction) -> &'static str {
+  match instruction {
+    Instruction::Const { .. } | Instruction::ArithPlus { .. } | Instruction::ArithMinus { .. } | Instruction::ArithMul { .. } | Instruction::ArithDiv { .. } | Instruction::Select { .. } => "register",
+    Instruction::Cond { .. } => "jump",
+    Instruction::Call { .. } => "call",
+    Instruction::Ret => "ret",
+    Instruction::Push { .. } => "push",
+    Instruction::Pop { .. } => "pop",
+    Instruction::Load { .. } => "load",
+    Instruction::Store { .. } => "store",
+    Instruction::Cas { .. } => "cas",
+    Instruction::Fai { op, .. } => op.mnemonic(),
+    Instruction::Fence { .. } => "fence",
+    Instruction::Lock { .. } => "lock",
+    Instruction::Unlock { .. } => "unlock",
+    Instruction::SemWait { .. } => "sem_wait",
+    Instruction::SemPost { .. } => "sem_post",
+    Instruction::Barrier { .. } => "barrier",
+    Instruction::Wait { .. } => "wait",
+    Instruction::Wake { .. } => "wake",
+    Instruction::LoadLinked { .. } => "ll",
+    Instruction::StoreConditional { .. } => "sc",
+    Instruction::ClearExclusive => "clrex",
+    Instruction::Flush { .. } => "flush",
+    Instruction::TxBegin { .. } => "txbegin",
+    Instruction::TxCommit => "txcommit",
+    Instruction::TxAbort => "txabort",
+    Instruction::Propagate { .. } => "propagate"
+  }
+}
+
+// The registers of a thread that hold one value wherever they are read: those every assignment
+// sets to the same constant, possibly computed from other such registers, and those never assigned.
+fn constants(instructions: &[LabeledInstruction]) -> HashMap<String, i32> {
+  let mut assignments: HashMap<&String, Vec<&Instruction>> = HashMap::new();
+  for instruction in instructions {
+    for r in instruction.instruction.write_registers() {
+      assignments.entry(r).or_default().push(&instruction.instruction);
+    }
+  }
+  let mut known: HashMap<String, i32> = HashMap::new();
+  loop {
+    let value = |r: &String| match assignments.contains_key(r) {
+      true => known.get(r).copied(),
+      false => Some(0)
+    };
+    let evaluate = |instruction: &Instruction| match instruction {
+      Instruction::Const { r: _, value } => Some(*value),
+      Instruction::ArithPlus { r1: _, r2, r3 } => value(r2)?.checked_add(value(r3)?),
+      Instruction::ArithMinus { r1: _, r2, r3 } => value(r2)?.checked_sub(value(r3)?),
+      Instruction::ArithMul { r1: _, r2, r3 } => value(r2)?.checked_mul(value(r3)?),
+      Instruction::ArithDiv { r1: _, r2, r3 } => value(r2)?.checked_div(value(r3)?),
+      _ => None
+    };
+    let found: Vec<(String, i32)> = assignments.iter()
+      .filter(|(r, _)| !known.contains_key(**r))
+      .filter_map(|(r, assigned)| {
+        let values: Option<BTreeSet<i32>> = assigned.iter().map(|instruction| evaluate(instruction)).collect();
+        match values?.into_iter().collect::<Vec<i32>>().as_slice() {
+          [value] => Some(((*r).clone(), *value)),
+          _ => None
+        }
+      })
+      .collect();
+    if found.is_empty() {
+      break;
+    }
+    known.extend(found);
+  }
+  for instruction in instructions {
+    for r in instruction.instruction.read_registers() {
+      if !assignments.contains_key(r) {
+        known.insert(r.clone(), 0);
+      }
+    }
+  }
+  known
+}
+
+fn access(instruction: &Instruction) -> Option<(&String, bool, bool, Mode)> {
+  match instruction {
+    Instruction::Load { mode, address, r: _ } => Some((address, true, false, *mode)),
+    Instruction::Store { mode, address, r: _ } => Some((address, false, true, *mode)),
+    Instruction::Cas { mode, failure: _, address, to: _, exp: _, des: _, flag: _ } => Some((address, true, true, *mode)),
+    Instruction::Fai { op: _, mode, address, to: _, inc: _ } => Some((address, true, true, *mode)),
+    Instruction::Wait { address, r: _ } => Some((address, true, false, Mode::Acq)),
+    Instruction::LoadLinked { address, r: _ } => Some((address, true, false, Mode::Rlx)),
+    Instruction::StoreConditional { address, r: _, flag: _ } => Some((address, false, true, Mode::Rlx)),
+    _ => None
+  }
+}
+
+pub fn analyze(program: &Program) -> Metrics {
+  let mut threads = Vec::new();
+  let mut accesses: Vec<Access> = Vec::new();
+  for (thread_id, instructions) in program.threads.iter().enumerate() {
+    // Recovery threads are empty until a crash, and their code is kept apart.
+    let code: Vec<LabeledInstruction> = instructions.iter().chain(program.recovery.get(thread_id).into_iter().flatten()).cloned().collect();
+    let constants = constants(&code);
+    let address = |r: &String| constants.get(r).map_or(Address::Unknown, |address| Address::Known(*address));
+    let mut metrics = ThreadMetrics { instructions: code.len(), ..ThreadMetrics::default() };
+    let mut locks = BTreeSet::new();
+    for instruction in code.iter() {
+      *metrics.kinds.entry(kind(&instruction.instruction)).or_default() += 1;
+      match &instruction.instruction {
+        Instruction::Fence { .. } => {
+          let fence = format!("{:?}", instruction.instruction);
+          *metrics.fences.entry(fence.trim_start_matches("fence ").to_string()).or_default() += 1;
+        },
+        Instruction::Lock { address: r } => if let Address::Known(address) = address(r) {
+          locks.insert(address);
+        },
+        Instruction::Unlock { address: r } => if let Address::Known(address) = address(r) {
+          locks.remove(&address);
+        },
+        _ => {}
+      }
+      let Some((r, reads, writes, mode)) = access(&instruction.instruction) else {
+        continue;
+      };
+      *metrics.modes.entry(format!("{:?}", mode)).or_default() += 1;
+      if reads {
+        metrics.reads.insert(address(r));
+      }
+      if writes {
+        metrics.writes.insert(address(r));
+      }
+      accesses.push(Access { thread_id, instruction: instruction.clone(), address: address(r), reads, writes, mode, locks: locks.clone() });
+    }
+    threads.push(metrics);
+  }
+  let mut races = Vec::new();
+  for (index, first) in accesses.iter().enumerate() {
+    for second in accesses[index + 1..].iter().filter(|second| second.thread_id != first.thread_id) {
+      let conflicting = first.writes || second.writes;
+      let relaxed = [first.mode, second.mode].iter().any(|mode| matches!(mode, Mode::Rlx | Mode::NA));
+      if conflicting && relaxed && first.address.may_alias(second.address) && first.locks.is_disjoint(&second.locks) {
+        races.push((first.clone(), second.clone()));
+      }
+    }
+  }
+  Metrics { threads, races }
+}
@@ -1,6 +1,13 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::str::FromStr;
 
-use crate::instruction::{Mode, LabeledInstruction, Instruction};
+use crate::instruction::{Accesses, Mode, LabeledInstruction, Instruction, FetchOp, Scope, Target};
+use crate::condition::Condition;
+use crate::expression;
+use crate::linearizability::{Operation, Spec};
+use crate::memory_model::MemoryModelType;
+use crate::program::{check_region, Initial, Program, Region};
+use crate::scheduler::Latencies;
 
 impl FromStr for Mode {
     type Err = ();
@@ -12,11 +19,270 @@ impl FromStr for Mode {
             "ACQ" => Ok(Mode::Acq),
             "REL_ACQ" => Ok(Mode::RelAcq),
             "RLX" => Ok(Mode::Rlx),
+            "NA" => Ok(Mode::NA),
             _ => Err(()),
         }
     }
 }
 
+// Only loads and stores can be plain; updates and fences are always atomic.
+fn atomic_mode(mode: &str) -> Result<Mode, String> {
+    match mode.parse() {
+        Ok(Mode::NA) => Err("Invalid mode: only loads and stores can be NA".to_string()),
+        Ok(mode) => Ok(mode),
+        Err(_) => Err("Invalid mode".to_string())
+    }
+}
+
+// Java spells SEQ_CST and RLX as VOLATILE and PLAIN, which only the JMM model takes.
+fn java_mode<'a>(mode: &'a str, java: &mut bool) -> &'a str {
+    match mode {
+        "VOLATILE" => { *java = true; "SEQ_CST" },
+        "PLAIN" => { *java = true; "RLX" },
+        _ => mode
+    }
+}
+
+impl FromStr for Scope {
+    type Err = ();
+
+    fn from_str(input: &str) -> Result<Scope, Self::Err> {
+        match input {
+            "wg" => Ok(Scope::Workgroup),
+            "dev" => Ok(Scope::Device),
+            "sys" => Ok(Scope::System),
+            _ => Err(()),
+        }
+    }
+}
+
+impl FromStr for MemoryModelType {
+    type Err = ();
+
+    fn from_str(input: &str) -> Result<MemoryModelType, Self::Err> {
+        match input {
+            "SC" => Ok(MemoryModelType::SC),
+            "TSO" => Ok(MemoryModelType::TSO),
+            "PSO" => Ok(MemoryModelType::PSO),
+            "C11" => Ok(MemoryModelType::C11),
+            "JMM" => Ok(MemoryModelType::JMM),
+            "ITANIUM" => Ok(MemoryModelType::Itanium),
+            "PRAM" => Ok(MemoryModelType::PRAM),
+            "PC" => Ok(MemoryModelType::PC),
+            "NMCA" => Ok(MemoryModelType::NMCA),
+            "SCOPED" => Ok(MemoryModelType::Scoped),
+            "EVENTUAL" => Ok(MemoryModelType::Eventual),
+            "CAUSAL" => Ok(MemoryModelType::Causal),
+            "SPECULATIVE" => Ok(MemoryModelType::Speculative),
+            _ => Err(()),
+        }
+    }
+}
+
+// `init #a = v`, `init #a = ?`, `init #a in lo..hi` or `init #a in lo..`.
+fn parse_initial(line: &str) -> Result<(i32, Initial), String> {
+    let parts: Vec<&str> = line.split_whitespace().collect();
+    let (address, initial) = match parts.as_slice() {
+        [address, "=", "?"] => (address, Initial::Symbolic),
+        [address, "=", value] => (address, Initial::Value(value.parse().map_err(|_| format!("Invalid value {}", value))?)),
+        [address, "in", range] => {
+            let (low, high) = range.split_once("..").ok_or(format!("Invalid range {}", range))?;
+            let low = low.parse().map_err(|_| format!("Invalid range {}", range))?;
+            let high = match high {
+                "" => None,
+                high => Some(high.parse().map_err(|_| format!("Invalid range {}", range))?),
+            };
+            if high.is_some_and(|high| high <= low) {
+                return Err(format!("Empty range {}", range));
+            }
+            (address, Initial::Range(low, high))
+        }
+        _ => return Err("expected init #a = v, init #a = ? or init #a in lo..hi".to_string()),
+    };
+    let address = address.strip_prefix('#')
+        .and_then(|address| address.parse::<i32>().ok())
+        .filter(|address| *address >= 0)
+        .ok_or(format!("Invalid address {}", address))?;
+    Ok((address, initial))
+}
+
+// `region NAME #base size`, the addresses base up to but not including base + size.
+fn parse_region(line: &str) -> Result<Region, String> {
+    let [name, base, size] = line.split_whitespace().collect::<Vec<&str>>()[..] else {
+        return Err("expected region NAME #base size".to_string());
+    };
+    let base = base.strip_prefix('#')
+        .and_then(|base| base.parse::<i32>().ok())
+        .filter(|base| *base >= 0)
+        .ok_or(format!("Invalid address {}", base))?;
+    let size = size.parse::<i32>().ok()
+        .filter(|size| *size > 0 && base.checked_add(*size).is_some())
+        .ok_or(format!("Invalid size {}", size))?;
+    Ok(Region { name: name.to_string(), base, size })
+}
+
+fn parse_latency(weight: &str) -> Result<u32, String> {
+    weight.parse::<u32>().ok().filter(|weight| *weight > 0).ok_or(format!("Invalid latency {}; expected a positive integer", weight))
+}
+
+// `persistent #a #b ...`, the addresses whose values survive a crash.
+fn parse_persistent(line: &str) -> Result<Vec<i32>, String> {
+    let addresses: Vec<&str> = line.split_whitespace().collect();
+    if addresses.is_empty() {
+        return Err("expected persistent #a #b ...".to_string());
+    }
+    addresses.iter()
+        .map(|address| address.strip_prefix('#')
+            .and_then(|address| address.parse::<i32>().ok())
+            .filter(|address| *address >= 0)
+            .ok_or(format!("Invalid address {}", address)))
+        .collect()
+}
+
+// `op NAME(args) -> r {` with the arguments as integers; the result register is optional.
+fn parse_operation(line: &str) -> Result<(String, Vec<i32>, Option<String>), String> {
+    let head = line.strip_suffix('{').ok_or("expected op NAME(args) -> r {")?;
+    let (invocation, result) = match head.split_once("->") {
+        Some((invocation, result)) => (invocation, Some(result.trim().to_string())),
+        None => (head, None),
+    };
+    let (name, arguments) = parse_invocation(invocation).ok_or("expected op NAME(args) -> r {")?;
+    let args = arguments.iter()
+        .map(|argument| argument.parse::<i32>().map_err(|_| format!("Invalid argument {}", argument)))
+        .collect::<Result<Vec<i32>, String>>()?;
+    if result.as_ref().is_some_and(|r| r.is_empty() || r.contains(char::is_whitespace)) {
+        return Err("expected a register after ->".to_string());
+    }
+    Ok((name.to_string(), args, result))
+}
+
+struct Macro {
+    parameters: Vec<String>,
+    body: Vec<String>,
+}
+
+#[derive(Default)]
+struct Macros {
+    definitions: HashMap<String, Macro>,
+    expansions: usize,
+}
+
+// `NAME(a, b)`, a macro invocation or the head of a definition.
+fn parse_invocation(line: &str) -> Option<(&str, Vec<&str>)> {
+    let (name, arguments) = line.trim().strip_suffix(')')?.split_once('(')?;
+    if name.is_empty() || !name.chars().all(|c| c.is_alphanumeric() || c == '_') {
+        return None;
+    }
+    let arguments = match arguments.trim() {
+        "" => Vec::new(),
+        arguments => arguments.split(',').map(str::trim).collect(),
+    };
+    Some((name, arguments))
+}
+
+impl Macros {
+    // Drops `macro NAME(p) ... endmacro` definitions and replaces invocations by their bodies.
+    // Expanded lines keep the number of the invocation line.
+    fn expand_program(&mut self, content: &str) -> Result<Vec<(usize, String)>, String> {
+        let mut lines = Vec::new();
+        let mut definition: Option<(usize, String, Macro)> = None;
+        for (line_number, line) in content.lines().enumerate() {
+            if let Some((start, name, body)) = definition.as_mut() {
+                if line.trim() == "endmacro" {
+                    let (_, name, body) = definition.take().unwrap();
+                    self.definitions.insert(name, body);
+                } else if line.trim().is_empty() {
+                    return Err(format!("Line {}: macro {} defined on line {} contains a blank line", line_number + 1, name, start));
+                } else if line.trim().starts_with("macro ") {
+                    return Err(format!("Line {}: macro definitions cannot be nested", line_number + 1));
+                } else {
+                    body.body.push(line.to_string());
+                }
+                continue;
+            }
+            if let Some(head) = line.trim().strip_prefix("macro ") {
+                let (name, parameters) = parse_invocation(head)
+                    .ok_or(format!("Line {}: expected macro NAME(parameters)", line_number + 1))?;
+                if self.definitions.contains_key(name) {
+                    return Err(format!("Line {}: macro {} is defined twice", line_number + 1, name));
+                }
+                let parameters = parameters.into_iter().map(str::to_string).collect();
+                definition = Some((line_number + 1, name.to_string(), Macro { parameters, body: Vec::new() }));
+                continue;
+            }
+            self.expand(line_number + 1, line, &mut Vec::new(), &mut lines)?;
+        }
+        if let Some((start, name, _)) = definition {
+            return Err(format!("Line {}: macro {} has no endmacro", start, name));
+        }
+        Ok(lines)
+    }
+
+    // Substitutes arguments for parameters and gives the body's labels a suffix unique to this expansion.
+    fn expand(&mut self, line_number: usize, line: &str, stack: &mut Vec<String>, lines: &mut Vec<(usize, String)>) -> Result<(), String> {
+        let Some((name, arguments)) = parse_invocation(line) else {
+            lines.push((line_number, line.to_string()));
+            return Ok(());
+        };
+        self.expansions += 1;
+        let expansion = self.expansions;
+        let definition = self.definitions.get(name)
+            .ok_or(format!("Line {}: unknown macro {}", line_number, name))?;
+        if arguments.len() != definition.parameters.len() {
+            return Err(format!("Line {}: macro {} takes {} arguments, got {}", line_number, name, definition.parameters.len(), arguments.len()));
+        }
+        if stack.iter().any(|expanding| expanding == name) {
+            return Err(format!("Line {}: macro {} expands to itself", line_number, name));
+        }
+        let labels: Vec<&str> = definition.body.iter()
+            .filter_map(|line| line.split_whitespace().next()?.strip_suffix(':'))
+            .collect();
+        let substitute = |token: &str| {
+            let (prefix, token) = match token.strip_prefix('#') {
+                Some(token) => ("#", token),
+                None => ("", token),
+            };
+            let (token, suffix) = match token.strip_suffix(':') {
+                Some(token) => (token, ":"),
+                None => (token, ""),
+            };
+            match definition.parameters.iter().position(|parameter| parameter == token) {
+                Some(index) => format!("{}{}{}", prefix, arguments[index], suffix),
+                None if labels.contains(&token) => format!("{}{}.{}{}", prefix, token, expansion, suffix),
+                None => format!("{}{}{}", prefix, token, suffix),
+            }
+        };
+        let body: Vec<String> = definition.body.iter()
+            .map(|line| line.split_whitespace().map(substitute).collect::<Vec<String>>().join(" "))
+            .collect();
+        stack.push(name.to_string());
+        for line in body {
+            self.expand(line_number, &line, stack, lines)?;
+        }
+        stack.pop();
+        Ok(())
+    }
+}
+
+// One side of a hardware fence: `ld`, `st` or `ldst` for both.
+fn parse_accesses(accesses: &str) -> Result<Accesses, String> {
+    match accesses {
+        "ld" => Ok(Accesses::Loads),
+        "st" => Ok(Accesses::Stores),
+        "ldst" => Ok(Accesses::All),
+        _ => Err(format!("Invalid fence accesses {}; expected ld, st or ldst", accesses)),
+    }
+}
+
+// A label or an offset such as `+2` or `-1`.
+fn parse_target(target: &str) -> Result<Target, String> {
+    if target.starts_with(['+', '-']) {
+        target.parse().map(Target::Relative).map_err(|_| format!("Invalid jump offset {}", target))
+    } else {
+        Ok(Target::Label(target.to_string()))
+    }
+}
+
 pub fn parse_instruction(line: &str) -> Result<LabeledInstruction, String> {
     let mut parts: Vec<&str> = line.split_whitespace().collect();
   
@@ -31,6 +297,26 @@ pub fn parse_instruction(line: &str) -> Result<LabeledInstruction, String> {
       parts.remove(0);
     }
 
+    // `r, ok := cas ...` also sets ok to whether the swap succeeded.
+    let mut flag = None;
+    if let [to, ok, ":=", "cas", ..] = parts.as_slice() {
+        if let Some(to) = to.strip_suffix(',') {
+            if to == *ok {
+                return Err("Invalid cas: the value and the flag need different registers".to_string());
+            }
+            flag = Some(ok.to_string());
+            parts.splice(0..2, [to]);
+        }
+    }
+
+    // `store REL #a r @wg` limits an access or fence to the threads of a scope.
+    let mut scope = None;
+    if let Some(last) = parts.last().and_then(|last| last.strip_prefix('@')) {
+        scope = Some(last.parse::<Scope>().map_err(|_| format!("Invalid scope @{}; expected @wg, @dev or @sys", last))?);
+        parts.pop();
+    }
+
+    let mut java = false;
     let instruction: Instruction = match parts.as_slice() {
         [r, "=", value] => {
             let value: i32 = value.parse().map_err(|_| "Invalid constant".to_string())?;
@@ -40,32 +326,430 @@ pub fn parse_instruction(line: &str) -> Result<LabeledInstruction, String> {
         [r1, "=", r2, "-", r3] => Instruction::ArithMinus { r1: r1.to_string(), r2: r2.to_string(), r3: r3.to_string() },
         [r1, "=", r2, "*", r3] => Instruction::ArithMul { r1: r1.to_string(), r2: r2.to_string(), r3: r3.to_string() },
         [r1, "=", r2, "/", r3] => Instruction::ArithDiv { r1: r1.to_string(), r2: r2.to_string(), r3: r3.to_string() },
+        [r1, "=", r2, "?", r3, ":", r4] => Instruction::Select { r1: r1.to_string(), r2: r2.to_string(), r3: r3.to_string(), r4: r4.to_string() },
         ["load", mode, address, r] => {
-            let mode: Mode = mode.parse().map_err(|_| "Invalid mode".to_string())?;
+            let mode: Mode = java_mode(mode, &mut java).parse().map_err(|_| "Invalid mode".to_string())?;
             Instruction::Load { mode, address: address[1..].to_string(), r: r.to_string() }
         },
         ["store", mode, address, r] => {
-            let mode: Mode = mode.parse().map_err(|_| "Invalid mode".to_string())?;
+            let mode: Mode = java_mode(mode, &mut java).parse().map_err(|_| "Invalid mode".to_string())?;
             Instruction::Store { mode, address: address[1..].to_string(), r: r.to_string() }
         },
         [to, ":=", "cas", mode, address, exp, des] => {
-            let mode: Mode = mode.parse().map_err(|_| "Invalid mode".to_string())?;
-            Instruction::Cas { mode, address: address[1..].to_string(), to: to.to_string(), exp: exp.to_string(), des: des.to_string() }
+            let mode = atomic_mode(java_mode(mode, &mut java))?;
+            Instruction::Cas { mode, failure: mode.failure(), address: address[1..].to_string(), to: to.to_string(), exp: exp.to_string(), des: des.to_string(), flag }
+        },
+        [to, ":=", "cas", mode, failure, address, exp, des] => {
+            let mode = atomic_mode(java_mode(mode, &mut java))?;
+            let failure = atomic_mode(java_mode(failure, &mut java))?;
+            if failure.releases() && failure != Mode::SeqCst {
+                return Err("Invalid failure mode: a failed cas does not write".to_string());
+            }
+            Instruction::Cas { mode, failure, address: address[1..].to_string(), to: to.to_string(), exp: exp.to_string(), des: des.to_string(), flag }
         },
-        [to, ":=", "fai", mode, address, inc] => {
-            let mode: Mode = mode.parse().map_err(|_| "Invalid mode".to_string())?;
-            Instruction::Fai { mode, address: address[1..].to_string(), to: to.to_string(), inc: inc.to_string() }
+        [to, ":=", op @ ("fai" | "fetch_sub" | "fetch_max" | "fetch_min"), mode, address, inc] => {
+            let op = match *op {
+                "fetch_sub" => FetchOp::Sub,
+                "fetch_max" => FetchOp::Max,
+                "fetch_min" => FetchOp::Min,
+                _ => FetchOp::Add,
+            };
+            let mode = atomic_mode(java_mode(mode, &mut java))?;
+            Instruction::Fai { op, mode, address: address[1..].to_string(), to: to.to_string(), inc: inc.to_string() }
+        },
+        ["fence", "full"] => Instruction::Fence { mode: Mode::SeqCst, order: Some((Accesses::All, Accesses::All)) },
+        ["fence", order] if order.contains(',') => {
+            let (before, after) = order.split_once(',').unwrap();
+            let (before, after) = (parse_accesses(before)?, parse_accesses(after)?);
+            Instruction::Fence { mode: before.fence_mode(after), order: Some((before, after)) }
         },
         ["fence", mode] => {
-            let mode: Mode = mode.parse().map_err(|_| "Invalid mode".to_string())?;
-            Instruction::Fence { mode }
+            let mode = atomic_mode(java_mode(mode, &mut java))?;
+            Instruction::Fence { mode, order: None }
+        },
+        ["ld", address, r] => Instruction::Load { mode: Mode::Rlx, address: address[1..].to_string(), r: r.to_string() },
+        ["ld.acq", address, r] => Instruction::Load { mode: Mode::Acq, address: address[1..].to_string(), r: r.to_string() },
+        ["st", address, r] => Instruction::Store { mode: Mode::Rlx, address: address[1..].to_string(), r: r.to_string() },
+        ["st.rel", address, r] => Instruction::Store { mode: Mode::Rel, address: address[1..].to_string(), r: r.to_string() },
+        ["mf"] => Instruction::Fence { mode: Mode::SeqCst, order: None },
+        ["lock", address] => Instruction::Lock { address: address[1..].to_string() },
+        ["unlock", address] => Instruction::Unlock { address: address[1..].to_string() },
+        ["sem_wait", address] => Instruction::SemWait { address: address[1..].to_string() },
+        ["sem_post", address] => Instruction::SemPost { address: address[1..].to_string() },
+        ["barrier", address, count] => {
+            let count = count.parse::<i32>().ok().filter(|count| *count > 0).ok_or(format!("Invalid barrier count {}", count))?;
+            Instruction::Barrier { address: address[1..].to_string(), count }
         },
-        ["if", r, "goto", label] => Instruction::Cond { r: r.to_string(), label: label.to_string() },
+        ["wait", address, r] => Instruction::Wait { address: address[1..].to_string(), r: r.to_string() },
+        ["wake", address, count] => {
+            let count = count.parse::<i32>().ok().filter(|count| *count > 0).ok_or(format!("Invalid wake count {}", count))?;
+            Instruction::Wake { address: address[1..].to_string(), count }
+        },
+        ["ll", address, r] => Instruction::LoadLinked { address: address[1..].to_string(), r: r.to_string() },
+        ["sc", address, r, "->", flag] => Instruction::StoreConditional { address: address[1..].to_string(), r: r.to_string(), flag: flag.to_string() },
+        ["clrex"] => Instruction::ClearExclusive,
+        ["flush", address] => Instruction::Flush { address: address[1..].to_string() },
+        ["sfence"] => Instruction::Fence { mode: Mode::Rel, order: Some((Accesses::Stores, Accesses::Stores)) },
+        ["txbegin", r] => Instruction::TxBegin { r: r.to_string() },
+        ["txcommit"] => Instruction::TxCommit,
+        ["txabort"] => Instruction::TxAbort,
+        ["if", r, "goto", target] => Instruction::Cond { r: r.to_string(), target: parse_target(target)? },
+        ["call", target] => Instruction::Call { target: parse_target(target)? },
+        ["ret"] => Instruction::Ret,
+        ["push", r] => Instruction::Push { r: r.to_string() },
+        ["pop", r] => Instruction::Pop { r: r.to_string() },
         _ => return Err("Unknown instruction format".to_string()),
     };
+    if scope.is_some() && !matches!(instruction, Instruction::Load { .. } | Instruction::Store { .. } | Instruction::Cas { .. } | Instruction::Fai { .. } | Instruction::Fence { .. }) {
+        return Err("Only loads, stores, cas, fetch operations and fences take a scope".to_string());
+    }
 
     Ok(LabeledInstruction {
         label,
         instruction,
+        line: None,
+        scope,
+        java,
     })
 }
+
+// Jump offsets count instruction lines, so an offset over an expression lowered into several instructions
+// is widened to land on the instruction that the target line starts with. Offsets leaving the thread stay as they are.
+pub(crate) fn relocate_offsets(instructions: &mut [LabeledInstruction], starts: &[usize]) {
+    let len = instructions.len();
+    for (line, &pc) in starts.iter().enumerate() {
+        let (Instruction::Cond { target: Target::Relative(offset), .. } | Instruction::Call { target: Target::Relative(offset) }) = &mut instructions[pc].instruction else {
+            continue;
+        };
+        let target = line.checked_add_signed(*offset as isize)
+            .and_then(|target| starts.get(target).copied().or((target == starts.len()).then_some(len)));
+        if let Some(target) = target {
+            *offset = target as i32 - pc as i32;
+        }
+    }
+}
+
+// Labels are local to their thread, so every jump has to land inside its own thread.
+fn check_jumps(threads: &[Vec<LabeledInstruction>]) -> Result<(), String> {
+    for instructions in threads {
+        for (pc, instruction) in instructions.iter().enumerate() {
+            let line = instruction.line.unwrap_or(0);
+            if let Some(label) = &instruction.label {
+                if instructions[..pc].iter().any(|other| other.label.as_ref() == Some(label)) {
+                    return Err(format!("Line {}: label {} is declared twice in the thread", line, label));
+                }
+            }
+            if let Instruction::Cond { r: _, target } | Instruction::Call { target } = &instruction.instruction {
+                if target.resolve(instructions.iter(), pc).is_none() {
+                    return Err(format!("Line {}: jump target {:?} is not in the thread", line, target));
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+// Programs with at least this many instruction lines parse them on several cores.
+const PARALLEL_LINES: usize = 4096;
+
+// The instructions of every thread, and the index of the first instruction of every instruction
+// line, for offsets over lowered expressions.
+type Lowered = (Vec<Vec<LabeledInstruction>>, Vec<Vec<usize>>);
+
+// Parses and lowers the instruction lines of every thread. Lines parse on their own, so large
+// programs split them into one run of consecutive lines per core. The error is that of the first
+// line that does not parse.
+fn parse_lines(threads: Vec<Vec<(usize, String)>>) -> Result<Lowered, String> {
+    let parse = |(line_number, line): &(usize, String)| -> Result<Vec<LabeledInstruction>, String> {
+        let instructions = match expression::parse_assignment(line) {
+            Some(instructions) => instructions,
+            None => parse_instruction(line).map(|instruction| vec![instruction]),
+        }.map_err(|err| format!("Line {}: error parsing instruction {}: {}", line_number, line, err))?;
+        Ok(instructions.into_iter().map(|instruction| LabeledInstruction { line: Some(*line_number), ..instruction }).collect())
+    };
+    let lines: Vec<&(usize, String)> = threads.iter().flatten().collect();
+    let cores = std::thread::available_parallelism().map_or(1, |cores| cores.get());
+    let parsed: Vec<Result<Vec<LabeledInstruction>, String>> = if lines.len() < PARALLEL_LINES || cores == 1 {
+        lines.iter().map(|line| parse(line)).collect()
+    } else {
+        std::thread::scope(|scope| {
+            let parse = &parse;
+            let handles: Vec<_> = lines.chunks(lines.len().div_ceil(cores))
+                .map(|run| scope.spawn(move || run.iter().map(|line| parse(line)).collect::<Vec<_>>()))
+                .collect();
+            handles.into_iter().flat_map(|handle| handle.join().expect("parsing a line does not panic")).collect()
+        })
+    };
+    let mut parsed = parsed.into_iter();
+    let mut instructions = Vec::new();
+    let mut starts = Vec::new();
+    for thread in threads.iter() {
+        let mut thread_instructions = Vec::new();
+        let mut thread_starts = Vec::new();
+        for lowered in parsed.by_ref().take(thread.len()) {
+            thread_starts.push(thread_instructions.len());
+            thread_instructions.extend(lowered?);
+        }
+        instructions.push(thread_instructions);
+        starts.push(thread_starts);
+    }
+    Ok((instructions, starts))
+}
+
+// The threads of each `workgroup` or `device` line; a thread is in one of each at most.
+fn resolve_groups(program: &Program, kind: &str, groups: Vec<(usize, Vec<String>)>) -> Result<Vec<Vec<usize>>, String> {
+    let mut resolved: Vec<Vec<usize>> = Vec::new();
+    for (line_number, names) in groups {
+        let mut threads = Vec::new();
+        for name in names {
+            let thread_id = program.thread_id(&name).ok_or(format!("Line {}: {} refers to unknown thread {}", line_number, kind, name))?;
+            if threads.contains(&thread_id) || resolved.iter().any(|group| group.contains(&thread_id)) {
+                return Err(format!("Line {}: thread {} is in two {}s", line_number, name, kind));
+            }
+            threads.push(thread_id);
+        }
+        if threads.is_empty() {
+            return Err(format!("Line {}: expected {} THREAD THREAD ...", line_number, kind));
+        }
+        resolved.push(threads);
+    }
+    Ok(resolved)
+}
+
+pub fn parse_program(content: &str) -> Result<Program, String> {
+    // The instruction lines of every thread, parsed once all of them are known.
+    let mut threads: Vec<Vec<(usize, String)>> = vec![Vec::new()];
+    let mut thread_names: Vec<Option<String>> = vec![None];
+    let mut postcondition = None;
+    let mut inputs = Vec::new();
+    let mut initials = BTreeMap::new();
+    let mut initial_lines = Vec::new();
+    let mut regions: Vec<Region> = Vec::new();
+    let mut persistent = BTreeSet::new();
+    let mut persistent_lines = Vec::new();
+    let mut recovery: Vec<(usize, String)> = Vec::new();
+    let mut workgroups: Vec<(usize, Vec<String>)> = Vec::new();
+    let mut devices: Vec<(usize, Vec<String>)> = Vec::new();
+    let mut latencies = Latencies::default();
+    let mut thread_latencies: Vec<(usize, String, u32)> = Vec::new();
+    let mut spec = None;
+    let mut operations: Vec<Operation> = Vec::new();
+    let mut operation: Option<Operation> = None;
+    let mut macros = Macros::default();
+    let lines = macros.expand_program(content)?;
+    // Instruction lines are only parsed after the scan, but an error in one still comes before any error below it.
+    let scanned = (|| -> Result<(), String> {
+        for (line_number, line) in lines {
+            let line = line.as_str();
+            if line.is_empty() {
+                if let Some(operation) = &operation {
+                    return Err(format!("Line {}: op {} has no closing }}", operation.first, operation.name));
+                }
+                threads.push(Vec::new());
+                thread_names.push(None);
+                continue;
+            }
+            if let ["thread", name] = line.split_whitespace().collect::<Vec<&str>>().as_slice() {
+                if let Some(name) = name.strip_suffix(':') {
+                    if !threads.last().unwrap().is_empty() || thread_names.last().unwrap().is_some() {
+                        threads.push(Vec::new());
+                        thread_names.push(None);
+                    }
+                    if thread_names.contains(&Some(name.to_string())) {
+                        return Err(format!("Line {}: thread {} is declared twice", line_number, name));
+                    }
+                    *thread_names.last_mut().unwrap() = Some(name.to_string());
+                    continue;
+                }
+            }
+            if let Some(condition) = line.strip_prefix("assert ") {
+                if postcondition.is_some() {
+                    return Err(format!("Line {}: the postcondition is declared twice", line_number));
+                }
+                let condition = Condition::parse(condition)
+                    .map_err(|err| format!("Line {}: error parsing postcondition {}: {}", line_number, condition, err))?;
+                postcondition = Some((line_number, condition));
+                continue;
+            }
+            if let Some(initial) = line.strip_prefix("init ") {
+                let (address, initial) = parse_initial(initial)
+                    .map_err(|err| format!("Line {}: error parsing initial value {}: {}", line_number, line, err))?;
+                if initials.insert(address, initial).is_some() {
+                    return Err(format!("Line {}: the initial value of #{} is declared twice", line_number, address));
+                }
+                initial_lines.push((line_number, address));
+                continue;
+            }
+            if let Some(name) = line.strip_prefix("spec ") {
+                if spec.is_some() {
+                    return Err(format!("Line {}: the spec is declared twice", line_number));
+                }
+                spec = Some(Spec::parse(name.trim()).map_err(|err| format!("Line {}: {}", line_number, err))?);
+                continue;
+            }
+            if let Some(head) = line.trim().strip_prefix("op ").filter(|head| head.ends_with('{')) {
+                if let Some(operation) = &operation {
+                    return Err(format!("Line {}: op {} is inside op {}", line_number, head, operation.name));
+                }
+                let (name, args, result) = parse_operation(head.trim())
+                    .map_err(|err| format!("Line {}: error parsing operation {}: {}", line_number, line, err))?;
+                operation = Some(Operation { thread_id: threads.len() - 1, name, args, result, first: line_number, last: line_number });
+                continue;
+            }
+            if line.trim() == "}" {
+                let mut closed = operation.take().ok_or(format!("Line {}: }} without op", line_number))?;
+                closed.last = line_number;
+                operations.push(closed);
+                continue;
+            }
+            if let Some(addresses) = line.strip_prefix("persistent ") {
+                let addresses = parse_persistent(addresses)
+                    .map_err(|err| format!("Line {}: error parsing persistent addresses {}: {}", line_number, line, err))?;
+                for address in addresses {
+                    if !persistent.insert(address) {
+                        return Err(format!("Line {}: #{} is declared persistent twice", line_number, address));
+                    }
+                    persistent_lines.push((line_number, address));
+                }
+                continue;
+            }
+            if let Some(names) = line.strip_prefix("recovery ") {
+                for name in names.split_whitespace() {
+                    if recovery.iter().any(|(_, other)| other == name) {
+                        return Err(format!("Line {}: thread {} is declared a recovery thread twice", line_number, name));
+                    }
+                    recovery.push((line_number, name.to_string()));
+                }
+                continue;
+            }
+            if let Some((kind, names)) = line.split_once(' ').filter(|(kind, _)| *kind == "workgroup" || *kind == "device") {
+                let groups = if kind == "workgroup" { &mut workgroups } else { &mut devices };
+                groups.push((line_number, names.split_whitespace().map(str::to_string).collect()));
+                continue;
+            }
+            if let Some(latency) = line.strip_prefix("latency ") {
+                let [name, weight] = latency.split_whitespace().collect::<Vec<&str>>()[..] else {
+                    return Err(format!("Line {}: expected latency THREAD N", line_number));
+                };
+                thread_latencies.push((line_number, name.to_string(), parse_latency(weight).map_err(|err| format!("Line {}: {}", line_number, err))?));
+                continue;
+            }
+            if let Some(region) = line.strip_prefix("region ") {
+                let region = parse_region(region)
+                    .map_err(|err| format!("Line {}: error parsing region {}: {}", line_number, line, err))?;
+                if let Some(other) = regions.iter().find(|other| other.name == region.name || other.overlaps(&region)) {
+                    return Err(format!("Line {}: region {} overlaps or has the same name as region {}", line_number, region.name, other.name));
+                }
+                regions.push(region);
+                continue;
+            }
+            // `... ~N` gives the instruction a latency of N for the random scheduler.
+            let line = match line.trim_end().rsplit_once(" ~") {
+                Some((instruction, weight)) => {
+                    latencies.lines.insert(line_number, parse_latency(weight).map_err(|err| format!("Line {}: {}", line_number, err))?);
+                    instruction
+                }
+                None => line,
+            };
+            // `r = ?` gives the register a symbolic value, parsed as a constant that symbolic exploration replaces.
+            let (line, input) = match line.trim_end().strip_suffix("= ?") {
+                Some(assignment) => (format!("{}= 0", assignment), true),
+                None => (line.to_string(), false),
+            };
+            // Inputs count instruction lines until the lines are parsed and lowered.
+            if input {
+                inputs.push((threads.len() - 1, threads.last().unwrap().len()));
+            }
+            threads.last_mut().unwrap().push((line_number, line));
+        }
+        Ok(())
+    })();
+    let (mut threads, mut starts) = parse_lines(threads)?;
+    scanned?;
+    for (thread_id, line) in inputs.iter_mut() {
+        *line = starts[*thread_id][*line];
+    }
+    // Init lines or macro definitions before the first thread leave an empty thread in front of the blank line after them,
+    // and an assertion or init lines after the last thread one behind the blank line before them.
+    if let Some(operation) = &operation {
+        return Err(format!("Line {}: op {} has no closing }}", operation.first, operation.name));
+    }
+    let header = postcondition.is_some() || !initials.is_empty() || !regions.is_empty() || !persistent.is_empty() || !recovery.is_empty() || !workgroups.is_empty() || !devices.is_empty() || !thread_latencies.is_empty() || spec.is_some() || !macros.definitions.is_empty();
+    if header && threads.len() > 1 && threads[0].is_empty() && thread_names[0].is_none() {
+        threads.remove(0);
+        thread_names.remove(0);
+        starts.remove(0);
+        for (thread_id, _) in inputs.iter_mut() {
+            *thread_id -= 1;
+        }
+        for operation in operations.iter_mut() {
+            operation.thread_id -= 1;
+        }
+    }
+    if header && threads.len() > 1 && threads.last().unwrap().is_empty() && thread_names.last().unwrap().is_none() {
+        threads.pop();
+        thread_names.pop();
+        starts.pop();
+    }
+    for (instructions, starts) in threads.iter_mut().zip(&starts) {
+        relocate_offsets(instructions, starts);
+    }
+    check_jumps(&threads)?;
+    for (line_number, address) in initial_lines.into_iter().chain(persistent_lines) {
+        check_region(&regions, address).map_err(|err| format!("Line {}: {}", line_number, err))?;
+    }
+    let mut program = Program::new(threads);
+    program.inputs = inputs;
+    program.initial = initials;
+    program.regions = regions;
+    program.persistent = persistent;
+    if !operations.is_empty() {
+        let spec = spec.ok_or("op blocks need a spec line, e.g. spec queue")?;
+        for operation in operations.iter() {
+            let arity = spec.arity(&operation.name).map_err(|err| format!("Line {}: {}", operation.first, err))?;
+            if operation.args.len() != arity {
+                return Err(format!("Line {}: {} takes {} arguments", operation.first, operation.name, arity));
+            }
+        }
+    }
+    program.spec = spec;
+    program.operations = operations;
+    for (thread_id, name) in thread_names.into_iter().enumerate() {
+        if let Some(name) = name {
+            program.thread_names[thread_id] = name;
+        }
+    }
+    if !recovery.is_empty() {
+        program.recovery = vec![Vec::new(); program.threads.len()];
+        for (line_number, name) in recovery {
+            if program.persistent.is_empty() {
+                return Err(format!("Line {}: recovery threads need persistent addresses", line_number));
+            }
+            let thread_id = program.thread_id(&name).ok_or(format!("Line {}: recovery refers to unknown thread {}", line_number, name))?;
+            program.recovery[thread_id] = std::mem::take(&mut program.threads[thread_id]);
+        }
+    }
+    for (line_number, name, weight) in thread_latencies {
+        let thread_id = program.thread_id(&name).ok_or(format!("Line {}: latency refers to unknown thread {}", line_number, name))?;
+        if latencies.threads.insert(thread_id, weight).is_some() {
+            return Err(format!("Line {}: the latency of thread {} is declared twice", line_number, name));
+        }
+    }
+    program.latencies = latencies;
+    let lines: Vec<usize> = workgroups.iter().map(|(line_number, _)| *line_number).collect();
+    program.workgroups = resolve_groups(&program, "workgroup", workgroups)?;
+    program.devices = resolve_groups(&program, "device", devices)?;
+    let topology = program.topology();
+    for (line_number, threads) in lines.into_iter().zip(&program.workgroups) {
+        if threads.iter().any(|thread_id| !topology.shares(Scope::Device, threads[0], *thread_id)) {
+            return Err(format!("Line {}: a workgroup has to be on one device", line_number));
+        }
+    }
+    if let Some((line_number, condition)) = postcondition {
+        if let Some(thread) = condition.threads().into_iter().find(|thread| program.thread_id(thread).is_none()) {
+            return Err(format!("Line {}: postcondition refers to unknown thread {}", line_number, thread));
+        }
+        program.postcondition = Some(condition);
+    }
+    Ok(program)
+}
@@ -0,0 +1,90 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt::Debug;
+
+// The persistent copy of the addresses from `persistent` lines, in the style of Px86: a value reaches
+// it some time after it reaches memory, and the values of one address persist in the order memory
+// took them. A `flush` marks the values its address has had so far, and the next fence of the thread
+// that orders stores waits until they have persisted. A crash keeps, for every address on its own,
+// the value that surely persisted or any later one.
+#[derive(Clone, Default)]
+pub struct Persistence {
+  // Every value each address had in memory, starting with its initial value.
+  history: BTreeMap<i32, Vec<i32>>,
+  // How many values of each address have surely persisted, at least the initial one.
+  persisted: BTreeMap<i32, usize>,
+  // The flushes each thread's next fence completes, with the number of values the address had then.
+  flushes: HashMap<usize, Vec<(i32, usize)>>
+}
+
+impl Debug for Persistence {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    if self.history.is_empty() {
+      return Ok(());
+    }
+    writeln!(f, "# PERSISTED")?;
+    for (address, values) in self.history.iter() {
+      let persisted = self.persisted[address];
+      write!(f, "| #{} = {}", address, values[persisted - 1])?;
+      if persisted < values.len() {
+        write!(f, " (pending {})", values[persisted..].iter().map(|value| value.to_string()).collect::<Vec<String>>().join(", "))?;
+      }
+      writeln!(f)?;
+    }
+    Ok(())
+  }
+}
+
+impl Persistence {
+  pub fn new(addresses: &BTreeSet<i32>, memory: &HashMap<i32, i32>, default: i32) -> Persistence {
+    Persistence {
+      history: addresses.iter().map(|address| (*address, vec![*memory.get(address).unwrap_or(&default)])).collect(),
+      persisted: addresses.iter().map(|address| (*address, 1)).collect(),
+      flushes: HashMap::new()
+    }
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.history.is_empty()
+  }
+
+  // Records the values that reached memory since the last step. A step writes one address at most,
+  // and storing the value an address already holds changes nothing a crash could tell.
+  pub fn observe(&mut self, memory: &HashMap<i32, i32>, default: i32) {
+    for (address, values) in self.history.iter_mut() {
+      let value = *memory.get(address).unwrap_or(&default);
+      if values.last() != Some(&value) {
+        values.push(value);
+      }
+    }
+  }
+
+  pub fn flush(&mut self, thread_id: usize, address: i32) {
+    if let Some(values) = self.history.get(&address) {
+      self.flushes.entry(thread_id).or_default().push((address, values.len()));
+    }
+  }
+
+  pub fn complete(&mut self, thread_id: usize) {
+    for (address, count) in self.flushes.remove(&thread_id).unwrap_or_default() {
+      let persisted = self.persisted.get_mut(&address).unwrap();
+      *persisted = (*persisted).max(count);
+    }
+  }
+
+  // Every state of the persistent addresses a crash at this point can leave.
+  pub fn crash_states(&self) -> Vec<BTreeMap<i32, i32>> {
+    let mut states = vec![BTreeMap::new()];
+    for (address, values) in self.history.iter() {
+      states = states.into_iter()
+        .flat_map(|state| values[self.persisted[address] - 1..].iter().map(move |value| {
+          let mut state = state.clone();
+          state.insert(*address, *value);
+          state
+        }))
+        .collect();
+    }
+    states.sort();
+    states.dedup();
+    states
+  }
+}
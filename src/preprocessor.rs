@@ -0,0 +1,70 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// A program file with its `#include "file"` lines replaced by the included files.
+pub struct Source {
+  pub text: String,
+  main: PathBuf,
+  origins: Vec<(PathBuf, usize)>
+}
+
+impl Source {
+  pub fn read(path: &Path) -> Result<Source, String> {
+    let mut source = Source {
+      text: String::new(),
+      main: path.to_path_buf(),
+      origins: Vec::new()
+    };
+    source.include(path, &mut Vec::new())?;
+    Ok(source)
+  }
+
+  // Included paths are relative to the directory of the file that includes them.
+  fn include(&mut self, path: &Path, stack: &mut Vec<PathBuf>) -> Result<(), String> {
+    let canonical = fs::canonicalize(path)
+      .map_err(|err| format!("Error reading file {}: {}", path.display(), err))?;
+    if stack.contains(&canonical) {
+      return Err(format!("File {} includes itself", path.display()));
+    }
+    let content = fs::read_to_string(path)
+      .map_err(|err| format!("Error reading file {}: {}", path.display(), err))?;
+    stack.push(canonical);
+    for (line_number, line) in content.lines().enumerate() {
+      if let Some(included) = line.trim().strip_prefix("#include") {
+        let included = included.trim().strip_prefix('"').and_then(|included| included.strip_suffix('"'))
+          .ok_or_else(|| format!("{}: expected #include \"file\"", self.describe(path, line_number + 1)))?;
+        let directory = path.parent().unwrap_or(Path::new(""));
+        self.include(&directory.join(included), stack)
+          .map_err(|err| format!("{}: {}", self.describe(path, line_number + 1), err))?;
+        continue;
+      }
+      self.text.push_str(line);
+      self.text.push('\n');
+      self.origins.push((path.to_path_buf(), line_number + 1));
+    }
+    stack.pop();
+    Ok(())
+  }
+
+  fn describe(&self, path: &Path, line: usize) -> String {
+    if path == self.main {
+      format!("Line {}", line)
+    } else {
+      format!("Line {} of {}", line, path.display())
+    }
+  }
+
+  // Rewrites the `Line N` an error starts with to the file and line it came from.
+  pub fn locate(&self, err: String) -> String {
+    let Some(rest) = err.strip_prefix("Line ") else {
+      return err;
+    };
+    let Some((number, message)) = rest.split_once(':') else {
+      return err;
+    };
+    match number.parse::<usize>().ok().and_then(|number| self.origins.get(number.wrapping_sub(1))) {
+      Some((path, line)) => format!("{}:{}", self.describe(path, *line), message),
+      None => err
+    }
+  }
+}
@@ -0,0 +1,221 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+
+use crate::{condition::Condition, instruction::{LabeledInstruction, Mode, Scope}, linearizability::{Operation, Spec}, scheduler::Latencies};
+
+// The initial value of a shared location from an `init` line; ranges exclude their upper bound.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Initial {
+  Value(i32),
+  Range(i32, Option<i32>),
+  Symbolic
+}
+
+// A named block of `size` shared addresses starting at `base`, from a `region` line.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Region {
+  pub name: String,
+  pub base: i32,
+  pub size: i32
+}
+
+impl Region {
+  pub fn contains(&self, address: i32) -> bool {
+    self.base <= address && address - self.base < self.size
+  }
+
+  pub fn overlaps(&self, other: &Region) -> bool {
+    self.contains(other.base) || other.contains(self.base)
+  }
+}
+
+// Once a program declares regions, an access outside all of them is an error.
+pub fn check_region(regions: &[Region], address: i32) -> Result<(), String> {
+  if regions.is_empty() || regions.iter().any(|region| region.contains(address)) {
+    return Ok(());
+  }
+  match regions.iter().filter(|region| region.base <= address).max_by_key(|region| region.base) {
+    Some(region) => Err(format!("address #{} is past the end of region {} (#{} to #{})", address, region.name, region.base, region.base + region.size - 1)),
+    None => Err(format!("address #{} is not in any region", address))
+  }
+}
+
+// The workgroup and device of every thread, which the scopes of its accesses and fences refer to.
+#[derive(Clone, Debug)]
+pub struct Topology {
+  workgroups: Vec<usize>,
+  devices: Vec<usize>
+}
+
+impl Topology {
+  pub fn shares(&self, scope: Scope, thread_id: usize, other: usize) -> bool {
+    match scope {
+      Scope::Workgroup => self.workgroups[thread_id] == self.workgroups[other],
+      Scope::Device => self.devices[thread_id] == self.devices[other],
+      Scope::System => true
+    }
+  }
+}
+
+#[derive(Clone)]
+pub struct Program {
+  pub threads: Vec<Vec<LabeledInstruction>>,
+  pub thread_names: Vec<String>,
+  pub postcondition: Option<Condition>,
+  pub inputs: Vec<(usize, usize)>,
+  pub initial: BTreeMap<i32, Initial>,
+  // Whether loads of addresses that nothing stored to or initialized are runtime errors.
+  pub strict_memory: bool,
+  // The value of the addresses that no `init` line sets.
+  pub default_memory: i32,
+  // Whether switching to another thread clears the exclusive monitor of the thread switched away from.
+  pub clear_monitors: bool,
+  pub regions: Vec<Region>,
+  // The addresses from `persistent` lines, whose values a crash keeps.
+  pub persistent: BTreeSet<i32>,
+  // The code of the threads from the `recovery` line, which only runs after a crash; before it their
+  // threads are empty. Empty if the program has no recovery threads.
+  pub recovery: Vec<Vec<LabeledInstruction>>,
+  // The threads of each `workgroup` and `device` line.
+  pub workgroups: Vec<Vec<usize>>,
+  pub devices: Vec<Vec<usize>>,
+  // The weights that bias the random scheduler, from `~N` suffixes and `latency` lines.
+  pub latencies: Latencies,
+  // The sequential object the `op` blocks implement, from the `spec` line.
+  pub spec: Option<Spec>,
+  pub operations: Vec<Operation>
+}
+
+impl Program {
+  pub fn new(threads: Vec<Vec<LabeledInstruction>>) -> Program {
+    let thread_names = (0..threads.len()).map(|thread_id| thread_id.to_string()).collect();
+    Program {
+      threads,
+      thread_names,
+      postcondition: None,
+      inputs: Vec::new(),
+      initial: BTreeMap::new(),
+      strict_memory: false,
+      default_memory: 0,
+      clear_monitors: false,
+      regions: Vec::new(),
+      persistent: BTreeSet::new(),
+      recovery: Vec::new(),
+      workgroups: Vec::new(),
+      devices: Vec::new(),
+      latencies: Latencies::default(),
+      spec: None,
+      operations: Vec::new()
+    }
+  }
+
+  pub fn thread_id(&self, name: &str) -> Option<usize> {
+    self.thread_names.iter().position(|thread_name| thread_name == name)
+  }
+
+  // The program a crash leaves behind: the recovery threads run from their start with fresh
+  // registers, the persistent addresses hold the values that persisted and every other address
+  // starts over from its initial value. Nothing persists during recovery.
+  pub fn recover(&self, persisted: &BTreeMap<i32, i32>) -> Program {
+    let mut program = self.clone();
+    program.threads = self.recovery.clone();
+    program.recovery = Vec::new();
+    program.persistent = BTreeSet::new();
+    program.inputs = Vec::new();
+    program.spec = None;
+    program.operations = Vec::new();
+    for (address, value) in persisted {
+      program.initial.insert(*address, Initial::Value(*value));
+    }
+    program
+  }
+
+  // Whether any thread, including the recovery threads, starts a transaction.
+  pub fn transactional(&self) -> bool {
+    self.threads.iter().chain(self.recovery.iter()).flatten().any(|instruction| instruction.instruction.is_transactional())
+  }
+
+  // Whether any thread, including the recovery threads, makes a plain `NA` access.
+  pub fn non_atomic(&self) -> bool {
+    self.threads.iter().chain(self.recovery.iter()).flatten().any(|instruction| instruction.get_mode() == Some(Mode::NA))
+  }
+
+  // The first instruction, in the threads or the recovery threads, whose modes are written as
+  // Java's `VOLATILE` or `PLAIN`.
+  pub fn java(&self) -> Option<&LabeledInstruction> {
+    self.threads.iter().chain(self.recovery.iter()).flatten().find(|instruction| instruction.java)
+  }
+
+  // A thread on no `workgroup` line is a workgroup of its own, and the threads on no `device`
+  // line share one device.
+  pub fn topology(&self) -> Topology {
+    let group = |groups: &Vec<Vec<usize>>, thread_id: usize| groups.iter().position(|group| group.contains(&thread_id));
+    let threads = 0..self.threads.len();
+    Topology {
+      workgroups: threads.clone().map(|thread_id| group(&self.workgroups, thread_id).unwrap_or(self.workgroups.len() + thread_id)).collect(),
+      devices: threads.map(|thread_id| group(&self.devices, thread_id).map_or(0, |device| device + 1)).collect()
+    }
+  }
+
+  // Fixed initial values; a range starts at its lower bound.
+  pub fn initial_memory(&self) -> HashMap<i32, i32> {
+    self.initial.iter()
+      .filter_map(|(address, initial)| match initial {
+        Initial::Value(value) | Initial::Range(value, _) => Some((*address, *value)),
+        Initial::Symbolic => None
+      })
+      .collect()
+  }
+
+  pub fn has_ranges(&self) -> bool {
+    self.initial.values().any(|initial| !matches!(initial, Initial::Value(_)))
+  }
+
+  // One program per combination of values the ranges allow, each with fixed initial values.
+  pub fn instances(&self) -> Result<Vec<Program>, String> {
+    let mut instances = vec![self.clone()];
+    for (address, initial) in self.initial.iter() {
+      let values = match initial {
+        Initial::Value(_) => continue,
+        Initial::Range(low, Some(high)) => *low..*high,
+        Initial::Range(_, None) | Initial::Symbolic => return Err(format!("The initial value of #{} is unbounded; use --symbolic or --smt", address))
+      };
+      instances = instances.into_iter()
+        .flat_map(|instance| values.clone().map(move |value| {
+          let mut instance = instance.clone();
+          instance.initial.insert(*address, Initial::Value(value));
+          instance
+        }))
+        .collect();
+    }
+    Ok(instances)
+  }
+
+  // Reads of registers that the reading thread never assigns, usually a misspelt register name.
+  pub fn unassigned_reads(&self) -> Vec<(usize, &LabeledInstruction, &String)> {
+    let mut reads = Vec::new();
+    for (thread_id, instructions) in self.threads.iter().enumerate() {
+      let written: HashSet<&String> = instructions.iter().flat_map(|instruction| instruction.instruction.write_registers()).collect();
+      let mut reported = HashSet::new();
+      for instruction in instructions {
+        for r in instruction.instruction.read_registers() {
+          if !written.contains(r) && reported.insert(r) {
+            reads.push((thread_id, instruction, r));
+          }
+        }
+      }
+    }
+    reads
+  }
+
+  // Names the initial values that differ between instances, e.g. `#1 = 2`.
+  pub fn describe_initial(&self, template: &Program) -> String {
+    let values: Vec<String> = self.initial.iter()
+      .filter(|(address, _)| !matches!(template.initial.get(address), Some(Initial::Value(_))))
+      .map(|(address, initial)| match initial {
+        Initial::Value(value) => format!("#{} = {}", address, value),
+        other => format!("#{} = {:?}", address, other)
+      })
+      .collect();
+    values.join(", ")
+  }
+}
@@ -0,0 +1,185 @@
+use std::collections::HashMap;
+
+use crate::{event::{Access, AccessKind, Event, Observer}, instruction::{Instruction, Mode}};
+
+pub type VectorClock = Vec<usize>;
+
+fn join(clock: &mut VectorClock, other: &VectorClock) {
+  for (value, other_value) in clock.iter_mut().zip(other.iter()) {
+    *value = (*value).max(*other_value);
+  }
+}
+
+fn is_release(mode: Mode) -> bool {
+  matches!(mode, Mode::Rel | Mode::RelAcq | Mode::SeqCst)
+}
+
+fn is_acquire(mode: Mode) -> bool {
+  matches!(mode, Mode::Acq | Mode::RelAcq | Mode::SeqCst)
+}
+
+#[derive(Clone)]
+struct AccessRecord {
+  event: Event,
+  kind: AccessKind,
+  mode: Mode,
+  epoch: usize
+}
+
+#[derive(Clone)]
+pub struct Race {
+  pub address: i32,
+  pub first: Event,
+  pub second: Event
+}
+
+impl Race {
+  pub fn describe(&self, thread_names: &[String]) -> String {
+    format!("address {}: thread {} {:?} and thread {} {:?}",
+      self.address,
+      thread_names[self.first.node.thread_id], self.first.node,
+      thread_names[self.second.node.thread_id], self.second.node)
+  }
+
+  // Whether one of the accesses is plain, which makes the race undefined behavior.
+  pub fn undefined(&self) -> bool {
+    [&self.first, &self.second].iter().any(|event| event.access.as_ref().is_some_and(|access| access.mode == Mode::NA))
+  }
+}
+
+#[derive(Clone)]
+pub struct RaceDetector {
+  clocks: Vec<VectorClock>,
+  release_clocks: HashMap<i32, VectorClock>,
+  sequence_clocks: HashMap<(i32, usize), VectorClock>,
+  lock_clocks: HashMap<i32, VectorClock>,
+  fence_clock: VectorClock,
+  accesses: HashMap<i32, Vec<AccessRecord>>,
+  races: Vec<Race>
+}
+
+impl RaceDetector {
+  pub fn new(number_of_threads: usize) -> RaceDetector {
+    RaceDetector {
+      clocks: vec![vec![0; number_of_threads]; number_of_threads],
+      release_clocks: HashMap::new(),
+      sequence_clocks: HashMap::new(),
+      lock_clocks: HashMap::new(),
+      fence_clock: vec![0; number_of_threads],
+      accesses: HashMap::new(),
+      races: Vec::new()
+    }
+  }
+
+  pub fn races(&self) -> &[Race] {
+    &self.races
+  }
+
+  // With store identities, synchronisation follows reads-from: an acquire synchronises with the
+  // release heading the sequence of the store it read, and an update, which reads the store just
+  // before its own, extends that sequence.
+  fn release_sequence(&mut self, thread_id: usize, access: &Access, source: usize) {
+    let read = match access.kind {
+      AccessKind::Read => Some(source),
+      AccessKind::Update => source.checked_sub(1),
+      _ => None
+    };
+    let inherited = read.and_then(|read| self.sequence_clocks.get(&(access.address, read)).cloned());
+    if let Some(release_clock) = inherited.as_ref().filter(|_| is_acquire(access.mode)) {
+      join(&mut self.clocks[thread_id], release_clock);
+    }
+    if !access.kind.is_write() {
+      return;
+    }
+    let mut clock = if access.kind == AccessKind::Update { inherited } else { None };
+    if is_release(access.mode) {
+      let own = self.clocks[thread_id].clone();
+      match clock.as_mut() {
+        Some(clock) => join(clock, &own),
+        None => clock = Some(own)
+      }
+    }
+    if let Some(clock) = clock {
+      self.sequence_clocks.insert((access.address, source), clock);
+    }
+  }
+
+  fn check(&mut self, thread_id: usize, address: i32, kind: AccessKind, mode: Mode, event: &Event) {
+    let clock = &self.clocks[thread_id];
+    let history = self.accesses.entry(address).or_default();
+    for record in history.iter() {
+      let other_thread = record.event.node.thread_id;
+      let synchronizing = !matches!(mode, Mode::Rlx | Mode::NA) && !matches!(record.mode, Mode::Rlx | Mode::NA);
+      let conflicting = (kind.is_write() || record.kind.is_write()) && !synchronizing;
+      if other_thread != thread_id && conflicting && record.epoch > clock[other_thread] {
+        let known = self.races.iter().any(|race| race.first.node.id == record.event.node.id && race.second.node.id == event.node.id);
+        if !known {
+          self.races.push(Race { address, first: record.event.clone(), second: event.clone() });
+        }
+      }
+    }
+    history.push(AccessRecord { event: event.clone(), kind, mode, epoch: clock[thread_id] });
+  }
+}
+
+impl Observer for RaceDetector {
+  fn on_event(&mut self, event: &Event) {
+    let thread_id = event.node.thread_id;
+    if let Instruction::Fence { mode, order: _ } = event.node.instruction.instruction {
+      self.clocks[thread_id][thread_id] += 1;
+      if is_acquire(mode) {
+        let fence_clock = self.fence_clock.clone();
+        join(&mut self.clocks[thread_id], &fence_clock);
+      }
+      if is_release(mode) {
+        let clock = self.clocks[thread_id].clone();
+        join(&mut self.fence_clock, &clock);
+      }
+      return;
+    }
+    let access = match &event.access {
+      Some(access) => access.clone(),
+      None => return
+    };
+    self.clocks[thread_id][thread_id] += 1;
+    match access.kind {
+      AccessKind::Lock => {
+        if let Some(lock_clock) = self.lock_clocks.get(&access.address) {
+          let lock_clock = lock_clock.clone();
+          join(&mut self.clocks[thread_id], &lock_clock);
+        }
+        return;
+      }
+      AccessKind::Unlock => {
+        self.lock_clocks.insert(access.address, self.clocks[thread_id].clone());
+        return;
+      }
+      _ => {}
+    }
+    if let Some(source) = access.source {
+      self.release_sequence(thread_id, &access, source);
+      self.check(thread_id, access.address, access.kind, access.mode, event);
+      return;
+    }
+    if access.kind != AccessKind::Write && is_acquire(access.mode) {
+      if let Some(release_clock) = self.release_clocks.get(&access.address) {
+        let release_clock = release_clock.clone();
+        join(&mut self.clocks[thread_id], &release_clock);
+      }
+    }
+    self.check(thread_id, access.address, access.kind, access.mode, event);
+    if access.kind.is_write() {
+      let clock = self.clocks[thread_id].clone();
+      match (access.kind, is_release(access.mode)) {
+        (AccessKind::Update, true) => join(self.release_clocks.entry(access.address).or_insert_with(|| vec![0; clock.len()]), &clock),
+        (AccessKind::Update, false) => {}
+        (_, true) => {
+          self.release_clocks.insert(access.address, clock);
+        }
+        (_, false) => {
+          self.release_clocks.remove(&access.address);
+        }
+      }
+    }
+  }
+}
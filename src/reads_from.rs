@@ -0,0 +1,555 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use crate::{error::RuntimeError, explore::{describe_state, Exploration}, instruction::{Instruction, LabeledInstruction}, memory_model::MemoryModelType, program::{check_region, Program, Region}};
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Kind {
+  Read,
+  Write,
+  Update,
+  Fence
+}
+
+#[derive(Clone)]
+struct Event {
+  kind: Kind,
+  address: i32,
+  read: i32,
+  written: i32
+}
+
+impl Event {
+  fn reads(&self) -> bool {
+    matches!(self.kind, Kind::Read | Kind::Update)
+  }
+
+  fn writes(&self) -> bool {
+    matches!(self.kind, Kind::Write | Kind::Update)
+  }
+}
+
+#[derive(Clone)]
+struct ThreadRun {
+  events: Vec<Event>,
+  registers: HashMap<String, i32>,
+  stack: Vec<i32>,
+  error: Option<RuntimeError>
+}
+
+struct Simulation<'a> {
+  thread_id: usize,
+  thread_name: &'a str,
+  instructions: &'a [LabeledInstruction],
+  domains: &'a HashMap<i32, BTreeSet<i32>>,
+  initial: &'a HashMap<i32, i32>,
+  default: i32,
+  regions: &'a [Region],
+  runs: Vec<ThreadRun>
+}
+
+impl Simulation<'_> {
+  fn fail(&mut self, mut run: ThreadRun, instruction: &LabeledInstruction, message: &str) {
+    let mut registers: Vec<(String, i32)> = Vec::new();
+    for r in instruction.instruction.read_registers() {
+      let value = *run.registers.get(r).unwrap_or(&0);
+      if !registers.contains(&(r.clone(), value)) {
+        registers.push((r.clone(), value));
+      }
+    }
+    run.error = Some(RuntimeError {
+      thread_id: self.thread_id,
+      thread_name: self.thread_name.to_string(),
+      instruction: format!("{:?}", instruction),
+      line: instruction.line,
+      message: message.to_string(),
+      registers
+    });
+    self.runs.push(run);
+  }
+
+  fn values(&self, address: i32) -> BTreeSet<i32> {
+    let mut values = self.domains.get(&address).cloned().unwrap_or_default();
+    values.insert(*self.initial.get(&address).unwrap_or(&self.default));
+    values
+  }
+
+  // Runs the thread from `pc`, branching on every value a read may return.
+  fn simulate(&mut self, pc: usize, mut run: ThreadRun) {
+    let instruction = match self.instructions.get(pc) {
+      Some(instruction) => instruction,
+      None => return self.runs.push(run)
+    };
+    let get = |run: &ThreadRun, r: &String| *run.registers.get(r).unwrap_or(&0);
+    let arith = |run: &ThreadRun, r2: &String, r3: &String, op: fn(i32, i32) -> Option<i32>| op(get(run, r2), get(run, r3));
+    let result = match &instruction.instruction {
+      Instruction::Const { r, value } => Some((r, *value)),
+      Instruction::ArithPlus { r1, r2, r3 } => arith(&run, r2, r3, i32::checked_add).map(|value| (r1, value)),
+      Instruction::ArithMinus { r1, r2, r3 } => arith(&run, r2, r3, i32::checked_sub).map(|value| (r1, value)),
+      Instruction::ArithMul { r1, r2, r3 } => arith(&run, r2, r3, i32::checked_mul).map(|value| (r1, value)),
+      Instruction::ArithDiv { r1, r2, r3 } => {
+        if get(&run, r3) == 0 {
+          return self.fail(run, instruction, "division by zero");
+        }
+        arith(&run, r2, r3, i32::checked_div).map(|value| (r1, value))
+      }
+      Instruction::Select { r1, r2, r3, r4 } => Some((r1, get(&run, if get(&run, r2) != 0 { r3 } else { r4 }))),
+      Instruction::Push { r } => {
+        let value = get(&run, r);
+        run.stack.push(value);
+        return self.simulate(pc + 1, run);
+      }
+      Instruction::Pop { r } => match run.stack.pop() {
+        Some(value) => Some((r, value)),
+        None => return self.fail(run, instruction, "pop from an empty stack")
+      },
+      Instruction::Fence { mode: _, order: _ } => {
+        run.events.push(Event { kind: Kind::Fence, address: 0, read: 0, written: 0 });
+        return self.simulate(pc + 1, run);
+      }
+      Instruction::Load { mode: _, address, r: _ }
+      | Instruction::Store { mode: _, address, r: _ }
+      | Instruction::Cas { mode: _, failure: _, address, to: _, exp: _, des: _, flag: _ }
+      | Instruction::Fai { op: _, mode: _, address, to: _, inc: _ } => {
+        let address_value = get(&run, address);
+        if address_value < 0 {
+          return self.fail(run, instruction, "negative memory address");
+        }
+        if let Err(message) = check_region(self.regions, address_value) {
+          return self.fail(run, instruction, &message);
+        }
+        return self.access(pc, run, instruction, address_value);
+      }
+      _ => unreachable!("unsupported instructions are rejected before the simulation")
+    };
+    match result {
+      Some((r, value)) => {
+        run.registers.insert(r.clone(), value);
+        self.simulate(pc + 1, run);
+      }
+      None => self.fail(run, instruction, "arithmetic overflow")
+    }
+  }
+
+  fn access(&mut self, pc: usize, mut run: ThreadRun, instruction: &LabeledInstruction, address: i32) {
+    let get = |run: &ThreadRun, r: &String| *run.registers.get(r).unwrap_or(&0);
+    if let Instruction::Store { mode: _, address: _, r } = &instruction.instruction {
+      let value = get(&run, r);
+      run.events.push(Event { kind: Kind::Write, address, read: 0, written: value });
+      return self.simulate(pc + 1, run);
+    }
+    for value in self.values(address) {
+      let mut run = run.clone();
+      match &instruction.instruction {
+        Instruction::Load { mode: _, address: _, r } => {
+          run.events.push(Event { kind: Kind::Read, address, read: value, written: 0 });
+          run.registers.insert(r.clone(), value);
+        }
+        Instruction::Cas { mode: _, failure: _, address: _, to, exp, des, flag } => {
+          let success = value == get(&run, exp);
+          if success {
+            let written = get(&run, des);
+            run.events.push(Event { kind: Kind::Update, address, read: value, written });
+          } else {
+            run.events.push(Event { kind: Kind::Read, address, read: value, written: 0 });
+          }
+          run.registers.insert(to.clone(), value);
+          if let Some(flag) = flag {
+            run.registers.insert(flag.clone(), success as i32);
+          }
+        }
+        Instruction::Fai { op, mode: _, address: _, to, inc } => {
+          let written = match op.apply(value, get(&run, inc)) {
+            Some(written) => written,
+            None => {
+              self.fail(run, instruction, "arithmetic overflow");
+              continue;
+            }
+          };
+          run.events.push(Event { kind: Kind::Update, address, read: value, written });
+          run.registers.insert(to.clone(), value);
+        }
+        _ => unreachable!()
+      }
+      self.simulate(pc + 1, run);
+    }
+  }
+}
+
+// Every value some execution can read is written by a chain of at most `writes` writes, so the
+// value domains are complete after that many rounds even when they would otherwise keep growing.
+fn thread_runs(program: &Program) -> Vec<Vec<ThreadRun>> {
+  let writes = program.threads.iter().flatten()
+    .filter(|instruction| matches!(instruction.instruction, Instruction::Store { mode: _, address: _, r: _ } | Instruction::Cas { mode: _, failure: _, address: _, to: _, exp: _, des: _, flag: _ } | Instruction::Fai { op: _, mode: _, address: _, to: _, inc: _ }))
+    .count();
+  let initial = program.initial_memory();
+  let mut domains: HashMap<i32, BTreeSet<i32>> = HashMap::new();
+  let mut round = 0;
+  loop {
+    let mut runs = Vec::new();
+    for (thread_id, instructions) in program.threads.iter().enumerate() {
+      let mut simulation = Simulation {
+        thread_id,
+        thread_name: &program.thread_names[thread_id],
+        instructions,
+        domains: &domains,
+        initial: &initial,
+        default: program.default_memory,
+        regions: &program.regions,
+        runs: Vec::new()
+      };
+      simulation.simulate(0, ThreadRun { events: Vec::new(), registers: HashMap::new(), stack: Vec::new(), error: None });
+      runs.push(simulation.runs);
+    }
+    let mut next_domains = domains.clone();
+    for event in runs.iter().flatten().flat_map(|run| run.events.iter()).filter(|event| event.writes()) {
+      next_domains.entry(event.address).or_default().insert(event.written);
+    }
+    round += 1;
+    if next_domains == domains || round > writes {
+      return runs;
+    }
+    domains = next_domains;
+  }
+}
+
+struct Execution<'a> {
+  model: MemoryModelType,
+  events: Vec<(usize, usize, &'a Event)>,
+  rf: Vec<Option<usize>>,
+  mo: HashMap<i32, Vec<usize>>
+}
+
+impl Execution<'_> {
+  fn preserved(&self, first: &Event, second: &Event, thread_events: &[&Event]) -> bool {
+    let fenced = thread_events.iter().any(|event| matches!(event.kind, Kind::Fence | Kind::Update));
+    match (self.model, first.kind, second.kind) {
+      (MemoryModelType::SC, _, _) => true,
+      (_, Kind::Write, Kind::Read) => fenced,
+      (MemoryModelType::PSO, Kind::Write, Kind::Write) => fenced || first.address == second.address,
+      _ => true
+    }
+  }
+
+  // fr: a read is before every write coherence-after the write it reads from.
+  fn edges(&self, global: bool) -> Vec<Vec<usize>> {
+    let mut edges = vec![Vec::new(); self.events.len()];
+    let mut add = |from: usize, to: usize| if from != to {
+      edges[from].push(to);
+    };
+    for (i, (thread, index, event)) in self.events.iter().enumerate() {
+      for (j, (other_thread, other_index, other)) in self.events.iter().enumerate() {
+        if thread != other_thread || index >= other_index {
+          continue;
+        }
+        let between: Vec<&Event> = self.events.iter()
+          .filter(|(t, k, _)| t == thread && k > index && k < other_index)
+          .map(|(_, _, event)| *event)
+          .collect();
+        let ordered = if global {
+          self.preserved(event, other, &between)
+        } else {
+          event.kind != Kind::Fence && other.kind != Kind::Fence && event.address == other.address
+        };
+        if ordered {
+          add(i, j);
+        }
+      }
+    }
+    for (r, source) in self.rf.iter().enumerate() {
+      if !self.events[r].2.reads() {
+        continue;
+      }
+      if let Some(w) = source {
+        if !global || self.model == MemoryModelType::SC || self.events[*w].0 != self.events[r].0 {
+          add(*w, r);
+        }
+      }
+      let order = self.mo.get(&self.events[r].2.address).map_or(&[][..], |order| order.as_slice());
+      let after = match source {
+        Some(w) => order.iter().position(|id| id == w).unwrap() + 1,
+        None => 0
+      };
+      for w in order[after..].iter() {
+        add(r, *w);
+      }
+    }
+    for order in self.mo.values() {
+      for pair in order.windows(2) {
+        add(pair[0], pair[1]);
+      }
+    }
+    edges
+  }
+
+  fn acyclic(edges: &[Vec<usize>]) -> bool {
+    let mut incoming = vec![0; edges.len()];
+    for to in edges.iter().flatten() {
+      incoming[*to] += 1;
+    }
+    let mut queue: Vec<usize> = (0..edges.len()).filter(|id| incoming[*id] == 0).collect();
+    let mut visited = 0;
+    while let Some(id) = queue.pop() {
+      visited += 1;
+      for to in edges[id].iter() {
+        incoming[*to] -= 1;
+        if incoming[*to] == 0 {
+          queue.push(*to);
+        }
+      }
+    }
+    visited == edges.len()
+  }
+
+  fn atomic(&self) -> bool {
+    self.events.iter().enumerate().filter(|(_, (_, _, event))| event.kind == Kind::Update).all(|(u, (_, _, event))| {
+      let order = &self.mo[&event.address];
+      let position = order.iter().position(|id| *id == u).unwrap();
+      match self.rf[u] {
+        Some(w) => position > 0 && order[position - 1] == w,
+        None => position == 0
+      }
+    })
+  }
+
+  fn consistent(&self) -> bool {
+    self.atomic() && Self::acyclic(&self.edges(false)) && Self::acyclic(&self.edges(true))
+  }
+}
+
+fn permutations(items: &[usize]) -> Vec<Vec<usize>> {
+  if items.is_empty() {
+    return vec![Vec::new()];
+  }
+  let mut result = Vec::new();
+  for (i, item) in items.iter().enumerate() {
+    let mut rest = items.to_vec();
+    rest.remove(i);
+    for mut permutation in permutations(&rest) {
+      permutation.insert(0, *item);
+      result.push(permutation);
+    }
+  }
+  result
+}
+
+fn product<T: Clone>(choices: &[Vec<T>]) -> Vec<Vec<T>> {
+  choices.iter().fold(vec![Vec::new()], |combinations, options| {
+    combinations.iter().flat_map(|combination| options.iter().map(move |option| {
+      let mut combination = combination.clone();
+      combination.push(option.clone());
+      combination
+    })).collect()
+  })
+}
+
+// Every choice of reads-from and coherence order for the events of the given thread runs.
+fn candidates<'a>(model: MemoryModelType, runs: &[&'a ThreadRun], initial: &HashMap<i32, i32>, default: i32) -> Vec<Execution<'a>> {
+  let events: Vec<(usize, usize, &Event)> = runs.iter().enumerate()
+    .flat_map(|(thread, run)| run.events.iter().enumerate().map(move |(index, event)| (thread, index, event)))
+    .collect();
+  let mut writes: HashMap<i32, Vec<usize>> = HashMap::new();
+  for (id, (_, _, event)) in events.iter().enumerate() {
+    if event.writes() {
+      writes.entry(event.address).or_default().push(id);
+    }
+  }
+  let mut sources: Vec<Vec<Option<usize>>> = Vec::new();
+  for (id, (_, _, event)) in events.iter().enumerate() {
+    if !event.reads() {
+      sources.push(vec![None]);
+      continue;
+    }
+    let mut candidates: Vec<Option<usize>> = writes.get(&event.address).into_iter().flatten()
+      .filter(|w| **w != id && events[**w].2.written == event.read)
+      .map(|w| Some(*w))
+      .collect();
+    if event.read == *initial.get(&event.address).unwrap_or(&default) {
+      candidates.push(None);
+    }
+    sources.push(candidates);
+  }
+  let addresses: Vec<i32> = writes.keys().cloned().collect();
+  let orders: Vec<Vec<Vec<usize>>> = addresses.iter().map(|address| permutations(&writes[address])).collect();
+  let mut executions = Vec::new();
+  for rf in product(&sources) {
+    for mo in product(&orders) {
+      executions.push(Execution {
+        model,
+        events: events.clone(),
+        rf: rf.clone(),
+        mo: addresses.iter().cloned().zip(mo).collect()
+      });
+    }
+  }
+  executions
+}
+
+fn explore_runs(model: MemoryModelType, program: &Program, runs: &[&ThreadRun], exploration: &mut Exploration) {
+  let initial = program.initial_memory();
+  for execution in candidates(model, runs, &initial, program.default_memory) {
+    if !execution.consistent() {
+      continue;
+    }
+    match runs.iter().find_map(|run| run.error.as_ref()) {
+      Some(err) => exploration.record(format!("error: {}", err), Vec::new()),
+      None => {
+        let mut memory = initial.clone();
+        memory.extend(execution.mo.iter().filter_map(|(address, order)| order.last().map(|w| (*address, execution.events[*w].2.written))));
+        let thread_names: Vec<&str> = program.thread_names.iter().map(|name| name.as_str()).collect();
+        let registers: Vec<&HashMap<String, i32>> = runs.iter().map(|run| &run.registers).collect();
+        exploration.record(describe_state(&thread_names, &registers, &memory), Vec::new());
+      }
+    }
+  }
+}
+
+fn check_supported(memory_model: MemoryModelType, program: &Program, what: &str) -> Result<(), String> {
+  if !matches!(memory_model, MemoryModelType::SC | MemoryModelType::TSO | MemoryModelType::PSO) {
+    return Err(format!("{} does not support the {:?} model", what, memory_model));
+  }
+  if program.has_ranges() {
+    return Err(format!("{} needs fixed initial values", what));
+  }
+  for instruction in program.threads.iter().flatten() {
+    if instruction.instruction.is_blocking() || instruction.instruction.is_exclusive() || instruction.instruction.is_transactional() || matches!(instruction.instruction, Instruction::Cond { r: _, target: _ } | Instruction::Call { target: _ } | Instruction::Ret | Instruction::Lock { address: _ } | Instruction::Unlock { address: _ } | Instruction::Fence { mode: _, order: Some(_) } | Instruction::Flush { address: _ }) {
+      return Err(match instruction.line {
+        Some(line) => format!("{} does not support {:?} (line {})", what, instruction, line),
+        None => format!("{} does not support {:?}", what, instruction)
+      });
+    }
+  }
+  Ok(())
+}
+
+// Enumerates executions by their reads-from and coherence orders instead of interleavings and keeps
+// the consistent ones: SC requires po, rf, co and fr to be acyclic, TSO drops write-to-read order and
+// PSO also write-to-write order between different addresses unless a fence or an RMW is in between.
+pub fn enumerate(memory_model: MemoryModelType, program: &Program) -> Result<Exploration, String> {
+  check_supported(memory_model, program, "Reads-from enumeration")?;
+  let thread_runs = thread_runs(program);
+  let mut exploration = Exploration::new();
+  let choices: Vec<Vec<&ThreadRun>> = thread_runs.iter().map(|runs| runs.iter().collect()).collect();
+  for runs in product(&choices) {
+    explore_runs(memory_model, program, &runs, &mut exploration);
+  }
+  Ok(exploration)
+}
+
+fn alloy_set(events: Vec<String>) -> String {
+  if events.is_empty() { "none".to_string() } else { events.join(" + ") }
+}
+
+fn alloy_relation(pairs: Vec<(String, String)>) -> String {
+  if pairs.is_empty() {
+    return "none -> none".to_string();
+  }
+  pairs.iter().map(|(from, to)| format!("{} -> {}", from, to)).collect::<Vec<String>>().join(" + ")
+}
+
+impl Execution<'_> {
+  fn alloy_name(&self, id: usize) -> String {
+    format!("e{}_{}", self.events[id].0, self.events[id].1)
+  }
+
+  fn describe(&self, id: usize, thread_names: &[String]) -> String {
+    let (thread, _, event) = self.events[id];
+    let access = match event.kind {
+      Kind::Read => format!("R #{} = {}", event.address, event.read),
+      Kind::Write => format!("W #{} = {}", event.address, event.written),
+      Kind::Update => format!("RMW #{} = {} -> {}", event.address, event.read, event.written),
+      Kind::Fence => "F".to_string()
+    };
+    format!("{} ({}): {}", self.alloy_name(id), thread_names[thread], access)
+  }
+
+  fn to_alloy(&self, number: usize, thread_names: &[String]) -> String {
+    let ids = 0..self.events.len();
+    let names = |filter: &dyn Fn(&Event) -> bool| alloy_set(ids.clone().filter(|id| filter(self.events[*id].2)).map(|id| self.alloy_name(id)).collect());
+    let pairs = |related: &dyn Fn(usize, usize) -> bool| alloy_relation(ids.clone()
+      .flat_map(|i| ids.clone().map(move |j| (i, j)))
+      .filter(|(i, j)| related(*i, *j))
+      .map(|(i, j)| (self.alloy_name(i), self.alloy_name(j)))
+      .collect());
+    let same_thread = |i: usize, j: usize| self.events[i].0 == self.events[j].0;
+    let co: Vec<(usize, usize)> = self.mo.values()
+      .flat_map(|order| order.iter().enumerate().flat_map(move |(k, w)| order[k + 1..].iter().map(move |later| (*w, *later))))
+      .collect();
+    let mut lines = vec![format!("// candidate {}: {} under {:?}", number, if self.consistent() { "consistent" } else { "inconsistent" }, self.model)];
+    for id in ids.clone() {
+      lines.push(format!("//   {}", self.describe(id, thread_names)));
+    }
+    lines.push(format!("pred candidate{} {{", number));
+    lines.push(format!("  X.EV = {}", names(&|_| true)));
+    lines.push(format!("  X.R = {}", names(&|event| event.reads())));
+    lines.push(format!("  X.W = {}", names(&|event| event.writes())));
+    lines.push(format!("  X.F = {}", names(&|event| event.kind == Kind::Fence)));
+    lines.push(format!("  X.sb = {}", pairs(&|i, j| same_thread(i, j) && self.events[i].1 < self.events[j].1)));
+    lines.push(format!("  X.sthd = {}", pairs(&same_thread)));
+    lines.push(format!("  X.sloc = {}", pairs(&|i, j| {
+      let (first, second) = (self.events[i].2, self.events[j].2);
+      first.kind != Kind::Fence && second.kind != Kind::Fence && first.address == second.address
+    })));
+    lines.push(format!("  X.rf = {}", pairs(&|w, r| self.events[r].2.reads() && self.rf[r] == Some(w))));
+    lines.push(format!("  X.co = {}", pairs(&|i, j| co.contains(&(i, j)))));
+    lines.push("}".to_string());
+    lines.join("\n")
+  }
+}
+
+// Writes every candidate execution of the program as an Alloy predicate over memalloy-style relations
+// and groups them by whether the axioms of `enumerate` accept them, so a relational specification of
+// the same model can be checked against this crate with `check { consistent iff spec[X] }`.
+// Read-modify-writes are single events in both R and W.
+pub fn alloy(memory_model: MemoryModelType, program: &Program) -> Result<String, String> {
+  check_supported(memory_model, program, "Alloy export")?;
+  let initial = program.initial_memory();
+  let thread_runs = thread_runs(program);
+  let choices: Vec<Vec<&ThreadRun>> = thread_runs.iter().map(|runs| runs.iter().filter(|run| run.error.is_none()).collect()).collect();
+  let mut events: BTreeSet<(usize, usize)> = BTreeSet::new();
+  let mut predicates = Vec::new();
+  let (mut consistent, mut inconsistent) = (Vec::new(), Vec::new());
+  for runs in product(&choices) {
+    for execution in candidates(memory_model, &runs, &initial, program.default_memory) {
+      let number = predicates.len();
+      events.extend(execution.events.iter().map(|(thread, index, _)| (*thread, *index)));
+      if execution.consistent() {
+        consistent.push(format!("candidate{}", number));
+      } else {
+        inconsistent.push(format!("candidate{}", number));
+      }
+      predicates.push(execution.to_alloy(number, &program.thread_names));
+    }
+  }
+  let mut lines = vec![
+    format!("// Candidate executions under {:?}", memory_model),
+    "module litmus".to_string(),
+    String::new(),
+    "abstract sig E {}".to_string()
+  ];
+  if program.default_memory != 0 {
+    lines.insert(1, format!("// Other addresses start at {}", program.default_memory));
+  }
+  if !initial.is_empty() {
+    let values: BTreeMap<&i32, &i32> = initial.iter().collect();
+    lines.insert(1, format!("// Initial values: {:?}", values));
+  }
+  if !events.is_empty() {
+    let names: Vec<String> = events.iter().map(|(thread, index)| format!("e{}_{}", thread, index)).collect();
+    lines.push(format!("one sig {} extends E {{}}", names.join(", ")));
+  }
+  lines.push("one sig X {".to_string());
+  lines.push("  EV, R, W, F: set E,".to_string());
+  lines.push("  sb, sthd, sloc, rf, co: E -> E".to_string());
+  lines.push("}".to_string());
+  lines.push(String::new());
+  let any = |names: Vec<String>| if names.is_empty() { "some none".to_string() } else { names.join(" or ") };
+  let all = (0..predicates.len()).map(|number| format!("candidate{}", number)).collect();
+  for predicate in predicates {
+    lines.push(predicate);
+    lines.push(String::new());
+  }
+  lines.push(format!("pred candidate {{ {} }}", any(all)));
+  lines.push(format!("pred consistent {{ {} }}", any(consistent)));
+  lines.push(format!("pred inconsistent {{ {} }}", any(inconsistent)));
+  Ok(lines.join("\n") + "\n")
+}
@@ -0,0 +1,40 @@
+use std::collections::BTreeMap;
+
+use crate::{explore::{replay, Exploration, Explorer, Outcome}, graph::Node, memory_model::MemoryModelType, program::Program};
+
+// A final state of the recovery threads, reached from a crash that left `persisted` behind.
+pub struct RecoveryOutcome {
+  pub count: usize,
+  // The steps up to the crash, and those of the recovery threads after it.
+  pub crash: Vec<Node>,
+  pub witness: Vec<Node>
+}
+
+pub struct Recovery {
+  pub outcomes: BTreeMap<String, RecoveryOutcome>,
+  pub executions: usize,
+  pub truncated: usize
+}
+
+// Runs the recovery threads exhaustively from every crash state the exploration found, once per
+// distinct state. Each outcome is prefixed with the crash state it recovered from.
+pub fn explore(memory_model: &MemoryModelType, program: &Program, exploration: &Exploration, max_steps: usize, sleep_sets: bool) -> Recovery {
+  let mut recovery = Recovery { outcomes: BTreeMap::new(), executions: 0, truncated: 0 };
+  for (crash, Outcome { count: _, witness: crash_witness }) in exploration.crashes.iter() {
+    let schedule: Vec<usize> = crash_witness.iter().map(|node| node.id).collect();
+    let model = replay(memory_model, program, &schedule).expect("crash witnesses are replayed without errors");
+    let persisted = model.persistence().crash_states().into_iter()
+      .find(|state| format!("{:?}", state) == *crash)
+      .expect("the state after a crash witness can crash as recorded");
+    let recovered = program.recover(&persisted);
+    let mut explorer = Explorer::new(memory_model, &recovered, max_steps);
+    explorer.set_sleep_sets(sleep_sets);
+    let explored = explorer.run();
+    recovery.executions += explored.executions;
+    recovery.truncated += explored.truncated;
+    for (outcome, Outcome { count, witness }) in explored.outcomes {
+      recovery.outcomes.insert(format!("crash {} -> {}", crash, outcome), RecoveryOutcome { count, crash: crash_witness.clone(), witness });
+    }
+  }
+  recovery
+}
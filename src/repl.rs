@@ -0,0 +1,295 @@
+use std::collections::HashSet;
+use std::fs;
+use std::io::{BufRead, Write};
+
+use rand::{rngs::SmallRng, SeedableRng};
+
+use crate::{deadlock, error::RuntimeError, graph::Node, instruction::{Instruction, LabeledInstruction}, json, memory_model::{MemoryModel, MemoryModelType}, parser::parse_instruction, program::Program, scheduler::SchedulerOptions};
+
+const HELP: &str = "\
+thread <name>       select the thread new instructions are appended to
+step [n]            execute n random steps (default 1)
+choose <i>, <i>     execute the i-th instruction from the candidates list
+random              execute a random instruction from the candidates list
+undo [n]            revert the last n steps or appended instructions (default 1)
+redo [n]            repeat the last n reverted actions (default 1)
+run                 execute random steps until a breakpoint or the end of the program
+break <label>       stop `run` before an instruction with the label is executed
+candidates          list numbered instructions that can be executed next
+print [regs|mem]    print registers, buffers and memory
+dump <file>         write registers, buffers and memory to the file as JSON
+help                print this message
+quit                leave the session
+Any other input is parsed as an instruction and appended to the selected thread.";
+
+#[derive(Clone)]
+enum Action {
+  Append { thread_id: usize, instruction: LabeledInstruction },
+  // The generator of a random step as it was before the step, to draw the same choice again.
+  Step { node: Node, rng: Option<SmallRng> }
+}
+
+pub struct Repl {
+  memory_model: MemoryModelType,
+  program: Program,
+  rng: SmallRng,
+  scheduler_options: SchedulerOptions,
+  model: Box<dyn MemoryModel>,
+  journal: Vec<Action>,
+  undone: Vec<Action>,
+  current_thread: usize,
+  breakpoints: HashSet<String>
+}
+
+impl Repl {
+  pub fn new(memory_model: MemoryModelType, program: Program) -> Repl {
+    Repl {
+      model: memory_model.create(&program),
+      memory_model,
+      program,
+      rng: rand::make_rng(),
+      scheduler_options: SchedulerOptions::default(),
+      journal: Vec::new(),
+      undone: Vec::new(),
+      current_thread: 0,
+      breakpoints: HashSet::new()
+    }
+  }
+
+  pub fn set_seed(&mut self, seed: u64) {
+    self.rng = SmallRng::seed_from_u64(seed);
+  }
+
+  pub fn set_scheduler_options(&mut self, options: SchedulerOptions) {
+    self.scheduler_options = options;
+    self.model.set_scheduler_options(options);
+  }
+
+  fn apply(&mut self, action: &Action, debug_print: bool) -> Result<(), RuntimeError> {
+    match action {
+      Action::Append { thread_id, instruction } => self.model.append_instruction(*thread_id, instruction.clone()),
+      Action::Step { node, rng: _ } => {
+        self.model.step(node.clone(), debug_print)?;
+      }
+    }
+    Ok(())
+  }
+
+  fn record(&mut self, action: Action) {
+    self.journal.push(action);
+    self.undone.clear();
+  }
+
+  fn rebuild(&mut self) {
+    self.model = self.memory_model.create(&self.program);
+    self.model.set_scheduler_options(self.scheduler_options);
+    for action in self.journal.clone() {
+      self.apply(&action, false).expect("journaled actions are replayed without errors");
+    }
+  }
+
+  // The model gets a generator derived from the session's for each random step, so that undoing the
+  // step can take the session's generator back to where it was.
+  fn random_step(&mut self) -> Result<(), String> {
+    let rng = self.rng.clone();
+    self.model.set_rng(SmallRng::from_rng(&mut self.rng));
+    match self.model.random_step(true) {
+      Ok(Some(event)) => self.record(Action::Step { node: event.node, rng: Some(rng) }),
+      Ok(None) => {}
+      Err(err) => {
+        self.rebuild();
+        return Err(err.to_string());
+      }
+    }
+    Ok(())
+  }
+
+  fn undo(&mut self, n: usize) {
+    for _ in 0..n {
+      match self.journal.pop() {
+        Some(action) => {
+          if let Action::Step { node: _, rng: Some(rng) } = &action {
+            self.rng = rng.clone();
+          }
+          self.undone.push(action);
+        }
+        None => break
+      }
+    }
+    self.rebuild();
+  }
+
+  fn redo(&mut self, n: usize) -> Result<(), String> {
+    for _ in 0..n {
+      match self.undone.pop() {
+        Some(action) => {
+          if let Err(err) = self.apply(&action, true) {
+            self.undone.push(action);
+            self.rebuild();
+            return Err(err.to_string());
+          }
+          if let Action::Step { node: _, rng: Some(rng) } = &action {
+            self.rng = rng.clone();
+            SmallRng::from_rng(&mut self.rng);
+          }
+          self.journal.push(action);
+        }
+        None => break
+      }
+    }
+    Ok(())
+  }
+
+  fn at_breakpoint(&self) -> Option<String> {
+    self.model.get_possible_executions().into_iter()
+      .filter_map(|node| node.instruction.label)
+      .find(|label| self.breakpoints.contains(label))
+  }
+
+  fn describe_candidate(&self, node: &Node) -> String {
+    match node.instruction.instruction {
+      Instruction::Propagate { thread_id, address, value } => {
+        format!("thread {}: propagate #{} = {} (buffer: {:?})", self.model.thread_name(node.thread_id), address, value, self.model.buffer(thread_id))
+      }
+      _ => {
+        let registers = self.model.registers(node.thread_id);
+        let mut operands: Vec<String> = Vec::new();
+        for r in node.instruction.instruction.read_registers() {
+          let operand = format!("{} = {}", r, registers.get(r).unwrap_or(&0));
+          if !operands.contains(&operand) {
+            operands.push(operand);
+          }
+        }
+        if operands.is_empty() {
+          format!("thread {}: {:?}", self.model.thread_name(node.thread_id), node)
+        } else {
+          format!("thread {}: {:?}    ({})", self.model.thread_name(node.thread_id), node, operands.join(", "))
+        }
+      }
+    }
+  }
+
+  fn print_candidates(&self) {
+    for (i, node) in self.model.get_possible_executions().iter().enumerate() {
+      println!("[{}] {}", i, self.describe_candidate(node));
+    }
+    if let Some(deadlock) = deadlock::detect(self.model.as_ref()) {
+      print!("{}", deadlock);
+    }
+  }
+
+  fn choose(&mut self, i: &str) -> Result<(), String> {
+    let i: usize = i.parse().map_err(|_| "Invalid candidate index".to_string())?;
+    let node = self.model.get_possible_executions().into_iter().nth(i)
+      .ok_or_else(|| format!("There is no candidate with index {}", i))?;
+    let action = Action::Step { node, rng: None };
+    if let Err(err) = self.apply(&action, true) {
+      self.rebuild();
+      return Err(err.to_string());
+    }
+    self.record(action);
+    Ok(())
+  }
+
+  pub fn execute(&mut self, line: &str) -> Result<bool, String> {
+    let parts: Vec<&str> = line.split_whitespace().collect();
+    match parts.as_slice() {
+      [] => {}
+      ["quit"] | ["exit"] => return Ok(false),
+      ["help"] => println!("{}", HELP),
+      ["thread", name] => {
+        self.current_thread = self.program.thread_id(name)
+          .ok_or_else(|| format!("There is no thread {}", name))?;
+      }
+      ["step"] => self.random_step()?,
+      ["step", n] => {
+        let n: usize = n.parse().map_err(|_| "Invalid number of steps".to_string())?;
+        for _ in 0..n {
+          self.random_step()?;
+        }
+      }
+      ["undo"] => self.undo(1),
+      ["undo", n] => self.undo(n.parse().map_err(|_| "Invalid number of steps".to_string())?),
+      ["redo"] => self.redo(1)?,
+      ["redo", n] => self.redo(n.parse().map_err(|_| "Invalid number of steps".to_string())?)?,
+      ["choose", i] => self.choose(i)?,
+      [i] if i.chars().all(|c| c.is_ascii_digit()) => self.choose(i)?,
+      ["random"] => self.random_step()?,
+      ["run"] => {
+        let mut first_step = true;
+        while !self.model.get_possible_executions().is_empty() {
+          if !first_step {
+            if let Some(label) = self.at_breakpoint() {
+              println!("# BREAKPOINT {}", label);
+              break;
+            }
+          }
+          self.random_step()?;
+          first_step = false;
+        }
+      }
+      ["break", label] => {
+        self.breakpoints.insert(label.to_string());
+      }
+      ["candidates"] => self.print_candidates(),
+      ["print"] | ["print", "all"] => print!("{:?}", self.model),
+      ["print", "regs"] => {
+        println!("# REGISTERS");
+        for thread_id in 0..self.model.number_of_threads() {
+          println!("| Thread {}: {:?}", self.model.thread_name(thread_id), self.model.registers(thread_id));
+        }
+      }
+      ["print", "mem"] => {
+        println!("# MEMORY");
+        println!("| {:?}", self.model.memory());
+      }
+      ["dump", file] => {
+        fs::write(file, json::model_state(self.model.as_ref()) + "\n")
+          .map_err(|err| format!("Error writing file {}: {}", file, err))?;
+      }
+      _ => {
+        let instruction = parse_instruction(line)?;
+        let action = Action::Append { thread_id: self.current_thread, instruction };
+        self.apply(&action, false).map_err(|err| err.to_string())?;
+        self.record(action);
+      }
+    }
+    Ok(true)
+  }
+
+  fn prompt(&self) {
+    self.print_candidates();
+    print!("{}> ", self.model.thread_name(self.current_thread));
+    std::io::stdout().flush().unwrap();
+  }
+
+  pub fn run<R: BufRead>(&mut self, input: R) {
+    self.prompt();
+    for line in input.lines() {
+      let line = line.unwrap();
+      match self.execute(&line) {
+        Ok(true) => {}
+        Ok(false) => return,
+        Err(err) => eprintln!("Error: {}", err)
+      }
+      self.prompt();
+    }
+  }
+
+  pub fn run_script<R: BufRead>(&mut self, input: R) -> Result<(), String> {
+    for (line_number, line) in input.lines().enumerate() {
+      let line = line.map_err(|err| err.to_string())?;
+      let command = line.trim();
+      if command.is_empty() || command.starts_with('#') {
+        continue;
+      }
+      println!("> {}", command);
+      match self.execute(command) {
+        Ok(true) => {}
+        Ok(false) => return Ok(()),
+        Err(err) => return Err(format!("Error at script line {}: {}", line_number + 1, err))
+      }
+    }
+    Ok(())
+  }
+}
@@ -0,0 +1,98 @@
+use std::collections::BTreeSet;
+
+use crate::{explore::{Explorer, Outcome}, graph::Node, instruction::Instruction, memory_model::MemoryModelType, program::Program};
+
+// Two memory accesses of a thread that took effect in the opposite of program order, such as a load
+// that read memory while an earlier store of its thread was still buffered.
+pub struct Reordering {
+  pub thread_id: usize,
+  pub earlier: Node,
+  pub later: Node
+}
+
+pub struct Robustness {
+  // The outcomes the model reaches and SC does not, each with a schedule reaching it.
+  pub violations: Vec<(String, Outcome)>,
+  // The reordered pairs in those schedules, once per pair of instructions.
+  pub reorderings: Vec<Reordering>,
+  pub outcomes: usize,
+  pub truncated: usize
+}
+
+impl Robustness {
+  pub fn is_robust(&self) -> bool {
+    self.violations.is_empty()
+  }
+}
+
+// A program is robust against TSO or PSO when the model reaches exactly its SC outcomes, so it
+// needs no more fences. Both sets are explored exhaustively.
+pub fn check(memory_model: &MemoryModelType, program: &Program, max_steps: usize, sleep_sets: bool) -> Result<Robustness, String> {
+  if !matches!(memory_model, MemoryModelType::TSO | MemoryModelType::PSO) {
+    return Err(format!("robustness is checked against TSO or PSO, not {:?}", memory_model));
+  }
+  let explore = |memory_model: &MemoryModelType| {
+    let mut explorer = Explorer::new(memory_model, program, max_steps);
+    explorer.set_sleep_sets(sleep_sets);
+    explorer.run()
+  };
+  let sc = explore(&MemoryModelType::SC);
+  let relaxed = explore(memory_model);
+  let outcomes = relaxed.outcomes.len();
+  let violations: Vec<(String, Outcome)> = relaxed.outcomes.into_iter()
+    .filter(|(outcome, _)| !sc.outcomes.contains_key(outcome))
+    .collect();
+  let mut reorderings = Vec::new();
+  let mut seen = BTreeSet::new();
+  for (_, outcome) in &violations {
+    for reordering in reorderings_in(memory_model, program, &outcome.witness) {
+      if seen.insert((reordering.thread_id, format!("{:?}", reordering.earlier), format!("{:?}", reordering.later))) {
+        reorderings.push(reordering);
+      }
+    }
+  }
+  Ok(Robustness { violations, reorderings, outcomes, truncated: sc.truncated + relaxed.truncated })
+}
+
+// Replays the schedule and dates every access by the step it took effect at: a buffered store when
+// it propagates and anything else when it runs. Graph ids follow program order within a thread.
+fn reorderings_in(memory_model: &MemoryModelType, program: &Program, witness: &[Node]) -> Vec<Reordering> {
+  let mut model = memory_model.create(program);
+  let mut effects: Vec<(Node, i32, usize)> = Vec::new();
+  // Buffered stores as (thread, address, value, index into `effects`).
+  let mut buffered: Vec<(usize, i32, i32, usize)> = Vec::new();
+  for (step, id) in witness.iter().map(|node| node.id).enumerate() {
+    let Some(node) = model.get_possible_executions().into_iter().find(|node| node.id == id) else {
+      break;
+    };
+    let thread_id = node.thread_id;
+    let before = model.buffer(thread_id).len();
+    let Ok(event) = model.step(node.clone(), false) else {
+      break;
+    };
+    if let Instruction::Propagate { thread_id, address, value } = node.instruction.instruction {
+      if let Some(position) = buffered.iter().position(|(t, a, v, _)| (*t, *a, *v) == (thread_id, address, value)) {
+        let (_, _, _, index) = buffered.remove(position);
+        effects[index].2 = step;
+      }
+      continue;
+    }
+    let Some(access) = event.access else {
+      continue;
+    };
+    if access.kind.is_write() && model.buffer(thread_id).len() > before {
+      buffered.push((thread_id, access.address, access.value, effects.len()));
+    }
+    effects.push((node, access.address, step));
+  }
+  let mut reorderings = Vec::new();
+  // Accesses to one address stay coherent, as a load reads a buffered store of its own thread.
+  for (earlier, earlier_address, earlier_step) in &effects {
+    for (later, later_address, later_step) in &effects {
+      if earlier.thread_id == later.thread_id && earlier.id < later.id && earlier_address != later_address && earlier_step > later_step {
+        reorderings.push(Reordering { thread_id: earlier.thread_id, earlier: earlier.clone(), later: later.clone() });
+      }
+    }
+  }
+  reorderings
+}
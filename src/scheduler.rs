@@ -0,0 +1,172 @@
+use std::collections::HashMap;
+
+use rand::{rngs::SmallRng, seq::IndexedRandom, SeedableRng};
+
+use crate::{graph::Node, instruction::Instruction};
+
+// The state of a model as a scheduler sees it when it picks the next step.
+pub trait StateView {
+  fn number_of_threads(&self) -> usize;
+  fn registers(&self, thread_id: usize) -> &HashMap<String, i32>;
+  fn memory(&self) -> &HashMap<i32, i32>;
+  // The stores of the thread that have not reached memory yet, oldest first, as (address, value).
+  fn buffer(&self, thread_id: usize) -> &[(i32, i32)];
+  fn lock_owner(&self, address: i32) -> Option<usize>;
+}
+
+// Picks the step a run takes next, by its index among the candidates, which are never empty.
+pub trait Scheduler: Send + Sync {
+  fn pick(&mut self, candidates: &[Node], state: &dyn StateView) -> usize;
+}
+
+#[derive(Clone, Copy, Default, Debug)]
+pub struct SchedulerOptions {
+  pub drain_bound: Option<usize>,
+  pub fairness_window: Option<usize>,
+  pub propagation_delay: Option<u32>,
+  // The number of steps after which a single run crashes and starts the recovery threads.
+  pub crash_after: Option<usize>
+}
+
+// Relative latencies from `~N` suffixes, by line, and from `latency` lines, by thread. A step of
+// latency N is N times less likely to be chosen than one of latency 1, so the other threads are more
+// likely to run while it is pending. Propagate steps have no latency of their own.
+#[derive(Clone, Default, Debug)]
+pub struct Latencies {
+  pub lines: HashMap<usize, u32>,
+  pub threads: HashMap<usize, u32>
+}
+
+impl Latencies {
+  pub fn is_empty(&self) -> bool {
+    self.lines.is_empty() && self.threads.is_empty()
+  }
+
+  fn of(&self, node: &Node) -> f64 {
+    if let Instruction::Propagate { thread_id: _, address: _, value: _ } = node.instruction.instruction {
+      return 1.0;
+    }
+    let line = node.instruction.line.and_then(|line| self.lines.get(&line));
+    *line.or(self.threads.get(&node.thread_id)).unwrap_or(&1) as f64
+  }
+}
+
+#[derive(Clone)]
+pub struct RandomScheduler {
+  rng: SmallRng,
+  options: SchedulerOptions,
+  latencies: Latencies,
+  postponed: HashMap<usize, usize>,
+  waiting: HashMap<usize, usize>,
+  last_thread: Option<usize>
+}
+
+impl Default for RandomScheduler {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl RandomScheduler {
+  // Seeded from the operating system; `with_rng` takes a generator from the caller instead.
+  pub fn new() -> RandomScheduler {
+    Self::with_rng(rand::make_rng())
+  }
+
+  pub fn with_rng(rng: SmallRng) -> RandomScheduler {
+    RandomScheduler {
+      rng,
+      options: SchedulerOptions::default(),
+      latencies: Latencies::default(),
+      postponed: HashMap::new(),
+      waiting: HashMap::new(),
+      last_thread: None
+    }
+  }
+
+  pub fn set_seed(&mut self, seed: u64) {
+    self.rng = SmallRng::seed_from_u64(seed);
+  }
+
+  pub fn set_rng(&mut self, rng: SmallRng) {
+    self.rng = rng;
+  }
+
+  pub fn set_options(&mut self, options: SchedulerOptions) {
+    self.options = options;
+  }
+
+  pub fn set_latencies(&mut self, latencies: Latencies) {
+    self.latencies = latencies;
+  }
+
+  // Propagate nodes postponed for more than `drain_bound` steps are executed first, oldest first.
+  fn starving_propagation(&mut self, executions: &[Node], bound: usize) -> Option<Node> {
+    let mut postponed: HashMap<usize, usize> = HashMap::new();
+    for node in executions {
+      if let Instruction::Propagate { thread_id: _, address: _, value: _ } = node.instruction.instruction {
+        postponed.insert(node.id, self.postponed.get(&node.id).map_or(0, |steps| steps + 1));
+      }
+    }
+    self.postponed = postponed;
+    executions.iter()
+      .filter(|node| self.postponed.get(&node.id).is_some_and(|steps| *steps >= bound))
+      .max_by_key(|node| (self.postponed[&node.id], std::cmp::Reverse(node.id)))
+      .cloned()
+  }
+
+  // Threads that stayed enabled for `window` steps without executing run next, longest waiting first.
+  fn starving_thread(&mut self, executions: &[Node], window: usize) -> Option<Node> {
+    let mut waiting: HashMap<usize, usize> = HashMap::new();
+    for node in executions {
+      waiting.insert(node.thread_id, self.waiting.get(&node.thread_id).map_or(0, |steps| steps + 1));
+    }
+    self.waiting = waiting;
+    let thread_id = self.waiting.iter()
+      .filter(|(_, steps)| **steps >= window)
+      .max_by_key(|(thread_id, steps)| (**steps, std::cmp::Reverse(**thread_id)))
+      .map(|(thread_id, _)| *thread_id)?;
+    let candidates: Vec<&Node> = executions.iter().filter(|node| node.thread_id == thread_id).collect();
+    candidates.choose(&mut self.rng).map(|node| (*node).clone())
+  }
+
+  // Instructions are `delay` times as likely as propagations, and the thread that ran last gets
+  // the same weight again, so stores stay buffered and threads run ahead of each other.
+  fn delayed(&mut self, executions: &[Node], delay: u32) -> Option<Node> {
+    let delay = delay as f64;
+    executions.choose_weighted(&mut self.rng, |node| match node.instruction.instruction {
+      Instruction::Propagate { thread_id: _, address: _, value: _ } => 1.0,
+      _ if self.last_thread == Some(node.thread_id) => 2.0 * delay / self.latencies.of(node),
+      _ => delay / self.latencies.of(node)
+    }).ok().cloned()
+  }
+
+  pub fn choose(&mut self, executions: &[Node]) -> Option<Node> {
+    let mut execution = None;
+    if let Some(bound) = self.options.drain_bound {
+      execution = self.starving_propagation(executions, bound);
+    }
+    if let Some(window) = self.options.fairness_window {
+      let starving = self.starving_thread(executions, window);
+      execution = execution.or(starving);
+    }
+    let execution = execution.or_else(|| match self.options.propagation_delay {
+      Some(delay) => self.delayed(executions, delay),
+      None if !self.latencies.is_empty() => executions.choose_weighted(&mut self.rng, |node| 1.0 / self.latencies.of(node)).ok().cloned(),
+      None => executions.choose(&mut self.rng).cloned()
+    });
+    if let Some(node) = &execution {
+      self.postponed.remove(&node.id);
+      self.waiting.remove(&node.thread_id);
+      self.last_thread = Some(node.thread_id);
+    }
+    execution
+  }
+}
+
+impl Scheduler for RandomScheduler {
+  fn pick(&mut self, candidates: &[Node], _state: &dyn StateView) -> usize {
+    let node = self.choose(candidates).expect("there is a candidate to pick");
+    candidates.iter().position(|candidate| candidate.id == node.id).expect("the pick is a candidate")
+  }
+}
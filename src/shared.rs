@@ -0,0 +1,127 @@
+use std::fmt::Debug;
+use std::ops::{Deref, DerefMut, Index, IndexMut};
+use std::sync::Arc;
+
+// A value that copies of a model share until one of them changes it, which then gets its own copy.
+// Snapshots of a model therefore only pay for the parts that the steps after them touch.
+#[derive(Clone, Default)]
+pub struct Shared<T: Clone>(Arc<T>);
+
+impl<T: Clone> Shared<T> {
+  pub fn new(value: T) -> Shared<T> {
+    Shared(Arc::new(value))
+  }
+}
+
+impl<T: Clone> From<T> for Shared<T> {
+  fn from(value: T) -> Shared<T> {
+    Shared::new(value)
+  }
+}
+
+impl<T: Clone> Deref for Shared<T> {
+  type Target = T;
+
+  fn deref(&self) -> &T {
+    &self.0
+  }
+}
+
+impl<T: Clone> DerefMut for Shared<T> {
+  fn deref_mut(&mut self) -> &mut T {
+    Arc::make_mut(&mut self.0)
+  }
+}
+
+impl<T: Clone + Debug> Debug for Shared<T> {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    self.0.fmt(f)
+  }
+}
+
+const CHUNK: usize = 64;
+
+// A vector kept in chunks of `CHUNK` elements that copies share, so changing or adding an element
+// copies one chunk rather than the whole vector.
+#[derive(Clone)]
+pub struct Chunks<T: Clone> {
+  chunks: Vec<Arc<Vec<T>>>,
+  len: usize
+}
+
+impl<T: Clone> Default for Chunks<T> {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl<T: Clone> Chunks<T> {
+  pub fn new() -> Chunks<T> {
+    Chunks { chunks: Vec::new(), len: 0 }
+  }
+
+  pub fn len(&self) -> usize {
+    self.len
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.len == 0
+  }
+
+  pub fn get(&self, index: usize) -> Option<&T> {
+    (index < self.len).then(|| &self.chunks[index / CHUNK][index % CHUNK])
+  }
+
+  pub fn push(&mut self, value: T) {
+    if self.len.is_multiple_of(CHUNK) {
+      self.chunks.push(Arc::new(Vec::with_capacity(CHUNK)));
+    }
+    Arc::make_mut(self.chunks.last_mut().unwrap()).push(value);
+    self.len += 1;
+  }
+
+  pub fn iter(&self) -> impl DoubleEndedIterator<Item = &T> {
+    self.chunks.iter().flat_map(|chunk| chunk.iter())
+  }
+
+  pub fn to_vec(&self) -> Vec<T> {
+    self.iter().cloned().collect()
+  }
+}
+
+impl<T: Clone> Index<usize> for Chunks<T> {
+  type Output = T;
+
+  fn index(&self, index: usize) -> &T {
+    self.get(index).unwrap_or_else(|| panic!("index {} out of {} elements", index, self.len))
+  }
+}
+
+impl<T: Clone> IndexMut<usize> for Chunks<T> {
+  fn index_mut(&mut self, index: usize) -> &mut T {
+    assert!(index < self.len, "index {} out of {} elements", index, self.len);
+    &mut Arc::make_mut(&mut self.chunks[index / CHUNK])[index % CHUNK]
+  }
+}
+
+impl<T: Clone> Extend<T> for Chunks<T> {
+  fn extend<I: IntoIterator<Item = T>>(&mut self, values: I) {
+    for value in values {
+      self.push(value);
+    }
+  }
+}
+
+impl<T: Clone> FromIterator<T> for Chunks<T> {
+  fn from_iter<I: IntoIterator<Item = T>>(values: I) -> Chunks<T> {
+    let mut chunks = Chunks::new();
+    chunks.extend(values);
+    chunks
+  }
+}
+
+impl<T: Clone + Debug> Debug for Chunks<T> {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.debug_list().entries(self.iter()).finish()
+  }
+}
@@ -0,0 +1,147 @@
+use std::collections::HashMap;
+
+use rand::rngs::SmallRng;
+
+use crate::{deadlock::{self, Deadlock}, error::RuntimeError, event::Event, explore, graph::Node, linearizability::History, memory_model::MemoryModelType, program::Program, scheduler::{Scheduler, SchedulerOptions}, statistics::Statistics, testing::MAX_STEPS};
+
+// Single random runs of a program for library users, as the command line makes them without
+// `--exhaustive` or `--runs`, but returning what happened instead of printing it.
+pub struct Simulator<'a> {
+  memory_model: &'a MemoryModelType,
+  program: &'a Program,
+  seed: Option<u64>,
+  rng: Option<SmallRng>,
+  options: SchedulerOptions,
+  max_steps: usize
+}
+
+// Everything a run did: the step it took each time and its access, the final registers of every
+// thread and the final memory, and what the checks at its end found. A run that fails or is cut
+// off still has the steps up to there.
+pub struct RunResult {
+  pub events: Vec<Event>,
+  pub registers: Vec<HashMap<String, i32>>,
+  pub memory: HashMap<i32, i32>,
+  // The final state as exploration outcomes describe it.
+  pub outcome: String,
+  pub error: Option<RuntimeError>,
+  pub truncated: bool,
+  pub deadlock: Option<Deadlock>,
+  // Whether the final state satisfies the `assert` line, if the program has one and the run ended.
+  pub postcondition: Option<bool>,
+  // Why the calls of the operations are not linearizable, if they are not.
+  pub linearizability: Option<String>,
+  pub statistics: Statistics
+}
+
+impl RunResult {
+  pub fn schedule(&self) -> impl Iterator<Item = &Node> {
+    self.events.iter().map(|event| &event.node)
+  }
+
+  // The verdict `--quiet` gives a run, or `truncated` for a run cut off at the step bound.
+  pub fn verdict(&self) -> &'static str {
+    match self {
+      RunResult { error: Some(_), .. } => "error",
+      RunResult { truncated: true, .. } => "truncated",
+      RunResult { deadlock: Some(_), .. } => "deadlock",
+      RunResult { postcondition: Some(false), .. } => "violation",
+      RunResult { linearizability: Some(_), .. } => "not linearizable",
+      _ => "ok"
+    }
+  }
+}
+
+impl<'a> Simulator<'a> {
+  pub fn new(memory_model: &'a MemoryModelType, program: &'a Program) -> Simulator<'a> {
+    Simulator { memory_model, program, seed: None, rng: None, options: SchedulerOptions::default(), max_steps: MAX_STEPS }
+  }
+
+  pub fn set_seed(&mut self, seed: u64) {
+    self.seed = Some(seed);
+  }
+
+  // Every run starts from a copy of the generator, so runs repeat like runs with the same seed.
+  pub fn set_rng(&mut self, rng: SmallRng) {
+    self.rng = Some(rng);
+  }
+
+  // Crashes are left to the command line, so `crash_after` is ignored.
+  pub fn set_scheduler_options(&mut self, options: SchedulerOptions) {
+    self.options = SchedulerOptions { crash_after: None, ..options };
+  }
+
+  pub fn set_max_steps(&mut self, max_steps: usize) {
+    self.max_steps = max_steps;
+  }
+
+  pub fn run(&self) -> RunResult {
+    self.simulate(None)
+  }
+
+  // Runs with the steps the scheduler picks instead of random ones; the seed, the generator and
+  // the scheduler options are left unused.
+  pub fn run_with(&self, scheduler: &mut dyn Scheduler) -> RunResult {
+    self.simulate(Some(scheduler))
+  }
+
+  fn simulate(&self, mut scheduler: Option<&mut dyn Scheduler>) -> RunResult {
+    let mut model = self.memory_model.create(self.program);
+    if let Some(seed) = self.seed {
+      model.set_seed(seed);
+    }
+    if let Some(rng) = &self.rng {
+      model.set_rng(rng.clone());
+    }
+    model.set_scheduler_options(self.options);
+    let mut history = History::new(self.program);
+    let mut events = Vec::new();
+    let mut error = None;
+    let mut truncated = false;
+    loop {
+      if events.len() >= self.max_steps {
+        truncated = !model.get_possible_executions().is_empty();
+        break;
+      }
+      let step = match scheduler.as_deref_mut() {
+        Some(scheduler) => {
+          let candidates = model.get_possible_executions();
+          match candidates.is_empty() {
+            true => Ok(None),
+            false => {
+              let pick = scheduler.pick(&candidates, &model);
+              assert!(pick < candidates.len(), "the scheduler picked candidate {} of {}", pick, candidates.len());
+              model.step(candidates[pick].clone(), false).map(Some)
+            }
+          }
+        },
+        None => model.random_step(false)
+      };
+      match step {
+        Ok(Some(event)) => {
+          history.record(self.program, &event, model.registers(event.node.thread_id));
+          events.push(event);
+        },
+        Ok(None) => break,
+        Err(err) => {
+          error = Some(err);
+          break;
+        }
+      }
+    }
+    let ended = error.is_none() && !truncated;
+    let deadlock = if ended { deadlock::detect(model.as_ref()) } else { None };
+    RunResult {
+      registers: (0..model.number_of_threads()).map(|thread_id| model.registers(thread_id).clone()).collect(),
+      memory: model.memory().clone(),
+      outcome: explore::outcome(model.as_ref()),
+      postcondition: self.program.postcondition.as_ref().filter(|_| ended).map(|condition| condition.holds(model.as_ref())),
+      linearizability: if ended { history.check(self.program).err() } else { None },
+      statistics: *model.statistics(),
+      events,
+      error,
+      truncated,
+      deadlock
+    }
+  }
+}
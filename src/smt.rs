@@ -0,0 +1,539 @@
+use std::collections::HashMap;
+
+use crate::{condition::{Comparison, Condition, Operand}, instruction::{FetchOp, Instruction, LabeledInstruction}, memory_model::MemoryModelType, program::{Initial, Program, Region}};
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Kind {
+  Load,
+  Store,
+  Cas,
+  Fai,
+  Fence
+}
+
+// A memory event of one path through a thread. `success` says whether a cas writes.
+#[derive(Clone)]
+struct Event {
+  kind: Kind,
+  address: String,
+  value: String,
+  written: String,
+  success: String
+}
+
+#[derive(Clone)]
+struct State {
+  registers: HashMap<String, String>,
+  guards: Vec<String>,
+  events: Vec<Event>,
+  taken: HashMap<usize, usize>,
+  returns: Vec<usize>,
+  stack: Vec<String>
+}
+
+struct Path {
+  thread: usize,
+  index: usize,
+  registers: HashMap<String, String>,
+  guards: Vec<String>,
+  events: Vec<Event>
+}
+
+fn and(terms: Vec<String>) -> String {
+  match terms.len() {
+    0 => "true".to_string(),
+    1 => terms.into_iter().next().unwrap(),
+    _ => format!("(and {})", terms.join(" "))
+  }
+}
+
+fn or(terms: Vec<String>) -> String {
+  match terms.len() {
+    0 => "false".to_string(),
+    1 => terms.into_iter().next().unwrap(),
+    _ => format!("(or {})", terms.join(" "))
+  }
+}
+
+fn literal(value: i64) -> String {
+  if value < 0 { format!("(- {})", -value) } else { value.to_string() }
+}
+
+// Accesses are to non-negative addresses or, once the program declares regions, to addresses in them.
+fn address_guard(regions: &[Region], address: &str) -> String {
+  if regions.is_empty() {
+    return format!("(>= {} 0)", address);
+  }
+  or(regions.iter().map(|region| format!("(and (<= {} {}) (< {} {}))", literal(region.base as i64), address, address, literal(region.base as i64 + region.size as i64))).collect())
+}
+
+struct Unroller<'a> {
+  instructions: &'a [LabeledInstruction],
+  regions: &'a [Region],
+  unroll: usize,
+  thread: usize,
+  values: usize,
+  paths: Vec<Path>
+}
+
+impl Unroller<'_> {
+  fn fresh(&mut self) -> String {
+    self.values += 1;
+    format!("v{}_{}", self.thread, self.values)
+  }
+
+  // Follows every path through the thread, taking each backward jump at most `unroll` times in a
+  // row and entering each call at most `unroll` times at once; paths that would go further are
+  // dropped, which assumes the bound is never exceeded.
+  fn walk(&mut self, pc: usize, mut state: State) -> Result<(), String> {
+    let instruction = match self.instructions.get(pc) {
+      Some(instruction) => instruction,
+      None => {
+        let index = self.paths.len();
+        self.paths.push(Path { thread: self.thread, index, registers: state.registers, guards: state.guards, events: state.events });
+        return Ok(());
+      }
+    };
+    let get = |state: &State, r: &String| state.registers.get(r).cloned().unwrap_or_else(|| "0".to_string());
+    let regions = self.regions;
+    let mut access = |state: &mut State, kind: Kind, address: String, written: String, success: String| {
+      let value = if matches!(kind, Kind::Store | Kind::Fence) { "0".to_string() } else { self.fresh() };
+      if kind != Kind::Fence {
+        state.guards.push(address_guard(regions, &address));
+      }
+      state.events.push(Event { kind, address, value: value.clone(), written, success });
+      value
+    };
+    match &instruction.instruction {
+      Instruction::Const { r, value } => {
+        state.registers.insert(r.clone(), literal(*value as i64));
+      }
+      Instruction::ArithPlus { r1, r2, r3 } => {
+        let term = format!("(+ {} {})", get(&state, r2), get(&state, r3));
+        state.registers.insert(r1.clone(), term);
+      }
+      Instruction::ArithMinus { r1, r2, r3 } => {
+        let term = format!("(- {} {})", get(&state, r2), get(&state, r3));
+        state.registers.insert(r1.clone(), term);
+      }
+      Instruction::ArithMul { r1, r2, r3 } => {
+        let term = format!("(* {} {})", get(&state, r2), get(&state, r3));
+        state.registers.insert(r1.clone(), term);
+      }
+      Instruction::ArithDiv { r1, r2, r3 } => {
+        let (a, b) = (get(&state, r2), get(&state, r3));
+        state.guards.push(format!("(not (= {} 0))", b));
+        // SMT-LIB `div` rounds towards negative infinity for positive divisors, the interpreter towards zero.
+        let term = format!("(ite (>= {a} 0) (div {a} {b}) (- (div (- {a}) {b})))", a = a, b = b);
+        state.registers.insert(r1.clone(), term);
+      }
+      Instruction::Select { r1, r2, r3, r4 } => {
+        let term = format!("(ite (not (= {} 0)) {} {})", get(&state, r2), get(&state, r3), get(&state, r4));
+        state.registers.insert(r1.clone(), term);
+      }
+      Instruction::Cond { r, target } => {
+        let target = target.resolve(self.instructions.iter(), pc)
+          .ok_or_else(|| format!("SMT export does not support jumps to {:?} outside the thread", target))?;
+        let condition = get(&state, r);
+        let taken = *state.taken.get(&pc).unwrap_or(&0);
+        if target > pc || taken < self.unroll {
+          let mut jump = state.clone();
+          jump.guards.push(format!("(not (= {} 0))", condition));
+          if target <= pc {
+            jump.taken.insert(pc, taken + 1);
+          }
+          self.walk(target, jump)?;
+        }
+        state.guards.push(format!("(= {} 0)", condition));
+        state.taken.remove(&pc);
+      }
+      Instruction::Call { target } => {
+        let target = target.resolve(self.instructions.iter(), pc)
+          .ok_or_else(|| format!("SMT export does not support jumps to {:?} outside the thread", target))?;
+        if state.returns.iter().filter(|call| **call == pc + 1).count() >= self.unroll {
+          return Ok(());
+        }
+        state.returns.push(pc + 1);
+        return self.walk(target, state);
+      }
+      Instruction::Ret => {
+        let target = state.returns.pop().unwrap_or(self.instructions.len());
+        return self.walk(target, state);
+      }
+      Instruction::Push { r } => {
+        let value = get(&state, r);
+        state.stack.push(value);
+      }
+      Instruction::Pop { r } => {
+        let Some(value) = state.stack.pop() else {
+          return Ok(());
+        };
+        state.registers.insert(r.clone(), value);
+      }
+      Instruction::Load { mode: _, address, r } => {
+        let address = get(&state, address);
+        let value = access(&mut state, Kind::Load, address, "0".to_string(), "false".to_string());
+        state.registers.insert(r.clone(), value);
+      }
+      Instruction::Store { mode: _, address, r } => {
+        let (address, written) = (get(&state, address), get(&state, r));
+        access(&mut state, Kind::Store, address, written, "true".to_string());
+      }
+      Instruction::Cas { mode: _, failure: _, address, to, exp, des, flag } => {
+        let (address, exp, des) = (get(&state, address), get(&state, exp), get(&state, des));
+        let value = self.fresh();
+        let success = format!("(= {} {})", value, exp);
+        state.guards.push(address_guard(regions, &address));
+        state.events.push(Event { kind: Kind::Cas, address, value: value.clone(), written: des, success: success.clone() });
+        state.registers.insert(to.clone(), value);
+        if let Some(flag) = flag {
+          state.registers.insert(flag.clone(), format!("(ite {} 1 0)", success));
+        }
+      }
+      Instruction::Fai { op, mode: _, address, to, inc } => {
+        let (address, inc) = (get(&state, address), get(&state, inc));
+        let value = self.fresh();
+        let written = match op {
+          FetchOp::Add => format!("(+ {} {})", value, inc),
+          FetchOp::Sub => format!("(- {} {})", value, inc),
+          FetchOp::Max => format!("(ite (>= {v} {i}) {v} {i})", v = value, i = inc),
+          FetchOp::Min => format!("(ite (<= {v} {i}) {v} {i})", v = value, i = inc)
+        };
+        state.guards.push(address_guard(regions, &address));
+        state.events.push(Event { kind: Kind::Fai, address, value: value.clone(), written, success: "true".to_string() });
+        state.registers.insert(to.clone(), value);
+      }
+      Instruction::Fence { mode: _, order: None } => {
+        access(&mut state, Kind::Fence, "0".to_string(), "0".to_string(), "false".to_string());
+      }
+      _ => {
+        return Err(match instruction.line {
+          Some(line) => format!("SMT export does not support {:?} (line {})", instruction, line),
+          None => format!("SMT export does not support {:?}", instruction)
+        });
+      }
+    }
+    self.walk(pc + 1, state)
+  }
+}
+
+struct Encoder<'a> {
+  model: MemoryModelType,
+  paths: &'a [Vec<Path>],
+  events: Vec<(&'a Path, usize, &'a Event)>,
+  initial: Vec<(i32, String)>,
+  default: i32,
+  lines: Vec<String>
+}
+
+impl Encoder<'_> {
+  // The value an address term holds before any store.
+  fn initial(&self, address: &str) -> String {
+    let mut term = literal(self.default as i64);
+    for (initialized, value) in self.initial.iter().rev() {
+      term = format!("(ite (= {} {}) {} {})", address, initialized, value, term);
+    }
+    term
+  }
+
+  // Initial values from `init` lines: ranges and `?` become constants for the solver to choose.
+  fn declare_initial(&mut self, program: &Program) {
+    for (address, initial) in program.initial.iter() {
+      let value = match initial {
+        Initial::Value(value) => literal(*value as i64),
+        Initial::Range(low, high) => {
+          self.lines.push(format!("(declare-const init{} Int)", address));
+          self.assert(format!("(>= init{} {})", address, literal(*low as i64)));
+          if let Some(high) = high {
+            self.assert(format!("(< init{} {})", address, literal(*high as i64)));
+          }
+          format!("init{}", address)
+        }
+        Initial::Symbolic => {
+          self.lines.push(format!("(declare-const init{} Int)", address));
+          format!("init{}", address)
+        }
+      };
+      self.initial.push((*address, value));
+    }
+  }
+
+  fn active(&self, i: usize) -> String {
+    let (path, _, _) = self.events[i];
+    match self.paths[path.thread].len() {
+      1 => "true".to_string(),
+      _ => format!("(= p{} {})", path.thread, path.index)
+    }
+  }
+
+  fn writes(&self, i: usize) -> String {
+    match self.events[i].2.kind {
+      Kind::Store | Kind::Fai => self.active(i),
+      Kind::Cas => and(vec![self.active(i), self.events[i].2.success.clone()]),
+      Kind::Load | Kind::Fence => "false".to_string()
+    }
+  }
+
+  fn reads(&self, i: usize) -> String {
+    match self.events[i].2.kind {
+      Kind::Load | Kind::Cas | Kind::Fai => self.active(i),
+      Kind::Store | Kind::Fence => "false".to_string()
+    }
+  }
+
+  fn may_write(&self, i: usize) -> bool {
+    matches!(self.events[i].2.kind, Kind::Store | Kind::Cas | Kind::Fai)
+  }
+
+  fn may_read(&self, i: usize) -> bool {
+    matches!(self.events[i].2.kind, Kind::Load | Kind::Cas | Kind::Fai)
+  }
+
+  // Plain reads and writes in the sense of the axioms: a successful cas is an update, a failed one a read.
+  fn plain_read(&self, i: usize) -> String {
+    match self.events[i].2.kind {
+      Kind::Load => "true".to_string(),
+      Kind::Cas => format!("(not {})", self.events[i].2.success),
+      _ => "false".to_string()
+    }
+  }
+
+  fn fencing(&self, i: usize) -> String {
+    match self.events[i].2.kind {
+      Kind::Fence | Kind::Fai => "true".to_string(),
+      Kind::Cas => self.events[i].2.success.clone(),
+      Kind::Load | Kind::Store => "false".to_string()
+    }
+  }
+
+  fn same_address(&self, i: usize, j: usize) -> String {
+    format!("(= {} {})", self.events[i].2.address, self.events[j].2.address)
+  }
+
+  fn assert(&mut self, term: String) {
+    self.lines.push(format!("(assert {})", term));
+  }
+
+  fn declare(&mut self) {
+    for (thread, paths) in self.paths.iter().enumerate() {
+      if paths.len() > 1 {
+        self.lines.push(format!("(declare-const p{} Int)", thread));
+      }
+    }
+    let mut values: Vec<&String> = self.events.iter().map(|(_, _, event)| &event.value).filter(|value| value.starts_with('v')).collect();
+    values.sort();
+    values.dedup();
+    let mut declarations: Vec<String> = values.into_iter().map(|value| format!("(declare-const {} Int)", value)).collect();
+    for i in 0..self.events.len() {
+      declarations.push(format!("(declare-const l{} Int)", i));
+      declarations.push(format!("(declare-const o{} Int)", i));
+      if self.may_write(i) {
+        declarations.push(format!("(declare-const c{} Int)", i));
+      }
+      if self.may_read(i) {
+        declarations.push(format!("(declare-const rf{} Int)", i));
+      }
+    }
+    self.lines.extend(declarations);
+  }
+
+  // Each thread follows one of its paths, whose branch conditions hold.
+  fn paths(&mut self) {
+    for thread in 0..self.paths.len() {
+      let count = self.paths[thread].len();
+      if count > 1 {
+        self.assert(format!("(and (>= p{t} 0) (< p{t} {}))", count, t = thread));
+      }
+      for index in 0..count {
+        let guard = and(self.paths[thread][index].guards.clone());
+        match count {
+          1 => self.assert(guard),
+          _ => self.assert(format!("(=> (= p{} {}) {})", thread, index, guard))
+        }
+      }
+    }
+  }
+
+  // A read takes its value from an active write to the same address, or from the initial value (`rf = -1`).
+  fn reads_from(&mut self) {
+    let readers: Vec<usize> = (0..self.events.len()).filter(|r| self.may_read(*r)).collect();
+    for r in readers {
+      let event = self.events[r].2;
+      let mut sources = vec![format!("(and (= rf{} (- 1)) (= {} {}))", r, event.value, self.initial(&event.address))];
+      for w in (0..self.events.len()).filter(|w| *w != r && self.may_write(*w)) {
+        sources.push(format!("(and (= rf{} {}) {} {} (= {} {}))", r, w, self.writes(w), self.same_address(r, w), self.events[w].2.written, event.value));
+      }
+      self.assert(format!("(=> {} {})", self.reads(r), or(sources)));
+    }
+  }
+
+  fn coherence(&mut self) {
+    let writers: Vec<usize> = (0..self.events.len()).filter(|w| self.may_write(*w)).collect();
+    for a in writers.iter() {
+      for b in writers.iter().filter(|b| a != *b) {
+        let both = and(vec![self.writes(*a), self.writes(*b), self.same_address(*a, *b)]);
+        if a < b {
+          self.assert(format!("(=> {} (not (= c{} c{})))", both, a, b));
+        }
+        self.assert(format!("(=> (and {} (< c{a} c{b})) (and (< l{a} l{b}) (< o{a} o{b})))", both, a = a, b = b));
+      }
+    }
+  }
+
+  // rf and fr edges, and updates reading the write immediately coherence-before them.
+  fn communication(&mut self) {
+    let writers: Vec<usize> = (0..self.events.len()).filter(|w| self.may_write(*w)).collect();
+    let readers: Vec<usize> = (0..self.events.len()).filter(|r| self.may_read(*r)).collect();
+    for r in readers {
+      for w in writers.iter().filter(|w| **w != r) {
+        let global = self.model == MemoryModelType::SC || self.events[*w].0.thread != self.events[r].0.thread;
+        let order = if global { format!("(and (< l{w} l{r}) (< o{w} o{r}))", w = w, r = r) } else { format!("(< l{} l{})", w, r) };
+        self.assert(format!("(=> (and {} (= rf{} {})) {})", self.reads(r), r, w, order));
+      }
+      for later in writers.iter().filter(|w| **w != r) {
+        let mut before = vec![format!("(= rf{} (- 1))", r)];
+        for w in writers.iter().filter(|w| **w != r && *w != later) {
+          before.push(format!("(and (= rf{} {}) (< c{} c{}))", r, w, w, later));
+        }
+        let condition = and(vec![self.reads(r), self.writes(*later), self.same_address(r, *later), or(before)]);
+        self.assert(format!("(=> {} (and (< l{r} l{w}) (< o{r} o{w})))", condition, r = r, w = later));
+      }
+      if matches!(self.events[r].2.kind, Kind::Cas | Kind::Fai) {
+        let update = self.writes(r);
+        for other in writers.iter().filter(|w| **w != r) {
+          let other_writes = and(vec![self.writes(*other), self.same_address(r, *other)]);
+          self.assert(format!("(=> (and {} (= rf{} (- 1)) {}) (< c{} c{}))", update, r, other_writes, r, other));
+          self.assert(format!("(=> (and {} (= rf{} {})) (< c{} c{}))", update, r, other, other, r));
+          for between in writers.iter().filter(|w| **w != r && *w != other) {
+            let between_writes = and(vec![self.writes(*between), self.same_address(r, *between)]);
+            self.assert(format!("(=> (and {} (= rf{} {}) {}) (not (and (< c{} c{}) (< c{} c{}))))", update, r, other, between_writes, other, between, between, r));
+          }
+        }
+      }
+    }
+  }
+
+  // Program order: per location in the local order, and as far as the model preserves it in the global one.
+  fn program_order(&mut self) {
+    let mut constraints = Vec::new();
+    for i in 0..self.events.len() {
+      for j in (i + 1)..self.events.len() {
+        let ((first_path, first_index, first), (second_path, second_index, second)) = (self.events[i], self.events[j]);
+        if first_path.thread != second_path.thread || first_path.index != second_path.index || first_index >= second_index {
+          continue;
+        }
+        let active = self.active(i);
+        if first.kind != Kind::Fence && second.kind != Kind::Fence {
+          constraints.push(format!("(=> (and {} {}) (< l{} l{}))", active, self.same_address(i, j), i, j));
+        }
+        let fenced = or(((i + 1)..j).map(|k| self.fencing(k)).collect());
+        let mut relaxed = Vec::new();
+        if self.model != MemoryModelType::SC && first.kind == Kind::Store {
+          relaxed.push(and(vec![self.plain_read(j), format!("(not {})", fenced)]));
+          if self.model == MemoryModelType::PSO && second.kind == Kind::Store {
+            relaxed.push(format!("(and (not {}) (not {}))", fenced, self.same_address(i, j)));
+          }
+        }
+        constraints.push(match relaxed.is_empty() {
+          true => format!("(=> {} (< o{} o{}))", active, i, j),
+          false => format!("(=> (and {} (not {})) (< o{} o{}))", active, or(relaxed), i, j)
+        });
+      }
+    }
+    for constraint in constraints {
+      self.assert(constraint);
+    }
+  }
+
+  fn register(&self, thread: usize, r: &str) -> String {
+    let paths = &self.paths[thread];
+    let value = |path: &Path| path.registers.get(r).cloned().unwrap_or_else(|| "0".to_string());
+    let mut term = value(paths.last().unwrap());
+    for path in paths.iter().rev().skip(1) {
+      term = format!("(ite (= p{} {}) {} {})", thread, path.index, value(path), term);
+    }
+    term
+  }
+
+  // The final value at an address is written by the coherence-last write to it, or the initial value.
+  fn memory(&mut self, address: i32) -> String {
+    let name = format!("m{}", address).replace('-', "_");
+    let writers: Vec<usize> = (0..self.events.len()).filter(|w| self.may_write(*w)).collect();
+    let at = |encoder: &Encoder, w: usize| and(vec![encoder.writes(w), format!("(= {} {})", encoder.events[w].2.address, literal(address as i64))]);
+    self.lines.push(format!("(declare-const {} Int)", name));
+    let mut any = Vec::new();
+    for w in writers.iter() {
+      let mut last = vec![at(self, *w)];
+      for other in writers.iter().filter(|other| *other != w) {
+        last.push(format!("(not (and {} (> c{} c{})))", at(self, *other), other, w));
+      }
+      self.assert(format!("(=> {} (= {} {}))", and(last), name, self.events[*w].2.written));
+      any.push(at(self, *w));
+    }
+    let initial = self.initial(&literal(address as i64));
+    self.assert(format!("(=> (not {}) (= {} {}))", or(any), name, initial));
+    name
+  }
+
+  fn condition(&mut self, program: &Program, condition: &Condition) -> String {
+    let mut clauses = Vec::new();
+    for atoms in condition.clauses.iter() {
+      let mut terms = Vec::new();
+      for atom in atoms {
+        let operand = match &atom.operand {
+          Operand::Register { thread, r } => self.register(program.thread_id(thread).unwrap(), r),
+          Operand::Memory { address } => self.memory(*address)
+        };
+        let value = literal(atom.value as i64);
+        terms.push(match atom.comparison {
+          Comparison::Eq => format!("(= {} {})", operand, value),
+          Comparison::Ne => format!("(not (= {} {}))", operand, value),
+          Comparison::Lt => format!("(< {} {})", operand, value),
+          Comparison::Le => format!("(<= {} {})", operand, value),
+          Comparison::Gt => format!("(> {} {})", operand, value),
+          Comparison::Ge => format!("(>= {} {})", operand, value)
+        });
+      }
+      clauses.push(and(terms));
+    }
+    or(clauses)
+  }
+}
+
+// Encodes the executions of the program under SC, TSO or PSO, with the axioms of the reads-from
+// enumeration, together with the negated postcondition: `sat` means some execution (taking no
+// backward jump more than `unroll` times in a row) breaks the postcondition, `unsat` that none does.
+pub fn encode(memory_model: MemoryModelType, program: &Program, unroll: usize) -> Result<String, String> {
+  if !matches!(memory_model, MemoryModelType::SC | MemoryModelType::TSO | MemoryModelType::PSO) {
+    return Err(format!("SMT export does not support the {:?} model", memory_model));
+  }
+  let condition = program.postcondition.as_ref().ok_or("SMT export needs an assert line in the program")?;
+  let mut paths = Vec::new();
+  for (thread, instructions) in program.threads.iter().enumerate() {
+    let mut unroller = Unroller { instructions, regions: &program.regions, unroll, thread, values: 0, paths: Vec::new() };
+    unroller.walk(0, State { registers: HashMap::new(), guards: Vec::new(), events: Vec::new(), taken: HashMap::new(), returns: Vec::new(), stack: Vec::new() })?;
+    if unroller.paths.is_empty() {
+      return Err(format!("Thread {} has no path within {} loop iterations", program.thread_names[thread], unroll));
+    }
+    paths.push(unroller.paths);
+  }
+  let events = paths.iter().flatten()
+    .flat_map(|path| path.events.iter().enumerate().map(move |(index, event)| (path, index, event)))
+    .collect();
+  let mut encoder = Encoder { model: memory_model, paths: &paths, events, initial: Vec::new(), default: program.default_memory, lines: Vec::new() };
+  encoder.lines.push(format!("; {:?} executions, backward jumps taken at most {} times in a row", memory_model, unroll));
+  encoder.lines.push(format!("; sat: some execution breaks the postcondition {:?}", condition));
+  encoder.lines.push("(set-logic ALL)".to_string());
+  encoder.declare();
+  encoder.declare_initial(program);
+  encoder.paths();
+  encoder.reads_from();
+  encoder.coherence();
+  encoder.communication();
+  encoder.program_order();
+  let holds = encoder.condition(program, condition);
+  encoder.assert(format!("(not {})", holds));
+  encoder.lines.push("(check-sat)".to_string());
+  encoder.lines.push("(get-model)".to_string());
+  Ok(encoder.lines.join("\n") + "\n")
+}
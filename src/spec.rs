@@ -0,0 +1,77 @@
+use std::{fs, path::Path};
+
+use crate::{condition::Condition, memory_model::MemoryModelType};
+
+// `MODEL allowed CONDITION` or `MODEL forbidden CONDITION`, where MODEL is `*` for every model.
+#[derive(Clone, Debug)]
+struct Expectation {
+  model: Option<MemoryModelType>,
+  allowed: bool,
+  condition: Condition
+}
+
+// The final states a program may and may not reach under each model, kept apart from the program
+// so that one test can be checked against several models with different expectations.
+#[derive(Clone, Debug, Default)]
+pub struct Spec {
+  expectations: Vec<Expectation>
+}
+
+impl Spec {
+  pub fn read(path: &Path) -> Result<Spec, String> {
+    let content = fs::read_to_string(path)
+      .map_err(|err| format!("Error reading file {}: {}", path.display(), err))?;
+    Spec::parse(&content).map_err(|err| format!("{}: {}", path.display(), err))
+  }
+
+  // Conditions address memory as `#a`, so only whole lines starting with `#` are comments.
+  pub fn parse(content: &str) -> Result<Spec, String> {
+    let mut expectations = Vec::new();
+    for (line_number, line) in content.lines().enumerate() {
+      let line = line.trim();
+      if line.is_empty() || line.starts_with('#') {
+        continue;
+      }
+      expectations.push(parse_expectation(line).map_err(|err| format!("Line {}: {}", line_number + 1, err))?);
+    }
+    Ok(Spec { expectations })
+  }
+
+  // The models the spec names, in the order it first names them.
+  pub fn models(&self) -> Vec<MemoryModelType> {
+    let mut models = Vec::new();
+    for model in self.expectations.iter().filter_map(|expectation| expectation.model) {
+      if !models.contains(&model) {
+        models.push(model);
+      }
+    }
+    models
+  }
+
+  // The conditions that some final state under the model has to meet and those that none may meet.
+  pub fn expectations(&self, model: MemoryModelType) -> (Vec<Condition>, Vec<Condition>) {
+    let (allowed, forbidden): (Vec<&Expectation>, Vec<&Expectation>) = self.expectations.iter()
+      .filter(|expectation| expectation.model.is_none_or(|m| m == model))
+      .partition(|expectation| expectation.allowed);
+    let conditions = |expectations: Vec<&Expectation>| expectations.into_iter().map(|expectation| expectation.condition.clone()).collect();
+    (conditions(allowed), conditions(forbidden))
+  }
+}
+
+fn parse_expectation(line: &str) -> Result<Expectation, String> {
+  let parts = line.split_once(char::is_whitespace)
+    .and_then(|(model, rest)| rest.trim_start().split_once(char::is_whitespace).map(|(kind, condition)| (model, kind, condition)));
+  let Some((model, kind, condition)) = parts else {
+    return Err("Expected MODEL allowed CONDITION or MODEL forbidden CONDITION".to_string());
+  };
+  let model = match model {
+    "*" => None,
+    model => Some(model.parse::<MemoryModelType>().map_err(|_| format!("Invalid memory model {}", model))?)
+  };
+  let allowed = match kind {
+    "allowed" => true,
+    "forbidden" => false,
+    _ => return Err(format!("Invalid expectation {}; expected allowed or forbidden", kind))
+  };
+  Ok(Expectation { model, allowed, condition: Condition::parse(condition)? })
+}
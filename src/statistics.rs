@@ -0,0 +1,48 @@
+use std::fmt::Display;
+
+use crate::json;
+
+// Counters describing how far a run strayed from sequential consistency.
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq)]
+pub struct Statistics {
+  pub steps: usize,
+  pub buffered_stores: usize,
+  pub max_buffer_depth: usize,
+  pub forwarded_loads: usize,
+  pub reorderings: usize
+}
+
+impl Statistics {
+  pub fn new() -> Statistics {
+    Statistics::default()
+  }
+
+  pub fn merge(&mut self, other: &Statistics) {
+    self.steps += other.steps;
+    self.buffered_stores += other.buffered_stores;
+    self.max_buffer_depth = self.max_buffer_depth.max(other.max_buffer_depth);
+    self.forwarded_loads += other.forwarded_loads;
+    self.reorderings += other.reorderings;
+  }
+
+  pub fn to_json(&self) -> String {
+    json::object(vec![
+      ("steps".to_string(), self.steps.to_string()),
+      ("buffered_stores".to_string(), self.buffered_stores.to_string()),
+      ("max_buffer_depth".to_string(), self.max_buffer_depth.to_string()),
+      ("forwarded_loads".to_string(), self.forwarded_loads.to_string()),
+      ("reorderings".to_string(), self.reorderings.to_string())
+    ])
+  }
+}
+
+impl Display for Statistics {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    writeln!(f, "# STATISTICS")?;
+    writeln!(f, "| steps: {}", self.steps)?;
+    writeln!(f, "| buffered stores: {}", self.buffered_stores)?;
+    writeln!(f, "| max buffer depth: {}", self.max_buffer_depth)?;
+    writeln!(f, "| loads forwarded from a buffer: {}", self.forwarded_loads)?;
+    writeln!(f, "| reorderings: {}", self.reorderings)
+  }
+}
@@ -1,72 +1,206 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, VecDeque};
 use core::fmt::Debug;
 
-pub trait StorageSystem {
-  fn load(&self, thread_id: usize, address: i32) -> i32;
-  fn store(&mut self, thread_id: usize, address: i32, value: i32);
-  fn cas(&mut self, thread_id: usize, address: i32, exp: i32, des: i32) -> i32;
-  fn fai(&mut self, thread_id: usize, address: i32, inc: i32) -> i32;
+use smallvec::SmallVec;
+
+use crate::{instruction::{FetchOp, Mode, Scope}, program::Topology, shared::Shared};
+
+// The instruction that made a store and the value it stored.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Origin {
+  pub thread_id: usize,
+  pub line: Option<usize>,
+  pub value: i32
+}
+
+// The threads' private stacks for `push` and `pop`, which bypass buffers and are never propagated.
+#[derive(Clone, Default, Debug)]
+pub struct Stacks {
+  stacks: HashMap<usize, Vec<i32>>
 }
 
+impl Stacks {
+  pub fn push(&mut self, thread_id: usize, value: i32) {
+    self.stacks.entry(thread_id).or_default().push(value);
+  }
+
+  pub fn pop(&mut self, thread_id: usize) -> Option<i32> {
+    self.stacks.get_mut(&thread_id)?.pop()
+  }
+}
+
+pub trait StorageSystem: Send + Sync {
+  fn load(&mut self, thread_id: usize, address: i32, mode: Mode) -> i32;
+  fn store(&mut self, thread_id: usize, address: i32, value: i32, mode: Mode);
+  fn cas(&mut self, thread_id: usize, address: i32, exp: i32, des: i32, success: Mode, failure: Mode) -> i32;
+  fn fai(&mut self, thread_id: usize, address: i32, op: FetchOp, inc: i32, mode: Mode) -> Option<i32>;
+  fn fence(&mut self, thread_id: usize, mode: Mode);
+  fn memory(&self) -> &HashMap<i32, i32>;
+  // The value an update of the thread would read at the address, without recording the read.
+  fn peek(&self, _thread_id: usize, address: i32) -> i32 {
+    *self.memory().get(&address).unwrap_or(&self.default_value())
+  }
+  // Makes the thread see what the latest store to the address released, as an acquire of it would.
+  fn acquire(&mut self, _thread_id: usize, _address: i32) {}
+  // Sets the value the address holds before any store, as seen by every thread.
+  fn initialize(&mut self, address: i32, value: i32);
+  fn buffer(&self, thread_id: usize) -> &[(i32, i32)];
+  fn propagate(&mut self, thread_id: usize, address: i32, observer: usize);
+  // Records the origin of the thread's latest store to the address.
+  fn tag(&mut self, thread_id: usize, address: i32, origin: Origin);
+  // The store the thread's last read returned, `None` for the initial value.
+  fn read_from(&self, thread_id: usize) -> Option<Origin>;
+  // Whether the thread's last read was served from its own store buffer rather than memory.
+  fn forwarded(&self, _thread_id: usize) -> bool {
+    false
+  }
+  // The store, numbered per address, that the thread's last access to the address read or wrote.
+  fn source(&self, _thread_id: usize, _address: i32) -> Option<usize> {
+    None
+  }
+  fn stacks(&mut self) -> &mut Stacks;
+  // Names the threads in what the storage prints.
+  fn set_thread_names(&mut self, _thread_names: Vec<String>) {}
+  // Sets the value of every address until it is initialized or stored to, 0 unless set.
+  fn set_default(&mut self, value: i32);
+  fn default_value(&self) -> i32;
+  fn push(&mut self, thread_id: usize, value: i32) {
+    self.stacks().push(thread_id, value);
+  }
+  fn pop(&mut self, thread_id: usize) -> Option<i32> {
+    self.stacks().pop(thread_id)
+  }
+}
+
+#[derive(Clone)]
 pub struct SCStorageSystem {
-  memory: HashMap<i32, i32>
+  memory: Shared<HashMap<i32, i32>>,
+  writers: Shared<HashMap<i32, Origin>>,
+  reads: HashMap<usize, Origin>,
+  stacks: Stacks,
+  default: i32
+}
+
+impl Default for SCStorageSystem {
+  fn default() -> Self {
+    Self::new()
+  }
 }
 
 impl SCStorageSystem {
   pub fn new() -> SCStorageSystem {
     SCStorageSystem {
-      memory: HashMap::new()
+      memory: Shared::default(),
+      writers: Shared::default(),
+      reads: HashMap::new(),
+      stacks: Stacks::default(),
+      default: 0
     }
   }
 }
 
 impl Debug for SCStorageSystem {
   fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-    write!(f, "# MEMORY\n")?;
-    write!(f, "| {:?}\n", self.memory)
+    writeln!(f, "# MEMORY")?;
+    writeln!(f, "| {:?}", self.memory)
   }
 }
 
 impl StorageSystem for SCStorageSystem {
-  fn load(&self, _thread_id: usize, address: i32) -> i32 {
+  fn load(&mut self, thread_id: usize, address: i32, _mode: Mode) -> i32 {
+    match self.writers.get(&address) {
+      Some(origin) => self.reads.insert(thread_id, *origin),
+      None => self.reads.remove(&thread_id)
+    };
     match self.memory.get(&address) {
       Some(value) => *value,
-      None => 0
+      None => self.default
     }
   }
 
-  fn store(&mut self, _thread_id: usize, address: i32, value: i32) {
+  fn store(&mut self, _thread_id: usize, address: i32, value: i32, _mode: Mode) {
     self.memory.insert(address, value);
   }
 
-  fn cas(&mut self, thread_id: usize, address: i32, exp: i32, des: i32) -> i32 {
-    let value = self.load(thread_id, address);
+  fn cas(&mut self, thread_id: usize, address: i32, exp: i32, des: i32, success: Mode, failure: Mode) -> i32 {
+    let value = self.load(thread_id, address, failure);
     if value == exp {
-      self.store(thread_id, address, des);
+      self.store(thread_id, address, des, success);
     }
     value
   }
 
-  fn fai(&mut self, thread_id: usize, address: i32, inc: i32) -> i32 {
-    let value = self.load(thread_id, address);
-    self.store(thread_id, address, value + inc);
-    value
+  fn fai(&mut self, thread_id: usize, address: i32, op: FetchOp, inc: i32, mode: Mode) -> Option<i32> {
+    let value = self.load(thread_id, address, mode);
+    self.store(thread_id, address, op.apply(value, inc)?, mode);
+    Some(value)
+  }
+
+  fn fence(&mut self, _thread_id: usize, _mode: Mode) {}
+
+  fn memory(&self) -> &HashMap<i32, i32> {
+    &self.memory
+  }
+
+  fn initialize(&mut self, address: i32, value: i32) {
+    self.memory.insert(address, value);
+  }
+
+  fn stacks(&mut self) -> &mut Stacks {
+    &mut self.stacks
+  }
+
+  fn set_default(&mut self, value: i32) {
+    self.default = value;
+  }
+
+  fn default_value(&self) -> i32 {
+    self.default
+  }
+
+  fn buffer(&self, _thread_id: usize) -> &[(i32, i32)] {
+    &[]
+  }
+
+  fn propagate(&mut self, _thread_id: usize, _address: i32, _observer: usize) {}
+
+  fn tag(&mut self, _thread_id: usize, address: i32, origin: Origin) {
+    self.writers.insert(address, origin);
+  }
+
+  fn read_from(&self, thread_id: usize) -> Option<Origin> {
+    self.reads.get(&thread_id).cloned()
   }
 }
 
+// A thread's buffered stores, and their origins, which rarely grow past a few entries.
+type Buffer = SmallVec<[(i32, i32); 4]>;
+type Origins = SmallVec<[Option<Origin>; 4]>;
+
+#[derive(Clone)]
 pub struct TSOStorageSystem {
-  buffers: Vec<Vec<(i32, i32)>>,
-  memory: HashMap<i32, i32>
+  buffers: Vec<Shared<Buffer>>,
+  origins: Vec<Shared<Origins>>,
+  memory: Shared<HashMap<i32, i32>>,
+  writers: Shared<HashMap<i32, Origin>>,
+  reads: Vec<Option<Origin>>,
+  forwarded: Vec<bool>,
+  thread_names: Vec<String>,
+  stacks: Stacks,
+  default: i32
 }
 
 impl Debug for TSOStorageSystem {
   fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-    write!(f, "# BUFFERS\n")?;
+    writeln!(f, "# BUFFERS")?;
     for (i, buffer) in self.buffers.iter().enumerate() {
-      write!(f, "| Thread {}: {:?}\n", i, buffer)?;
+      match buffer.first() {
+        Some((address, value)) => writeln!(f, "| Thread {}: {} pending, oldest #{} = {}: {:?}", self.thread_names[i], buffer.len(), address, value, buffer)?,
+        None => writeln!(f, "| Thread {}: empty", self.thread_names[i])?
+      }
     }
-    write!(f, "# MEMORY\n")?;
-    write!(f, "| {:?}\n", self.memory)
+    writeln!(f, "# MEMORY")?;
+    writeln!(f, "| {:?}", self.memory)
   }
 }
 
@@ -74,70 +208,150 @@ impl TSOStorageSystem {
   pub fn new(number_of_threads: usize) -> TSOStorageSystem {
     let mut buffers = Vec::new();
     for _ in 0..number_of_threads {
-      buffers.push(Vec::new());
+      buffers.push(Shared::default());
     }
     TSOStorageSystem {
       buffers,
-      memory: HashMap::new()
-    }
-  }
-
-  pub fn propagate(&mut self, thread_id: usize, address: i32) {
-    let buffers_copy = self.buffers[thread_id].clone();
-    let element = buffers_copy.iter().enumerate().rev().find(|(_, (a, _))| *a == address);
-    match element {
-      Some((i, (_, value) )) => {
-        self.buffers[thread_id as usize].remove(i);
-        self.memory.insert(address, *value);
-      }
-      _ => {}
+      origins: vec![Shared::default(); number_of_threads],
+      memory: Shared::default(),
+      writers: Shared::default(),
+      reads: vec![None; number_of_threads],
+      forwarded: vec![false; number_of_threads],
+      thread_names: (0..number_of_threads).map(|thread_id| thread_id.to_string()).collect(),
+      stacks: Stacks::default(),
+      default: 0
     }
   }
 }
 
 impl StorageSystem for TSOStorageSystem {
-  fn load(&self, thread_id: usize, address: i32) -> i32 {
-    match self.buffers[thread_id as usize].iter().rev().find(|(a, _)| *a == address) {
-      Some((_, value)) => *value,
+  fn load(&mut self, thread_id: usize, address: i32, _mode: Mode) -> i32 {
+    let position = self.buffers[thread_id].iter().rposition(|(a, _)| *a == address);
+    self.forwarded[thread_id] = position.is_some();
+    match position {
+      Some(i) => {
+        self.reads[thread_id] = self.origins[thread_id][i];
+        self.buffers[thread_id][i].1
+      }
       None => match self.memory.get(&address) {
-        Some(value) => *value,
-        None => 0
+        Some(value) => {
+          self.reads[thread_id] = self.writers.get(&address).cloned();
+          *value
+        }
+        None => {
+          self.reads[thread_id] = None;
+          self.default
+        }
       }
     }
   }
 
-  fn store(&mut self, thread_id: usize, address: i32, value: i32) {
-    self.buffers[thread_id as usize].push((address, value));
+  fn store(&mut self, thread_id: usize, address: i32, value: i32, _mode: Mode) {
+    self.buffers[thread_id].push((address, value));
+    self.origins[thread_id].push(None);
   }
 
-  fn cas(&mut self, thread_id: usize, address: i32, exp: i32, des: i32) -> i32 {
-    let value = self.load(thread_id, address);
+  fn cas(&mut self, thread_id: usize, address: i32, exp: i32, des: i32, success: Mode, failure: Mode) -> i32 {
+    let value = self.load(thread_id, address, failure);
     if value == exp {
-      self.store(thread_id, address, des);
+      self.store(thread_id, address, des, success);
     }
     value
   }
 
-  fn fai(&mut self, thread_id: usize, address: i32, inc: i32) -> i32 {
-    let value = self.load(thread_id, address);
-    self.store(thread_id, address, value + inc);
-    value
+  fn fai(&mut self, thread_id: usize, address: i32, op: FetchOp, inc: i32, mode: Mode) -> Option<i32> {
+    let value = self.load(thread_id, address, mode);
+    self.store(thread_id, address, op.apply(value, inc)?, mode);
+    Some(value)
+  }
+
+  fn fence(&mut self, _thread_id: usize, _mode: Mode) {}
+
+  fn memory(&self) -> &HashMap<i32, i32> {
+    &self.memory
+  }
+
+  fn peek(&self, thread_id: usize, address: i32) -> i32 {
+    match self.buffers[thread_id].iter().rfind(|(a, _)| *a == address) {
+      Some((_, value)) => *value,
+      None => *self.memory.get(&address).unwrap_or(&self.default)
+    }
+  }
+
+  fn initialize(&mut self, address: i32, value: i32) {
+    self.memory.insert(address, value);
+  }
+
+  fn stacks(&mut self) -> &mut Stacks {
+    &mut self.stacks
+  }
+
+  fn set_thread_names(&mut self, thread_names: Vec<String>) {
+    self.thread_names = thread_names;
+  }
+
+  fn set_default(&mut self, value: i32) {
+    self.default = value;
+  }
+
+  fn default_value(&self) -> i32 {
+    self.default
+  }
+
+  fn buffer(&self, thread_id: usize) -> &[(i32, i32)] {
+    &self.buffers[thread_id]
+  }
+
+  fn propagate(&mut self, thread_id: usize, address: i32, _observer: usize) {
+    if let Some(i) = self.buffers[thread_id].iter().rposition(|(a, _)| *a == address) {
+      let (_, value) = self.buffers[thread_id].remove(i);
+      match self.origins[thread_id].remove(i) {
+        Some(origin) => self.writers.insert(address, origin),
+        None => self.writers.remove(&address)
+      };
+      self.memory.insert(address, value);
+    }
+  }
+
+  fn tag(&mut self, thread_id: usize, address: i32, origin: Origin) {
+    if let Some(i) = self.buffers[thread_id].iter().rposition(|(a, _)| *a == address) {
+      self.origins[thread_id][i] = Some(origin);
+    }
+  }
+
+  fn read_from(&self, thread_id: usize) -> Option<Origin> {
+    self.reads[thread_id]
+  }
+
+  fn forwarded(&self, thread_id: usize) -> bool {
+    self.forwarded[thread_id]
   }
 }
 
+#[derive(Clone)]
 pub struct PSOStorageSystem {
-  buffers: Vec<Vec<(i32, i32)>>,
-  memory: HashMap<i32, i32>
+  buffers: Vec<Shared<Buffer>>,
+  origins: Vec<Shared<Origins>>,
+  memory: Shared<HashMap<i32, i32>>,
+  writers: Shared<HashMap<i32, Origin>>,
+  reads: Vec<Option<Origin>>,
+  forwarded: Vec<bool>,
+  thread_names: Vec<String>,
+  stacks: Stacks,
+  default: i32
 }
 
 impl Debug for PSOStorageSystem {
   fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-    write!(f, "# BUFFERS\n")?;
+    writeln!(f, "# BUFFERS")?;
     for (i, buffer) in self.buffers.iter().enumerate() {
-      write!(f, "| Thread {}: {:?}\n", i, buffer)?;
+      match buffer.first() {
+        Some((address, value)) => writeln!(f, "| Thread {}: {} pending, oldest #{} = {}: {:?}", self.thread_names[i], buffer.len(), address, value, buffer)?,
+        None => writeln!(f, "| Thread {}: empty", self.thread_names[i])?
+      }
     }
-    write!(f, "# MEMORY\n")?;
-    write!(f, "| {:?}\n", self.memory)
+    writeln!(f, "# MEMORY")?;
+    writeln!(f, "| {:?}", self.memory)
   }
 }
 
@@ -145,53 +359,959 @@ impl PSOStorageSystem {
   pub fn new(number_of_threads: usize) -> PSOStorageSystem {
     let mut buffers = Vec::new();
     for _ in 0..number_of_threads {
-      buffers.push(Vec::new());
+      buffers.push(Shared::default());
     }
     PSOStorageSystem {
       buffers,
-      memory: HashMap::new()
+      origins: vec![Shared::default(); number_of_threads],
+      memory: Shared::default(),
+      writers: Shared::default(),
+      reads: vec![None; number_of_threads],
+      forwarded: vec![false; number_of_threads],
+      thread_names: (0..number_of_threads).map(|thread_id| thread_id.to_string()).collect(),
+      stacks: Stacks::default(),
+      default: 0
     }
   }
+}
 
-  pub fn propagate(&mut self, thread_id: usize, address: i32) {
-    let buffers_copy = self.buffers[thread_id].clone();
-    let element = buffers_copy.iter().enumerate().rev().find(|(_, (a, _))| *a == address);
-    match element {
-      Some((i, (_, value) )) => {
-        self.buffers[thread_id as usize].remove(i);
-        self.memory.insert(address, *value);
+impl StorageSystem for PSOStorageSystem {
+  fn load(&mut self, thread_id: usize, address: i32, _mode: Mode) -> i32 {
+    let position = self.buffers[thread_id].iter().rposition(|(a, _)| *a == address);
+    self.forwarded[thread_id] = position.is_some();
+    match position {
+      Some(i) => {
+        self.reads[thread_id] = self.origins[thread_id][i];
+        self.buffers[thread_id][i].1
+      }
+      None => match self.memory.get(&address) {
+        Some(value) => {
+          self.reads[thread_id] = self.writers.get(&address).cloned();
+          *value
+        }
+        None => {
+          self.reads[thread_id] = None;
+          self.default
+        }
       }
-      _ => {}
     }
   }
-}
 
-impl StorageSystem for PSOStorageSystem {
-  fn load(&self, thread_id: usize, address: i32) -> i32 {
-    match self.buffers[thread_id as usize].iter().rev().find(|(a, _)| *a == address) {
+  fn store(&mut self, thread_id: usize, address: i32, value: i32, _mode: Mode) {
+    self.buffers[thread_id].push((address, value));
+    self.origins[thread_id].push(None);
+  }
+
+  fn cas(&mut self, thread_id: usize, address: i32, exp: i32, des: i32, success: Mode, failure: Mode) -> i32 {
+    let value = self.load(thread_id, address, failure);
+    if value == exp {
+      self.store(thread_id, address, des, success);
+    }
+    value
+  }
+
+  fn fai(&mut self, thread_id: usize, address: i32, op: FetchOp, inc: i32, mode: Mode) -> Option<i32> {
+    let value = self.load(thread_id, address, mode);
+    self.store(thread_id, address, op.apply(value, inc)?, mode);
+    Some(value)
+  }
+
+  fn fence(&mut self, _thread_id: usize, _mode: Mode) {}
+
+  fn memory(&self) -> &HashMap<i32, i32> {
+    &self.memory
+  }
+
+  fn peek(&self, thread_id: usize, address: i32) -> i32 {
+    match self.buffers[thread_id].iter().rfind(|(a, _)| *a == address) {
       Some((_, value)) => *value,
-      None => match self.memory.get(&address) {
-        Some(value) => *value,
-        None => 0
+      None => *self.memory.get(&address).unwrap_or(&self.default)
+    }
+  }
+
+  fn initialize(&mut self, address: i32, value: i32) {
+    self.memory.insert(address, value);
+  }
+
+  fn stacks(&mut self) -> &mut Stacks {
+    &mut self.stacks
+  }
+
+  fn set_thread_names(&mut self, thread_names: Vec<String>) {
+    self.thread_names = thread_names;
+  }
+
+  fn set_default(&mut self, value: i32) {
+    self.default = value;
+  }
+
+  fn default_value(&self) -> i32 {
+    self.default
+  }
+
+  fn buffer(&self, thread_id: usize) -> &[(i32, i32)] {
+    &self.buffers[thread_id]
+  }
+
+  fn propagate(&mut self, thread_id: usize, address: i32, _observer: usize) {
+    if let Some(i) = self.buffers[thread_id].iter().rposition(|(a, _)| *a == address) {
+      let (_, value) = self.buffers[thread_id].remove(i);
+      match self.origins[thread_id].remove(i) {
+        Some(origin) => self.writers.insert(address, origin),
+        None => self.writers.remove(&address)
+      };
+      self.memory.insert(address, value);
+    }
+  }
+
+  fn tag(&mut self, thread_id: usize, address: i32, origin: Origin) {
+    if let Some(i) = self.buffers[thread_id].iter().rposition(|(a, _)| *a == address) {
+      self.origins[thread_id][i] = Some(origin);
+    }
+  }
+
+  fn read_from(&self, thread_id: usize) -> Option<Origin> {
+    self.reads[thread_id]
+  }
+
+  fn forwarded(&self, thread_id: usize) -> bool {
+    self.forwarded[thread_id]
+  }
+}
+type View = HashMap<i32, usize>;
+
+fn join(view: &mut View, other: &View) {
+  for (address, timestamp) in other {
+    let entry = view.entry(*address).or_insert(0);
+    *entry = (*entry).max(*timestamp);
+  }
+}
+
+fn acquires(mode: Mode) -> bool {
+  matches!(mode, Mode::Acq | Mode::RelAcq | Mode::SeqCst)
+}
+
+fn releases(mode: Mode) -> bool {
+  matches!(mode, Mode::Rel | Mode::RelAcq | Mode::SeqCst)
+}
+
+// Every address keeps its history of messages (value and the view released with it) ordered by
+// timestamp, the first one being the initial zero. A thread reads the message at its view of the
+// address; `propagate` moves that view one message forward.
+#[derive(Clone)]
+pub struct C11StorageSystem {
+  messages: Shared<HashMap<i32, Vec<(i32, View)>>>,
+  views: Vec<View>,
+  acquire_views: Vec<View>,
+  release_views: Vec<View>,
+  sc_view: View,
+  origins: Shared<HashMap<(i32, usize), Origin>>,
+  reads: Vec<Option<Origin>>,
+  memory: Shared<HashMap<i32, i32>>,
+  unobserved: Vec<Vec<(i32, i32)>>,
+  thread_names: Vec<String>,
+  stacks: Stacks,
+  default: i32
+}
+
+impl Debug for C11StorageSystem {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    writeln!(f, "# VIEWS")?;
+    for (i, view) in self.views.iter().enumerate() {
+      let view: BTreeMap<&i32, &usize> = view.iter().collect();
+      writeln!(f, "| Thread {}: {:?} (not observed: {:?})", self.thread_names[i], view, self.unobserved[i])?;
+    }
+    writeln!(f, "# MEMORY")?;
+    writeln!(f, "| {:?}", self.memory)
+  }
+}
+
+impl C11StorageSystem {
+  pub fn new(number_of_threads: usize) -> C11StorageSystem {
+    C11StorageSystem {
+      messages: Shared::default(),
+      views: vec![View::new(); number_of_threads],
+      acquire_views: vec![View::new(); number_of_threads],
+      release_views: vec![View::new(); number_of_threads],
+      sc_view: View::new(),
+      origins: Shared::default(),
+      reads: vec![None; number_of_threads],
+      memory: Shared::default(),
+      unobserved: vec![Vec::new(); number_of_threads],
+      thread_names: (0..number_of_threads).map(|thread_id| thread_id.to_string()).collect(),
+      stacks: Stacks::default(),
+      default: 0
+    }
+  }
+
+  pub fn timestamp(&self, thread_id: usize, address: i32) -> usize {
+    *self.views[thread_id].get(&address).unwrap_or(&0)
+  }
+
+  fn history(&mut self, address: i32) -> &mut Vec<(i32, View)> {
+    let default = self.default;
+    self.messages.entry(address).or_insert_with(|| vec![(default, View::new())])
+  }
+
+  fn read(&mut self, thread_id: usize, address: i32, timestamp: usize, mode: Mode) -> i32 {
+    let (value, view) = self.history(address)[timestamp].clone();
+    self.views[thread_id].insert(address, timestamp);
+    self.reads[thread_id] = self.origins.get(&(address, timestamp)).cloned();
+    join(&mut self.acquire_views[thread_id], &view);
+    if acquires(mode) {
+      join(&mut self.views[thread_id], &view);
+    }
+    value
+  }
+
+  // `carried` is the view of the message an update reads, which continues its release sequence.
+  fn write(&mut self, thread_id: usize, address: i32, value: i32, mode: Mode, carried: View) {
+    let timestamp = self.history(address).len();
+    self.views[thread_id].insert(address, timestamp);
+    let mut view = if releases(mode) {
+      self.views[thread_id].clone()
+    } else {
+      let mut view = self.release_views[thread_id].clone();
+      view.insert(address, timestamp);
+      view
+    };
+    join(&mut view, &carried);
+    self.history(address).push((value, view));
+    self.memory.insert(address, value);
+  }
+
+  // SEQ_CST accesses and fences are totally ordered through a global view.
+  fn sc_fence(&mut self, thread_id: usize, mode: Mode) {
+    if let Mode::SeqCst = mode {
+      join(&mut self.views[thread_id], &self.sc_view);
+      self.sc_view = self.views[thread_id].clone();
+    }
+  }
+
+  fn refresh(&mut self) {
+    let mut addresses: Vec<&i32> = self.messages.keys().collect();
+    addresses.sort();
+    for (thread_id, unobserved) in self.unobserved.iter_mut().enumerate() {
+      unobserved.clear();
+      for address in addresses.iter() {
+        let timestamp = *self.views[thread_id].get(address).unwrap_or(&0);
+        unobserved.extend(self.messages[address][timestamp + 1..].iter().map(|(value, _)| (**address, *value)));
+      }
+    }
+  }
+}
+
+impl StorageSystem for C11StorageSystem {
+  fn load(&mut self, thread_id: usize, address: i32, mode: Mode) -> i32 {
+    self.sc_fence(thread_id, mode);
+    let value = self.read(thread_id, address, self.timestamp(thread_id, address), mode);
+    self.sc_fence(thread_id, mode);
+    self.refresh();
+    value
+  }
+
+  fn store(&mut self, thread_id: usize, address: i32, value: i32, mode: Mode) {
+    self.sc_fence(thread_id, mode);
+    self.write(thread_id, address, value, mode, View::new());
+    self.sc_fence(thread_id, mode);
+    self.refresh();
+  }
+
+  // Updates always read the latest message, so they are never reordered with other writes.
+  fn cas(&mut self, thread_id: usize, address: i32, exp: i32, des: i32, success: Mode, failure: Mode) -> i32 {
+    let latest = self.history(address).len() - 1;
+    let mode = if self.history(address)[latest].0 == exp { success } else { failure };
+    self.sc_fence(thread_id, mode);
+    let value = self.read(thread_id, address, latest, mode);
+    if value == exp {
+      let carried = self.history(address)[latest].1.clone();
+      self.write(thread_id, address, des, mode, carried);
+    }
+    self.sc_fence(thread_id, mode);
+    self.refresh();
+    value
+  }
+
+  fn fai(&mut self, thread_id: usize, address: i32, op: FetchOp, inc: i32, mode: Mode) -> Option<i32> {
+    let latest = self.history(address).len() - 1;
+    let written = op.apply(self.history(address)[latest].0, inc)?;
+    self.sc_fence(thread_id, mode);
+    let value = self.read(thread_id, address, latest, mode);
+    let carried = self.history(address)[latest].1.clone();
+    self.write(thread_id, address, written, mode, carried);
+    self.sc_fence(thread_id, mode);
+    self.refresh();
+    Some(value)
+  }
+
+  fn acquire(&mut self, thread_id: usize, address: i32) {
+    let latest = self.history(address).len() - 1;
+    let view = self.history(address)[latest].1.clone();
+    join(&mut self.views[thread_id], &view);
+    self.refresh();
+  }
+
+  fn fence(&mut self, thread_id: usize, mode: Mode) {
+    if acquires(mode) {
+      let acquired = self.acquire_views[thread_id].clone();
+      join(&mut self.views[thread_id], &acquired);
+    }
+    self.sc_fence(thread_id, mode);
+    if releases(mode) {
+      self.release_views[thread_id] = self.views[thread_id].clone();
+    }
+    self.refresh();
+  }
+
+  fn memory(&self) -> &HashMap<i32, i32> {
+    &self.memory
+  }
+
+  fn initialize(&mut self, address: i32, value: i32) {
+    self.history(address)[0].0 = value;
+    self.memory.insert(address, value);
+  }
+
+  fn stacks(&mut self) -> &mut Stacks {
+    &mut self.stacks
+  }
+
+  fn set_thread_names(&mut self, thread_names: Vec<String>) {
+    self.thread_names = thread_names;
+  }
+
+  fn set_default(&mut self, value: i32) {
+    self.default = value;
+  }
+
+  fn default_value(&self) -> i32 {
+    self.default
+  }
+
+  fn buffer(&self, thread_id: usize) -> &[(i32, i32)] {
+    &self.unobserved[thread_id]
+  }
+
+  fn propagate(&mut self, thread_id: usize, address: i32, _observer: usize) {
+    let timestamp = self.timestamp(thread_id, address);
+    if timestamp + 1 < self.history(address).len() {
+      self.views[thread_id].insert(address, timestamp + 1);
+    }
+    self.refresh();
+  }
+
+  fn tag(&mut self, _thread_id: usize, address: i32, origin: Origin) {
+    let timestamp = self.history(address).len() - 1;
+    self.origins.insert((address, timestamp), origin);
+  }
+
+  fn read_from(&self, thread_id: usize) -> Option<Origin> {
+    self.reads[thread_id]
+  }
+
+  fn source(&self, thread_id: usize, address: i32) -> Option<usize> {
+    Some(self.timestamp(thread_id, address))
+  }
+}
+
+// Every thread works on its own copy of memory. A store is queued to every other thread over a
+// FIFO channel and `propagate` delivers the oldest one. With `coherent` (processor consistency)
+// stores to an address are numbered and a thread ignores a store older than the one it has.
+#[derive(Clone)]
+pub struct PRAMStorageSystem {
+  coherent: bool,
+  memories: Vec<Shared<HashMap<i32, i32>>>,
+  channels: Vec<Vec<VecDeque<(i32, i32, usize)>>>,
+  seen: Vec<HashMap<i32, usize>>,
+  stores: HashMap<i32, usize>,
+  origins: Shared<HashMap<(i32, usize), Origin>>,
+  reads: Vec<Option<Origin>>,
+  memory: Shared<HashMap<i32, i32>>,
+  undelivered: Vec<Vec<(i32, i32)>>,
+  thread_names: Vec<String>,
+  stacks: Stacks,
+  default: i32
+}
+
+impl Debug for PRAMStorageSystem {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    writeln!(f, "# MEMORIES")?;
+    for (i, memory) in self.memories.iter().enumerate() {
+      writeln!(f, "| Thread {}: {:?}", self.thread_names[i], memory)?;
+    }
+    writeln!(f, "# CHANNELS")?;
+    for (writer, channels) in self.channels.iter().enumerate() {
+      for (reader, channel) in channels.iter().enumerate().filter(|(_, channel)| !channel.is_empty()) {
+        let stores: Vec<(i32, i32)> = channel.iter().map(|(address, value, _)| (*address, *value)).collect();
+        writeln!(f, "| Thread {} -> {}: {:?}", self.thread_names[writer], self.thread_names[reader], stores)?;
       }
     }
+    writeln!(f, "# MEMORY")?;
+    writeln!(f, "| {:?}", self.memory)
   }
+}
+
+impl PRAMStorageSystem {
+  pub fn new(number_of_threads: usize, coherent: bool) -> PRAMStorageSystem {
+    PRAMStorageSystem {
+      coherent,
+      memories: vec![Shared::default(); number_of_threads],
+      channels: vec![vec![VecDeque::new(); number_of_threads]; number_of_threads],
+      seen: vec![HashMap::new(); number_of_threads],
+      stores: HashMap::new(),
+      origins: Shared::default(),
+      reads: vec![None; number_of_threads],
+      memory: Shared::default(),
+      undelivered: vec![Vec::new(); number_of_threads],
+      thread_names: (0..number_of_threads).map(|thread_id| thread_id.to_string()).collect(),
+      stacks: Stacks::default(),
+      default: 0
+    }
+  }
+
+  fn refresh(&mut self, thread_id: usize) {
+    let mut stores: Vec<(usize, i32, i32)> = Vec::new();
+    for (address, value, index) in self.channels[thread_id].iter().flatten() {
+      if !stores.iter().any(|(other, _, _)| other == index) {
+        stores.push((*index, *address, *value));
+      }
+    }
+    stores.sort();
+    self.undelivered[thread_id] = stores.into_iter().map(|(_, address, value)| (address, value)).collect();
+  }
+
+  fn apply(&mut self, thread_id: usize, address: i32, value: i32, index: usize) {
+    let seen = self.seen[thread_id].entry(address).or_insert(0);
+    if self.coherent && *seen > index {
+      return;
+    }
+    *seen = index;
+    self.memories[thread_id].insert(address, value);
+  }
+}
 
-  fn store(&mut self, thread_id: usize, address: i32, value: i32) {
-    self.buffers[thread_id as usize].push((address, value));
+impl StorageSystem for PRAMStorageSystem {
+  fn load(&mut self, thread_id: usize, address: i32, _mode: Mode) -> i32 {
+    let index = *self.seen[thread_id].get(&address).unwrap_or(&0);
+    self.reads[thread_id] = self.origins.get(&(address, index)).cloned();
+    *self.memories[thread_id].get(&address).unwrap_or(&self.default)
   }
 
-  fn cas(&mut self, thread_id: usize, address: i32, exp: i32, des: i32) -> i32 {
-    let value = self.load(thread_id, address);
+  fn store(&mut self, thread_id: usize, address: i32, value: i32, _mode: Mode) {
+    let index = self.stores.entry(address).or_insert(0);
+    *index += 1;
+    let index = *index;
+    self.apply(thread_id, address, value, index);
+    for (reader, channel) in self.channels[thread_id].iter_mut().enumerate() {
+      if reader != thread_id {
+        channel.push_back((address, value, index));
+      }
+    }
+    self.memory.insert(address, value);
+    self.refresh(thread_id);
+  }
+
+  fn cas(&mut self, thread_id: usize, address: i32, exp: i32, des: i32, success: Mode, failure: Mode) -> i32 {
+    let value = self.load(thread_id, address, failure);
     if value == exp {
-      self.store(thread_id, address, des);
+      self.store(thread_id, address, des, success);
     }
     value
   }
 
-  fn fai(&mut self, thread_id: usize, address: i32, inc: i32) -> i32 {
-    let value = self.load(thread_id, address);
-    self.store(thread_id, address, value + inc);
+  fn fai(&mut self, thread_id: usize, address: i32, op: FetchOp, inc: i32, mode: Mode) -> Option<i32> {
+    let value = self.load(thread_id, address, mode);
+    self.store(thread_id, address, op.apply(value, inc)?, mode);
+    Some(value)
+  }
+
+  fn fence(&mut self, _thread_id: usize, _mode: Mode) {}
+
+  fn memory(&self) -> &HashMap<i32, i32> {
+    &self.memory
+  }
+
+  fn peek(&self, thread_id: usize, address: i32) -> i32 {
+    *self.memories[thread_id].get(&address).unwrap_or(&self.default)
+  }
+
+  fn initialize(&mut self, address: i32, value: i32) {
+    for memory in self.memories.iter_mut() {
+      memory.insert(address, value);
+    }
+    self.memory.insert(address, value);
+  }
+
+  fn stacks(&mut self) -> &mut Stacks {
+    &mut self.stacks
+  }
+
+  fn set_thread_names(&mut self, thread_names: Vec<String>) {
+    self.thread_names = thread_names;
+  }
+
+  fn set_default(&mut self, value: i32) {
+    self.default = value;
+  }
+
+  fn default_value(&self) -> i32 {
+    self.default
+  }
+
+  fn buffer(&self, thread_id: usize) -> &[(i32, i32)] {
+    &self.undelivered[thread_id]
+  }
+
+  fn propagate(&mut self, thread_id: usize, _address: i32, observer: usize) {
+    if let Some((address, value, index)) = self.channels[thread_id][observer].pop_front() {
+      self.apply(observer, address, value, index);
+    }
+    self.refresh(thread_id);
+  }
+
+  fn tag(&mut self, _thread_id: usize, address: i32, origin: Origin) {
+    let index = *self.stores.get(&address).unwrap_or(&0);
+    self.origins.insert((address, index), origin);
+  }
+
+  fn read_from(&self, thread_id: usize) -> Option<Origin> {
+    self.reads[thread_id]
+  }
+}
+
+#[derive(Clone)]
+struct Message {
+  value: i32,
+  writer: usize,
+  requires: Vec<(i32, usize)>,
+  // The threads in the scope of the store, for which `requires` holds.
+  ordered: Vec<bool>,
+  delivered: Vec<bool>
+}
+
+// Stores become visible to every other thread separately, in any order. Stores to an address are
+// numbered and a thread ignores a store older than the one it has. A release store is delivered
+// to a thread only after the stores its writer had seen, and a fence waits until the latest stores
+// its thread has seen have reached every thread. With a topology, both only hold for the threads in
+// the scope of the store or fence.
+#[derive(Clone)]
+pub struct NMCAStorageSystem {
+  memories: Vec<Shared<HashMap<i32, i32>>>,
+  seen: Vec<HashMap<i32, usize>>,
+  messages: HashMap<(i32, usize), Message>,
+  origins: Shared<HashMap<(i32, usize), Origin>>,
+  reads: Vec<Option<Origin>>,
+  memory: Shared<HashMap<i32, i32>>,
+  invisible: Vec<Vec<(i32, i32)>>,
+  topology: Option<Topology>,
+  // The scope of the instruction running.
+  scope: Scope,
+  thread_names: Vec<String>,
+  stacks: Stacks,
+  default: i32
+}
+
+impl Debug for NMCAStorageSystem {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    writeln!(f, "# MEMORIES")?;
+    for (i, memory) in self.memories.iter().enumerate() {
+      writeln!(f, "| Thread {}: {:?} (not visible: {:?})", self.thread_names[i], memory, self.invisible[i])?;
+    }
+    writeln!(f, "# MEMORY")?;
+    writeln!(f, "| {:?}", self.memory)
+  }
+}
+
+impl NMCAStorageSystem {
+  pub fn new(number_of_threads: usize, topology: Option<Topology>) -> NMCAStorageSystem {
+    NMCAStorageSystem {
+      memories: vec![Shared::default(); number_of_threads],
+      seen: vec![HashMap::new(); number_of_threads],
+      messages: HashMap::new(),
+      origins: Shared::default(),
+      reads: vec![None; number_of_threads],
+      memory: Shared::default(),
+      invisible: vec![Vec::new(); number_of_threads],
+      topology,
+      scope: Scope::System,
+      thread_names: (0..number_of_threads).map(|thread_id| thread_id.to_string()).collect(),
+      stacks: Stacks::default(),
+      default: 0
+    }
+  }
+
+  pub fn set_scope(&mut self, scope: Option<Scope>) {
+    self.scope = scope.unwrap_or(Scope::System);
+  }
+
+  // Without a topology every thread is in every scope.
+  fn shares(&self, scope: Scope, thread_id: usize, other: usize) -> bool {
+    self.topology.as_ref().is_none_or(|topology| topology.shares(scope, thread_id, other))
+  }
+
+  fn latest(&self, address: i32) -> usize {
+    self.messages.keys().filter(|(other, _)| *other == address).map(|(_, index)| *index).max().unwrap_or(0)
+  }
+
+  fn reached(&self, message: (i32, usize), thread_id: usize) -> bool {
+    let (address, index) = message;
+    *self.seen[thread_id].get(&address).unwrap_or(&0) >= index || self.messages[&message].delivered[thread_id]
+  }
+
+  pub fn deliverable(&self, message: (i32, usize), thread_id: usize) -> bool {
+    let message = &self.messages[&message];
+    !message.ordered[thread_id] || message.requires.iter().all(|required| self.reached(*required, thread_id))
+  }
+
+  pub fn settled(&self, thread_id: usize, scope: Scope) -> bool {
+    self.seen[thread_id].iter()
+      .all(|(address, index)| (0..self.memories.len()).all(|other| !self.shares(scope, thread_id, other) || self.reached((*address, *index), other)))
+  }
+
+  fn apply(&mut self, thread_id: usize, address: i32, index: usize) {
+    let value = self.messages[&(address, index)].value;
+    let seen = self.seen[thread_id].entry(address).or_insert(0);
+    if *seen < index {
+      *seen = index;
+      self.memories[thread_id].insert(address, value);
+    }
+  }
+
+  fn refresh(&mut self) {
+    let mut messages: Vec<(&(i32, usize), &Message)> = self.messages.iter().collect();
+    messages.sort_by_key(|(message, _)| **message);
+    for (thread_id, invisible) in self.invisible.iter_mut().enumerate() {
+      *invisible = messages.iter()
+        .filter(|((address, index), message)| message.writer != thread_id && !message.delivered[thread_id] && *self.seen[thread_id].get(address).unwrap_or(&0) < *index)
+        .map(|((address, _), message)| (*address, message.value))
+        .collect();
+    }
+  }
+}
+
+impl StorageSystem for NMCAStorageSystem {
+  fn load(&mut self, thread_id: usize, address: i32, _mode: Mode) -> i32 {
+    let index = *self.seen[thread_id].get(&address).unwrap_or(&0);
+    self.reads[thread_id] = self.origins.get(&(address, index)).cloned();
+    *self.memories[thread_id].get(&address).unwrap_or(&self.default)
+  }
+
+  fn store(&mut self, thread_id: usize, address: i32, value: i32, mode: Mode) {
+    let index = self.latest(address) + 1;
+    let requires = if releases(mode) {
+      self.seen[thread_id].iter().map(|(address, index)| (*address, *index)).collect()
+    } else {
+      Vec::new()
+    };
+    let ordered = (0..self.memories.len()).map(|reader| self.shares(self.scope, thread_id, reader)).collect();
+    let mut delivered = vec![false; self.memories.len()];
+    delivered[thread_id] = true;
+    self.messages.insert((address, index), Message { value, writer: thread_id, requires, ordered, delivered });
+    self.apply(thread_id, address, index);
+    self.memory.insert(address, value);
+    self.refresh();
+  }
+
+  // Updates act on the latest store to the address, so they stay atomic.
+  fn cas(&mut self, thread_id: usize, address: i32, exp: i32, des: i32, success: Mode, failure: Mode) -> i32 {
+    let latest = self.latest(address);
+    if latest > 0 {
+      self.apply(thread_id, address, latest);
+    }
+    let value = self.load(thread_id, address, failure);
+    if value == exp {
+      self.store(thread_id, address, des, success);
+    }
     value
   }
-}
\ No newline at end of file
+
+  fn fai(&mut self, thread_id: usize, address: i32, op: FetchOp, inc: i32, mode: Mode) -> Option<i32> {
+    let latest = self.latest(address);
+    let value = if latest > 0 { self.messages[&(address, latest)].value } else { *self.memory.get(&address).unwrap_or(&self.default) };
+    let written = op.apply(value, inc)?;
+    self.reads[thread_id] = self.origins.get(&(address, latest)).cloned();
+    if latest > 0 {
+      self.apply(thread_id, address, latest);
+    }
+    self.store(thread_id, address, written, mode);
+    Some(value)
+  }
+
+  fn fence(&mut self, _thread_id: usize, _mode: Mode) {}
+
+  fn memory(&self) -> &HashMap<i32, i32> {
+    &self.memory
+  }
+
+  fn initialize(&mut self, address: i32, value: i32) {
+    for memory in self.memories.iter_mut() {
+      memory.insert(address, value);
+    }
+    self.memory.insert(address, value);
+  }
+
+  fn stacks(&mut self) -> &mut Stacks {
+    &mut self.stacks
+  }
+
+  fn set_thread_names(&mut self, thread_names: Vec<String>) {
+    self.thread_names = thread_names;
+  }
+
+  fn set_default(&mut self, value: i32) {
+    self.default = value;
+  }
+
+  fn default_value(&self) -> i32 {
+    self.default
+  }
+
+  fn buffer(&self, thread_id: usize) -> &[(i32, i32)] {
+    &self.invisible[thread_id]
+  }
+
+  // Delivers the writer's oldest deliverable store to the address that the observer has not got yet.
+  fn propagate(&mut self, thread_id: usize, address: i32, observer: usize) {
+    let message = self.messages.iter()
+      .filter(|((other, index), message)| {
+        *other == address && message.writer == thread_id && !message.delivered[observer] && self.deliverable((*other, *index), observer)
+      })
+      .map(|(message, _)| *message)
+      .min();
+    if let Some((address, index)) = message {
+      self.messages.get_mut(&(address, index)).unwrap().delivered[observer] = true;
+      self.apply(observer, address, index);
+    }
+    self.refresh();
+  }
+
+  fn tag(&mut self, _thread_id: usize, address: i32, origin: Origin) {
+    let index = self.latest(address);
+    self.origins.insert((address, index), origin);
+  }
+
+  fn read_from(&self, thread_id: usize) -> Option<Origin> {
+    self.reads[thread_id]
+  }
+
+  fn source(&self, thread_id: usize, address: i32) -> Option<usize> {
+    Some(*self.seen[thread_id].get(&address).unwrap_or(&0))
+  }
+}
+
+// The Lamport time of a store, with its writer breaking ties.
+type Stamp = (usize, usize);
+
+#[derive(Clone)]
+struct Replication {
+  value: i32,
+  writer: usize,
+  stamp: Stamp,
+  // How many stores of each thread its writer had applied, itself included.
+  clock: Vec<usize>,
+  delivered: Vec<bool>
+}
+
+// Distributed shared memory: every thread holds a full replica and each store travels to every other
+// replica as a message of its own, delivered by a `propagate` step after any delay. A replica keeps the
+// store with the latest Lamport time to each address, so replicas agree once every message is in.
+// With `causal` delivery a message waits for every store its writer had applied before it; without it,
+// messages overtake each other freely and only eventual consistency holds.
+#[derive(Clone)]
+pub struct DSMStorageSystem {
+  causal: bool,
+  replicas: Vec<Shared<HashMap<i32, i32>>>,
+  // The stamp and number of the store each replica holds for each address.
+  applied: Vec<HashMap<i32, (Stamp, usize)>>,
+  clocks: Vec<Vec<usize>>,
+  times: Vec<usize>,
+  messages: HashMap<(i32, usize), Replication>,
+  origins: Shared<HashMap<(i32, usize), Origin>>,
+  reads: Vec<Option<Origin>>,
+  memory: Shared<HashMap<i32, i32>>,
+  in_flight: Vec<Vec<(i32, i32)>>,
+  thread_names: Vec<String>,
+  stacks: Stacks,
+  default: i32
+}
+
+impl Debug for DSMStorageSystem {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    writeln!(f, "# REPLICAS")?;
+    for (i, replica) in self.replicas.iter().enumerate() {
+      writeln!(f, "| Thread {}: {:?}", self.thread_names[i], replica)?;
+    }
+    writeln!(f, "# IN FLIGHT")?;
+    let mut messages: Vec<(&(i32, usize), &Replication)> = self.messages.iter().collect();
+    messages.sort_by_key(|(message, _)| **message);
+    for reader in 0..self.replicas.len() {
+      for writer in 0..self.replicas.len() {
+        let stores: Vec<(i32, i32)> = messages.iter()
+          .filter(|(_, message)| message.writer == writer && !message.delivered[reader])
+          .map(|((address, _), message)| (*address, message.value))
+          .collect();
+        if !stores.is_empty() {
+          writeln!(f, "| Thread {} -> {}: {:?}", self.thread_names[writer], self.thread_names[reader], stores)?;
+        }
+      }
+    }
+    writeln!(f, "# MEMORY")?;
+    writeln!(f, "| {:?}", self.memory)
+  }
+}
+
+impl DSMStorageSystem {
+  pub fn new(number_of_threads: usize, causal: bool) -> DSMStorageSystem {
+    DSMStorageSystem {
+      causal,
+      replicas: vec![Shared::default(); number_of_threads],
+      applied: vec![HashMap::new(); number_of_threads],
+      clocks: vec![vec![0; number_of_threads]; number_of_threads],
+      times: vec![0; number_of_threads],
+      messages: HashMap::new(),
+      origins: Shared::default(),
+      reads: vec![None; number_of_threads],
+      memory: Shared::default(),
+      in_flight: vec![Vec::new(); number_of_threads],
+      thread_names: (0..number_of_threads).map(|thread_id| thread_id.to_string()).collect(),
+      stacks: Stacks::default(),
+      default: 0
+    }
+  }
+
+  fn latest(&self, address: i32) -> usize {
+    self.messages.keys().filter(|(other, _)| *other == address).map(|(_, index)| *index).max().unwrap_or(0)
+  }
+
+  // Causal delivery takes the stores of each writer in order and only after those they depend on.
+  pub fn deliverable(&self, message: (i32, usize), thread_id: usize) -> bool {
+    let message = &self.messages[&message];
+    !self.causal || message.clock.iter().enumerate().all(|(writer, count)| match writer == message.writer {
+      true => *count == self.clocks[thread_id][writer] + 1,
+      false => *count <= self.clocks[thread_id][writer]
+    })
+  }
+
+  // Whether every store of the thread has reached every replica.
+  pub fn settled(&self, thread_id: usize) -> bool {
+    self.in_flight[thread_id].is_empty()
+  }
+
+  fn apply(&mut self, thread_id: usize, address: i32, index: usize) {
+    let message = &self.messages[&(address, index)];
+    let (value, writer, stamp) = (message.value, message.writer, message.stamp);
+    self.clocks[thread_id][writer] += 1;
+    self.times[thread_id] = self.times[thread_id].max(stamp.0);
+    if self.applied[thread_id].get(&address).is_none_or(|(applied, _)| *applied < stamp) {
+      self.applied[thread_id].insert(address, (stamp, index));
+      self.replicas[thread_id].insert(address, value);
+    }
+  }
+
+  fn refresh(&mut self) {
+    let mut messages: Vec<(&(i32, usize), &Replication)> = self.messages.iter().collect();
+    messages.sort_by_key(|(message, _)| **message);
+    for (writer, in_flight) in self.in_flight.iter_mut().enumerate() {
+      *in_flight = messages.iter()
+        .filter(|(_, message)| message.writer == writer && message.delivered.iter().any(|delivered| !delivered))
+        .map(|((address, _), message)| (*address, message.value))
+        .collect();
+    }
+  }
+}
+
+impl StorageSystem for DSMStorageSystem {
+  fn load(&mut self, thread_id: usize, address: i32, _mode: Mode) -> i32 {
+    let index = self.applied[thread_id].get(&address).map_or(0, |(_, index)| *index);
+    self.reads[thread_id] = self.origins.get(&(address, index)).cloned();
+    *self.replicas[thread_id].get(&address).unwrap_or(&self.default)
+  }
+
+  fn store(&mut self, thread_id: usize, address: i32, value: i32, _mode: Mode) {
+    let index = self.latest(address) + 1;
+    self.times[thread_id] += 1;
+    let mut clock = self.clocks[thread_id].clone();
+    clock[thread_id] += 1;
+    let mut delivered = vec![false; self.replicas.len()];
+    delivered[thread_id] = true;
+    self.messages.insert((address, index), Replication { value, writer: thread_id, stamp: (self.times[thread_id], thread_id), clock, delivered });
+    self.apply(thread_id, address, index);
+    self.memory.insert(address, value);
+    self.refresh();
+  }
+
+  fn cas(&mut self, thread_id: usize, address: i32, exp: i32, des: i32, success: Mode, failure: Mode) -> i32 {
+    let value = self.load(thread_id, address, failure);
+    if value == exp {
+      self.store(thread_id, address, des, success);
+    }
+    value
+  }
+
+  fn fai(&mut self, thread_id: usize, address: i32, op: FetchOp, inc: i32, mode: Mode) -> Option<i32> {
+    let value = self.load(thread_id, address, mode);
+    self.store(thread_id, address, op.apply(value, inc)?, mode);
+    Some(value)
+  }
+
+  fn fence(&mut self, _thread_id: usize, _mode: Mode) {}
+
+  fn memory(&self) -> &HashMap<i32, i32> {
+    &self.memory
+  }
+
+  fn peek(&self, thread_id: usize, address: i32) -> i32 {
+    *self.replicas[thread_id].get(&address).unwrap_or(&self.default)
+  }
+
+  fn initialize(&mut self, address: i32, value: i32) {
+    for replica in self.replicas.iter_mut() {
+      replica.insert(address, value);
+    }
+    self.memory.insert(address, value);
+  }
+
+  fn stacks(&mut self) -> &mut Stacks {
+    &mut self.stacks
+  }
+
+  fn set_thread_names(&mut self, thread_names: Vec<String>) {
+    self.thread_names = thread_names;
+  }
+
+  fn set_default(&mut self, value: i32) {
+    self.default = value;
+  }
+
+  fn default_value(&self) -> i32 {
+    self.default
+  }
+
+  fn buffer(&self, thread_id: usize) -> &[(i32, i32)] {
+    &self.in_flight[thread_id]
+  }
+
+  // Delivers the writer's oldest deliverable message for the address that the observer has not got yet.
+  fn propagate(&mut self, thread_id: usize, address: i32, observer: usize) {
+    let message = self.messages.iter()
+      .filter(|((other, index), message)| {
+        *other == address && message.writer == thread_id && !message.delivered[observer] && self.deliverable((*other, *index), observer)
+      })
+      .map(|(message, _)| *message)
+      .min();
+    if let Some((address, index)) = message {
+      self.messages.get_mut(&(address, index)).unwrap().delivered[observer] = true;
+      self.apply(observer, address, index);
+    }
+    self.refresh();
+  }
+
+  fn tag(&mut self, _thread_id: usize, address: i32, origin: Origin) {
+    let index = self.latest(address);
+    self.origins.insert((address, index), origin);
+  }
+
+  fn read_from(&self, thread_id: usize) -> Option<Origin> {
+    self.reads[thread_id]
+  }
+}
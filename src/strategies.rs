@@ -0,0 +1,122 @@
+use std::collections::BTreeSet;
+use std::ops::RangeInclusive;
+
+use proptest::{collection::vec, prelude::*, sample::Index};
+
+use crate::{explore, memory_model::MemoryModelType, parser::parse_program, program::Program, testing};
+
+// Generated threads compute with registers `r0`, `r1`, ... and start by setting register `aN` to
+// address N for every address they access. Every step multiplies the schedules to explore, so
+// nothing else is set up front.
+const REGISTERS: usize = 3;
+
+#[derive(Clone, Debug)]
+enum Line {
+  Constant(usize, i32),
+  Load(&'static str, i32, usize),
+  Store(&'static str, i32, usize),
+  Fai(&'static str, i32, usize, usize),
+  Fence(&'static str)
+}
+
+fn line(addresses: i32) -> impl Strategy<Value = Line> {
+  let address = 0..addresses.max(1);
+  let register = 0..REGISTERS;
+  prop_oneof![
+    (register.clone(), 0..4).prop_map(|(r, value)| Line::Constant(r, value)),
+    (prop::sample::select(&["RLX", "ACQ", "SEQ_CST"][..]), address.clone(), register.clone()).prop_map(|(mode, address, r)| Line::Load(mode, address, r)),
+    (prop::sample::select(&["RLX", "REL", "SEQ_CST"][..]), address.clone(), register.clone()).prop_map(|(mode, address, r)| Line::Store(mode, address, r)),
+    (prop::sample::select(&["RLX", "REL_ACQ", "SEQ_CST"][..]), address, register.clone(), register).prop_map(|(mode, address, r1, r2)| Line::Fai(mode, address, r1, r2)),
+    prop::sample::select(&["REL", "ACQ", "REL_ACQ", "SEQ_CST"][..]).prop_map(Line::Fence)
+  ]
+}
+
+fn render(threads: &[Vec<Line>], addresses: i32) -> String {
+  let threads: Vec<String> = threads.iter().map(|lines| {
+    let mut text: Vec<String> = (0..addresses.max(1)).map(|address| format!("a{} = {}", address, address)).collect();
+    text.extend(lines.iter().map(|line| match line {
+      Line::Constant(r, value) => format!("r{} = {}", r, value),
+      Line::Load(mode, address, r) => format!("load {} #a{} r{}", mode, address, r),
+      Line::Store(mode, address, r) => format!("store {} #a{} r{}", mode, address, r),
+      Line::Fai(mode, address, r1, r2) => format!("r{} := fai {} #a{} r{}", r1, mode, address, r2),
+      Line::Fence(mode) => format!("fence {}", mode)
+    }));
+    text.join("\n")
+  }).collect();
+  format!("{}\n", threads.join("\n\n"))
+}
+
+// The text of well-formed programs with loads, stores, `fai` and fences on the first `addresses`
+// addresses. Every thread runs to the end under every model, and programs shrink to fewer threads
+// and instructions.
+pub fn program(threads: RangeInclusive<usize>, instructions: RangeInclusive<usize>, addresses: i32) -> impl Strategy<Value = String> {
+  vec(vec(line(addresses), instructions), threads).prop_map(move |threads| render(&threads, addresses))
+}
+
+// Feasible schedules of the program under the model, as the ids of the nodes run one after another.
+// Every step picks one of the nodes that can run; once the choices run out the first one is taken,
+// so a schedule ends with the program, with a failing step or after `max_steps` steps.
+pub fn schedule(memory_model: MemoryModelType, program: Program, max_steps: usize) -> impl Strategy<Value = Vec<usize>> {
+  vec(any::<Index>(), 0..=max_steps).prop_map(move |choices| {
+    let mut model = memory_model.create(&program);
+    let mut schedule = Vec::new();
+    while schedule.len() < max_steps {
+      let candidates = model.get_possible_executions();
+      if candidates.is_empty() {
+        break;
+      }
+      let node = match choices.get(schedule.len()) {
+        Some(choice) => candidates[choice.index(candidates.len())].clone(),
+        None => candidates[0].clone()
+      };
+      schedule.push(node.id);
+      if model.step(node, false).is_err() {
+        break;
+      }
+    }
+    schedule
+  })
+}
+
+// Programs from `programs` together with a feasible schedule of each.
+pub fn scheduled(memory_model: MemoryModelType, programs: impl Strategy<Value = String>, max_steps: usize) -> impl Strategy<Value = (String, Vec<usize>)> {
+  programs.prop_flat_map(move |source| {
+    let program = parse_program(&source).expect("generated programs parse");
+    (Just(source), schedule(memory_model, program, max_steps))
+  })
+}
+
+// The final state a schedule of the program leads to, written as `--exhaustive` lists outcomes,
+// including the error a failing step stops it with.
+pub fn run(memory_model: &MemoryModelType, source: &str, schedule: &[usize]) -> Result<String, String> {
+  let program = parse_program(source)?;
+  let model = match explore::replay(memory_model, &program, schedule) {
+    Ok(model) => model,
+    Err(err) => return Ok(format!("error: {}", err))
+  };
+  if !model.get_possible_executions().is_empty() {
+    return Err(format!("the schedule stops after {} steps, before the program ends", schedule.len()));
+  }
+  Ok(explore::final_outcome(model.as_ref()))
+}
+
+// Checks that the weaker model allows every outcome of the stronger one, as TSO does for SC.
+pub fn allows_all(source: &str, weaker: &MemoryModelType, stronger: &MemoryModelType) -> Result<(), String> {
+  let weaker_outcomes: BTreeSet<String> = testing::explore(source, weaker)?.outcomes.into_keys().collect();
+  let missing: Vec<String> = testing::explore(source, stronger)?.outcomes.into_keys()
+    .filter(|outcome| !weaker_outcomes.contains(outcome))
+    .collect();
+  match missing.is_empty() {
+    true => Ok(()),
+    false => Err(format!("{:?} reaches outcomes that {:?} does not:\n{}", stronger, weaker, missing.iter().map(|outcome| format!("| {}", outcome)).collect::<Vec<String>>().join("\n")))
+  }
+}
+
+// Checks that the final state of a schedule is among the outcomes exhaustive exploration finds.
+pub fn explored(memory_model: &MemoryModelType, source: &str, schedule: &[usize]) -> Result<(), String> {
+  let outcome = run(memory_model, source, schedule)?;
+  match testing::explore(source, memory_model)?.outcomes.contains_key(&outcome) {
+    true => Ok(()),
+    false => Err(format!("exhaustive exploration under {:?} misses {}", memory_model, outcome))
+  }
+}
@@ -0,0 +1,602 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt::Debug;
+
+use crate::{condition::{Comparison, Condition, Operand}, error::RuntimeError, explore::Exploration, instruction::{FetchOp, Instruction}, memory_model::MemoryModelType, program::{check_region, Initial, Program}};
+
+// A register or memory value: a constant or an expression over the program's symbolic inputs.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub enum Value {
+  Const(i32),
+  Input(String),
+  Plus(Box<Value>, Box<Value>),
+  Minus(Box<Value>, Box<Value>),
+  Mul(Box<Value>, Box<Value>),
+  Div(Box<Value>, Box<Value>)
+}
+
+type Checked = fn(i32, i32) -> Option<i32>;
+type Operation = fn(Box<Value>, Box<Value>) -> Value;
+
+impl Debug for Value {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      Value::Const(value) => write!(f, "{}", value),
+      Value::Input(name) => write!(f, "{}", name),
+      Value::Plus(a, b) => write!(f, "({:?} + {:?})", a, b),
+      Value::Minus(a, b) => write!(f, "({:?} - {:?})", a, b),
+      Value::Mul(a, b) => write!(f, "({:?} * {:?})", a, b),
+      Value::Div(a, b) => write!(f, "({:?} / {:?})", a, b)
+    }
+  }
+}
+
+impl Value {
+  // Folds constants; None when folding overflows.
+  fn arith(a: &Value, b: &Value, op: Checked, symbolic: Operation) -> Option<Value> {
+    match (a, b) {
+      (Value::Const(a), Value::Const(b)) => op(*a, *b).map(Value::Const),
+      _ => Some(symbolic(Box::new(a.clone()), Box::new(b.clone())))
+    }
+  }
+
+  pub fn eval(&self, inputs: &HashMap<String, i32>) -> Option<i32> {
+    match self {
+      Value::Const(value) => Some(*value),
+      Value::Input(name) => inputs.get(name).cloned(),
+      Value::Plus(a, b) => a.eval(inputs)?.checked_add(b.eval(inputs)?),
+      Value::Minus(a, b) => a.eval(inputs)?.checked_sub(b.eval(inputs)?),
+      Value::Mul(a, b) => a.eval(inputs)?.checked_mul(b.eval(inputs)?),
+      Value::Div(a, b) => a.eval(inputs)?.checked_div(b.eval(inputs)?)
+    }
+  }
+
+  fn inputs(&self, names: &mut BTreeSet<String>) {
+    match self {
+      Value::Const(_) => {}
+      Value::Input(name) => {
+        names.insert(name.clone());
+      }
+      Value::Plus(a, b) | Value::Minus(a, b) | Value::Mul(a, b) | Value::Div(a, b) => {
+        a.inputs(names);
+        b.inputs(names);
+      }
+    }
+  }
+
+  fn constants(&self, constants: &mut BTreeSet<i32>) {
+    match self {
+      Value::Const(value) => {
+        constants.insert(*value);
+      }
+      Value::Input(_) => {}
+      Value::Plus(a, b) | Value::Minus(a, b) | Value::Mul(a, b) | Value::Div(a, b) => {
+        a.constants(constants);
+        b.constants(constants);
+      }
+    }
+  }
+}
+
+#[derive(Clone, PartialEq, Eq)]
+pub struct Constraint {
+  left: Value,
+  comparison: Comparison,
+  right: Value
+}
+
+impl Debug for Constraint {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    let comparison = match self.comparison {
+      Comparison::Eq => "==",
+      Comparison::Ne => "!=",
+      Comparison::Lt => "<",
+      Comparison::Le => "<=",
+      Comparison::Gt => ">",
+      Comparison::Ge => ">="
+    };
+    write!(f, "{:?} {} {:?}", self.left, comparison, self.right)
+  }
+}
+
+impl Constraint {
+  fn new(left: &Value, comparison: Comparison, right: &Value) -> Constraint {
+    Constraint { left: left.clone(), comparison, right: right.clone() }
+  }
+
+  fn holds(&self, inputs: &HashMap<String, i32>) -> bool {
+    let (Some(left), Some(right)) = (self.left.eval(inputs), self.right.eval(inputs)) else {
+      return false;
+    };
+    match self.comparison {
+      Comparison::Eq => left == right,
+      Comparison::Ne => left != right,
+      Comparison::Lt => left < right,
+      Comparison::Le => left <= right,
+      Comparison::Gt => left > right,
+      Comparison::Ge => left >= right
+    }
+  }
+
+  fn inputs(&self) -> BTreeSet<String> {
+    let mut names = BTreeSet::new();
+    self.left.inputs(&mut names);
+    self.right.inputs(&mut names);
+    names
+  }
+
+  // The value of `input` that makes an equality hold when it is linear in the only unassigned input.
+  fn solution(&self, input: &str, inputs: &HashMap<String, i32>) -> Option<i32> {
+    if self.comparison != Comparison::Eq {
+      return None;
+    }
+    let difference = |value: i32| {
+      let mut inputs = inputs.clone();
+      inputs.insert(input.to_string(), value);
+      Some(self.left.eval(&inputs)? as i64 - self.right.eval(&inputs)? as i64)
+    };
+    let (d0, d1, d2) = (difference(0)?, difference(1)?, difference(2)?);
+    let slope = d1 - d0;
+    if slope == 0 || d2 - d1 != slope || d0 % slope != 0 {
+      return None;
+    }
+    i32::try_from(-d0 / slope).ok()
+  }
+}
+
+// Searches for inputs satisfying every constraint: each input tries the values that solve an equality
+// on it, small numbers, the extremes and the constants around it. A path whose constraints have no such
+// witness is treated as infeasible.
+pub fn solve(constraints: &[Constraint]) -> Option<HashMap<String, i32>> {
+  if constraints.iter().any(|constraint| constraint.inputs().is_empty() && !constraint.holds(&HashMap::new())) {
+    return None;
+  }
+  let mut names = BTreeSet::new();
+  let mut constants: BTreeSet<i32> = (-3..=3).chain([i32::MIN, i32::MAX]).collect();
+  for constraint in constraints {
+    names.extend(constraint.inputs());
+    constraint.left.constants(&mut constants);
+    constraint.right.constants(&mut constants);
+  }
+  let mut pool: BTreeSet<i32> = BTreeSet::new();
+  for constant in constants {
+    pool.extend([constant.checked_sub(1), Some(constant), constant.checked_add(1), constant.checked_neg()].into_iter().flatten());
+  }
+  let mut pool: Vec<i32> = pool.into_iter().collect();
+  pool.sort_by_key(|value| ((*value as i64).abs(), *value < 0));
+  let names: Vec<String> = names.into_iter().collect();
+  let mut inputs = HashMap::new();
+  if assign(&names, constraints, &pool, &mut inputs) { Some(inputs) } else { None }
+}
+
+fn assign(names: &[String], constraints: &[Constraint], pool: &[i32], inputs: &mut HashMap<String, i32>) -> bool {
+  let Some((name, rest)) = names.split_first() else {
+    return true;
+  };
+  let relevant: Vec<&Constraint> = constraints.iter()
+    .filter(|constraint| constraint.inputs().iter().all(|input| input == name || inputs.contains_key(input)) && constraint.inputs().contains(name))
+    .collect();
+  let mut candidates: Vec<i32> = relevant.iter().filter_map(|constraint| constraint.solution(name, inputs)).collect();
+  candidates.extend(pool.iter().cloned());
+  let mut tried = BTreeSet::new();
+  for candidate in candidates {
+    if !tried.insert(candidate) {
+      continue;
+    }
+    inputs.insert(name.clone(), candidate);
+    if relevant.iter().all(|constraint| constraint.holds(inputs)) && assign(rest, constraints, pool, inputs) {
+      return true;
+    }
+  }
+  inputs.remove(name);
+  false
+}
+
+fn describe_inputs(inputs: &HashMap<String, i32>) -> String {
+  let inputs: BTreeMap<&String, &i32> = inputs.iter().collect();
+  inputs.iter().map(|(name, value)| format!("{} = {}", name, value)).collect::<Vec<String>>().join(", ")
+}
+
+#[derive(Clone)]
+struct State {
+  pcs: Vec<usize>,
+  returns: Vec<Vec<usize>>,
+  stacks: Vec<Vec<Value>>,
+  registers: Vec<HashMap<String, Value>>,
+  memory: HashMap<i32, Value>,
+  locks: HashMap<i32, usize>,
+  constraints: Vec<Constraint>,
+  steps: usize
+}
+
+impl State {
+  fn get(&self, thread_id: usize, r: &String) -> Value {
+    self.registers[thread_id].get(r).cloned().unwrap_or(Value::Const(0))
+  }
+
+  // Adds a constraint, keeping the state only if the path stays feasible.
+  fn assume(mut self, constraint: Constraint) -> Option<State> {
+    if constraint.inputs().is_empty() {
+      return if constraint.holds(&HashMap::new()) { Some(self) } else { None };
+    }
+    if self.constraints.contains(&constraint) {
+      return Some(self);
+    }
+    self.constraints.push(constraint);
+    solve(&self.constraints).map(|_| self)
+  }
+
+  fn describe(&self, thread_names: &[String]) -> String {
+    let mut parts = Vec::new();
+    for (thread_name, registers) in thread_names.iter().zip(self.registers.iter()) {
+      let registers: BTreeMap<&String, &Value> = registers.iter().collect();
+      parts.push(format!("{}: {:?}", thread_name, registers));
+    }
+    let memory: BTreeMap<&i32, &Value> = self.memory.iter().collect();
+    parts.push(format!("memory: {:?}", memory));
+    parts.join(" ")
+  }
+
+  fn path(&self) -> String {
+    match solve(&self.constraints) {
+      Some(inputs) if !self.constraints.is_empty() => {
+        let constraints: Vec<String> = self.constraints.iter().map(|constraint| format!("{:?}", constraint)).collect();
+        format!(" when {} (e.g. {})", constraints.join(" && "), describe_inputs(&inputs))
+      }
+      _ => String::new()
+    }
+  }
+}
+
+enum Branch {
+  Next(State),
+  Error(State, String)
+}
+
+struct Simulation<'a> {
+  program: &'a Program,
+  max_steps: usize,
+  exploration: Exploration,
+  finals: Vec<State>
+}
+
+impl Simulation<'_> {
+  fn enabled(&self, state: &State, thread_id: usize) -> bool {
+    let Some(instruction) = self.program.threads[thread_id].get(state.pcs[thread_id]) else {
+      return false;
+    };
+    match &instruction.instruction {
+      Instruction::Lock { address } => match state.get(thread_id, address) {
+        Value::Const(address) => !state.locks.contains_key(&address),
+        _ => true
+      },
+      _ => true
+    }
+  }
+
+  fn explore(&mut self, state: State) {
+    let enabled: Vec<usize> = (0..state.pcs.len()).filter(|thread_id| self.enabled(&state, *thread_id)).collect();
+    if enabled.is_empty() {
+      let finished = state.pcs.iter().enumerate().all(|(thread_id, pc)| *pc >= self.program.threads[thread_id].len());
+      let outcome = if finished { state.describe(&self.program.thread_names) } else { format!("{} (deadlock)", state.describe(&self.program.thread_names)) };
+      self.exploration.record(format!("{}{}", outcome, state.path()), Vec::new());
+      self.finals.push(state);
+      return;
+    }
+    if state.steps >= self.max_steps {
+      self.exploration.executions += 1;
+      self.exploration.truncated += 1;
+      return;
+    }
+    for thread_id in enabled {
+      for branch in self.step(&state, thread_id) {
+        match branch {
+          Branch::Next(state) => self.explore(state),
+          Branch::Error(state, message) => self.fail(state, thread_id, message)
+        }
+      }
+    }
+  }
+
+  fn fail(&mut self, state: State, thread_id: usize, message: String) {
+    let instruction = &self.program.threads[thread_id][state.pcs[thread_id]];
+    let inputs = solve(&state.constraints).unwrap_or_default();
+    let registers = instruction.instruction.read_registers().into_iter()
+      .filter_map(|r| state.get(thread_id, r).eval(&inputs).map(|value| (r.clone(), value)))
+      .collect();
+    let error = RuntimeError {
+      thread_id,
+      thread_name: self.program.thread_names[thread_id].clone(),
+      instruction: format!("{:?}", instruction),
+      line: instruction.line,
+      message,
+      registers
+    };
+    self.exploration.record(format!("error: {}{}", error, state.path()), Vec::new());
+  }
+
+  // The states after the thread's next instruction, one per feasible outcome of the comparisons it makes.
+  fn step(&self, state: &State, thread_id: usize) -> Vec<Branch> {
+    let pc = state.pcs[thread_id];
+    let instruction = &self.program.threads[thread_id][pc];
+    let mut next = state.clone();
+    next.pcs[thread_id] += 1;
+    next.steps += 1;
+    let get = |r: &String| state.get(thread_id, r);
+    let fail = |message: &str| vec![Branch::Error(state.clone(), message.to_string())];
+    let address = |r: &String| match get(r) {
+      Value::Const(address) if address < 0 => Err("negative memory address".to_string()),
+      Value::Const(address) => check_region(&self.program.regions, address).map(|_| address),
+      _ => Err("symbolic memory address".to_string())
+    };
+    let zero = Value::Const(0);
+    let default = Value::Const(self.program.default_memory);
+    match &instruction.instruction {
+      Instruction::Const { r, value } => {
+        let value = if self.program.inputs.contains(&(thread_id, pc)) {
+          Value::Input(format!("{}:{}", self.program.thread_names[thread_id], r))
+        } else {
+          Value::Const(*value)
+        };
+        next.registers[thread_id].insert(r.clone(), value);
+        vec![Branch::Next(next)]
+      }
+      Instruction::ArithPlus { r1, r2, r3 }
+      | Instruction::ArithMinus { r1, r2, r3 }
+      | Instruction::ArithMul { r1, r2, r3 }
+      | Instruction::ArithDiv { r1, r2, r3 } => {
+        let mut branches = Vec::new();
+        let (op, symbolic): (Checked, Operation) = match &instruction.instruction {
+          Instruction::ArithPlus { r1: _, r2: _, r3: _ } => (i32::checked_add, Value::Plus),
+          Instruction::ArithMinus { r1: _, r2: _, r3: _ } => (i32::checked_sub, Value::Minus),
+          Instruction::ArithMul { r1: _, r2: _, r3: _ } => (i32::checked_mul, Value::Mul),
+          _ => {
+            if let Some(failing) = state.clone().assume(Constraint::new(&get(r3), Comparison::Eq, &zero)) {
+              branches.push(Branch::Error(failing, "division by zero".to_string()));
+            }
+            match next.assume(Constraint::new(&get(r3), Comparison::Ne, &zero)) {
+              Some(dividing) => next = dividing,
+              None => return branches
+            }
+            (i32::checked_div, Value::Div)
+          }
+        };
+        match Value::arith(&get(r2), &get(r3), op, symbolic) {
+          Some(value) => {
+            next.registers[thread_id].insert(r1.clone(), value);
+            branches.push(Branch::Next(next));
+          }
+          None => branches.push(Branch::Error(state.clone(), "arithmetic overflow".to_string()))
+        }
+        branches
+      }
+      // Values have no conditional form, so a select on an input splits the state like a jump.
+      Instruction::Select { r1, r2, r3, r4 } => {
+        let mut branches = Vec::new();
+        if let Some(mut chosen) = next.clone().assume(Constraint::new(&get(r2), Comparison::Ne, &zero)) {
+          chosen.registers[thread_id].insert(r1.clone(), get(r3));
+          branches.push(Branch::Next(chosen));
+        }
+        if let Some(mut chosen) = next.assume(Constraint::new(&get(r2), Comparison::Eq, &zero)) {
+          chosen.registers[thread_id].insert(r1.clone(), get(r4));
+          branches.push(Branch::Next(chosen));
+        }
+        branches
+      }
+      Instruction::Cond { r, target } => {
+        let target = target.resolve(self.program.threads[thread_id].iter(), pc);
+        let mut branches = Vec::new();
+        if let Some(mut taken) = next.clone().assume(Constraint::new(&get(r), Comparison::Ne, &zero)) {
+          if let Some(target) = target {
+            taken.pcs[thread_id] = target;
+          }
+          branches.push(Branch::Next(taken));
+        }
+        branches.extend(next.assume(Constraint::new(&get(r), Comparison::Eq, &zero)).map(Branch::Next));
+        branches
+      }
+      Instruction::Call { target } => {
+        if let Some(target) = target.resolve(self.program.threads[thread_id].iter(), pc) {
+          next.returns[thread_id].push(pc + 1);
+          next.pcs[thread_id] = target;
+        }
+        vec![Branch::Next(next)]
+      }
+      Instruction::Ret => {
+        next.pcs[thread_id] = next.returns[thread_id].pop().unwrap_or(self.program.threads[thread_id].len());
+        vec![Branch::Next(next)]
+      }
+      Instruction::Push { r } => {
+        next.stacks[thread_id].push(get(r));
+        vec![Branch::Next(next)]
+      }
+      Instruction::Pop { r } => {
+        let Some(value) = next.stacks[thread_id].pop() else {
+          return fail("pop from an empty stack");
+        };
+        next.registers[thread_id].insert(r.clone(), value);
+        vec![Branch::Next(next)]
+      }
+      Instruction::Load { mode: _, address: a, r } => {
+        let address = match address(a) {
+          Ok(address) => address,
+          Err(message) => return fail(&message)
+        };
+        let value = state.memory.get(&address).cloned().unwrap_or_else(|| default.clone());
+        next.registers[thread_id].insert(r.clone(), value);
+        vec![Branch::Next(next)]
+      }
+      Instruction::Store { mode: _, address: a, r } => {
+        let address = match address(a) {
+          Ok(address) => address,
+          Err(message) => return fail(&message)
+        };
+        next.memory.insert(address, get(r));
+        vec![Branch::Next(next)]
+      }
+      Instruction::Cas { mode: _, failure: _, address: a, to, exp, des, flag } => {
+        let address = match address(a) {
+          Ok(address) => address,
+          Err(message) => return fail(&message)
+        };
+        let value = state.memory.get(&address).cloned().unwrap_or_else(|| default.clone());
+        next.registers[thread_id].insert(to.clone(), value.clone());
+        let mut branches = Vec::new();
+        if let Some(mut success) = next.clone().assume(Constraint::new(&value, Comparison::Eq, &get(exp))) {
+          success.memory.insert(address, get(des));
+          if let Some(flag) = flag {
+            success.registers[thread_id].insert(flag.clone(), Value::Const(1));
+          }
+          branches.push(Branch::Next(success));
+        }
+        if let Some(mut failure) = next.assume(Constraint::new(&value, Comparison::Ne, &get(exp))) {
+          if let Some(flag) = flag {
+            failure.registers[thread_id].insert(flag.clone(), Value::Const(0));
+          }
+          branches.push(Branch::Next(failure));
+        }
+        branches
+      }
+      Instruction::Fai { op, mode: _, address: a, to, inc } => {
+        let address = match address(a) {
+          Ok(address) => address,
+          Err(message) => return fail(&message)
+        };
+        let value = state.memory.get(&address).cloned().unwrap_or_else(|| default.clone());
+        let inc = get(inc);
+        next.registers[thread_id].insert(to.clone(), value.clone());
+        let (keeps, replaces) = match op {
+          FetchOp::Add | FetchOp::Sub => {
+            let written = match op {
+              FetchOp::Add => Value::arith(&value, &inc, i32::checked_add, Value::Plus),
+              _ => Value::arith(&value, &inc, i32::checked_sub, Value::Minus)
+            };
+            return match written {
+              Some(written) => {
+                next.memory.insert(address, written);
+                vec![Branch::Next(next)]
+              }
+              None => fail("arithmetic overflow")
+            };
+          }
+          FetchOp::Max => (Comparison::Ge, Comparison::Lt),
+          FetchOp::Min => (Comparison::Le, Comparison::Gt)
+        };
+        let mut branches = Vec::new();
+        if let Some(mut kept) = next.clone().assume(Constraint::new(&value, keeps, &inc)) {
+          kept.memory.insert(address, value.clone());
+          branches.push(Branch::Next(kept));
+        }
+        if let Some(mut replaced) = next.assume(Constraint::new(&value, replaces, &inc)) {
+          replaced.memory.insert(address, inc);
+          branches.push(Branch::Next(replaced));
+        }
+        branches
+      }
+      Instruction::Fence { mode: _, order: _ } | Instruction::Propagate { thread_id: _, address: _, value: _ } => vec![Branch::Next(next)],
+      Instruction::Lock { address: a } => {
+        let address = match address(a) {
+          Ok(address) => address,
+          Err(message) => return fail(&message)
+        };
+        next.locks.insert(address, thread_id);
+        vec![Branch::Next(next)]
+      }
+      Instruction::Unlock { address: a } => {
+        let address = match address(a) {
+          Ok(address) => address,
+          Err(message) => return fail(&message)
+        };
+        if state.locks.get(&address) != Some(&thread_id) {
+          return fail("lock is not held by the thread");
+        }
+        next.locks.remove(&address);
+        vec![Branch::Next(next)]
+      }
+      _ => unreachable!("unsupported instructions are rejected before the simulation")
+    }
+  }
+}
+
+pub struct Symbolic {
+  pub exploration: Exploration,
+  pub violation: Option<(String, String)>
+}
+
+fn atom_constraint(state: &State, program: &Program, operand: &Operand, comparison: Comparison, value: i32) -> Constraint {
+  let left = match operand {
+    Operand::Register { thread, r } => state.get(program.thread_id(thread).unwrap(), r),
+    Operand::Memory { address } => state.memory.get(address).cloned().unwrap_or(Value::Const(program.default_memory))
+  };
+  Constraint::new(&left, comparison, &Value::Const(value))
+}
+
+fn negate(comparison: Comparison) -> Comparison {
+  match comparison {
+    Comparison::Eq => Comparison::Ne,
+    Comparison::Ne => Comparison::Eq,
+    Comparison::Lt => Comparison::Ge,
+    Comparison::Le => Comparison::Gt,
+    Comparison::Gt => Comparison::Le,
+    Comparison::Ge => Comparison::Lt
+  }
+}
+
+// Inputs for which the final state breaks the condition: one atom of every clause must fail.
+fn violation(state: &State, program: &Program, condition: &Condition) -> Option<HashMap<String, i32>> {
+  let mut choices: Vec<Vec<Constraint>> = vec![state.constraints.clone()];
+  for atoms in condition.clauses.iter() {
+    choices = choices.into_iter()
+      .flat_map(|constraints| atoms.iter().map(move |atom| {
+        let mut constraints = constraints.clone();
+        constraints.push(atom_constraint(state, program, &atom.operand, negate(atom.comparison), atom.value));
+        constraints
+      }))
+      .collect();
+  }
+  choices.iter().find_map(|constraints| solve(constraints))
+}
+
+// Explores every interleaving under SC once for all values of the program's `r = ?` inputs: values are
+// expressions over the inputs, and every comparison the program makes on them splits the path.
+pub fn explore(memory_model: MemoryModelType, program: &Program, max_steps: usize) -> Result<Symbolic, String> {
+  if memory_model != MemoryModelType::SC {
+    return Err(format!("Symbolic exploration does not support the {:?} model", memory_model));
+  }
+  for instruction in program.threads.iter().flatten() {
+    if instruction.instruction.is_blocking() || instruction.instruction.is_exclusive() || instruction.instruction.is_transactional() || matches!(instruction.instruction, Instruction::Flush { address: _ }) {
+      return Err(format!("Symbolic exploration does not support {:?}", instruction));
+    }
+  }
+  let threads = program.threads.len();
+  let mut state = State {
+    pcs: vec![0; threads],
+    returns: vec![Vec::new(); threads],
+    stacks: vec![Vec::new(); threads],
+    registers: vec![HashMap::new(); threads],
+    memory: HashMap::new(),
+    locks: HashMap::new(),
+    constraints: Vec::new(),
+    steps: 0
+  };
+  for (address, initial) in program.initial.iter() {
+    let input = Value::Input(format!("#{}", address));
+    let value = match initial {
+      Initial::Value(value) => Value::Const(*value),
+      Initial::Range(low, high) => {
+        state.constraints.push(Constraint::new(&input, Comparison::Ge, &Value::Const(*low)));
+        if let Some(high) = high {
+          state.constraints.push(Constraint::new(&input, Comparison::Lt, &Value::Const(*high)));
+        }
+        input
+      }
+      Initial::Symbolic => input
+    };
+    state.memory.insert(*address, value);
+  }
+  let mut simulation = Simulation { program, max_steps, exploration: Exploration::new(), finals: Vec::new() };
+  simulation.explore(state);
+  let violation = program.postcondition.as_ref().and_then(|condition| simulation.finals.iter().find_map(|state| {
+    violation(state, program, condition).map(|inputs| (format!("{}{}", state.describe(&program.thread_names), state.path()), describe_inputs(&inputs)))
+  }));
+  Ok(Symbolic { exploration: simulation.exploration, violation })
+}
@@ -0,0 +1,97 @@
+use crate::{condition::Condition, explore::{self, Exploration, Explorer}, memory_model::MemoryModelType, parser::parse_program, program::Program};
+
+// Executions longer than this are cut off, as with `--max-steps` on the command line.
+pub const MAX_STEPS: usize = 1000;
+
+fn parse(source: &str, memory_model: &MemoryModelType) -> Result<Program, String> {
+  let program = parse_program(source)?;
+  if let Some((thread_id, index)) = program.inputs.first() {
+    return Err(format!("Line {}: symbolic inputs cannot be explored", program.threads[*thread_id][*index].line.unwrap_or(0)));
+  }
+  if let Some(instruction) = program.java().filter(|_| *memory_model != MemoryModelType::JMM) {
+    return Err(format!("Line {}: VOLATILE and PLAIN need the JMM model", instruction.line.unwrap_or(0)));
+  }
+  let operational = matches!(memory_model, MemoryModelType::SC | MemoryModelType::TSO | MemoryModelType::PSO);
+  if !operational && (program.transactional() || !program.persistent.is_empty() || !program.recovery.is_empty()) {
+    return Err("transactions, persistent addresses and recovery threads need the SC, TSO or PSO model".to_string());
+  }
+  Ok(program)
+}
+
+// Explores every schedule of the program text under the model, as `--exhaustive` does.
+pub fn explore(source: &str, memory_model: &MemoryModelType) -> Result<Exploration, String> {
+  let program = parse(source, memory_model)?;
+  explore::quantify(&program, |instance| Ok(Explorer::new(memory_model, instance, MAX_STEPS).run()))
+}
+
+// Checks litmus expectations written as conditions in the syntax of the `assert` line: every
+// `allowed` condition holds in some final state and no `forbidden` one holds in any.
+pub fn check(source: &str, memory_model: &MemoryModelType, allowed: &[&str], forbidden: &[&str]) -> Result<(), String> {
+  let program = parse(source, memory_model)?;
+  let conditions = |expectations: &[&str]| expectations.iter()
+    .map(|expectation| Condition::parse(expectation).map_err(|err| format!("{}: {}", expectation, err)))
+    .collect::<Result<Vec<Condition>, String>>();
+  check_program(&program, memory_model, MAX_STEPS, &conditions(allowed)?, &conditions(forbidden)?)
+}
+
+// Explores the program exhaustively and checks the expectations. Executions that fail or are cut
+// off make the expectations fail too, since the outcomes would be incomplete.
+pub fn check_program(program: &Program, memory_model: &MemoryModelType, max_steps: usize, allowed: &[Condition], forbidden: &[Condition]) -> Result<(), String> {
+  let expectations: Vec<(&Condition, bool)> = allowed.iter().map(|condition| (condition, true))
+    .chain(forbidden.iter().map(|condition| (condition, false)))
+    .collect();
+  // Whether each condition holds in some final state, found by replaying the witnesses of every
+  // instance of the program in that instance.
+  let mut reached = vec![false; expectations.len()];
+  let mut exploration = Exploration::new();
+  for instance in program.instances()? {
+    let explored = Explorer::new(memory_model, &instance, max_steps).run();
+    for outcome in explored.outcomes.iter().filter(|(outcome, _)| !outcome.starts_with("error: ")).map(|(_, outcome)| outcome) {
+      let schedule: Vec<usize> = outcome.witness.iter().map(|node| node.id).collect();
+      let model = explore::replay(memory_model, &instance, &schedule).map_err(|err| format!("a witness schedule fails on replay: {}", err))?;
+      for ((condition, _), reached) in expectations.iter().zip(reached.iter_mut()) {
+        *reached |= condition.holds(model.as_ref());
+      }
+    }
+    let prefix = match program.has_ranges() {
+      true => format!("[{}] ", instance.describe_initial(program)),
+      false => String::new()
+    };
+    exploration.merge(explored, &prefix);
+  }
+  let mut failures: Vec<String> = exploration.outcomes.keys()
+    .filter(|outcome| outcome.contains("error: "))
+    .map(|outcome| format!("an execution fails: {}", outcome))
+    .collect();
+  if exploration.truncated > 0 {
+    failures.push(format!("{} executions are longer than {} steps", exploration.truncated, max_steps));
+  }
+  for ((condition, expected), reached) in expectations.iter().zip(reached) {
+    if reached != *expected {
+      failures.push(format!("{:?} is {} under {:?}", condition, if *expected { "never reached" } else { "reached" }, memory_model));
+    }
+  }
+  if failures.is_empty() {
+    return Ok(());
+  }
+  let outcomes: Vec<String> = exploration.outcomes.keys().map(|outcome| format!("| {}", outcome)).collect();
+  Err(format!("{}\noutcomes:\n{}", failures.join("\n"), outcomes.join("\n")))
+}
+
+// Explores a program exhaustively and panics unless every `allowed` condition holds in some final
+// state and no `forbidden` one in any:
+//   assert_outcomes!(source, MemoryModelType::TSO, allowed = ["0:r == 0 && 1:r == 0"], forbidden = []);
+#[macro_export]
+macro_rules! assert_outcomes {
+  ($source:expr, $model:expr, allowed = [$($allowed:expr),* $(,)?], forbidden = [$($forbidden:expr),* $(,)?] $(,)?) => {
+    if let Err(err) = $crate::testing::check($source, &$model, &[$($allowed),*], &[$($forbidden),*]) {
+      panic!("{}", err);
+    }
+  };
+  ($source:expr, $model:expr, allowed = [$($allowed:expr),* $(,)?] $(,)?) => {
+    $crate::assert_outcomes!($source, $model, allowed = [$($allowed),*], forbidden = [])
+  };
+  ($source:expr, $model:expr, forbidden = [$($forbidden:expr),* $(,)?] $(,)?) => {
+    $crate::assert_outcomes!($source, $model, allowed = [], forbidden = [$($forbidden),*])
+  };
+}
@@ -1,25 +1,129 @@
 use std::collections::{HashMap, HashSet};
 use core::fmt::Debug;
-use crate::{graph::{Node, Graph}, instruction::{LabeledInstruction, self}};
+use smallvec::SmallVec;
+use crate::{graph::{Node, Graph, EdgeKind}, instruction::{LabeledInstruction, self}, shared::Shared};
 
-pub trait ThreadSystem {
+pub trait ThreadSystem: Send + Sync {
   fn get_possible_executions(&self) -> Vec<Node>;
+  // The nodes that the threads can run next as far as their own order goes, before the model
+  // checks locks and parked threads.
+  fn pending_nodes(&self) -> Vec<Node> {
+    self.get_possible_executions()
+  }
   fn assign_register(&mut self, thread_id: usize, register: String, value: i32);
   fn get_register(&self, thread_id: usize, register: String) -> i32;
   fn remove_node(&mut self, node: &Node);
-  fn goto(&mut self, label: String);
+  fn jump(&mut self, branch: &Node);
+  fn append_instruction(&mut self, thread_id: usize, instruction: LabeledInstruction) -> usize;
+  fn number_of_threads(&self) -> usize;
+  fn registers(&self, thread_id: usize) -> &HashMap<String, i32>;
+  fn set_thread_names(&mut self, thread_names: Vec<String>);
+  fn thread_name(&self, thread_id: usize) -> &str;
+  fn graph(&self) -> &Graph;
+  // Lets new propagate nodes reuse the ids of executed ones.
+  fn set_recycling(&mut self);
+  fn add_propagate_node(&mut self, thread_id: usize, address: i32, value: i32);
+  fn rollback(&mut self, _branch: usize) {}
+  // Replaces all of the thread's registers, as when a transaction aborts.
+  fn set_registers(&mut self, thread_id: usize, registers: HashMap<String, i32>);
+  // Makes the thread run again from the instruction after `from`.
+  fn restart(&mut self, from: &Node);
+}
+
+// What a thread system keeps per thread while appending its instructions, besides their ids.
+trait Segment: Default + Send {
+  // The thread's nodes moved up by `offset` when its graph segment was joined.
+  fn shift(&mut self, offset: usize);
+}
+
+impl Segment for () {
+  fn shift(&mut self, _offset: usize) {}
+}
+
+// Programs with at least this many instructions build their graph on several cores.
+const PARALLEL_INSTRUCTIONS: usize = 4096;
+
+// Appends every thread's instructions with `append`, which gets the ids of the thread's earlier
+// instructions. No edge crosses threads yet, so large programs split the threads into one run of
+// consecutive threads per core, build their graph segments side by side and join them, giving every
+// node the id appending the threads in order would have.
+fn build_graph<S: Segment>(instructions: Vec<Vec<LabeledInstruction>>, append: impl Fn(&mut Graph, &mut S, &[usize], usize, LabeledInstruction) -> usize + Sync) -> (Graph, Vec<Vec<usize>>, Vec<S>) {
+  let build = |graph: &mut Graph, first: usize, threads: Vec<Vec<LabeledInstruction>>| -> Vec<(Vec<usize>, S)> {
+    threads.into_iter().enumerate().map(|(index, instructions)| {
+      let mut ids = Vec::new();
+      let mut segment = S::default();
+      for instruction in instructions {
+        let id = append(graph, &mut segment, &ids, first + index, instruction);
+        ids.push(id);
+      }
+      (ids, segment)
+    }).collect()
+  };
+  let total: usize = instructions.iter().map(Vec::len).sum();
+  let cores = std::thread::available_parallelism().map_or(1, |cores| cores.get());
+  if total < PARALLEL_INSTRUCTIONS || cores == 1 || instructions.len() == 1 {
+    let mut graph = Graph::new();
+    let (ids, segments) = build(&mut graph, 0, instructions).into_iter().unzip();
+    graph.validate();
+    return (graph, ids, segments);
+  }
+  let mut runs: Vec<(usize, Vec<Vec<LabeledInstruction>>)> = Vec::new();
+  let mut size = total;
+  for (thread_id, thread) in instructions.into_iter().enumerate() {
+    if size >= total.div_ceil(cores) {
+      runs.push((thread_id, Vec::new()));
+      size = 0;
+    }
+    size += thread.len();
+    runs.last_mut().unwrap().1.push(thread);
+  }
+  let built: Vec<_> = std::thread::scope(|scope| {
+    let build = &build;
+    let handles: Vec<_> = runs.into_iter().map(|(first, threads)| scope.spawn(move || {
+      let mut graph = Graph::new();
+      let threads = build(&mut graph, first, threads);
+      (graph, threads)
+    })).collect();
+    handles.into_iter().map(|handle| handle.join().expect("building a graph segment does not panic")).collect()
+  });
+  let mut offset = 0;
+  let mut ids = Vec::new();
+  let mut segments = Vec::new();
+  let mut graphs = Vec::new();
+  for (graph, threads) in built {
+    for (thread_ids, mut segment) in threads {
+      ids.push(thread_ids.into_iter().map(|id| id + offset).collect());
+      segment.shift(offset);
+      segments.push(segment);
+    }
+    offset += graph.instructions.len();
+    graphs.push(graph);
+  }
+  (Graph::join(graphs), ids, segments)
 }
 
+// Every instruction waits for all earlier ones of its thread.
+fn append_in_order(graph: &mut Graph, _: &mut (), earlier: &[usize], thread_id: usize, instruction: LabeledInstruction) -> usize {
+  let id = graph.add_node(thread_id, instruction);
+  for previous_instruction in earlier {
+    graph.add_edge(id, *previous_instruction, EdgeKind::ProgramOrder);
+  }
+  id
+}
+
+#[derive(Clone)]
 pub struct SCThreadSystem {
   graph: Graph,
-  registers: Vec<HashMap<String, i32>>
+  registers: Vec<Shared<HashMap<String, i32>>>,
+  thread_instructions: Vec<Shared<Vec<usize>>>,
+  thread_names: Vec<String>
 }
 
 impl Debug for SCThreadSystem {
   fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-    write!(f, "# REGISTERS\n")?;
+    writeln!(f, "# REGISTERS")?;
     for (i, register) in self.registers.iter().enumerate() {
-      write!(f, "| Thread {}: {:?}\n", i, register)?;
+      writeln!(f, "| Thread {}: {:?}", self.thread_names[i], register)?;
     }
     Ok(())
   }
@@ -27,31 +131,26 @@ impl Debug for SCThreadSystem {
 
 impl SCThreadSystem {
   pub fn new(instructions: Vec<Vec<LabeledInstruction>>) -> SCThreadSystem {
-    let mut graph = Graph::new();
-    let mut registers = Vec::new();
-    for _ in 0..instructions.len() {
-      registers.push(HashMap::new());
-    }
-    for (thread_id, thread_instructions) in instructions.iter().enumerate() {
-      let mut instruction_ids: Vec<usize> = Vec::new();
-      for instruction in thread_instructions.iter() {
-        let id = graph.add_node(thread_id as usize, instruction.clone());
-        for previous_instruction in instruction_ids.iter() {
-          graph.add_edge(id, *previous_instruction);
-        }
-        instruction_ids.push(id);
-      }
-    }
-    SCThreadSystem {
-      graph,
-      registers
+    let mut thread_system = SCThreadSystem {
+      graph: Graph::new(),
+      registers: Vec::new(),
+      thread_instructions: Vec::new(),
+      thread_names: Vec::new()
+    };
+    for thread_id in 0..instructions.len() {
+      thread_system.registers.push(Shared::default());
+      thread_system.thread_names.push(thread_id.to_string());
     }
+    let (graph, thread_instructions, _) = build_graph(instructions, append_in_order);
+    thread_system.graph = graph;
+    thread_system.thread_instructions = thread_instructions.into_iter().map(Shared::new).collect();
+    thread_system
   }
 }
 
 impl ThreadSystem for SCThreadSystem {
     fn get_possible_executions(&self) -> Vec<Node> {
-      self.graph.execution_candidates.iter().map(|id| self.graph.instructions[*id].clone()).collect()
+      self.graph.execution_candidates.iter().map(|id| self.graph.instructions[id].clone()).collect()
     }
 
     fn assign_register(&mut self, thread_id: usize, register: String, value: i32) {
@@ -65,31 +164,321 @@ impl ThreadSystem for SCThreadSystem {
       }
     }
 
+    fn number_of_threads(&self) -> usize {
+      self.registers.len()
+    }
+
+    fn registers(&self, thread_id: usize) -> &HashMap<String, i32> {
+      &self.registers[thread_id]
+    }
+
+    fn set_thread_names(&mut self, thread_names: Vec<String>) {
+      self.thread_names = thread_names;
+    }
+
+    fn thread_name(&self, thread_id: usize) -> &str {
+      &self.thread_names[thread_id]
+    }
+
+    fn graph(&self) -> &Graph {
+      &self.graph
+    }
+
+    fn set_recycling(&mut self) {
+      self.graph.set_recycling();
+    }
+
     fn remove_node(&mut self, node: &Node) {
       self.graph.remove_node(node.id);
     }
 
-    fn goto(&mut self, label: String) {
-      if !self.graph.is_label_active(label.clone()) {
-        let mut current_label: Option<String> = None;
-        while current_label != Some(label.clone()) {
-          current_label = self.graph.restore_node().clone();
-        }
-      }
+    fn jump(&mut self, branch: &Node) {
+      self.graph.jump(&self.thread_instructions[branch.thread_id], branch.id);
     }
+
+    fn set_registers(&mut self, thread_id: usize, registers: HashMap<String, i32>) {
+      self.registers[thread_id] = registers.into();
+    }
+
+    fn restart(&mut self, from: &Node) {
+      self.graph.restart(&self.thread_instructions[from.thread_id], from.id);
+    }
+
+    fn append_instruction(&mut self, thread_id: usize, instruction: LabeledInstruction) -> usize {
+      let id = append_in_order(&mut self.graph, &mut (), &self.thread_instructions[thread_id], thread_id, instruction);
+      self.thread_instructions[thread_id].push(id);
+      id
+    }
+
+    fn add_propagate_node(&mut self, _thread_id: usize, _address: i32, _value: i32) {}
+}
+
+fn reads_memory(instruction: &instruction::Instruction) -> bool {
+  matches!(instruction, instruction::Instruction::Load { mode: _, address: _, r: _ }
+    | instruction::Instruction::LoadLinked { address: _, r: _ }
+    | instruction::Instruction::Cas { mode: _, failure: _, address: _, to: _, exp: _, des: _, flag: _ }
+    | instruction::Instruction::Fai { op: _, mode: _, address: _, to: _, inc: _ })
+}
+
+fn writes_memory(instruction: &instruction::Instruction) -> bool {
+  matches!(instruction, instruction::Instruction::Store { mode: _, address: _, r: _ }
+    | instruction::Instruction::StoreConditional { address: _, r: _, flag: _ }
+    | instruction::Instruction::Cas { mode: _, failure: _, address: _, to: _, exp: _, des: _, flag: _ }
+    | instruction::Instruction::Fai { op: _, mode: _, address: _, to: _, inc: _ })
+}
+
+// The instructions a fence keeps on their side of it: REL orders stores, ACQ loads, SEQ_CST everything.
+// A hardware fence orders the accesses of the class on that side.
+fn fenced(mode: instruction::Mode, order: Option<instruction::Accesses>, other: &instruction::Instruction) -> bool {
+  if let Some(accesses) = order {
+    return (accesses.loads() && reads_memory(other)) || (accesses.stores() && writes_memory(other));
+  }
+  match mode {
+    instruction::Mode::SeqCst => true,
+    instruction::Mode::RelAcq => reads_memory(other) || writes_memory(other),
+    instruction::Mode::Rel => writes_memory(other),
+    instruction::Mode::Acq => reads_memory(other),
+    instruction::Mode::Rlx | instruction::Mode::NA => false
+  }
+}
+
+// Accesses, locks and the like that acquire or release; a fence is ordered by its mode instead.
+fn is_acquire(instruction: &LabeledInstruction) -> bool {
+  !matches!(instruction.instruction, instruction::Instruction::Fence { mode: _, order: _ }) && matches!(instruction.get_mode(), Some(instruction::Mode::Acq | instruction::Mode::RelAcq))
+}
+
+fn is_release(instruction: &LabeledInstruction) -> bool {
+  !matches!(instruction.instruction, instruction::Instruction::Fence { mode: _, order: _ }) && matches!(instruction.get_mode(), Some(instruction::Mode::Rel | instruction::Mode::RelAcq))
+}
+
+// Whether the next instruction reads or writes a register the previous one writes, or writes one it reads.
+fn dependent(previous: &instruction::Instruction, next: &instruction::Instruction) -> bool {
+  previous.write_registers().iter().any(|r| next.read_registers().contains(r) || next.write_registers().contains(r))
+    || next.write_registers().iter().any(|r| previous.read_registers().contains(r))
+}
+
+// An access that acquires or releases, SEQ_CST ones included; a fence is ordered by its mode instead.
+fn synchronizes(instruction: &LabeledInstruction) -> bool {
+  !matches!(instruction.instruction, instruction::Instruction::Fence { mode: _, order: _ }) && instruction.get_mode().is_some_and(|mode| mode.acquires() || mode.releases())
+}
+
+// Under TSO and PSO only acquires, releases, fences, jumps, stack operations and register
+// dependencies order a thread's instructions. A fence is ordered by its mode alone rather than acting as an acquire or release of
+// its own. With `races_checked` every synchronising access is a barrier, so that no access moves
+// out of the happens-before order the race checks of `NA` accesses assume.
+fn ordered_in_buffered(previous: &LabeledInstruction, next: &LabeledInstruction, races_checked: bool) -> Option<EdgeKind> {
+  if is_acquire(next) {
+    return Some(EdgeKind::Acquire);
+  }
+  if is_release(previous) {
+    return Some(EdgeKind::Release);
+  }
+  if races_checked && synchronizes(next) {
+    return Some(EdgeKind::Release);
+  }
+  if races_checked && synchronizes(previous) {
+    return Some(EdgeKind::Acquire);
+  }
+  if previous.instruction.is_jump() || next.instruction.is_jump() {
+    return Some(EdgeKind::Control);
+  }
+  if previous.instruction.uses_stack() && next.instruction.uses_stack() {
+    return Some(EdgeKind::Dependency);
+  }
+  // An `sc` must not run ahead of the `ll` that reserves its address, nor either of them past a `clrex`.
+  if previous.instruction.is_exclusive() && next.instruction.is_exclusive() {
+    return Some(EdgeKind::Dependency);
+  }
+  if dependent(&previous.instruction, &next.instruction) {
+    return Some(EdgeKind::Dependency);
+  }
+  match (&previous.instruction, &next.instruction) {
+    // A flush comes after the thread's earlier stores, and before the fence that completes it.
+    (_, instruction::Instruction::Flush { address: _ }) => writes_memory(&previous.instruction).then_some(EdgeKind::Release),
+    (instruction::Instruction::Flush { address: _ }, _) if next.instruction.completes_flushes() => Some(EdgeKind::Release),
+    (_, instruction::Instruction::Fence { mode, order }) => fenced(*mode, order.map(|(before, _)| before), &previous.instruction).then_some(EdgeKind::Release),
+    (instruction::Instruction::Fence { mode, order }, _) => fenced(*mode, order.map(|(_, after)| after), &next.instruction).then_some(EdgeKind::Acquire),
+    _ => None
+  }
+}
+
+// The instructions a new instruction of a thread is ordered against under TSO and PSO. Rather than
+// with every earlier instruction that `ordered_in_buffered` pairs it with, it is ordered with the
+// nearest ones that already wait for the rest, so long threads have about one edge per ordering:
+// a barrier (a jump or a REL_ACQ access) is ordered with everything on either side of it, an
+// acquire or SEQ_CST fence waits for everything before it, and everything after a release waits for
+// it. Other fences order more selectively. A fence waits for the previous fence of its kind, which
+// orders the same instructions, so only the latest fence of each kind is kept until the next barrier.
+// Likewise a flush waits for the latest fence completing flushes and the stores after it.
+#[derive(Clone, Default)]
+struct Orderings {
+  barrier: Option<usize>,
+  // The latest instruction that waits for everything before it, and the instructions after it.
+  covered: Option<usize>,
+  since: Vec<usize>,
+  release: Option<usize>,
+  // Stack and exclusive operations are ordered among themselves, so the latest of each is enough.
+  stack: Option<usize>,
+  exclusive: Option<usize>,
+  // The latest instruction writing each register, and the ones reading it since.
+  writers: HashMap<String, usize>,
+  readers: HashMap<String, Vec<usize>>,
+  // The instructions since the barrier, the latest fence of each kind among them with its position
+  // in `window`, and the latest fence completing flushes with the stores and releases since.
+  window: Vec<usize>,
+  fences: Vec<(usize, usize)>,
+  completing: Option<usize>,
+  writes: Vec<usize>
+}
+
+// The mode and order of a fence, which decide what it orders.
+fn fence_kind(instruction: &instruction::Instruction) -> Option<(instruction::Mode, Option<(instruction::Accesses, instruction::Accesses)>)> {
+  match instruction {
+    instruction::Instruction::Fence { mode, order } => Some((*mode, *order)),
+    _ => None
+  }
+}
+
+impl Orderings {
+  fn append(&mut self, graph: &mut Graph, id: usize, races_checked: bool) {
+    let next = &graph.instructions[id].instruction;
+    let barrier = next.instruction.is_jump() || (is_acquire(next) && is_release(next)) || (races_checked && synchronizes(next));
+    let waits_for_all = barrier || is_acquire(next) || matches!(next.instruction, instruction::Instruction::Fence { mode: instruction::Mode::SeqCst, order: None });
+    let kind = fence_kind(&next.instruction);
+    let fence = kind.is_some();
+    let flush = matches!(next.instruction, instruction::Instruction::Flush { address: _ });
+    // The previous fence of the same kind and the position in `window` after it.
+    let previous_fence = self.fences.iter().find(|(fence, _)| fence_kind(&graph.instructions[*fence].instruction.instruction) == kind).cloned();
+    let release = is_release(next);
+    let stack = next.instruction.uses_stack();
+    let exclusive = next.instruction.is_exclusive();
+    let mut candidates: Vec<usize> = if waits_for_all {
+      self.covered.into_iter().chain(self.since.iter().cloned()).collect()
+    } else if fence {
+      let start = previous_fence.map_or(0, |(_, position)| position + 1);
+      self.barrier.into_iter().chain(self.window[start..].iter().cloned()).collect()
+    } else if flush {
+      self.barrier.into_iter().chain(self.writes.iter().cloned()).collect()
+    } else {
+      self.barrier.into_iter().chain(self.release).chain(self.stack).chain(self.exclusive).chain(self.fences.iter().map(|(fence, _)| *fence)).collect()
+    };
+    for r in next.instruction.read_registers() {
+      candidates.extend(self.writers.get(r));
+    }
+    for r in next.instruction.write_registers() {
+      candidates.extend(self.writers.get(r));
+      candidates.extend(self.readers.get(r).into_iter().flatten());
+    }
+    candidates.sort();
+    candidates.dedup();
+    let edges: Vec<(usize, EdgeKind)> = candidates.into_iter()
+      .filter_map(|previous| ordered_in_buffered(&graph.instructions[previous].instruction, next, races_checked).map(|kind| (previous, kind)))
+      .collect();
+    for (previous, kind) in edges {
+      graph.add_edge(id, previous, kind);
+    }
+    let chained = if waits_for_all { None } else if fence { previous_fence.map(|(previous, _)| previous) } else if flush { self.completing } else { None };
+    if let Some(previous) = chained {
+      let kind = ordered_in_buffered(&graph.instructions[previous].instruction, &graph.instructions[id].instruction, races_checked);
+      graph.add_edge(id, previous, kind.unwrap_or(EdgeKind::ProgramOrder));
+    }
+    if barrier {
+      *self = Orderings { barrier: Some(id), covered: Some(id), ..Orderings::default() };
+      return;
+    }
+    if waits_for_all {
+      self.covered = Some(id);
+      self.since.clear();
+    } else {
+      self.since.push(id);
+    }
+    if release {
+      self.release = Some(id);
+    }
+    self.window.push(id);
+    if fence {
+      self.fences.retain(|(fence, _)| Some(*fence) != previous_fence.map(|(previous, _)| previous));
+      self.fences.push((id, self.window.len() - 1));
+    }
+    let next = &graph.instructions[id].instruction;
+    if next.instruction.completes_flushes() {
+      self.completing = Some(id);
+      self.writes.clear();
+    } else if writes_memory(&next.instruction) || release {
+      self.writes.push(id);
+    }
+    if stack {
+      self.stack = Some(id);
+    }
+    if exclusive {
+      self.exclusive = Some(id);
+    }
+    let next = &graph.instructions[id].instruction.instruction;
+    for r in next.read_registers() {
+      self.readers.entry(r.clone()).or_default().push(id);
+    }
+    for r in next.write_registers() {
+      self.writers.insert(r.clone(), id);
+      self.readers.remove(r);
+    }
+  }
+}
+
+fn append_buffered(graph: &mut Graph, orderings: &mut Orderings, thread_id: usize, instruction: LabeledInstruction, races_checked: bool) -> usize {
+  let id = graph.add_node(thread_id, instruction);
+  orderings.append(graph, id, races_checked);
+  id
+}
+
+// Races are checked in the programs with `NA` accesses.
+fn races_checked(instructions: &[Vec<LabeledInstruction>]) -> bool {
+  instructions.iter().flatten().any(|instruction| instruction.get_mode() == Some(instruction::Mode::NA))
+}
+
+impl Segment for Orderings {
+  fn shift(&mut self, offset: usize) {
+    for id in [&mut self.barrier, &mut self.covered, &mut self.release, &mut self.stack, &mut self.exclusive].into_iter().flatten() {
+      *id += offset;
+    }
+    for id in self.since.iter_mut().chain(self.window.iter_mut()).chain(self.completing.iter_mut()).chain(self.writes.iter_mut()).chain(self.fences.iter_mut().map(|(fence, _)| fence)) {
+      *id += offset;
+    }
+    for id in self.writers.values_mut().chain(self.readers.values_mut().flatten()) {
+      *id += offset;
+    }
+  }
 }
 
+// Whether a fence or lock waits for a store of the writer to leave the buffer: a releasing fence, a
+// hardware fence ordering stores, a flush, `txbegin` or `txcommit` drains its own thread's stores,
+// SEQ_CST fences, locks, semaphores and barriers every thread's.
+fn drains(fence: &Node, writer: usize) -> bool {
+  match fence.instruction.instruction {
+    instruction::Instruction::Fence { mode: _, order: Some((before, _)) } => before.stores() && fence.thread_id == writer,
+    instruction::Instruction::Fence { mode: instruction::Mode::Rel | instruction::Mode::RelAcq, order: _ } => fence.thread_id == writer,
+    instruction::Instruction::Fence { mode: instruction::Mode::Acq | instruction::Mode::Rlx, order: _ } => false,
+    instruction::Instruction::Flush { address: _ } => fence.thread_id == writer,
+    instruction::Instruction::TxBegin { r: _ } | instruction::Instruction::TxCommit => fence.thread_id == writer,
+    _ => true
+  }
+}
+
+#[derive(Clone)]
 pub struct TSOThreadSystem {
   graph: Graph,
-  registers: Vec<HashMap<String, i32>>,
-  propagate_nodes: Vec<HashSet<usize>>
+  registers: Vec<Shared<HashMap<String, i32>>>,
+  propagate_nodes: Vec<HashSet<usize>>,
+  thread_instructions: Vec<Shared<Vec<usize>>>,
+  orderings: Vec<Shared<Orderings>>,
+  thread_names: Vec<String>,
+  races_checked: bool
 }
 
 impl Debug for TSOThreadSystem {
   fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-    write!(f, "# REGISTERS\n")?;
+    writeln!(f, "# REGISTERS")?;
     for (i, register) in self.registers.iter().enumerate() {
-      write!(f, "| Thread {}: {:?}\n", i, register)?;
+      writeln!(f, "| Thread {}: {:?}", self.thread_names[i], register)?;
     }
     Ok(())
   }
@@ -97,71 +486,32 @@ impl Debug for TSOThreadSystem {
 
 impl TSOThreadSystem {
   pub fn new(instructions: Vec<Vec<LabeledInstruction>>) -> TSOThreadSystem {
-    let mut graph = Graph::new();
-    let mut registers = Vec::new();
-    let mut propagate_nodes = Vec::new();
-    for _ in 0..instructions.len() {
-      registers.push(HashMap::new());
-      propagate_nodes.push(HashSet::new());
-    }
-    for (thread_id, thread_instructions) in instructions.iter().enumerate() {
-      let mut instruction_ids: Vec<usize> = Vec::new();
-      for instruction in thread_instructions.iter() {
-        let id = graph.add_node(thread_id as usize, instruction.clone());
-        instruction_ids.push(id);
-      }
-      for (i, instruction) in thread_instructions.iter().enumerate() {
-        match instruction.get_mode() {
-          Some(instruction::Mode::Rel) => {
-            for j in i + 1..thread_instructions.len() {
-              graph.add_edge(instruction_ids[j], instruction_ids[i]);
-            }
-          }
-          Some(instruction::Mode::Acq) => {
-            for j in 0..i {
-              graph.add_edge(instruction_ids[i], instruction_ids[j]);
-            }
-          }
-          Some(instruction::Mode::RelAcq) => {
-            for j in 0..i {
-              graph.add_edge(instruction_ids[i], instruction_ids[j]);
-            }
-            for j in i + 1..thread_instructions.len() {
-              graph.add_edge(instruction_ids[j], instruction_ids[i]);
-            }
-          }
-          Some(instruction::Mode::SeqCst) => {}
-          Some(instruction::Mode::Rlx) => {}
-          None => {}
-        }
-      }
-    }
-    TSOThreadSystem {
-      graph,
-      registers,
-      propagate_nodes
-    }
-  }
-
-  pub fn add_propagate_node(&mut self, thread_id: usize, address: i32, value: i32) {
-    let id = self.graph.add_node(thread_id, LabeledInstruction {
-      label: None,
-      instruction: instruction::Instruction::Propagate { thread_id, address, value }
-    });
-    let active_fence_nodes = self.graph.active_fence_nodes.clone();
-    for node in active_fence_nodes {
-      self.graph.add_edge(node, id);
-    }
-    for node in self.propagate_nodes[thread_id].clone() {
-      self.graph.add_edge(id, node);
+    let mut thread_system = TSOThreadSystem {
+      graph: Graph::new(),
+      registers: Vec::new(),
+      propagate_nodes: Vec::new(),
+      thread_instructions: Vec::new(),
+      orderings: Vec::new(),
+      thread_names: Vec::new(),
+      races_checked: races_checked(&instructions)
+    };
+    for thread_id in 0..instructions.len() {
+      thread_system.registers.push(Shared::default());
+      thread_system.thread_names.push(thread_id.to_string());
+      thread_system.propagate_nodes.push(HashSet::new());
     }
-    self.propagate_nodes[thread_id].insert(id);
+    let races_checked = thread_system.races_checked;
+    let (graph, thread_instructions, orderings) = build_graph(instructions, |graph, orderings, _, thread_id, instruction| append_buffered(graph, orderings, thread_id, instruction, races_checked));
+    thread_system.graph = graph;
+    thread_system.thread_instructions = thread_instructions.into_iter().map(Shared::new).collect();
+    thread_system.orderings = orderings.into_iter().map(Shared::new).collect();
+    thread_system
   }
 }
 
 impl ThreadSystem for TSOThreadSystem {
     fn get_possible_executions(&self) -> Vec<Node> {
-      self.graph.execution_candidates.iter().map(|id| self.graph.instructions[*id].clone()).collect()
+      self.graph.execution_candidates.iter().map(|id| self.graph.instructions[id].clone()).collect()
     }
 
     fn assign_register(&mut self, thread_id: usize, register: String, value: i32) {
@@ -175,38 +525,94 @@ impl ThreadSystem for TSOThreadSystem {
       }
     }
 
+    fn number_of_threads(&self) -> usize {
+      self.registers.len()
+    }
+
+    fn registers(&self, thread_id: usize) -> &HashMap<String, i32> {
+      &self.registers[thread_id]
+    }
+
+    fn set_thread_names(&mut self, thread_names: Vec<String>) {
+      self.thread_names = thread_names;
+    }
+
+    fn thread_name(&self, thread_id: usize) -> &str {
+      &self.thread_names[thread_id]
+    }
+
+    fn graph(&self) -> &Graph {
+      &self.graph
+    }
+
+    fn set_recycling(&mut self) {
+      self.graph.set_recycling();
+    }
+
     fn remove_node(&mut self, node: &Node) {
-      match node.instruction.instruction {
-        instruction::Instruction::Propagate { thread_id: _, address: _, value: _ } => {
-          self.propagate_nodes[node.thread_id].remove(&node.id);
-        }
-        _ => {}
+      if let instruction::Instruction::Propagate { thread_id: _, address: _, value: _ } = node.instruction.instruction {
+        self.propagate_nodes[node.thread_id].remove(&node.id);
       }
       self.graph.remove_node(node.id);
     }
 
-    fn goto(&mut self, label: String) {
-      if !self.graph.is_label_active(label.clone()) {
-        let mut current_label: Option<String> = None;
-        while current_label != Some(label.clone()) {
-          current_label = self.graph.restore_node().clone();
-        }
+    fn jump(&mut self, branch: &Node) {
+      self.graph.jump(&self.thread_instructions[branch.thread_id], branch.id);
+    }
+
+    fn set_registers(&mut self, thread_id: usize, registers: HashMap<String, i32>) {
+      self.registers[thread_id] = registers.into();
+    }
+
+    fn restart(&mut self, from: &Node) {
+      self.graph.restart(&self.thread_instructions[from.thread_id], from.id);
+    }
+
+    fn append_instruction(&mut self, thread_id: usize, instruction: LabeledInstruction) -> usize {
+      let id = append_buffered(&mut self.graph, &mut self.orderings[thread_id], thread_id, instruction, self.races_checked);
+      self.thread_instructions[thread_id].push(id);
+      id
+    }
+
+    fn add_propagate_node(&mut self, thread_id: usize, address: i32, value: i32) {
+      let id = self.graph.add_node(thread_id, LabeledInstruction {
+        label: None,
+        instruction: instruction::Instruction::Propagate { thread_id, address, value },
+        line: None,
+        scope: None,
+        java: false
+      });
+      let fences: SmallVec<[usize; 4]> = self.graph.active_fence_nodes.iter()
+        .filter(|node| drains(&self.graph.instructions[**node], thread_id))
+        .cloned()
+        .collect();
+      for node in fences {
+        self.graph.add_edge(node, id, EdgeKind::Fence);
       }
+      for node in self.propagate_nodes[thread_id].iter() {
+        self.graph.add_edge(id, *node, EdgeKind::Buffer);
+      }
+      self.propagate_nodes[thread_id].insert(id);
     }
 }
 
 
+#[derive(Clone)]
 pub struct PSOThreadSystem {
   graph: Graph,
-  registers: Vec<HashMap<String, i32>>,
-  propagate_nodes: Vec<HashSet<(usize, i32)>>
+  registers: Vec<Shared<HashMap<String, i32>>>,
+  propagate_nodes: Vec<HashSet<(usize, i32)>>,
+  thread_instructions: Vec<Shared<Vec<usize>>>,
+  orderings: Vec<Shared<Orderings>>,
+  thread_names: Vec<String>,
+  races_checked: bool
 }
 
 impl Debug for PSOThreadSystem {
   fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-    write!(f, "# REGISTERS\n")?;
+    writeln!(f, "# REGISTERS")?;
     for (i, register) in self.registers.iter().enumerate() {
-      write!(f, "| Thread {}: {:?}\n", i, register)?;
+      writeln!(f, "| Thread {}: {:?}", self.thread_names[i], register)?;
     }
     Ok(())
   }
@@ -214,73 +620,497 @@ impl Debug for PSOThreadSystem {
 
 impl PSOThreadSystem {
   pub fn new(instructions: Vec<Vec<LabeledInstruction>>) -> PSOThreadSystem {
-    let mut graph = Graph::new();
-    let mut registers = Vec::new();
-    let mut propagate_nodes = Vec::new();
-    for _ in 0..instructions.len() {
-      registers.push(HashMap::new());
-      propagate_nodes.push(HashSet::new());
-    }
-    for (thread_id, thread_instructions) in instructions.iter().enumerate() {
-      let mut instruction_ids: Vec<usize> = Vec::new();
-      for instruction in thread_instructions.iter() {
-        let id = graph.add_node(thread_id as usize, instruction.clone());
-        instruction_ids.push(id);
+    let mut thread_system = PSOThreadSystem {
+      graph: Graph::new(),
+      registers: Vec::new(),
+      propagate_nodes: Vec::new(),
+      thread_instructions: Vec::new(),
+      orderings: Vec::new(),
+      thread_names: Vec::new(),
+      races_checked: races_checked(&instructions)
+    };
+    for thread_id in 0..instructions.len() {
+      thread_system.registers.push(Shared::default());
+      thread_system.thread_names.push(thread_id.to_string());
+      thread_system.propagate_nodes.push(HashSet::new());
+    }
+    let races_checked = thread_system.races_checked;
+    let (graph, thread_instructions, orderings) = build_graph(instructions, |graph, orderings, _, thread_id, instruction| append_buffered(graph, orderings, thread_id, instruction, races_checked));
+    thread_system.graph = graph;
+    thread_system.thread_instructions = thread_instructions.into_iter().map(Shared::new).collect();
+    thread_system.orderings = orderings.into_iter().map(Shared::new).collect();
+    thread_system
+  }
+}
+
+impl ThreadSystem for PSOThreadSystem {
+    fn get_possible_executions(&self) -> Vec<Node> {
+      self.graph.execution_candidates.iter().map(|id| self.graph.instructions[id].clone()).collect()
+    }
+
+    fn assign_register(&mut self, thread_id: usize, register: String, value: i32) {
+      self.registers[thread_id].insert(register, value);
+    }
+
+    fn get_register(&self, thread_id: usize, register: String) -> i32 {
+      match self.registers[thread_id].get(&register) {
+        Some(value) => *value,
+        None => 0
       }
-      for (i, instruction) in thread_instructions.iter().enumerate() {
-        match instruction.get_mode() {
-          Some(instruction::Mode::Rel) => {
-            for j in i + 1..thread_instructions.len() {
-              graph.add_edge(instruction_ids[j], instruction_ids[i]);
-            }
-          }
-          Some(instruction::Mode::Acq) => {
-            for j in 0..i {
-              graph.add_edge(instruction_ids[i], instruction_ids[j]);
-            }
-          }
-          Some(instruction::Mode::RelAcq) => {
-            for j in 0..i {
-              graph.add_edge(instruction_ids[i], instruction_ids[j]);
-            }
-            for j in i + 1..thread_instructions.len() {
-              graph.add_edge(instruction_ids[j], instruction_ids[i]);
-            }
-          }
-          Some(instruction::Mode::SeqCst) => {}
-          Some(instruction::Mode::Rlx) => {}
-          None => {}
-        }
+    }
+
+    fn number_of_threads(&self) -> usize {
+      self.registers.len()
+    }
+
+    fn registers(&self, thread_id: usize) -> &HashMap<String, i32> {
+      &self.registers[thread_id]
+    }
+
+    fn set_thread_names(&mut self, thread_names: Vec<String>) {
+      self.thread_names = thread_names;
+    }
+
+    fn thread_name(&self, thread_id: usize) -> &str {
+      &self.thread_names[thread_id]
+    }
+
+    fn graph(&self) -> &Graph {
+      &self.graph
+    }
+
+    fn set_recycling(&mut self) {
+      self.graph.set_recycling();
+    }
+
+    fn remove_node(&mut self, node: &Node) {
+      if let instruction::Instruction::Propagate { thread_id: _, address, value: _ } = node.instruction.instruction {
+        self.propagate_nodes[node.thread_id].remove(&(node.id, address));
+      }
+      self.graph.remove_node(node.id);
+    }
+
+    fn jump(&mut self, branch: &Node) {
+      self.graph.jump(&self.thread_instructions[branch.thread_id], branch.id);
+    }
+
+    fn set_registers(&mut self, thread_id: usize, registers: HashMap<String, i32>) {
+      self.registers[thread_id] = registers.into();
+    }
+
+    fn restart(&mut self, from: &Node) {
+      self.graph.restart(&self.thread_instructions[from.thread_id], from.id);
+    }
+
+    fn append_instruction(&mut self, thread_id: usize, instruction: LabeledInstruction) -> usize {
+      let id = append_buffered(&mut self.graph, &mut self.orderings[thread_id], thread_id, instruction, self.races_checked);
+      self.thread_instructions[thread_id].push(id);
+      id
+    }
+
+    fn add_propagate_node(&mut self, thread_id: usize, address: i32, value: i32) {
+      let id = self.graph.add_node(thread_id, LabeledInstruction {
+        label: None,
+        instruction: instruction::Instruction::Propagate { thread_id, address, value },
+        line: None,
+        scope: None,
+        java: false
+      });
+      let fences: SmallVec<[usize; 4]> = self.graph.active_fence_nodes.iter()
+        .filter(|node| drains(&self.graph.instructions[**node], thread_id))
+        .cloned()
+        .collect();
+      for node in fences {
+        self.graph.add_edge(node, id, EdgeKind::Fence);
+      }
+      for (node, _) in self.propagate_nodes[thread_id].iter().filter(|(_, add)| *add == address) {
+        self.graph.add_edge(id, *node, EdgeKind::Buffer);
+      }
+      self.propagate_nodes[thread_id].insert((id, address));
+    }
+}
+
+#[derive(Clone)]
+pub struct C11ThreadSystem {
+  graph: Graph,
+  registers: Vec<Shared<HashMap<String, i32>>>,
+  observations: HashMap<usize, (i32, usize)>,
+  messages: HashMap<i32, usize>,
+  thread_instructions: Vec<Shared<Vec<usize>>>,
+  thread_names: Vec<String>
+}
+
+impl Debug for C11ThreadSystem {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    writeln!(f, "# REGISTERS")?;
+    for (i, register) in self.registers.iter().enumerate() {
+      writeln!(f, "| Thread {}: {:?}", self.thread_names[i], register)?;
+    }
+    Ok(())
+  }
+}
+
+impl C11ThreadSystem {
+  pub fn new(instructions: Vec<Vec<LabeledInstruction>>) -> C11ThreadSystem {
+    let mut thread_system = C11ThreadSystem {
+      graph: Graph::new(),
+      registers: Vec::new(),
+      observations: HashMap::new(),
+      messages: HashMap::new(),
+      thread_instructions: Vec::new(),
+      thread_names: Vec::new()
+    };
+    for thread_id in 0..instructions.len() {
+      thread_system.registers.push(Shared::default());
+      thread_system.thread_names.push(thread_id.to_string());
+    }
+    let (graph, thread_instructions, _) = build_graph(instructions, append_in_order);
+    thread_system.graph = graph;
+    thread_system.thread_instructions = thread_instructions.into_iter().map(Shared::new).collect();
+    thread_system
+  }
+
+  // The address and timestamp of the message a propagate node lets its thread observe.
+  pub fn observation(&self, id: usize) -> Option<(i32, usize)> {
+    self.observations.get(&id).cloned()
+  }
+}
+
+impl ThreadSystem for C11ThreadSystem {
+    fn get_possible_executions(&self) -> Vec<Node> {
+      self.graph.execution_candidates.iter().map(|id| self.graph.instructions[id].clone()).collect()
+    }
+
+    fn assign_register(&mut self, thread_id: usize, register: String, value: i32) {
+      self.registers[thread_id].insert(register, value);
+    }
+
+    fn get_register(&self, thread_id: usize, register: String) -> i32 {
+      match self.registers[thread_id].get(&register) {
+        Some(value) => *value,
+        None => 0
       }
     }
-    PSOThreadSystem {
-      graph,
-      registers,
-      propagate_nodes
+
+    fn number_of_threads(&self) -> usize {
+      self.registers.len()
+    }
+
+    fn registers(&self, thread_id: usize) -> &HashMap<String, i32> {
+      &self.registers[thread_id]
+    }
+
+    fn set_thread_names(&mut self, thread_names: Vec<String>) {
+      self.thread_names = thread_names;
+    }
+
+    fn thread_name(&self, thread_id: usize) -> &str {
+      &self.thread_names[thread_id]
     }
+
+    fn graph(&self) -> &Graph {
+      &self.graph
+    }
+
+    fn set_recycling(&mut self) {
+      self.graph.set_recycling();
+    }
+
+    fn remove_node(&mut self, node: &Node) {
+      self.observations.remove(&node.id);
+      self.graph.remove_node(node.id);
+    }
+
+    fn jump(&mut self, branch: &Node) {
+      self.graph.jump(&self.thread_instructions[branch.thread_id], branch.id);
+    }
+
+    fn set_registers(&mut self, thread_id: usize, registers: HashMap<String, i32>) {
+      self.registers[thread_id] = registers.into();
+    }
+
+    fn restart(&mut self, from: &Node) {
+      self.graph.restart(&self.thread_instructions[from.thread_id], from.id);
+    }
+
+    fn append_instruction(&mut self, thread_id: usize, instruction: LabeledInstruction) -> usize {
+      let id = append_in_order(&mut self.graph, &mut (), &self.thread_instructions[thread_id], thread_id, instruction);
+      self.thread_instructions[thread_id].push(id);
+      id
+    }
+
+    // Every other thread gets a node observing the new message.
+    fn add_propagate_node(&mut self, thread_id: usize, address: i32, value: i32) {
+      let timestamp = self.messages.entry(address).or_insert(0);
+      *timestamp += 1;
+      let timestamp = *timestamp;
+      for observer in (0..self.registers.len()).filter(|observer| *observer != thread_id) {
+        let id = self.graph.add_node(observer, LabeledInstruction {
+          label: None,
+          instruction: instruction::Instruction::Propagate { thread_id: observer, address, value },
+          line: None,
+          scope: None,
+          java: false
+        });
+        self.observations.insert(id, (address, timestamp));
+      }
+    }
+}
+
+#[derive(Clone)]
+pub struct ItaniumThreadSystem {
+  graph: Graph,
+  registers: Vec<Shared<HashMap<String, i32>>>,
+  speculative: bool,
+  races_checked: bool,
+  undo: HashMap<usize, Vec<(String, Option<i32>)>>,
+  thread_instructions: Vec<Shared<Vec<usize>>>,
+  thread_names: Vec<String>
+}
+
+impl Debug for ItaniumThreadSystem {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    writeln!(f, "# REGISTERS")?;
+    for (i, register) in self.registers.iter().enumerate() {
+      writeln!(f, "| Thread {}: {:?}", self.thread_names[i], register)?;
+    }
+    Ok(())
   }
+}
 
-  pub fn add_propagate_node(&mut self, thread_id: usize, address: i32, value: i32) {
-    let id = self.graph.add_node(thread_id, LabeledInstruction {
-      label: None,
-      instruction: instruction::Instruction::Propagate { thread_id, address, value }
+fn ordered_in_itanium(previous: &instruction::Instruction, next: &instruction::Instruction, previous_mode: Option<instruction::Mode>, next_mode: Option<instruction::Mode>) -> Option<EdgeKind> {
+  let acquire = matches!(previous_mode, Some(instruction::Mode::Acq | instruction::Mode::RelAcq | instruction::Mode::SeqCst));
+  let release = matches!(next_mode, Some(instruction::Mode::Rel | instruction::Mode::RelAcq | instruction::Mode::SeqCst));
+  let jump = previous.is_jump() || next.is_jump();
+  let dependent = dependent(previous, next) || (previous.uses_stack() && next.uses_stack());
+  if acquire {
+    Some(EdgeKind::Acquire)
+  } else if release {
+    Some(EdgeKind::Release)
+  } else if jump {
+    Some(EdgeKind::Control)
+  } else if dependent {
+    Some(EdgeKind::Dependency)
+  } else {
+    None
+  }
+}
+
+// Loads and constants may run ahead of an earlier branch unless they overwrite its register. With
+// `races_checked` loads wait, since a load rolled back with its branch would still count as racing.
+fn speculates_past(branch: &instruction::Instruction, next: &instruction::Instruction, next_mode: Option<instruction::Mode>, races_checked: bool) -> bool {
+  matches!(branch, instruction::Instruction::Cond { r: _, target: _ })
+    && (matches!(next, instruction::Instruction::Const { r: _, value: _ }) || (matches!(next, instruction::Instruction::Load { mode: _, address: _, r: _ }) && !races_checked))
+    && !matches!(next_mode, Some(instruction::Mode::Rel | instruction::Mode::RelAcq | instruction::Mode::SeqCst))
+    && !next.write_registers().iter().any(|r| branch.read_registers().contains(r))
+}
+
+// ld.acq orders the instructions after it, st.rel the ones before it and mf both; otherwise
+// only register dependencies and jumps keep program order.
+fn append_in_itanium(graph: &mut Graph, earlier: &[usize], speculative: bool, races_checked: bool, thread_id: usize, instruction: LabeledInstruction) -> usize {
+  let mode = instruction.get_mode();
+  let next = instruction.instruction.clone();
+  let id = graph.add_node(thread_id, instruction);
+  for previous_instruction in earlier {
+    let previous = &graph.instructions[*previous_instruction].instruction;
+    if speculative && speculates_past(&previous.instruction, &next, mode, races_checked) {
+      graph.add_edge(id, *previous_instruction, EdgeKind::Speculation);
+    } else if let Some(kind) = ordered_in_itanium(&previous.instruction, &next, previous.get_mode(), mode) {
+      graph.add_edge(id, *previous_instruction, kind);
+    }
+  }
+  id
+}
+
+impl ItaniumThreadSystem {
+  pub fn new(instructions: Vec<Vec<LabeledInstruction>>, speculative: bool) -> ItaniumThreadSystem {
+    let mut thread_system = ItaniumThreadSystem {
+      graph: Graph::new(),
+      registers: Vec::new(),
+      speculative,
+      races_checked: races_checked(&instructions),
+      undo: HashMap::new(),
+      thread_instructions: Vec::new(),
+      thread_names: Vec::new()
+    };
+    for thread_id in 0..instructions.len() {
+      thread_system.registers.push(Shared::default());
+      thread_system.thread_names.push(thread_id.to_string());
+    }
+    let races_checked = thread_system.races_checked;
+    let (graph, thread_instructions, _) = build_graph(instructions, |graph, _: &mut (), earlier, thread_id, instruction| {
+      append_in_itanium(graph, earlier, speculative, races_checked, thread_id, instruction)
     });
-    let active_fence_nodes = self.graph.active_fence_nodes.clone();
-    for node in active_fence_nodes {
-      self.graph.add_edge(node, id);
+    thread_system.graph = graph;
+    thread_system.thread_instructions = thread_instructions.into_iter().map(Shared::new).collect();
+    thread_system
+  }
+
+  // The address a memory access uses and whether it writes, once its address register is final.
+  fn access(&self, id: usize, earlier: &[usize]) -> Option<Option<(i32, bool)>> {
+    let node = &self.graph.instructions[id];
+    let (address, write) = match &node.instruction.instruction {
+      instruction::Instruction::Load { mode: _, address, r: _ } | instruction::Instruction::LoadLinked { address, r: _ } => (address, false),
+      instruction::Instruction::Store { mode: _, address, r: _ }
+      | instruction::Instruction::StoreConditional { address, r: _, flag: _ }
+      | instruction::Instruction::Cas { mode: _, failure: _, address, to: _, exp: _, des: _, flag: _ }
+      | instruction::Instruction::Fai { op: _, mode: _, address, to: _, inc: _ } => (address, true),
+      _ => return None
+    };
+    let pending = earlier.iter()
+      .any(|previous| self.graph.is_node_active(*previous) && self.graph.instructions[*previous].instruction.instruction.write_registers().contains(&address));
+    if pending {
+      return Some(None);
+    }
+    Some(Some((self.get_register(node.thread_id, address.clone()), write)))
+  }
+
+  // Accesses to the same address stay in program order unless both are loads; an access whose
+  // address is not known yet may alias anything.
+  fn waits_for_address(&self, id: usize) -> bool {
+    let thread_instructions = &self.thread_instructions[self.graph.instructions[id].thread_id];
+    let position = thread_instructions.iter().position(|other| *other == id).unwrap();
+    let (address, write) = match self.access(id, &thread_instructions[..position]) {
+      Some(Some(access)) => access,
+      _ => return false
+    };
+    (0..position).any(|i| {
+      let previous = thread_instructions[i];
+      self.graph.is_node_active(previous) && match self.access(previous, &thread_instructions[..i]) {
+        Some(Some((previous_address, previous_write))) => previous_address == address && (write || previous_write),
+        Some(None) => true,
+        None => false
+      }
+    })
+  }
+}
+
+impl ThreadSystem for ItaniumThreadSystem {
+    fn get_possible_executions(&self) -> Vec<Node> {
+      self.graph.execution_candidates.iter().filter(|id| !self.waits_for_address(*id)).map(|id| self.graph.instructions[id].clone()).collect()
+    }
+
+    fn assign_register(&mut self, thread_id: usize, register: String, value: i32) {
+      self.registers[thread_id].insert(register, value);
+    }
+
+    fn get_register(&self, thread_id: usize, register: String) -> i32 {
+      match self.registers[thread_id].get(&register) {
+        Some(value) => *value,
+        None => 0
+      }
     }
-    for (node, add) in self.propagate_nodes[thread_id].clone() {
-      if address == add {
-        self.graph.add_edge(id, node);
+
+    fn number_of_threads(&self) -> usize {
+      self.registers.len()
+    }
+
+    fn registers(&self, thread_id: usize) -> &HashMap<String, i32> {
+      &self.registers[thread_id]
+    }
+
+    fn set_thread_names(&mut self, thread_names: Vec<String>) {
+      self.thread_names = thread_names;
+    }
+
+    fn thread_name(&self, thread_id: usize) -> &str {
+      &self.thread_names[thread_id]
+    }
+
+    fn graph(&self) -> &Graph {
+      &self.graph
+    }
+
+    fn set_recycling(&mut self) {
+      self.graph.set_recycling();
+    }
+
+    fn remove_node(&mut self, node: &Node) {
+      if self.graph.is_speculative(node.id) {
+        let registers = &self.registers[node.thread_id];
+        let saved = node.instruction.instruction.write_registers().into_iter().map(|r| (r.clone(), registers.get(r).cloned())).collect();
+        self.undo.insert(node.id, saved);
       }
+      self.graph.remove_node(node.id);
+    }
+
+    fn jump(&mut self, branch: &Node) {
+      self.graph.jump(&self.thread_instructions[branch.thread_id], branch.id);
+    }
+
+    fn set_registers(&mut self, thread_id: usize, registers: HashMap<String, i32>) {
+      self.registers[thread_id] = registers.into();
+    }
+
+    fn restart(&mut self, from: &Node) {
+      self.graph.restart(&self.thread_instructions[from.thread_id], from.id);
     }
-    self.propagate_nodes[thread_id].insert((id, address));
+
+    fn append_instruction(&mut self, thread_id: usize, instruction: LabeledInstruction) -> usize {
+      let id = append_in_itanium(&mut self.graph, &self.thread_instructions[thread_id], self.speculative, self.races_checked, thread_id, instruction);
+      self.thread_instructions[thread_id].push(id);
+      id
+    }
+
+    fn add_propagate_node(&mut self, _thread_id: usize, _address: i32, _value: i32) {}
+
+    // A jump discards the work done ahead of the branch, restoring the registers it overwrote.
+    fn rollback(&mut self, branch: usize) {
+      for id in self.graph.speculated(branch) {
+        let thread_id = self.graph.instructions[id].thread_id;
+        for (r, value) in self.undo.remove(&id).unwrap_or_default() {
+          match value {
+            Some(value) => self.registers[thread_id].insert(r, value),
+            None => self.registers[thread_id].remove(&r)
+          };
+        }
+        self.graph.rollback_node(id);
+      }
+    }
+}
+
+#[derive(Clone)]
+pub struct PRAMThreadSystem {
+  graph: Graph,
+  registers: Vec<Shared<HashMap<String, i32>>>,
+  channels: HashMap<(usize, usize), HashSet<usize>>,
+  thread_instructions: Vec<Shared<Vec<usize>>>,
+  thread_names: Vec<String>
+}
+
+impl Debug for PRAMThreadSystem {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    writeln!(f, "# REGISTERS")?;
+    for (i, register) in self.registers.iter().enumerate() {
+      writeln!(f, "| Thread {}: {:?}", self.thread_names[i], register)?;
+    }
+    Ok(())
   }
 }
 
-impl ThreadSystem for PSOThreadSystem {
+impl PRAMThreadSystem {
+  pub fn new(instructions: Vec<Vec<LabeledInstruction>>) -> PRAMThreadSystem {
+    let mut thread_system = PRAMThreadSystem {
+      graph: Graph::new(),
+      registers: Vec::new(),
+      channels: HashMap::new(),
+      thread_instructions: Vec::new(),
+      thread_names: Vec::new()
+    };
+    for thread_id in 0..instructions.len() {
+      thread_system.registers.push(Shared::default());
+      thread_system.thread_names.push(thread_id.to_string());
+    }
+    let (graph, thread_instructions, _) = build_graph(instructions, append_in_order);
+    thread_system.graph = graph;
+    thread_system.thread_instructions = thread_instructions.into_iter().map(Shared::new).collect();
+    thread_system
+  }
+}
+
+impl ThreadSystem for PRAMThreadSystem {
     fn get_possible_executions(&self) -> Vec<Node> {
-      self.graph.execution_candidates.iter().map(|id| self.graph.instructions[*id].clone()).collect()
+      self.graph.execution_candidates.iter().map(|id| self.graph.instructions[id].clone()).collect()
     }
 
     fn assign_register(&mut self, thread_id: usize, register: String, value: i32) {
@@ -294,22 +1124,206 @@ impl ThreadSystem for PSOThreadSystem {
       }
     }
 
+    fn number_of_threads(&self) -> usize {
+      self.registers.len()
+    }
+
+    fn registers(&self, thread_id: usize) -> &HashMap<String, i32> {
+      &self.registers[thread_id]
+    }
+
+    fn set_thread_names(&mut self, thread_names: Vec<String>) {
+      self.thread_names = thread_names;
+    }
+
+    fn thread_name(&self, thread_id: usize) -> &str {
+      &self.thread_names[thread_id]
+    }
+
+    fn graph(&self) -> &Graph {
+      &self.graph
+    }
+
+    fn set_recycling(&mut self) {
+      self.graph.set_recycling();
+    }
+
     fn remove_node(&mut self, node: &Node) {
-      match node.instruction.instruction {
-        instruction::Instruction::Propagate { thread_id: _, address, value: _ } => {
-          self.propagate_nodes[node.thread_id].remove(&(node.id, address));
+      if let instruction::Instruction::Propagate { thread_id, address: _, value: _ } = node.instruction.instruction {
+        if let Some(channel) = self.channels.get_mut(&(thread_id, node.thread_id)) {
+          channel.remove(&node.id);
         }
-        _ => {}
       }
       self.graph.remove_node(node.id);
     }
 
-    fn goto(&mut self, label: String) {
-      if !self.graph.is_label_active(label.clone()) {
-        let mut current_label: Option<String> = None;
-        while current_label != Some(label.clone()) {
-          current_label = self.graph.restore_node().clone();
+    fn jump(&mut self, branch: &Node) {
+      self.graph.jump(&self.thread_instructions[branch.thread_id], branch.id);
+    }
+
+    fn set_registers(&mut self, thread_id: usize, registers: HashMap<String, i32>) {
+      self.registers[thread_id] = registers.into();
+    }
+
+    fn restart(&mut self, from: &Node) {
+      self.graph.restart(&self.thread_instructions[from.thread_id], from.id);
+    }
+
+    fn append_instruction(&mut self, thread_id: usize, instruction: LabeledInstruction) -> usize {
+      let id = append_in_order(&mut self.graph, &mut (), &self.thread_instructions[thread_id], thread_id, instruction);
+      self.thread_instructions[thread_id].push(id);
+      id
+    }
+
+    // One delivery per other thread, in order with the earlier deliveries between the same pair;
+    // the writer's pending fences wait for all of them.
+    fn add_propagate_node(&mut self, thread_id: usize, address: i32, value: i32) {
+      for reader in (0..self.registers.len()).filter(|reader| *reader != thread_id) {
+        let id = self.graph.add_node(reader, LabeledInstruction {
+          label: None,
+          instruction: instruction::Instruction::Propagate { thread_id, address, value },
+          line: None,
+          scope: None,
+          java: false
+        });
+        let fences: SmallVec<[usize; 4]> = self.graph.active_fence_nodes.iter()
+          .filter(|node| self.graph.instructions[**node].thread_id == thread_id)
+          .cloned()
+          .collect();
+        for node in fences {
+          self.graph.add_edge(node, id, EdgeKind::Fence);
         }
+        let channel = self.channels.entry((thread_id, reader)).or_default();
+        for node in channel.iter() {
+          self.graph.add_edge(id, *node, EdgeKind::Buffer);
+        }
+        channel.insert(id);
+      }
+    }
+}
+
+#[derive(Clone)]
+pub struct NMCAThreadSystem {
+  graph: Graph,
+  registers: Vec<Shared<HashMap<String, i32>>>,
+  deliveries: HashMap<usize, (i32, usize)>,
+  stores: HashMap<i32, usize>,
+  thread_instructions: Vec<Shared<Vec<usize>>>,
+  thread_names: Vec<String>
+}
+
+impl Debug for NMCAThreadSystem {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    writeln!(f, "# REGISTERS")?;
+    for (i, register) in self.registers.iter().enumerate() {
+      writeln!(f, "| Thread {}: {:?}", self.thread_names[i], register)?;
+    }
+    Ok(())
+  }
+}
+
+impl NMCAThreadSystem {
+  pub fn new(instructions: Vec<Vec<LabeledInstruction>>) -> NMCAThreadSystem {
+    let mut thread_system = NMCAThreadSystem {
+      graph: Graph::new(),
+      registers: Vec::new(),
+      deliveries: HashMap::new(),
+      stores: HashMap::new(),
+      thread_instructions: Vec::new(),
+      thread_names: Vec::new()
+    };
+    for thread_id in 0..instructions.len() {
+      thread_system.registers.push(Shared::default());
+      thread_system.thread_names.push(thread_id.to_string());
+    }
+    let (graph, thread_instructions, _) = build_graph(instructions, append_in_order);
+    thread_system.graph = graph;
+    thread_system.thread_instructions = thread_instructions.into_iter().map(Shared::new).collect();
+    thread_system
+  }
+
+  // The address and number of the store a propagate node delivers.
+  pub fn delivery(&self, id: usize) -> Option<(i32, usize)> {
+    self.deliveries.get(&id).cloned()
+  }
+}
+
+impl ThreadSystem for NMCAThreadSystem {
+    fn get_possible_executions(&self) -> Vec<Node> {
+      self.graph.execution_candidates.iter().map(|id| self.graph.instructions[id].clone()).collect()
+    }
+
+    fn assign_register(&mut self, thread_id: usize, register: String, value: i32) {
+      self.registers[thread_id].insert(register, value);
+    }
+
+    fn get_register(&self, thread_id: usize, register: String) -> i32 {
+      match self.registers[thread_id].get(&register) {
+        Some(value) => *value,
+        None => 0
+      }
+    }
+
+    fn number_of_threads(&self) -> usize {
+      self.registers.len()
+    }
+
+    fn registers(&self, thread_id: usize) -> &HashMap<String, i32> {
+      &self.registers[thread_id]
+    }
+
+    fn set_thread_names(&mut self, thread_names: Vec<String>) {
+      self.thread_names = thread_names;
+    }
+
+    fn thread_name(&self, thread_id: usize) -> &str {
+      &self.thread_names[thread_id]
+    }
+
+    fn graph(&self) -> &Graph {
+      &self.graph
+    }
+
+    fn set_recycling(&mut self) {
+      self.graph.set_recycling();
+    }
+
+    fn remove_node(&mut self, node: &Node) {
+      self.deliveries.remove(&node.id);
+      self.graph.remove_node(node.id);
+    }
+
+    fn jump(&mut self, branch: &Node) {
+      self.graph.jump(&self.thread_instructions[branch.thread_id], branch.id);
+    }
+
+    fn set_registers(&mut self, thread_id: usize, registers: HashMap<String, i32>) {
+      self.registers[thread_id] = registers.into();
+    }
+
+    fn restart(&mut self, from: &Node) {
+      self.graph.restart(&self.thread_instructions[from.thread_id], from.id);
+    }
+
+    fn append_instruction(&mut self, thread_id: usize, instruction: LabeledInstruction) -> usize {
+      let id = append_in_order(&mut self.graph, &mut (), &self.thread_instructions[thread_id], thread_id, instruction);
+      self.thread_instructions[thread_id].push(id);
+      id
+    }
+
+    fn add_propagate_node(&mut self, thread_id: usize, address: i32, value: i32) {
+      let index = self.stores.entry(address).or_insert(0);
+      *index += 1;
+      let index = *index;
+      for reader in (0..self.registers.len()).filter(|reader| *reader != thread_id) {
+        let id = self.graph.add_node(reader, LabeledInstruction {
+          label: None,
+          instruction: instruction::Instruction::Propagate { thread_id, address, value },
+          line: None,
+          scope: None,
+          java: false
+        });
+        self.deliveries.insert(id, (address, index));
       }
     }
 }
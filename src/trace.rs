@@ -0,0 +1,403 @@
+use std::collections::HashMap;
+use std::io::{self, BufRead, Read, Write};
+
+use crate::{error::RuntimeError, event::{Access, AccessKind, Event}, graph::{EdgeKind, Node}, instruction::Mode, json::{self, Value}, memory_model::MemoryModel};
+
+// A compact binary trace: a header with the thread names, then one record per step. Numbers are
+// LEB128 varints (zigzag for signed values) and the text of each instruction is written only once.
+const MAGIC: &[u8; 4] = b"ISAT";
+const VERSION: u8 = 2;
+
+const STEP: u8 = 0;
+const INSTRUCTION: u8 = 1;
+const ERROR: u8 = 2;
+
+const KINDS: [AccessKind; 5] = [AccessKind::Read, AccessKind::Write, AccessKind::Update, AccessKind::Lock, AccessKind::Unlock];
+const MODES: [Mode; 5] = [Mode::SeqCst, Mode::Rel, Mode::Acq, Mode::RelAcq, Mode::Rlx];
+// Accesses of `NA` mode come after those of every other mode, so that older traces keep their codes.
+const NON_ATOMIC: u8 = 1 + (KINDS.len() * MODES.len()) as u8;
+
+// A recorded step; only JSON traces keep the registers of the thread after the step.
+#[derive(Clone, Debug)]
+pub struct Step {
+  pub thread_id: usize,
+  pub instruction: String,
+  pub line: Option<usize>,
+  pub access: Option<Access>,
+  pub registers: Option<HashMap<String, i32>>
+}
+
+#[derive(Clone, Debug)]
+pub enum Record {
+  Step(Step),
+  // The step that failed and the report of the runtime error.
+  Error(Step, String)
+}
+
+pub struct TraceWriter<W: Write> {
+  writer: W,
+  instructions: HashMap<(String, Option<usize>), u64>
+}
+
+impl<W: Write> TraceWriter<W> {
+  pub fn new(mut writer: W, thread_names: &[String]) -> io::Result<TraceWriter<W>> {
+    writer.write_all(MAGIC)?;
+    writer.write_all(&[VERSION])?;
+    write_varint(&mut writer, thread_names.len() as u64)?;
+    for name in thread_names {
+      write_string(&mut writer, name)?;
+    }
+    Ok(TraceWriter { writer, instructions: HashMap::new() })
+  }
+
+  fn instruction(&mut self, instruction: String, line: Option<usize>) -> io::Result<u64> {
+    let next = self.instructions.len() as u64;
+    if let Some(id) = self.instructions.get(&(instruction.clone(), line)) {
+      return Ok(*id);
+    }
+    self.writer.write_all(&[INSTRUCTION])?;
+    write_string(&mut self.writer, &instruction)?;
+    write_varint(&mut self.writer, line.map_or(0, |line| line as u64 + 1))?;
+    self.instructions.insert((instruction, line), next);
+    Ok(next)
+  }
+
+  pub fn write_event(&mut self, event: &Event) -> io::Result<()> {
+    let instruction = self.instruction(format!("{:?}", event.node.instruction), event.node.instruction.line)?;
+    self.writer.write_all(&[STEP])?;
+    write_varint(&mut self.writer, event.node.thread_id as u64)?;
+    write_varint(&mut self.writer, instruction)?;
+    match &event.access {
+      Some(Access { address, kind, mode, value, source }) => {
+        let kind = KINDS.iter().position(|k| k == kind).unwrap() as u8;
+        let code = match MODES.iter().position(|m| m == mode) {
+          Some(mode) => 1 + kind * MODES.len() as u8 + mode as u8,
+          None => NON_ATOMIC + kind
+        };
+        self.writer.write_all(&[code])?;
+        write_varint(&mut self.writer, zigzag(*address))?;
+        write_varint(&mut self.writer, zigzag(*value))?;
+        write_varint(&mut self.writer, source.map_or(0, |source| source as u64 + 1))
+      },
+      None => self.writer.write_all(&[0])
+    }
+  }
+
+  pub fn write_error(&mut self, error: &RuntimeError) -> io::Result<()> {
+    let instruction = self.instruction(error.instruction.clone(), error.line)?;
+    self.writer.write_all(&[ERROR])?;
+    write_varint(&mut self.writer, error.thread_id as u64)?;
+    write_varint(&mut self.writer, instruction)?;
+    write_string(&mut self.writer, &error.to_string())
+  }
+
+  pub fn flush(&mut self) -> io::Result<()> {
+    self.writer.flush()
+  }
+}
+
+// Reads the records back; a trace cut off in the middle of a record, e.g. by a timeout, ends with
+// an error after the complete steps.
+pub struct TraceReader<R: Read> {
+  reader: R,
+  thread_names: Vec<String>,
+  instructions: Vec<(String, Option<usize>)>,
+  done: bool
+}
+
+impl<R: Read> TraceReader<R> {
+  pub fn new(mut reader: R) -> Result<TraceReader<R>, String> {
+    let mut magic = [0; 5];
+    reader.read_exact(&mut magic).map_err(|_| "not a binary trace".to_string())?;
+    if &magic[..4] != MAGIC {
+      return Err("not a binary trace".to_string());
+    }
+    if magic[4] != VERSION {
+      return Err(format!("unsupported binary trace version {}", magic[4]));
+    }
+    let count = read_varint(&mut reader)?;
+    let mut thread_names = Vec::new();
+    for _ in 0..count {
+      thread_names.push(read_string(&mut reader)?);
+    }
+    Ok(TraceReader { reader, thread_names, instructions: Vec::new(), done: false })
+  }
+
+  pub fn thread_names(&self) -> &[String] {
+    &self.thread_names
+  }
+
+  fn read_record(&mut self) -> Result<Option<Record>, String> {
+    loop {
+      let mut tag = [0];
+      match self.reader.read(&mut tag) {
+        Ok(0) => return Ok(None),
+        Ok(_) => {},
+        Err(err) => return Err(err.to_string())
+      }
+      match tag[0] {
+        INSTRUCTION => {
+          let text = read_string(&mut self.reader)?;
+          let line = read_varint(&mut self.reader)?;
+          self.instructions.push((text, line.checked_sub(1).map(|line| line as usize)));
+        },
+        STEP => {
+          let mut step = self.read_instruction()?;
+          step.access = self.read_access()?;
+          return Ok(Some(Record::Step(step)));
+        },
+        ERROR => {
+          let step = self.read_instruction()?;
+          return read_string(&mut self.reader).map(|message| Some(Record::Error(step, message)));
+        },
+        tag => return Err(format!("unknown record {} in binary trace", tag))
+      }
+    }
+  }
+
+  fn read_instruction(&mut self) -> Result<Step, String> {
+    let thread_id = read_varint(&mut self.reader)? as usize;
+    if thread_id >= self.thread_names.len() {
+      return Err(format!("unknown thread {} in binary trace", thread_id));
+    }
+    let (instruction, line) = self.instructions.get(read_varint(&mut self.reader)? as usize)
+      .cloned()
+      .ok_or_else(|| "unknown instruction in binary trace".to_string())?;
+    Ok(Step { thread_id, instruction, line, access: None, registers: None })
+  }
+
+  fn read_access(&mut self) -> Result<Option<Access>, String> {
+    let mut access = [0];
+    self.reader.read_exact(&mut access).map_err(truncated)?;
+    match access[0] {
+      0 => Ok(None),
+      code if code < NON_ATOMIC + KINDS.len() as u8 => {
+        let (kind, mode) = match code.checked_sub(NON_ATOMIC) {
+          Some(kind) => (KINDS[kind as usize], Mode::NA),
+          None => (KINDS[(code as usize - 1) / MODES.len()], MODES[(code as usize - 1) % MODES.len()])
+        };
+        let address = unzigzag(read_varint(&mut self.reader)?);
+        let value = unzigzag(read_varint(&mut self.reader)?);
+        let source = read_varint(&mut self.reader)?.checked_sub(1).map(|source| source as usize);
+        Ok(Some(Access { address, kind, mode, value, source }))
+      },
+      code => Err(format!("unknown access {} in binary trace", code))
+    }
+  }
+}
+
+impl<R: Read> Iterator for TraceReader<R> {
+  type Item = Result<Record, String>;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    if self.done {
+      return None;
+    }
+    let record = self.read_record().transpose();
+    self.done = !matches!(record, Some(Ok(_)));
+    record
+  }
+}
+
+// Reads a trace written by --trace-jsonl, naming threads as in `thread_names`.
+pub fn read_jsonl<'a, R: BufRead + 'a>(reader: R, thread_names: &'a [String]) -> impl Iterator<Item = Result<Record, String>> + 'a {
+  reader.lines().enumerate().map(move |(index, line)| {
+    let line = line.map_err(|err| err.to_string())?;
+    parse_record(&line, thread_names).map_err(|err| format!("line {}: {}", index + 1, err))
+  })
+}
+
+fn parse_record(line: &str, thread_names: &[String]) -> Result<Record, String> {
+  let value = json::parse(line)?;
+  let thread_id = match value.get("thread") {
+    Some(Value::String(name)) => thread_names.iter().position(|thread_name| thread_name == name).ok_or_else(|| format!("unknown thread {}", name))?,
+    _ => return Err("missing thread".to_string())
+  };
+  let instruction = match value.get("instruction") {
+    Some(Value::String(instruction)) => instruction.clone(),
+    _ => return Err("missing instruction".to_string())
+  };
+  let line = match value.get("line") {
+    Some(Value::Number(line)) => Some(*line as usize),
+    _ => None
+  };
+  let registers = match value.get("registers") {
+    Some(Value::Object(fields)) => Some(fields.iter()
+      .map(|(r, value)| match value {
+        Value::Number(value) => Ok((r.clone(), *value as i32)),
+        _ => Err(format!("register {} is not a number", r))
+      })
+      .collect::<Result<HashMap<String, i32>, String>>()?),
+    _ => None
+  };
+  let mut step = Step { thread_id, instruction, line, access: None, registers };
+  if let Some(Value::String(message)) = value.get("error") {
+    return Ok(Record::Error(step, message.clone()));
+  }
+  if let Some(access) = value.get("access").filter(|access| **access != Value::Null) {
+    let number = |key: &str| match access.get(key) {
+      Some(Value::Number(value)) => Ok(*value),
+      _ => Err(format!("access without {}", key))
+    };
+    let kind = KINDS.iter().find(|kind| matches!(access.get("kind"), Some(Value::String(name)) if *name == format!("{:?}", kind).to_lowercase()));
+    let mode = MODES.iter().chain(&[Mode::NA]).find(|mode| matches!(access.get("mode"), Some(Value::String(name)) if *name == format!("{:?}", mode)));
+    step.access = Some(Access {
+      address: number("address")? as i32,
+      kind: *kind.ok_or_else(|| "access of unknown kind".to_string())?,
+      mode: *mode.ok_or_else(|| "access with unknown mode".to_string())?,
+      value: number("value")? as i32,
+      source: number("source").ok().map(|source| source as usize)
+    });
+  }
+  Ok(Record::Step(step))
+}
+
+// Re-executes the recorded steps on `model`, checking that each step can run, makes the recorded
+// access and, when the trace has them, leaves the recorded registers. A recorded runtime error must
+// happen again. Returns the number of steps replayed.
+pub fn replay(model: &mut dyn MemoryModel, records: impl Iterator<Item = Result<Record, String>>) -> Result<usize, String> {
+  replay_with(model, records, false, |_, _, _| {}).map(|events| events.len())
+}
+
+// Replays the trace as `replay` does and returns the events of its steps.
+pub fn events(model: &mut dyn MemoryModel, records: impl Iterator<Item = Result<Record, String>>) -> Result<Vec<Event>, String> {
+  replay_with(model, records, false, |_, _, _| {})
+}
+
+// Replays the trace with the model printing every step as -vvv does, which shows where each load
+// read from and the buffers and memory after it, and names the latest earlier step of each kind of
+// ordering that the step had to wait for.
+pub fn explain(model: &mut dyn MemoryModel, records: impl Iterator<Item = Result<Record, String>>) -> Result<usize, String> {
+  let mut executed: HashMap<usize, usize> = HashMap::new();
+  replay_with(model, records, true, |step, node, model| {
+    let mut waited: Vec<(EdgeKind, usize)> = Vec::new();
+    for edge in model.graph().edges_from(node.id).iter().filter(|edge| edge.kind.is_blocking()) {
+      let Some(earlier) = executed.get(&edge.to) else {
+        continue;
+      };
+      match waited.iter_mut().find(|(kind, _)| *kind == edge.kind) {
+        Some((_, latest)) => *latest = (*latest).max(*earlier),
+        None => waited.push((edge.kind, *earlier))
+      }
+    }
+    waited.sort_by_key(|(_, earlier)| std::cmp::Reverse(*earlier));
+    println!("# STEP {}", step);
+    if !waited.is_empty() {
+      let waited: Vec<String> = waited.iter().map(|(kind, earlier)| format!("step {} ({})", earlier, kind.name())).collect();
+      println!("# AFTER {}", waited.join(", "));
+    }
+    executed.insert(node.id, step);
+  }).map(|events| events.len())
+}
+
+// `before` sees each step before it runs, with its index and the model in the state it runs in.
+fn replay_with(model: &mut dyn MemoryModel, records: impl Iterator<Item = Result<Record, String>>, debug_print: bool, mut before: impl FnMut(usize, &Node, &dyn MemoryModel)) -> Result<Vec<Event>, String> {
+  let mut events = Vec::new();
+  for record in records {
+    let steps = events.len();
+    let (step, error) = match record? {
+      Record::Step(step) => (step, None),
+      Record::Error(step, message) => (step, Some(message))
+    };
+    let describe = |step: &Step| match step.line {
+      Some(line) => format!("{} (line {})", step.instruction, line),
+      None => step.instruction.clone()
+    };
+    let node = model.get_possible_executions().into_iter()
+      .find(|node| node.thread_id == step.thread_id && node.instruction.line == step.line && format!("{:?}", node.instruction) == step.instruction)
+      .ok_or_else(|| format!("step {}: thread {} cannot run {} at this point", steps, model.thread_name(step.thread_id), describe(&step)))?;
+    before(steps, &node, model);
+    let event = match (model.step(node, debug_print), error) {
+      (Ok(event), None) => event,
+      (Err(err), Some(message)) if err.to_string() == message => return Ok(events),
+      (Err(err), Some(message)) => return Err(format!("step {}: the trace records the error\n  {}\nbut the replay fails with\n  {}", steps, message, err)),
+      (Err(err), None) => return Err(format!("step {}: {}", steps, err)),
+      (Ok(_), Some(message)) => return Err(format!("step {}: the trace records the error\n  {}\nbut the replay runs {}", steps, message, describe(&step)))
+    };
+    if event.access != step.access {
+      return Err(format!("step {}: {} in thread {} recorded {} but replays as {}", steps, describe(&step), model.thread_name(step.thread_id), describe_access(&step.access), describe_access(&event.access)));
+    }
+    if let Some(registers) = &step.registers {
+      if registers != model.registers(step.thread_id) {
+        let mut names: Vec<&String> = registers.keys().chain(model.registers(step.thread_id).keys()).collect();
+        names.sort();
+        names.dedup();
+        let differences: Vec<String> = names.into_iter()
+          .filter(|r| registers.get(*r) != model.registers(step.thread_id).get(*r))
+          .map(|r| {
+            let show = |value: Option<&i32>| value.map_or("unset".to_string(), |value| value.to_string());
+            format!("{} recorded {} but replays as {}", r, show(registers.get(r)), show(model.registers(step.thread_id).get(r)))
+          })
+          .collect();
+        return Err(format!("step {}: after {} in thread {} {}", steps, describe(&step), model.thread_name(step.thread_id), differences.join(", ")));
+      }
+    }
+    events.push(event);
+  }
+  Ok(events)
+}
+
+fn describe_access(access: &Option<Access>) -> String {
+  match access {
+    Some(Access { address, kind, mode, value, source }) => {
+      let source = source.map_or(String::new(), |source| format!(" from store {}", source));
+      format!("{} {:?} of #{} = {}{}", format!("{:?}", kind).to_lowercase(), mode, address, value, source)
+    },
+    None => "no access".to_string()
+  }
+}
+
+fn zigzag(value: i32) -> u64 {
+  ((value << 1) ^ (value >> 31)) as u32 as u64
+}
+
+fn unzigzag(value: u64) -> i32 {
+  let value = value as u32;
+  ((value >> 1) as i32) ^ -((value & 1) as i32)
+}
+
+fn write_varint(writer: &mut impl Write, mut value: u64) -> io::Result<()> {
+  loop {
+    let byte = (value & 0x7f) as u8;
+    value >>= 7;
+    if value == 0 {
+      return writer.write_all(&[byte]);
+    }
+    writer.write_all(&[byte | 0x80])?;
+  }
+}
+
+fn read_varint(reader: &mut impl Read) -> Result<u64, String> {
+  let mut value = 0;
+  for shift in (0..64).step_by(7) {
+    let mut byte = [0];
+    reader.read_exact(&mut byte).map_err(truncated)?;
+    value |= ((byte[0] & 0x7f) as u64) << shift;
+    if byte[0] & 0x80 == 0 {
+      return Ok(value);
+    }
+  }
+  Err("malformed number in binary trace".to_string())
+}
+
+fn write_string(writer: &mut impl Write, value: &str) -> io::Result<()> {
+  write_varint(writer, value.len() as u64)?;
+  writer.write_all(value.as_bytes())
+}
+
+fn read_string(reader: &mut impl Read) -> Result<String, String> {
+  let length = read_varint(reader)?;
+  let mut bytes = Vec::new();
+  reader.take(length).read_to_end(&mut bytes).map_err(truncated)?;
+  if bytes.len() as u64 != length {
+    return Err(truncated(io::ErrorKind::UnexpectedEof.into()));
+  }
+  String::from_utf8(bytes).map_err(|_| "malformed text in binary trace".to_string())
+}
+
+fn truncated(err: io::Error) -> String {
+  match err.kind() {
+    io::ErrorKind::UnexpectedEof => "binary trace ends in the middle of a step".to_string(),
+    _ => err.to_string()
+  }
+}
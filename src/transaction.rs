@@ -0,0 +1,256 @@
+use std::collections::{BTreeSet, HashMap};
+use std::fmt::Debug;
+
+use crate::{graph::Node, instruction::{FetchOp, Mode}, storage::{Origin, Stacks, StorageSystem}};
+
+// The abort codes `txbegin` leaves in its register: another thread wrote what the transaction
+// accessed, or the transaction ran `txabort`.
+pub const CONFLICT: i32 = 1;
+pub const EXPLICIT: i32 = 2;
+
+// An open transaction: where it started, the registers to go back to, the addresses it read and
+// the stores it holds back until it commits, in the order it first wrote each address.
+#[derive(Clone)]
+struct Transaction {
+  begin: Node,
+  status: String,
+  registers: HashMap<String, i32>,
+  reads: BTreeSet<i32>,
+  writes: Vec<(i32, i32, Option<Origin>)>,
+  // The store of the transaction its last load returned, if it read one.
+  local: Option<Option<Origin>>
+}
+
+// A transaction that has aborted; the thread restarts after `begin` with `registers` and the code in `status`.
+#[derive(Clone)]
+pub struct Aborted {
+  pub thread_id: usize,
+  pub begin: Node,
+  pub status: String,
+  pub code: i32,
+  pub registers: HashMap<String, i32>
+}
+
+// Hardware transactions over a storage system. Transactional loads read the transaction's own
+// stores or go to the storage system, and transactional stores stay in the transaction until it
+// commits and writes them all to memory in one step. A transaction aborts as soon as another thread's
+// store to an address it read or wrote reaches memory, so it only ever commits what it could have
+// done in that one step.
+#[derive(Clone)]
+pub struct Transactional<S: StorageSystem> {
+  pub inner: S,
+  transactions: HashMap<usize, Transaction>,
+  aborted: Vec<Aborted>
+}
+
+impl<S: StorageSystem + Debug> Debug for Transactional<S> {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(f, "{:?}", self.inner)?;
+    if self.transactions.is_empty() {
+      return Ok(());
+    }
+    writeln!(f, "# TRANSACTIONS")?;
+    let mut threads: Vec<&usize> = self.transactions.keys().collect();
+    threads.sort();
+    for thread_id in threads {
+      let transaction = &self.transactions[thread_id];
+      let writes: Vec<(i32, i32)> = transaction.writes.iter().map(|(address, value, _)| (*address, *value)).collect();
+      writeln!(f, "| Thread {}: read {:?}, wrote {:?}", thread_id, transaction.reads, writes)?;
+    }
+    Ok(())
+  }
+}
+
+impl<S: StorageSystem> Transactional<S> {
+  pub fn new(inner: S) -> Transactional<S> {
+    Transactional { inner, transactions: HashMap::new(), aborted: Vec::new() }
+  }
+
+  pub fn in_transaction(&self, thread_id: usize) -> bool {
+    self.transactions.contains_key(&thread_id)
+  }
+
+  pub fn begin(&mut self, thread_id: usize, begin: Node, status: String, registers: HashMap<String, i32>) {
+    self.transactions.insert(thread_id, Transaction { begin, status, registers, reads: BTreeSet::new(), writes: Vec::new(), local: None });
+  }
+
+  // Writes the transaction's stores to memory, returning false if the thread has no transaction.
+  pub fn commit(&mut self, thread_id: usize) -> bool {
+    let Some(transaction) = self.transactions.remove(&thread_id) else {
+      return false;
+    };
+    for (address, value, origin) in transaction.writes {
+      self.inner.store(thread_id, address, value, Mode::SeqCst);
+      if let Some(origin) = origin {
+        self.inner.tag(thread_id, address, origin);
+      }
+      self.inner.propagate(thread_id, address, thread_id);
+      self.conflict(thread_id, address);
+    }
+    true
+  }
+
+  pub fn abort(&mut self, thread_id: usize, code: i32) {
+    if let Some(transaction) = self.transactions.remove(&thread_id) {
+      self.aborted.push(Aborted { thread_id, begin: transaction.begin, status: transaction.status, code, registers: transaction.registers });
+    }
+  }
+
+  // The transactions that aborted since the last call, for the thread system to roll back.
+  pub fn take_aborted(&mut self) -> Vec<Aborted> {
+    std::mem::take(&mut self.aborted)
+  }
+
+  // A store of the writer to the address has reached memory.
+  fn conflict(&mut self, writer: usize, address: i32) {
+    let mut conflicting: Vec<usize> = self.transactions.iter()
+      .filter(|(thread_id, transaction)| **thread_id != writer && (transaction.reads.contains(&address) || transaction.writes.iter().any(|(a, _, _)| *a == address)))
+      .map(|(thread_id, _)| *thread_id)
+      .collect();
+    conflicting.sort();
+    for thread_id in conflicting {
+      self.abort(thread_id, CONFLICT);
+    }
+  }
+
+  // Runs a store outside any transaction, which conflicts once it is in memory rather than in a buffer.
+  fn write<T>(&mut self, thread_id: usize, address: i32, store: impl FnOnce(&mut S) -> (T, bool)) -> T {
+    let buffered = self.inner.buffer(thread_id).len();
+    let (result, wrote) = store(&mut self.inner);
+    if wrote && self.inner.buffer(thread_id).len() <= buffered {
+      self.conflict(thread_id, address);
+    }
+    result
+  }
+}
+
+impl<S: StorageSystem> StorageSystem for Transactional<S> {
+  fn load(&mut self, thread_id: usize, address: i32, mode: Mode) -> i32 {
+    let Some(transaction) = self.transactions.get_mut(&thread_id) else {
+      return self.inner.load(thread_id, address, mode);
+    };
+    if let Some((_, value, origin)) = transaction.writes.iter().find(|(a, _, _)| *a == address) {
+      transaction.local = Some(*origin);
+      return *value;
+    }
+    transaction.local = None;
+    transaction.reads.insert(address);
+    self.inner.load(thread_id, address, mode)
+  }
+
+  fn store(&mut self, thread_id: usize, address: i32, value: i32, mode: Mode) {
+    let Some(transaction) = self.transactions.get_mut(&thread_id) else {
+      return self.write(thread_id, address, |inner| (inner.store(thread_id, address, value, mode), true));
+    };
+    match transaction.writes.iter_mut().find(|(a, _, _)| *a == address) {
+      Some(write) => *write = (address, value, None),
+      None => transaction.writes.push((address, value, None))
+    }
+  }
+
+  fn cas(&mut self, thread_id: usize, address: i32, exp: i32, des: i32, success: Mode, failure: Mode) -> i32 {
+    if !self.in_transaction(thread_id) {
+      return self.write(thread_id, address, |inner| {
+        let value = inner.cas(thread_id, address, exp, des, success, failure);
+        (value, value == exp)
+      });
+    }
+    let value = self.load(thread_id, address, failure);
+    if value == exp {
+      self.store(thread_id, address, des, success);
+    }
+    value
+  }
+
+  fn fai(&mut self, thread_id: usize, address: i32, op: FetchOp, inc: i32, mode: Mode) -> Option<i32> {
+    if !self.in_transaction(thread_id) {
+      return self.write(thread_id, address, |inner| {
+        let value = inner.fai(thread_id, address, op, inc, mode);
+        (value, value.is_some())
+      });
+    }
+    let value = self.load(thread_id, address, mode);
+    self.store(thread_id, address, op.apply(value, inc)?, mode);
+    Some(value)
+  }
+
+  fn fence(&mut self, thread_id: usize, mode: Mode) {
+    self.inner.fence(thread_id, mode);
+  }
+
+  fn memory(&self) -> &HashMap<i32, i32> {
+    self.inner.memory()
+  }
+
+  fn peek(&self, thread_id: usize, address: i32) -> i32 {
+    let write = self.transactions.get(&thread_id).and_then(|transaction| transaction.writes.iter().find(|(a, _, _)| *a == address));
+    match write {
+      Some((_, value, _)) => *value,
+      None => self.inner.peek(thread_id, address)
+    }
+  }
+
+  fn acquire(&mut self, thread_id: usize, address: i32) {
+    self.inner.acquire(thread_id, address);
+  }
+
+  fn initialize(&mut self, address: i32, value: i32) {
+    self.inner.initialize(address, value);
+  }
+
+  fn buffer(&self, thread_id: usize) -> &[(i32, i32)] {
+    self.inner.buffer(thread_id)
+  }
+
+  fn propagate(&mut self, thread_id: usize, address: i32, observer: usize) {
+    self.inner.propagate(thread_id, address, observer);
+    self.conflict(thread_id, address);
+  }
+
+  fn tag(&mut self, thread_id: usize, address: i32, origin: Origin) {
+    let Some(transaction) = self.transactions.get_mut(&thread_id) else {
+      return self.inner.tag(thread_id, address, origin);
+    };
+    if let Some(write) = transaction.writes.iter_mut().find(|(a, _, _)| *a == address) {
+      write.2 = Some(origin);
+    }
+  }
+
+  fn read_from(&self, thread_id: usize) -> Option<Origin> {
+    match self.transactions.get(&thread_id).and_then(|transaction| transaction.local) {
+      Some(origin) => origin,
+      None => self.inner.read_from(thread_id)
+    }
+  }
+
+  // A load of a transaction forwards its own stores as a buffer would.
+  fn forwarded(&self, thread_id: usize) -> bool {
+    match self.transactions.get(&thread_id) {
+      Some(transaction) if transaction.local.is_some() => true,
+      _ => self.inner.forwarded(thread_id)
+    }
+  }
+
+  fn source(&self, thread_id: usize, address: i32) -> Option<usize> {
+    match self.transactions.get(&thread_id) {
+      Some(transaction) if transaction.writes.iter().any(|(a, _, _)| *a == address) => None,
+      _ => self.inner.source(thread_id, address)
+    }
+  }
+
+  fn stacks(&mut self) -> &mut Stacks {
+    self.inner.stacks()
+  }
+
+  fn set_thread_names(&mut self, thread_names: Vec<String>) {
+    self.inner.set_thread_names(thread_names);
+  }
+
+  fn set_default(&mut self, value: i32) {
+    self.inner.set_default(value);
+  }
+
+  fn default_value(&self) -> i32 {
+    self.inner.default_value()
+  }
+}
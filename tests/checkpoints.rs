@@ -0,0 +1,63 @@
+use std::{collections::BTreeMap, fs, process::Command};
+
+use isa::{explore::{Exploration, Explorer}, memory_model::MemoryModelType, parser::parse_program};
+
+const SB: &str = "x = 1
+y = 2
+store RLX #x x
+load RLX #y r
+
+x = 1
+y = 2
+store RLX #y x
+load RLX #x r
+";
+
+fn counts(exploration: &Exploration) -> BTreeMap<String, usize> {
+  exploration.outcomes.iter().map(|(outcome, found)| (outcome.clone(), found.count)).collect()
+}
+
+#[test]
+fn resumed_explorations_reach_the_same_outcomes() {
+  let program = parse_program(SB).unwrap();
+  let model = MemoryModelType::TSO;
+  let full = Explorer::new(&model, &program, 1000).run();
+  for steps in [1, 5, 20] {
+    let mut interrupted = Explorer::new(&model, &program, 1000);
+    for _ in 0..steps {
+      interrupted.step();
+    }
+    let checkpoint = interrupted.checkpoint();
+    let mut resumed = Explorer::new(&model, &program, 1000);
+    resumed.resume(&checkpoint).unwrap();
+    let resumed = resumed.run();
+    assert_eq!(counts(&resumed), counts(&full), "after {} steps", steps);
+    assert_eq!((resumed.executions, resumed.truncated), (full.executions, full.truncated));
+  }
+}
+
+#[test]
+fn checkpoints_only_resume_the_same_exploration() {
+  let program = parse_program(SB).unwrap();
+  let checkpoint = Explorer::new(&MemoryModelType::TSO, &program, 1000).checkpoint();
+  let err = Explorer::new(&MemoryModelType::SC, &program, 1000).resume(&checkpoint).err().unwrap();
+  assert_eq!(err, "Checkpoint was made with model TSO, not SC");
+  let err = Explorer::new(&MemoryModelType::TSO, &program, 1000).resume("bogus").err().unwrap();
+  assert_eq!(err, "Checkpoint line 1: invalid entry bogus");
+}
+
+#[test]
+fn exhaustive_runs_resume_from_the_checkpoint_file() {
+  let directory = std::env::temp_dir().join(format!("isa-checkpoint-{}", std::process::id()));
+  fs::create_dir_all(&directory).unwrap();
+  let (program, checkpoint) = (directory.join("sb.isa"), directory.join("sb.checkpoint"));
+  fs::write(&program, SB).unwrap();
+  let run = || Command::new(env!("CARGO_BIN_EXE_main"))
+    .args(["-f", program.to_str().unwrap(), "-m", "TSO", "--exhaustive", "--checkpoint", checkpoint.to_str().unwrap()])
+    .output().unwrap();
+  let first = String::from_utf8(run().stdout).unwrap();
+  assert!(fs::read_to_string(&checkpoint).unwrap().starts_with("# isa exploration checkpoint\nmodel TSO\n"));
+  let second = String::from_utf8(run().stdout).unwrap();
+  assert_eq!(second, format!("# RESUMED {} (9 executions explored)\n{}", checkpoint.display(), first));
+  fs::remove_dir_all(&directory).unwrap();
+}
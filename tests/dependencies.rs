@@ -0,0 +1,59 @@
+use isa::{assert_outcomes, memory_model::MemoryModelType};
+
+// The store needs x for its address and v for its value.
+const ADDRESS: &str = "x = 1
+v = 5
+store RLX #x v
+";
+
+// The constant must not run before the load that it overwrites.
+const OVERWRITE: &str = "x = 1
+load RLX #x r
+r = 7
+";
+
+const SELECT: &str = "c = 1
+a = 2
+b = 3
+r = c ? a : b
+";
+
+// Independent accesses still pass each other through the store buffer.
+const SB: &str = "x = 1
+y = 2
+store RLX #x x
+load RLX #y r
+
+x = 1
+y = 2
+store RLX #y x
+load RLX #x r
+";
+
+#[test]
+fn accesses_wait_for_their_registers() {
+  for model in [MemoryModelType::TSO, MemoryModelType::PSO] {
+    assert_outcomes!(ADDRESS, model, allowed = ["#1 == 5"], forbidden = ["#1 != 5", "#0 != 0"]);
+  }
+}
+
+#[test]
+fn writes_wait_for_earlier_reads() {
+  for model in [MemoryModelType::TSO, MemoryModelType::PSO] {
+    assert_outcomes!(OVERWRITE, model, allowed = ["0:r == 7"], forbidden = ["0:r != 7"]);
+  }
+}
+
+#[test]
+fn select_waits_for_its_operands() {
+  for model in [MemoryModelType::TSO, MemoryModelType::PSO] {
+    assert_outcomes!(SELECT, model, allowed = ["0:r == 2"], forbidden = ["0:r != 2"]);
+  }
+}
+
+#[test]
+fn independent_accesses_stay_unordered() {
+  for model in [MemoryModelType::TSO, MemoryModelType::PSO] {
+    assert_outcomes!(SB, model, allowed = ["0:r == 0 && 1:r == 0", "0:r == 1 && 1:r == 1"]);
+  }
+}
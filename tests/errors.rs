@@ -0,0 +1,77 @@
+use isa::{error::RuntimeError, memory_model::MemoryModelType, parser::parse_program, testing};
+
+const DIVISION: &str = "thread main:
+a = 7
+z = 0
+r = a / z
+";
+
+const OVERFLOW: &str = "a = 2147483647
+one = 1
+r = a + one
+";
+
+const NEGATIVE_ADDRESS: &str = "a = -1
+v = 1
+store RLX #a v
+";
+
+const UNLOCK: &str = "x = 1
+unlock #x
+";
+
+// Runs the program with the random scheduler up to its first runtime error.
+fn first_error(source: &str, model: MemoryModelType) -> RuntimeError {
+  let program = parse_program(source).unwrap();
+  let mut model = model.create(&program);
+  loop {
+    match model.random_step(false) {
+      Ok(Some(_)) => {}
+      Ok(None) => panic!("the program ends without a runtime error"),
+      Err(err) => return err
+    }
+  }
+}
+
+#[test]
+fn division_by_zero() {
+  let err = first_error(DIVISION, MemoryModelType::SC);
+  assert_eq!((err.thread_id, err.thread_name.as_str(), err.line), (0, "main", Some(4)));
+  assert_eq!(err.instruction, "r = a / z");
+  assert_eq!(err.message, "division by zero");
+  assert_eq!(err.registers, vec![("a".to_string(), 7), ("z".to_string(), 0)]);
+  assert_eq!(err.to_string(), "Runtime error in thread main at r = a / z (line 4): division by zero (a = 7, z = 0)");
+}
+
+#[test]
+fn arithmetic_overflow() {
+  let err = first_error(OVERFLOW, MemoryModelType::TSO);
+  assert_eq!(err.to_string(), "Runtime error in thread 0 at r = a + one (line 3): arithmetic overflow (a = 2147483647, one = 1)");
+}
+
+#[test]
+fn negative_address() {
+  let err = first_error(NEGATIVE_ADDRESS, MemoryModelType::PSO);
+  assert_eq!(err.to_string(), "Runtime error in thread 0 at store RLX #a v (line 3): negative memory address (a = -1, v = 1)");
+}
+
+#[test]
+fn unlock_without_lock() {
+  let err = first_error(UNLOCK, MemoryModelType::SC);
+  assert_eq!(err.to_string(), "Runtime error in thread 0 at unlock #x (line 2): lock is not held by the thread (x = 1)");
+}
+
+#[test]
+fn errors_are_outcomes_of_exploration() {
+  let exploration = testing::explore(DIVISION, &MemoryModelType::TSO).unwrap();
+  let outcomes: Vec<&String> = exploration.outcomes.keys().collect();
+  assert_eq!(outcomes, ["error: Runtime error in thread main at r = a / z (line 4): division by zero (a = 7, z = 0)"]);
+}
+
+#[test]
+fn errors_convert_to_boxed_errors() {
+  fn run(source: &str) -> Result<(), Box<dyn std::error::Error>> {
+    Err(first_error(source, MemoryModelType::SC))?
+  }
+  assert!(run(OVERFLOW).unwrap_err().to_string().ends_with("arithmetic overflow (a = 2147483647, one = 1)"));
+}
@@ -0,0 +1,73 @@
+use std::{fs, path::PathBuf, process::Command};
+
+use isa::{json::{self, Value}, memory_model::MemoryModelType, parser::parse_program};
+
+const OK: &str = "x = 1
+v = 2
+store RLX #x v
+";
+
+const DIVISION: &str = "a = 7
+z = 0
+r = a / z
+";
+
+const INVALID: &str = "x = 1
+foo
+";
+
+// Runs the program with `-q` and returns the JSON object it prints and whether it exited with 0.
+fn quiet(name: &str, source: &str) -> (Value, bool) {
+  let file: PathBuf = std::env::temp_dir().join(format!("isa-json-{}-{}.isa", std::process::id(), name));
+  fs::write(&file, source).unwrap();
+  let output = Command::new(env!("CARGO_BIN_EXE_main")).args(["-f", file.to_str().unwrap(), "--seed", "7", "-q"]).output().unwrap();
+  fs::remove_file(&file).unwrap();
+  (json::parse(&String::from_utf8(output.stdout).unwrap()).unwrap(), output.status.success())
+}
+
+fn field<'a>(value: &'a Value, key: &str) -> &'a Value {
+  value.get(key).unwrap_or_else(|| panic!("no {} in {:?}", key, value))
+}
+
+#[test]
+fn quiet_runs_print_a_verdict() {
+  let (value, success) = quiet("ok", OK);
+  assert!(success);
+  assert_eq!(field(&value, "verdict"), &Value::String("ok".to_string()));
+  assert_eq!(field(&value, "model"), &Value::String("SC".to_string()));
+  assert_eq!(field(&value, "seed"), &Value::Number(7));
+  assert_eq!(field(&value, "outcome"), &Value::String("0: {\"v\": 2, \"x\": 1} memory: {1: 2}".to_string()));
+  assert_eq!(field(&value, "steps"), &Value::Number(3));
+  assert_eq!(value.get("error"), None);
+}
+
+#[test]
+fn quiet_runtime_errors_are_error_verdicts() {
+  let (value, success) = quiet("division", DIVISION);
+  assert!(!success);
+  assert_eq!(field(&value, "verdict"), &Value::String("error".to_string()));
+  assert_eq!(field(&value, "error"), &Value::String("Runtime error in thread 0 at r = a / z (line 3): division by zero (a = 7, z = 0)".to_string()));
+}
+
+#[test]
+fn quiet_parse_errors_are_invalid_verdicts() {
+  let (value, success) = quiet("invalid", INVALID);
+  assert!(!success);
+  assert_eq!(field(&value, "verdict"), &Value::String("invalid".to_string()));
+  assert_eq!(field(&value, "error"), &Value::String("Line 2: error parsing instruction foo: Unknown instruction format".to_string()));
+}
+
+#[test]
+fn model_state_lists_registers_buffers_and_memory() {
+  let program = parse_program(OK).unwrap();
+  let mut model = MemoryModelType::TSO.create(&program);
+  for _ in 0..3 {
+    model.random_step(false).unwrap();
+  }
+  let state = json::parse(&json::model_state(model.as_ref())).unwrap();
+  let registers = Value::Object(vec![("v".to_string(), Value::Number(2)), ("x".to_string(), Value::Number(1))]);
+  assert_eq!(field(&state, "threads"), &Value::Array(vec![Value::String("0".to_string())]));
+  assert_eq!(field(&state, "registers"), &Value::Array(vec![registers]));
+  assert_eq!(field(&state, "buffers"), &Value::Array(vec![Value::Array(vec![Value::Array(vec![Value::Number(1), Value::Number(2)])])]));
+  assert_eq!(field(&state, "memory"), &Value::Object(Vec::new()));
+}
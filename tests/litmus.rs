@@ -0,0 +1,86 @@
+use isa::{assert_outcomes, memory_model::MemoryModelType};
+
+const SB: &str = "x = 1
+y = 2
+store RLX #x x
+load RLX #y r
+
+x = 1
+y = 2
+store RLX #y x
+load RLX #x r
+";
+
+const MP: &str = "x = 1
+y = 2
+store RLX #x x
+store RLX #y y
+
+x = 1
+y = 2
+load RLX #y r1
+load RLX #x r2
+";
+
+// TSO and PSO keep RLX accesses of a thread in order only across fences, acquires and releases.
+const MP_FENCED: &str = "x = 1
+y = 2
+store RLX #x x
+fence st,st
+store RLX #y y
+
+x = 1
+y = 2
+load RLX #y r1
+fence ld,ld
+load RLX #x r2
+";
+
+const CORR: &str = "x = 1
+store RLX #x x
+
+x = 1
+load RLX #x r1
+load RLX #x r2
+";
+
+const CORR_FENCED: &str = "x = 1
+store RLX #x x
+
+x = 1
+load RLX #x r1
+fence ld,ld
+load RLX #x r2
+";
+
+#[test]
+fn store_buffering() {
+  assert_outcomes!(SB, MemoryModelType::SC, allowed = ["0:r == 1 && 1:r == 1"], forbidden = ["0:r == 0 && 1:r == 0"]);
+  assert_outcomes!(SB, MemoryModelType::TSO, allowed = ["0:r == 0 && 1:r == 0"]);
+  assert_outcomes!(SB, MemoryModelType::C11, allowed = ["0:r == 0 && 1:r == 0"]);
+  assert_outcomes!(SB, MemoryModelType::NMCA, allowed = ["0:r == 0 && 1:r == 0"]);
+}
+
+#[test]
+fn message_passing() {
+  assert_outcomes!(MP, MemoryModelType::SC, allowed = ["1:r1 == 2 && 1:r2 == 1"], forbidden = ["1:r1 == 2 && 1:r2 == 0"]);
+  assert_outcomes!(MP_FENCED, MemoryModelType::TSO, allowed = ["1:r1 == 2 && 1:r2 == 1"], forbidden = ["1:r1 == 2 && 1:r2 == 0"]);
+  assert_outcomes!(MP, MemoryModelType::C11, allowed = ["1:r1 == 2 && 1:r2 == 0"]);
+  assert_outcomes!(MP_FENCED, MemoryModelType::C11, forbidden = ["1:r1 == 2 && 1:r2 == 0"]);
+  assert_outcomes!(MP, MemoryModelType::NMCA, allowed = ["1:r1 == 2 && 1:r2 == 0"]);
+  assert_outcomes!(MP_FENCED, MemoryModelType::NMCA, forbidden = ["1:r1 == 2 && 1:r2 == 0"]);
+}
+
+#[test]
+fn coherence_of_reads() {
+  assert_outcomes!(CORR, MemoryModelType::SC, allowed = ["1:r1 == 1 && 1:r2 == 1"], forbidden = ["1:r1 == 1 && 1:r2 == 0"]);
+  assert_outcomes!(CORR_FENCED, MemoryModelType::TSO, allowed = ["1:r1 == 1 && 1:r2 == 1"], forbidden = ["1:r1 == 1 && 1:r2 == 0"]);
+  assert_outcomes!(CORR, MemoryModelType::C11, forbidden = ["1:r1 == 1 && 1:r2 == 0"]);
+  assert_outcomes!(CORR, MemoryModelType::NMCA, forbidden = ["1:r1 == 1 && 1:r2 == 0"]);
+}
+
+#[test]
+#[should_panic(expected = "is reached under TSO")]
+fn reached_forbidden_outcome_panics() {
+  assert_outcomes!(SB, MemoryModelType::TSO, forbidden = ["0:r == 0 && 1:r == 0"]);
+}
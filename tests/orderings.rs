@@ -0,0 +1,47 @@
+use isa::{assert_outcomes, memory_model::MemoryModelType, parser::parse_program};
+
+// A thread cycling through accesses, fences of several kinds and flushes.
+fn fenced_thread(length: usize) -> String {
+  let steps = ["store RLX #x v\n", "fence st,st\n", "load RLX #x r\n", "fence ld,ld\n", "fence REL\n", "flush #x\n", "fence st,ld\n"];
+  let mut source = String::from("persistent #1\nx = 1\nv = 1\n");
+  for step in 0..length {
+    source.push_str(steps[step % steps.len()]);
+  }
+  source
+}
+
+// Only the latest fence of each kind orders the instructions after it, so the edges grow with the
+// thread rather than with the square of its fences.
+#[test]
+fn long_fenced_threads_have_linear_edges() {
+  for model in [MemoryModelType::TSO, MemoryModelType::PSO] {
+    for length in [100, 1000] {
+      let program = parse_program(&fenced_thread(length)).unwrap();
+      let edges = model.create(&program).graph().edges().count();
+      assert!(edges < 4 * length, "{:?}: {} edges for {} instructions", model, edges, length);
+    }
+  }
+}
+
+// The second store is ordered after the first through the later `fence st,st` alone.
+const MP_REPEATED_FENCES: &str = "x = 1
+y = 2
+store RLX #x x
+fence st,st
+load RLX #y r
+fence st,st
+store RLX #y y
+
+x = 1
+y = 2
+load RLX #y r1
+fence ld,ld
+fence ld,ld
+load RLX #x r2
+";
+
+#[test]
+fn fences_of_a_kind_keep_ordering() {
+  assert_outcomes!(MP_REPEATED_FENCES, MemoryModelType::TSO, allowed = ["1:r1 == 2 && 1:r2 == 1"], forbidden = ["1:r1 == 2 && 1:r2 == 0"]);
+  assert_outcomes!(MP_REPEATED_FENCES, MemoryModelType::PSO, allowed = ["1:r1 == 2 && 1:r2 == 1"], forbidden = ["1:r1 == 2 && 1:r2 == 0"]);
+}
@@ -0,0 +1,102 @@
+use std::fs;
+
+use isa::{parser::parse_program, preprocessor::Source, program::Program};
+
+const PROGRAM: &str = "thread producer:
+x = 1
+v = 5
+store REL #x v
+fence st,st
+loop: r = v + v
+r = r - v
+r = r * v
+r = r / v
+s = r ? v : x
+if s goto loop
+t := cas SEQ_CST #x v r
+u := fai ACQ #x v
+lock #x
+unlock #x
+load NA #x w
+call end
+end: ret
+
+thread consumer:
+y = 1
+load ACQ #y r
+";
+
+const MACROS: &str = "macro INC(a, out)
+c = 1
+out := fai SEQ_CST #a c
+endmacro
+
+x = 1
+INC(x, r)
+INC(x, s)
+";
+
+// Writes the program back as text, one instruction per line as `Debug` prints it.
+fn print(program: &Program) -> String {
+  let threads: Vec<String> = program.threads.iter().zip(&program.thread_names).map(|(thread, name)| {
+    let instructions: Vec<String> = thread.iter().map(|instruction| format!("{:?}\n", instruction)).collect();
+    format!("thread {}:\n{}", name, instructions.concat())
+  }).collect();
+  threads.join("\n")
+}
+
+fn listing(program: &Program) -> Vec<String> {
+  program.threads.iter().map(|thread| format!("{:?}", thread)).collect()
+}
+
+#[test]
+fn printed_programs_parse_back() {
+  let program = parse_program(PROGRAM).unwrap();
+  assert_eq!(program.thread_names, ["producer", "consumer"]);
+  let reparsed = parse_program(&print(&program)).unwrap();
+  assert_eq!(reparsed.thread_names, program.thread_names);
+  assert_eq!(listing(&reparsed), listing(&program));
+  assert_eq!(print(&reparsed), print(&program));
+}
+
+#[test]
+fn instructions_keep_their_lines() {
+  let program = parse_program(PROGRAM).unwrap();
+  let lines: Vec<Option<usize>> = program.threads[1].iter().map(|instruction| instruction.line).collect();
+  assert_eq!(lines, [Some(21), Some(22)]);
+}
+
+#[test]
+fn parse_errors_name_the_line() {
+  let err = parse_program("x = 1\nfoo bar\n").err().unwrap();
+  assert_eq!(err, "Line 2: error parsing instruction foo bar: Unknown instruction format");
+}
+
+#[test]
+fn macros_expand_at_each_use() {
+  let program = parse_program(MACROS).unwrap();
+  assert_eq!(listing(&program), ["[x = 1, c = 1, r := fai SEQ_CST #x c, c = 1, s := fai SEQ_CST #x c]"]);
+  let lines: Vec<Option<usize>> = program.threads[0].iter().map(|instruction| instruction.line).collect();
+  assert_eq!(lines, [Some(6), Some(7), Some(7), Some(8), Some(8)]);
+}
+
+#[test]
+fn macros_cannot_use_themselves() {
+  let err = parse_program("macro LOOP(a)\nLOOP(a)\nendmacro\n\nLOOP(x)\n").err().unwrap();
+  assert!(err.ends_with("macro LOOP expands to itself"), "{}", err);
+}
+
+#[test]
+fn includes_are_read_relative_to_the_including_file() {
+  let directory = std::env::temp_dir().join(format!("isa-include-{}", std::process::id()));
+  fs::create_dir_all(directory.join("lib")).unwrap();
+  fs::write(directory.join("lib/setup.isa"), "x = 1\nfoo bar\n").unwrap();
+  fs::write(directory.join("main.isa"), "#include \"lib/setup.isa\"\nv = 5\n").unwrap();
+  fs::write(directory.join("self.isa"), "#include \"self.isa\"\n").unwrap();
+  let source = Source::read(&directory.join("main.isa")).unwrap();
+  assert_eq!(source.text, "x = 1\nfoo bar\nv = 5\n");
+  let err = source.locate(parse_program(&source.text).err().unwrap());
+  assert_eq!(err, format!("Line 2 of {}: error parsing instruction foo bar: Unknown instruction format", directory.join("lib/setup.isa").display()));
+  assert!(Source::read(&directory.join("self.isa")).err().unwrap().contains("includes itself"));
+  fs::remove_dir_all(&directory).unwrap();
+}
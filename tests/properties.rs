@@ -0,0 +1,20 @@
+#![cfg(feature = "proptest")]
+
+use isa::{memory_model::MemoryModelType, strategies};
+use proptest::prelude::*;
+
+// Every program is explored exhaustively under both models, so programs stay at two threads and
+// each property checks a few dozen of them rather than proptest's default 256.
+proptest! {
+  #![proptest_config(ProptestConfig::with_cases(32))]
+
+  #[test]
+  fn tso_allows_sc_outcomes(source in strategies::program(1..=2, 0..=4, 2)) {
+    strategies::allows_all(&source, &MemoryModelType::TSO, &MemoryModelType::SC).map_err(TestCaseError::fail)?;
+  }
+
+  #[test]
+  fn pso_allows_tso_outcomes(source in strategies::program(1..=2, 0..=4, 2)) {
+    strategies::allows_all(&source, &MemoryModelType::PSO, &MemoryModelType::TSO).map_err(TestCaseError::fail)?;
+  }
+}
@@ -0,0 +1,46 @@
+use isa::{memory_model::MemoryModelType, testing};
+
+const MODELS: [MemoryModelType; 13] = [
+  MemoryModelType::SC, MemoryModelType::TSO, MemoryModelType::PSO, MemoryModelType::C11, MemoryModelType::JMM,
+  MemoryModelType::Itanium, MemoryModelType::PRAM, MemoryModelType::PC, MemoryModelType::NMCA, MemoryModelType::Scoped,
+  MemoryModelType::Eventual, MemoryModelType::Causal, MemoryModelType::Speculative
+];
+
+// Message passing of a plain store through a flag; the reader only reads the data after the flag.
+fn message_passing(store: &str, load: &str) -> String {
+  format!("a = 1
+f = 2
+v = 5
+one = 1
+store NA #a v
+store {} #f one
+
+a = 1
+f = 2
+load {} #f r
+if r goto +2
+ret
+load NA #a d
+", store, load)
+}
+
+fn errors(source: &str, model: &MemoryModelType) -> Vec<String> {
+  let exploration = testing::explore(source, model).unwrap();
+  exploration.outcomes.into_keys().filter(|outcome| outcome.starts_with("error")).collect()
+}
+
+#[test]
+fn release_acquire_publishes_plain_stores() {
+  let source = message_passing("REL", "ACQ");
+  for model in MODELS {
+    assert_eq!(errors(&source, &model), Vec::<String>::new(), "{:?}", model);
+  }
+}
+
+#[test]
+fn relaxed_flags_leave_plain_accesses_racing() {
+  let source = message_passing("RLX", "RLX");
+  for model in MODELS {
+    assert!(!errors(&source, &model).is_empty(), "{:?}", model);
+  }
+}
@@ -0,0 +1,57 @@
+use std::{fs, sync::atomic::{AtomicUsize, Ordering}};
+
+use isa::{json::{self, Value}, memory_model::MemoryModelType, parser::parse_program, repl::Repl};
+
+const PROGRAM: &str = "a = 1
+b = 2
+c = 3
+
+d = 4
+e = 5
+f = 6
+";
+
+static DUMPS: AtomicUsize = AtomicUsize::new(0);
+
+// Runs the script in a session with the seed and returns what its `dump` command wrote.
+fn dump(seed: u64, script: &str) -> String {
+  let file = std::env::temp_dir().join(format!("isa-repl-{}-{}.json", std::process::id(), DUMPS.fetch_add(1, Ordering::Relaxed)));
+  let mut repl = Repl::new(MemoryModelType::SC, parse_program(PROGRAM).unwrap());
+  repl.set_seed(seed);
+  repl.run_script(format!("{}\ndump {}\n", script, file.display()).as_bytes()).unwrap();
+  let state = fs::read_to_string(&file).unwrap();
+  fs::remove_file(&file).unwrap();
+  state
+}
+
+#[test]
+fn undone_random_steps_are_drawn_again() {
+  for seed in 0..16 {
+    assert_eq!(dump(seed, "step 3\nundo 2\nstep 2"), dump(seed, "step 3"), "seed {}", seed);
+  }
+}
+
+#[test]
+fn redone_random_steps_leave_the_generator_after_them() {
+  for seed in 0..16 {
+    assert_eq!(dump(seed, "step 3\nundo 2\nredo 2\nstep 2"), dump(seed, "step 5"), "seed {}", seed);
+  }
+}
+
+fn registers(values: &[(&str, i64)]) -> Value {
+  Value::Object(values.iter().map(|(r, value)| (r.to_string(), Value::Number(*value))).collect())
+}
+
+#[test]
+fn typed_instructions_join_the_selected_thread() {
+  let state = json::parse(&dump(0, "thread 1\nr = d + e\nrun")).unwrap();
+  let expected = Value::Array(vec![registers(&[("a", 1), ("b", 2), ("c", 3)]), registers(&[("d", 4), ("e", 5), ("f", 6), ("r", 9)])]);
+  assert_eq!(state.get("registers"), Some(&expected));
+}
+
+#[test]
+fn script_errors_name_the_script_line() {
+  let mut repl = Repl::new(MemoryModelType::SC, parse_program(PROGRAM).unwrap());
+  assert_eq!(repl.run_script("step\n\nchoose 9\n".as_bytes()), Err("Error at script line 3: There is no candidate with index 9".to_string()));
+  assert_eq!(repl.run_script("thread 7\n".as_bytes()), Err("Error at script line 1: There is no thread 7".to_string()));
+}
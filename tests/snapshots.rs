@@ -0,0 +1,46 @@
+use isa::{memory_model::{MemoryModel, MemoryModelType}, parser::parse_program};
+
+// A short first thread, which the steps run, and a long second one, so the graph spans many chunks.
+fn program() -> String {
+  let mut source = String::from("y = 2\nw = 2\nstore RLX #y w\nload RLX #y r\n\nx = 1\nv = 1\n");
+  for _ in 0..300 {
+    source.push_str("store RLX #x v\n");
+  }
+  source
+}
+
+fn step_thread(model: &mut dyn MemoryModel, thread_id: usize) {
+  let node = model.get_possible_executions().into_iter().find(|node| node.thread_id == thread_id).unwrap();
+  model.step(node, false).unwrap();
+}
+
+// A snapshot shares the state of the model, and a step of it copies only the parts the step changes.
+#[test]
+fn snapshots_share_what_their_steps_leave_alone() {
+  for model_type in [MemoryModelType::TSO, MemoryModelType::PSO] {
+    let program = parse_program(&program()).unwrap();
+    let mut model = model_type.create(&program);
+    step_thread(model.as_mut(), 1);
+    let mut snapshot = model.snapshot();
+    // The two register assignments and the store.
+    for _ in 0..3 {
+      step_thread(snapshot.as_mut(), 0);
+    }
+    assert!(!std::ptr::eq(model.registers(0), snapshot.registers(0)), "{:?}", model_type);
+    assert!(std::ptr::eq(model.registers(1), snapshot.registers(1)), "{:?}", model_type);
+    assert!(!std::ptr::eq(model.buffer(0), snapshot.buffer(0)), "{:?}", model_type);
+    assert!(std::ptr::eq(model.buffer(1), snapshot.buffer(1)), "{:?}", model_type);
+    assert!(std::ptr::eq(model.storage_system().memory(), snapshot.storage_system().memory()), "{:?}", model_type);
+    // The store adds a propagate node at the end of the graph, and the first nodes have run.
+    let (graph, copy) = (model.graph(), snapshot.graph());
+    assert_eq!(copy.instructions.len(), graph.instructions.len() + 1, "{:?}", model_type);
+    assert!(!std::ptr::eq(&graph.is_active[0], &copy.is_active[0]), "{:?}", model_type);
+    for id in [0, 100, 200] {
+      assert!(std::ptr::eq(&graph.instructions[id], &copy.instructions[id]), "{:?}", model_type);
+    }
+    for id in [100, 200] {
+      assert!(std::ptr::eq(&graph.is_active[id], &copy.is_active[id]), "{:?}", model_type);
+      assert!(std::ptr::eq(&graph.active_neighbors[id], &copy.active_neighbors[id]), "{:?}", model_type);
+    }
+  }
+}